@@ -0,0 +1,19 @@
+const fields = ["user_name", "label", "color", "style"];
+const query = () => ["label", "color", "style"]
+  .map((field) => field + "=" + encodeURIComponent(document.getElementById(field).value))
+  .join("&");
+
+let debounce;
+const render = () => {
+  const userName = encodeURIComponent(document.getElementById("user_name").value);
+  document.getElementById("markdown").value =
+    "![Profile views](" + window.location.origin + "/" + userName + "/counter.svg?" + query() + ")";
+
+  clearTimeout(debounce);
+  debounce = setTimeout(() => {
+    document.getElementById("badge").src = "/builder/preview.svg?" + query();
+  }, 300);
+};
+
+fields.forEach((field) => document.getElementById(field).addEventListener("input", render));
+render();
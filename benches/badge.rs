@@ -0,0 +1,117 @@
+//! Benchmarks of the badge hot paths: laying badges out locally, filling cached shields.io
+//! templates in, and the local cache under contention.
+//!
+//! ```text
+//! cargo bench --features bench
+//! ```
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use github_profile_views_counter::bench::{
+    CacheStore, CacheStores, Config, Font, LruStore, MemoryStore, Rasterizer, Shields,
+    ShieldsIoFetcher, ShieldsIoParams,
+};
+use tokio::runtime::Runtime;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// shields.io's flat badge, with the padding standing in for a 4 characters message
+const TEMPLATE: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="108" height="20" role="img" aria-label="Profile views: ****"><title>Profile views: ****</title><linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient><clipPath id="r"><rect width="108" height="20" rx="3" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="79" height="20" fill="#555"/><rect x="79" width="29" height="20" fill="#007ec6"/><rect width="108" height="20" fill="url(#s)"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" text-rendering="geometricPrecision" font-size="110"><text aria-hidden="true" x="405" y="150" fill="#010101" fill-opacity=".3" transform="scale(.1)" textLength="690">Profile views</text><text x="405" y="140" transform="scale(.1)" fill="#fff" textLength="690">Profile views</text><text aria-hidden="true" x="925" y="150" fill="#010101" fill-opacity=".3" transform="scale(.1)" textLength="190">****</text><text x="925" y="140" transform="scale(.1)" fill="#fff" textLength="190">****</text></g></svg>"##;
+
+// cache operations per task and iteration, one in ten of them a write
+const CACHE_OPS: usize = 100;
+const CACHE_KEYS: usize = 64;
+
+fn layout(c: &mut Criterion) {
+    let rasterizer = Rasterizer::new(None);
+    let mut group = c.benchmark_group("layout");
+    for (label, message) in [
+        ("views", "7"),
+        ("Profile views", "1234"),
+        ("Visitas al perfil", "1234567"),
+    ] {
+        let params = ShieldsIoParams::new(label, "blue", "flat");
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}: {}", label, message)),
+            &params,
+            |b, params| b.iter(|| rasterizer.layout(params, black_box(message), Font::default())),
+        );
+    }
+    group.finish();
+}
+
+fn cached_template(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let shields = runtime.block_on(async {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/static/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(TEMPLATE))
+            .mount(&server)
+            .await;
+
+        let config = Config::from_env();
+        let shields = Shields::new(&config, CacheStores::new(&config))
+            .unwrap()
+            .with_service_url(&format!("{}/static/v1", server.uri()));
+        // every view count of the benchmark is served from the template fetched here
+        shields
+            .fetch(&ShieldsIoParams::new("Profile views", "blue", "flat"), 1000)
+            .await
+            .unwrap();
+        shields
+    });
+
+    let params = ShieldsIoParams::new("Profile views", "blue", "flat");
+    c.bench_function("cached template", |b| {
+        b.to_async(&runtime)
+            .iter(|| shields.fetch(&params, black_box(4321)))
+    });
+}
+
+/// Runs `tasks` tasks at once, each reading and writing the store `CACHE_OPS` times.
+async fn hammer(store: Arc<dyn CacheStore>, tasks: usize) {
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for op in 0..CACHE_OPS {
+                    let key = format!("badge:{}", (task * 7 + op) % CACHE_KEYS);
+                    match op % 10 {
+                        0 => store.set(&key, TEMPLATE, Duration::from_secs(60)).await,
+                        _ => {
+                            black_box(store.get(&key).await);
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap();
+    }
+}
+
+fn cache_contention(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let stores: [(&str, Arc<dyn CacheStore>); 2] = [
+        ("memory", Arc::new(MemoryStore::new())),
+        ("lru", Arc::new(LruStore::new(CACHE_KEYS / 2))),
+    ];
+
+    let mut group = c.benchmark_group("cache contention");
+    for (name, store) in stores {
+        for tasks in [1, 4, 16] {
+            group.bench_with_input(BenchmarkId::new(name, tasks), &tasks, |b, &tasks| {
+                b.to_async(&runtime).iter(|| hammer(store.clone(), tasks))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, layout, cached_template, cache_contention);
+criterion_main!(benches);
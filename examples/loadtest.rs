@@ -0,0 +1,225 @@
+//! Load generator hammering a running instance with badge traffic, then reporting latency
+//! percentiles.
+//!
+//! ```text
+//! cargo run --release --example loadtest -- http://127.0.0.1:3000 --concurrency 64 --duration 30
+//! ```
+//!
+//! Traffic mimics READMEs being rendered: a few popular users get most of the views, badges come
+//! in every style, and most requests come through GitHub's image proxy with some browsers and
+//! crawlers mixed in. Runs with the same `--seed` send the same traffic.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::header::USER_AGENT;
+use tokio::task::JoinSet;
+
+const USAGE: &str = "usage: loadtest <base url> [--concurrency N] [--duration SECS] [--users N] \
+                     [--user-prefix PREFIX] [--seed N]";
+
+const STYLES: &[&str] = &["flat", "flat-square", "plastic", "for-the-badge", "social"];
+const COLORS: &[&str] = &["blue", "brightgreen", "orange", "ff69b4", "informational"];
+const LABELS: &[&str] = &["Profile views", "views", "Visitors", "profile views"];
+
+// user agents by share of the traffic, in percent
+const USER_AGENTS: &[(&str, u32)] = &[
+    ("github-camo (876de43e)", 85),
+    (
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) \
+         Chrome/126.0 Safari/537.36",
+        8,
+    ),
+    (
+        "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+        3,
+    ),
+    (
+        "Mozilla/5.0 (compatible; bingbot/2.0; +http://www.bing.com/bingbot.htm)",
+        2,
+    ),
+    ("curl/8.5.0", 2),
+];
+
+struct Options {
+    base_url: String,
+    concurrency: usize,
+    duration: Duration,
+    users: usize,
+    user_prefix: String,
+    seed: u64,
+}
+
+impl Options {
+    fn from_args() -> Result<Options, String> {
+        let mut args = std::env::args().skip(1);
+        let mut options = Options {
+            base_url: String::new(),
+            concurrency: 32,
+            duration: Duration::from_secs(30),
+            users: 1000,
+            user_prefix: "loadtest-".to_string(),
+            seed: 42,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value of {}", arg));
+            match arg.as_str() {
+                "--concurrency" => options.concurrency = parse(&arg, value()?)?,
+                "--duration" => options.duration = Duration::from_secs(parse(&arg, value()?)?),
+                "--users" => options.users = parse(&arg, value()?)?,
+                "--user-prefix" => options.user_prefix = value()?,
+                "--seed" => options.seed = parse(&arg, value()?)?,
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => options.base_url = arg.trim_end_matches('/').to_string(),
+            }
+        }
+
+        if options.base_url.is_empty() {
+            return Err(USAGE.to_string());
+        }
+        if options.concurrency == 0 || options.users == 0 {
+            return Err("--concurrency and --users must be positive".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn parse<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value of {}: {}", name, value))
+}
+
+/// Outcome of a request: its latency and status, `None` when no response came back.
+struct Sample {
+    latency: Duration,
+    status: Option<u16>,
+}
+
+/// Badge url of a user picked so that a few popular users get most of the views.
+fn badge_url(rng: &mut fastrand::Rng, options: &Options) -> String {
+    let user = (options.users as f64 * rng.f64().powi(3)) as usize;
+    format!(
+        "{}/{}{}/counter.svg?label={}&color={}&style={}",
+        options.base_url,
+        options.user_prefix,
+        user,
+        LABELS[rng.usize(..LABELS.len())].replace(' ', "%20"),
+        COLORS[rng.usize(..COLORS.len())],
+        STYLES[rng.usize(..STYLES.len())],
+    )
+}
+
+fn user_agent(rng: &mut fastrand::Rng) -> &'static str {
+    let mut pick = rng.u32(..100);
+    for (user_agent, share) in USER_AGENTS {
+        if pick < *share {
+            return user_agent;
+        }
+        pick -= share;
+    }
+    USER_AGENTS[0].0
+}
+
+async fn worker(client: reqwest::Client, options: Arc<Options>, worker: u64) -> Vec<Sample> {
+    let mut rng = fastrand::Rng::with_seed(options.seed.wrapping_add(worker));
+    let deadline = Instant::now() + options.duration;
+    let mut samples = Vec::new();
+
+    while Instant::now() < deadline {
+        let request = client
+            .get(badge_url(&mut rng, &options))
+            .header(USER_AGENT, user_agent(&mut rng));
+        let started = Instant::now();
+        // the body is read too, so the latency covers the whole badge
+        let status = match request.send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                response.bytes().await.ok().map(|_| status)
+            }
+            Err(_) => None,
+        };
+        samples.push(Sample {
+            latency: started.elapsed(),
+            status,
+        });
+    }
+    samples
+}
+
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn report(samples: &[Sample], elapsed: Duration) {
+    if samples.is_empty() {
+        println!("no requests were sent");
+        return;
+    }
+
+    let mut statuses: BTreeMap<String, usize> = BTreeMap::new();
+    for sample in samples {
+        let status = match sample.status {
+            Some(status) => status.to_string(),
+            None => "failed".to_string(),
+        };
+        *statuses.entry(status).or_default() += 1;
+    }
+    let mut latencies: Vec<Duration> = samples.iter().map(|sample| sample.latency).collect();
+    latencies.sort();
+
+    println!(
+        "{} requests in {:.1}s, {:.1} requests/s",
+        samples.len(),
+        elapsed.as_secs_f64(),
+        samples.len() as f64 / elapsed.as_secs_f64()
+    );
+    for (status, count) in &statuses {
+        println!("  {}: {}", status, count);
+    }
+    println!("latency:");
+    for p in [50.0, 90.0, 99.0, 99.9] {
+        println!("  p{:<5} {:>10.2?}", p, percentile(&latencies, p));
+    }
+    println!("  max    {:>10.2?}", latencies[latencies.len() - 1]);
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match Options::from_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(options.concurrency)
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("failed to build http client");
+
+    println!(
+        "sending badge requests to {} from {} workers for {}s",
+        options.base_url,
+        options.concurrency,
+        options.duration.as_secs()
+    );
+    let options = Arc::new(options);
+    let started = Instant::now();
+    let mut workers = JoinSet::new();
+    for id in 0..options.concurrency as u64 {
+        workers.spawn(worker(client.clone(), options.clone(), id));
+    }
+    let mut samples = Vec::new();
+    while let Some(worker_samples) = workers.join_next().await {
+        samples.extend(worker_samples.expect("load test worker panicked"));
+    }
+
+    report(&samples, started.elapsed());
+}
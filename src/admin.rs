@@ -0,0 +1,907 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State as StateExtractor},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::anomaly::Flag;
+use super::auth;
+use super::badge::ShieldsIoFetcher;
+use super::cache::CacheStats;
+use super::datastore::{
+    CapturedRequest, DatastoreError, DatastoreOperations, DatastoreUsage, UserViews,
+};
+use super::error::{ApiError, ErrorCode};
+use super::sampling::RequestSample;
+use super::secrets;
+use super::state::AppState;
+use super::user_trace::UserTrace;
+
+const EXPORT_PAGE_SIZE: usize = 200;
+const DEFAULT_LIST_LIMIT: usize = 50;
+const MAX_LIST_LIMIT: usize = 200;
+const DEFAULT_TRACE_TTL: u64 = 600;
+const MAX_TRACE_TTL: u64 = 24 * 60 * 60;
+const MAX_MERGED_USERS: usize = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct UserPathParams {
+    user_name: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListSamplesParams {
+    /// Entries returned, between 1 and 200; defaults to 50
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TraceParams {
+    /// Seconds to trace the user for, between 1 and 86400; defaults to 600
+    ttl: Option<u64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ClearCacheParams {
+    /// Also flush the store shared by all instances, so every instance fetches badges again
+    #[serde(default)]
+    shared: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsersParams {
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    /// Users per page, between 1 and 200; defaults to 50
+    limit: Option<usize>,
+}
+
+impl ListUsersParams {
+    pub fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT)
+    }
+}
+
+/// Lists users with their views, oldest user names first.
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    params(ListUsersParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Page of users", body = UserRecordPage),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn list_users_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Query(params): Query<ListUsersParams>,
+) -> Response {
+    let limit = params.limit();
+    match state.db.list_users(params.cursor, limit).await {
+        Ok(page) => Json(page).into_response(),
+        Err(err) => {
+            tracing::error!("failed to list users, reason: {}", err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to list users").into_response()
+        }
+    }
+}
+
+/// Soft deletes a user, who stops being counted until restored.
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{user_name}",
+    params(UserPathParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn delete_user_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+) -> Response {
+    let result = state.db.delete_user(&path_params.user_name).await;
+    user_update_response("delete", &path_params.user_name, result)
+}
+
+/// Restores a soft deleted user.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_name}/restore",
+    params(UserPathParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "User restored"),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn restore_user_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+) -> Response {
+    let result = state.db.restore_user(&path_params.user_name).await;
+    user_update_response("restore", &path_params.user_name, result)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OwnerToken {
+    user_name: String,
+    /// Bearer token for the owner routes of the user, e.g. the export of their data
+    token: String,
+}
+
+/// Issues the token granting the owner routes of a user, to be handed to the owner of the
+/// profile once they proved it's theirs. Tokens can't be revoked one by one, rotating
+/// `OWNER_TOKEN_SECRET` revokes all of them.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_name}/token",
+    params(UserPathParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Token of the user", body = OwnerToken),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "User not found, or owner tokens are disabled", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn issue_owner_token_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+) -> Response {
+    let user_name = path_params.user_name;
+    let Some(secret) = state.config.secrets.get(secrets::OWNER_TOKEN_SECRET) else {
+        return ApiError::new(
+            ErrorCode::OwnerTokensDisabled,
+            "owner tokens are disabled as no `OWNER_TOKEN_SECRET` is configured",
+        )
+        .into_response();
+    };
+
+    match state.db.get_user(&user_name).await {
+        Ok(Some(_)) => {
+            tracing::info!("issued owner token of user `{}`", user_name);
+            Json(OwnerToken {
+                token: auth::owner_token(&secret, &user_name),
+                user_name,
+            })
+            .into_response()
+        }
+        Ok(None) => ApiError::new(
+            ErrorCode::UserNotFound,
+            format!("user `{}` not found", user_name),
+        )
+        .into_response(),
+        Err(err) => {
+            tracing::error!("failed to issue owner token, reason: {}", err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to get user").into_response()
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RenameRequest {
+    /// User the views are moved to, e.g. the new name of a renamed GitHub account
+    renamed_to: String,
+}
+
+/// Moves the views of a user over to another one, e.g. after the GitHub account got renamed,
+/// registering the other user when unknown. Badges of the old name keep working: their views
+/// are counted on the new name from then on. Purging the old name releases it.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_name}/rename",
+    params(UserPathParams),
+    request_body = RenameRequest,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Views of the user renamed to", body = UserViews),
+        (status = 400, description = "Missing user renamed to, or the same user", body = ErrorBody),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "User not found", body = ErrorBody),
+        (status = 410, description = "Either user is deleted or already renamed", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn rename_user_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+    Json(request): Json<RenameRequest>,
+) -> Response {
+    let (user_name, renamed_to) = (path_params.user_name, request.renamed_to);
+    if renamed_to.is_empty() || renamed_to == user_name {
+        return ApiError::new(
+            ErrorCode::InvalidRequest,
+            "`renamed_to` must name another user",
+        )
+        .into_response();
+    }
+
+    merge_users(&state, vec![user_name], renamed_to).await
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MergeRequest {
+    /// Users merged into the user of the path, up to 100
+    user_names: Vec<String>,
+}
+
+/// Merges the views, day stats and history of several users into another one all at once,
+/// e.g. counters of a profile spread over differently spelled names, registering it when
+/// unknown. The merged users are left as aliases like renamed users.
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_name}/merge",
+    params(UserPathParams),
+    request_body = MergeRequest,
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Views of the user merged into", body = UserViews),
+        (status = 400, description = "No users, too many, duplicates, or the user merged into", body = ErrorBody),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "A merged user not found", body = ErrorBody),
+        (status = 410, description = "A user is deleted or already renamed", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn merge_users_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+    Json(request): Json<MergeRequest>,
+) -> Response {
+    let (merged_into, user_names) = (path_params.user_name, request.user_names);
+    let mut unique: Vec<&String> = user_names.iter().collect();
+    unique.sort();
+    unique.dedup();
+    let mistake = match user_names.len() {
+        0 => Some("`user_names` must name at least one user".to_string()),
+        len if len > MAX_MERGED_USERS => Some(format!(
+            "`user_names` must name at most {} users",
+            MAX_MERGED_USERS
+        )),
+        len if unique.len() < len => Some("`user_names` must not repeat users".to_string()),
+        _ if user_names
+            .iter()
+            .any(|user_name| user_name.is_empty() || *user_name == merged_into) =>
+        {
+            Some("`user_names` must name users other than the one merged into".to_string())
+        }
+        _ => None,
+    };
+    if let Some(mistake) = mistake {
+        return ApiError::new(ErrorCode::InvalidRequest, mistake).into_response();
+    }
+
+    merge_users(&state, user_names, merged_into).await
+}
+
+async fn merge_users(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    user_names: Vec<String>,
+    merged_into: String,
+) -> Response {
+    match state.db.merge_users(&user_names, &merged_into).await {
+        Ok(views) => {
+            state.history.merge(&user_names, &merged_into);
+            tracing::info!(
+                "merged user(s) `{}` into `{}`",
+                user_names.join("`, `"),
+                merged_into
+            );
+            Json(UserViews {
+                user_name: merged_into,
+                views,
+            })
+            .into_response()
+        }
+        Err(DatastoreError::UserNotFound(user_name)) => ApiError::new(
+            ErrorCode::UserNotFound,
+            format!("user `{}` not found", user_name),
+        )
+        .into_response(),
+        Err(err @ (DatastoreError::UserDeleted(_) | DatastoreError::UserRenamed(_, _))) => {
+            ApiError::new(ErrorCode::UserDeleted, err.to_string()).into_response()
+        }
+        Err(err) => {
+            tracing::error!(
+                "failed to merge users into `{}`, reason: {}",
+                merged_into,
+                err
+            );
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to merge users").into_response()
+        }
+    }
+}
+
+/// Lists users flagged for unusual view spikes, in user name order.
+#[utoipa::path(
+    get,
+    path = "/admin/flags",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Flagged users; empty when anomaly detection is disabled", body = [Flag]),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn list_flags_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+) -> Response {
+    let Some(anomalies) = &state.anomalies else {
+        return Json(Vec::<Flag>::new()).into_response();
+    };
+    match anomalies.flags(&state.db).await {
+        Ok(flags) => Json(flags).into_response(),
+        Err(err) => {
+            tracing::error!("failed to list flags, reason: {}", err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to list flags").into_response()
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CacheReport {
+    /// shields.io badge templates by params and message length; `null` when badges aren't
+    /// fetched from shields.io
+    badge_templates: Option<CacheStats>,
+    /// Png and webp badges rendered without their message, by scale and params
+    raster_images: CacheStats,
+}
+
+/// Sizes, hits and misses and the most hit keys of the badge caches.
+#[utoipa::path(
+    get,
+    path = "/admin/cache",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Cache stats", body = CacheReport),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    )
+)]
+pub async fn cache_stats_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+) -> Json<CacheReport> {
+    Json(CacheReport {
+        badge_templates: state.badge.cache_stats().await,
+        raster_images: state.raster.cache_stats(),
+    })
+}
+
+/// Flushes the badge caches; badges get fetched and rendered again on their next request.
+#[utoipa::path(
+    delete,
+    path = "/admin/cache",
+    security(("admin_token" = [])),
+    params(ClearCacheParams),
+    responses(
+        (status = 204, description = "Caches flushed"),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    )
+)]
+pub async fn clear_cache_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Query(params): Query<ClearCacheParams>,
+) -> StatusCode {
+    state.badge.clear_cache().await;
+    state.raster.clear_cache();
+    if let Some(shared) = state.caches.shared.as_ref().filter(|_| params.shared) {
+        shared.clear().await;
+        tracing::info!("flushed shared cache");
+    }
+    tracing::info!("flushed badge caches");
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReloadedSecrets {
+    /// Names of the secrets which changed, e.g. `XATA_API_KEY`; values are never returned
+    reloaded: Vec<String>,
+}
+
+/// Reads the secrets kept in files and by the `SECRETS_PROVIDER` again, so a rotated xata api
+/// key or token applies from the next request on without a restart.
+#[utoipa::path(
+    post,
+    path = "/admin/reload-secrets",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Secrets reloaded", body = ReloadedSecrets),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 500, description = "Secrets provider unreachable, secrets from files were reloaded regardless", body = ErrorBody),
+    )
+)]
+pub async fn reload_secrets_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+) -> Result<Json<ReloadedSecrets>, ApiError> {
+    let reloaded = state.config.secrets.refresh().await.map_err(|err| {
+        tracing::error!("failed to fetch secrets from the provider: {}", err);
+        ApiError::new(
+            ErrorCode::SecretsUnavailable,
+            "failed to fetch secrets from the provider",
+        )
+    })?;
+    Ok(Json(ReloadedSecrets {
+        reloaded: reloaded.into_iter().map(str::to_string).collect(),
+    }))
+}
+
+/// Operations sent to metered datastore backends per hour and day, for predicting their bill.
+#[utoipa::path(
+    get,
+    path = "/admin/usage",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Operations per backend; empty when no backend is metered", body = [DatastoreUsage]),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    )
+)]
+pub async fn usage_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+) -> Json<Vec<DatastoreUsage>> {
+    Json(state.db.usage())
+}
+
+/// Lists the most recently sampled requests, newest first.
+#[utoipa::path(
+    get,
+    path = "/admin/samples",
+    params(ListSamplesParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Sampled requests; empty when sampling is disabled", body = [RequestSample]),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    )
+)]
+pub async fn list_samples_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Query(params): Query<ListSamplesParams>,
+) -> Json<Vec<RequestSample>> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    Json(
+        state
+            .sampler
+            .as_ref()
+            .map(|sampler| sampler.samples(limit))
+            .unwrap_or_default(),
+    )
+}
+
+/// Lists the latest requests to the datastore which failed, newest first, with the responses
+/// and a curl command replaying them; secrets are replaced by placeholders. Requests are only
+/// captured while `XATA_CAPTURE_FAILED_REQUESTS` is set.
+#[utoipa::path(
+    get,
+    path = "/admin/debug/requests",
+    params(ListSamplesParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "Failed requests; empty when capturing is disabled", body = [CapturedRequest]),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    )
+)]
+pub async fn list_captured_requests_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Query(params): Query<ListSamplesParams>,
+) -> Json<Vec<CapturedRequest>> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    Json(state.db.captured_requests(limit))
+}
+
+/// Logs every decision taken for the user's requests, e.g. why views were not counted, until the
+/// trace expires; other users' requests are not logged in detail.
+#[utoipa::path(
+    post,
+    path = "/admin/debug/{user_name}",
+    params(UserPathParams, TraceParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "User traced", body = UserTrace),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    )
+)]
+pub async fn start_trace_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+    Query(params): Query<TraceParams>,
+) -> Json<UserTrace> {
+    let ttl = params
+        .ttl
+        .unwrap_or(DEFAULT_TRACE_TTL)
+        .clamp(1, MAX_TRACE_TTL);
+    let trace = state.traces.start(
+        &path_params.user_name,
+        chrono::Duration::seconds(ttl as i64),
+    );
+    tracing::info!(
+        "tracing user `{}` until {}",
+        trace.user_name,
+        trace.expires_at
+    );
+    Json(trace)
+}
+
+/// Stops tracing the user before the trace expires.
+#[utoipa::path(
+    delete,
+    path = "/admin/debug/{user_name}",
+    params(UserPathParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "User no longer traced"),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+    )
+)]
+pub async fn stop_trace_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+) -> StatusCode {
+    if state.traces.stop(&path_params.user_name) {
+        tracing::info!("stopped tracing user `{}`", path_params.user_name);
+    }
+    StatusCode::NO_CONTENT
+}
+
+/// Clears the flag of a user after review; frozen users start counting views again.
+#[utoipa::path(
+    delete,
+    path = "/admin/flags/{user_name}",
+    params(UserPathParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 204, description = "Flag cleared"),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 404, description = "User not flagged", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn clear_flag_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<UserPathParams>,
+) -> Response {
+    let cleared = match &state.anomalies {
+        Some(anomalies) => anomalies.clear(&state.db, &path_params.user_name).await,
+        None => Ok(false),
+    };
+
+    match cleared {
+        Err(err) => {
+            tracing::error!(
+                "failed to clear flag of user `{}`, reason: {}",
+                path_params.user_name,
+                err
+            );
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to clear flag").into_response()
+        }
+        Ok(true) => {
+            tracing::info!("cleared flag of user `{}`", path_params.user_name);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => ApiError::new(
+            ErrorCode::FlagNotFound,
+            format!("user `{}` is not flagged", path_params.user_name),
+        )
+        .into_response(),
+    }
+}
+
+fn user_update_response(
+    action: &str,
+    user_name: &str,
+    result: Result<(), DatastoreError>,
+) -> Response {
+    match result {
+        Ok(()) => {
+            tracing::info!("{} user `{}` succeeded", action, user_name);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(DatastoreError::UserNotFound(_)) => ApiError::new(
+            ErrorCode::UserNotFound,
+            format!("user `{}` not found", user_name),
+        )
+        .into_response(),
+        Err(err) => {
+            tracing::error!("failed to {} user `{}`, reason: {}", action, user_name, err);
+            ApiError::new(
+                ErrorCode::DatastoreUnavailable,
+                format!("failed to {} user", action),
+            )
+            .into_response()
+        }
+    }
+}
+
+/// Exports views of all users as CSV.
+#[utoipa::path(
+    get,
+    path = "/admin/export.csv",
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "`user_name,views` rows", content_type = "text/csv"),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn export_handler<T, F>(
+    StateExtractor(state): StateExtractor<Arc<AppState<T, F>>>,
+) -> Response
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    // first page is fetched upfront so an unavailable datastore fails the whole request
+    let first_page = match state.db.scan(None, EXPORT_PAGE_SIZE).await {
+        Ok(page) => page,
+        Err(err) => {
+            tracing::error!("failed to export views, reason: {}", err);
+            return ApiError::new(ErrorCode::DatastoreUnavailable, "failed to export views")
+                .into_response();
+        }
+    };
+
+    let first_chunk = format!("user_name,views\n{}", csv_rows(&first_page.users));
+    let first_chunk = stream::once(async { Ok(first_chunk) });
+
+    // remaining pages are fetched lazily, so only a single page is ever held in memory
+    let rows = stream::try_unfold(first_page.next_cursor, move |cursor| {
+        let state = state.clone();
+        async move {
+            let Some(cursor) = cursor else {
+                return Ok(None);
+            };
+
+            let page = state
+                .db
+                .scan(Some(cursor), EXPORT_PAGE_SIZE)
+                .await
+                .map_err(|err| {
+                    tracing::error!("failed to export views, reason: {}", err);
+                    err
+                })?;
+            Ok::<_, DatastoreError>(Some((csv_rows(&page.users), page.next_cursor)))
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"export.csv\"",
+            ),
+        ],
+        Body::from_stream(first_chunk.chain(rows)),
+    )
+        .into_response()
+}
+
+fn csv_rows(users: &[UserViews]) -> String {
+    users
+        .iter()
+        .map(|user| format!("{},{}\n", csv_field(&user.user_name), user.views))
+        .collect()
+}
+
+/// Field of a CSV row, quoted when it holds separators or quotes. Fields starting like a formula
+/// are prefixed with `'`, so spreadsheets opening the export show them rather than evaluate them.
+pub fn csv_field(value: &str) -> String {
+    let value = match value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => format!("'{}", value),
+        false => value.to_string(),
+    };
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+    use crate::badge::Shields;
+    use crate::cache::CacheStores;
+    use crate::config::{Config, FaultConfig};
+    use crate::datastore::{FaultyDatastore, Memory};
+    use crate::runtime::TokioSpawner;
+    use pretty_assertions::assert_eq;
+
+    fn state() -> Arc<AppState<Memory, Shields>> {
+        let config = Config::from_env();
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        Arc::new(AppState::new(
+            Memory::new(),
+            shields,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        ))
+    }
+
+    async fn body_text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    fn user(user_name: &str) -> Path<UserPathParams> {
+        Path(UserPathParams {
+            user_name: user_name.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn it_deletes_and_restores_users() {
+        let state = state();
+        state.db.onboard_user("alice").await.unwrap();
+
+        let response = delete_user_handler(StateExtractor(state.clone()), user("alice")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(matches!(
+            state.db.get_latest_views("alice").await,
+            Err(DatastoreError::UserDeleted(_))
+        ));
+
+        let response = restore_user_handler(StateExtractor(state.clone()), user("alice")).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        // views counted while deleted were never stored
+        assert_eq!(state.db.get_latest_views("alice").await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_answers_updates_of_unknown_users_with_not_found() {
+        let state = state();
+
+        for response in [
+            delete_user_handler(StateExtractor(state.clone()), user("nobody")).await,
+            restore_user_handler(StateExtractor(state.clone()), user("nobody")).await,
+        ] {
+            assert_eq!(response.status(), ErrorCode::UserNotFound.status());
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("user `nobody` not found"));
+        }
+    }
+
+    #[test]
+    fn it_quotes_csv_fields_holding_separators() {
+        for (value, field) in [
+            ("alice", "alice"),
+            ("a,b", "\"a,b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("two\nlines", "\"two\nlines\""),
+            ("two\r\nlines", "\"two\r\nlines\""),
+        ] {
+            assert_eq!(csv_field(value), field, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn it_keeps_spreadsheets_from_evaluating_csv_fields() {
+        for (value, field) in [
+            ("=1+1", "'=1+1"),
+            ("+1", "'+1"),
+            ("-1", "'-1"),
+            ("@SUM(A1)", "'@SUM(A1)"),
+            ("\t=1", "'\t=1"),
+            (
+                "=HYPERLINK(\"http://x\",\"y\")",
+                "\"'=HYPERLINK(\"\"http://x\"\",\"\"y\"\")\"",
+            ),
+            // only leading characters start formulas
+            ("a=1", "a=1"),
+        ] {
+            assert_eq!(csv_field(value), field, "{:?}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_exports_views_of_every_page_as_csv() {
+        let state = state();
+        let mut user_names: Vec<String> = (0..EXPORT_PAGE_SIZE)
+            .map(|i| format!("user-{:03}", i))
+            .collect();
+        user_names.extend(["=cmd", "a,b", "quote\"d"].map(String::from));
+        for user_name in &user_names {
+            state.db.onboard_user(user_name).await.unwrap();
+        }
+        state.db.get_latest_views("a,b").await.unwrap();
+
+        let response = export_handler(StateExtractor(state)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        let csv = body_text(response).await;
+
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 1 + user_names.len());
+        assert_eq!(lines[0], "user_name,views");
+        assert!(lines.contains(&"'=cmd,1"));
+        assert!(lines.contains(&"\"a,b\",2"));
+        assert!(lines.contains(&"\"quote\"\"d\",1"));
+        assert!(lines.contains(&"user-000,1"));
+        assert!(lines.contains(&"user-199,1"));
+    }
+
+    #[tokio::test]
+    async fn it_fails_exports_of_an_unavailable_datastore() {
+        let config = Config::from_env();
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        let outage = Some(FaultConfig {
+            error_rate: 1.0,
+            ..FaultConfig::default()
+        });
+        let state = Arc::new(AppState::new(
+            FaultyDatastore::new(Memory::new(), outage),
+            shields,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        ));
+
+        let response = export_handler(StateExtractor(state)).await;
+        assert_eq!(response.status(), ErrorCode::DatastoreUnavailable.status());
+        assert!(body_text(response).await.contains("failed to export views"));
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Timelike, Utc};
+
+use super::config::AnomalyConfig;
+pub use super::datastore::Flag;
+use super::datastore::{DatastoreError, DatastoreOperations};
+
+// weight of the latest hour in the baseline
+const BASELINE_ALPHA: f64 = 0.1;
+const ANALYZE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Flags users whose hourly views jump far above their usual rate, optionally freezing their
+/// counts until an admin clears the flag. Rates live in process memory, flags in the `flags`
+/// table of the datastore, so they outlive restarts and every instance freezes flagged users.
+pub struct AnomalyDetector {
+    config: AnomalyConfig,
+    rates: Mutex<HashMap<String, Rate>>,
+    // flags of every instance as of the last analysis, read for every counted view
+    flags: Mutex<BTreeMap<String, Flag>>,
+}
+
+struct Rate {
+    hour: i64,
+    current: u64,
+    previous: u64,
+    // average of completed hours, `None` until the first hour completed
+    baseline: Option<f64>,
+    views: u64,
+}
+
+impl Rate {
+    fn roll(&mut self, hour: i64) {
+        if hour <= self.hour {
+            return;
+        }
+
+        let mut baseline = match self.baseline {
+            Some(baseline) => {
+                baseline * (1.0 - BASELINE_ALPHA) + self.current as f64 * BASELINE_ALPHA
+            }
+            None => self.current as f64,
+        };
+        // hours without views pull the baseline down as well
+        let idle_hours = (hour - self.hour - 1).min(i32::MAX as i64) as i32;
+        baseline *= (1.0 - BASELINE_ALPHA).powi(idle_hours);
+
+        self.baseline = Some(baseline);
+        self.previous = if idle_hours == 0 { self.current } else { 0 };
+        self.current = 0;
+        self.hour = hour;
+    }
+
+    /// Views over the last 60 minutes, assuming the previous hour's views were spread evenly.
+    fn hourly_views(&self, now: DateTime<Utc>) -> u64 {
+        let elapsed = f64::from(now.minute()) / 60.0;
+        self.current + (self.previous as f64 * (1.0 - elapsed)).round() as u64
+    }
+}
+
+fn hour_of(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(3600)
+}
+
+impl AnomalyDetector {
+    pub fn new(config: AnomalyConfig) -> AnomalyDetector {
+        AnomalyDetector {
+            config,
+            rates: Mutex::new(HashMap::new()),
+            flags: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Views to serve instead of counting, when the user is flagged and flagged users are frozen.
+    pub fn frozen_views(&self, user_name: &str) -> Option<u64> {
+        match self.config.freeze {
+            true => self
+                .flags
+                .lock()
+                .unwrap()
+                .get(user_name)
+                .map(|flag| flag.views),
+            false => None,
+        }
+    }
+
+    /// Records a counted view.
+    pub fn record(&self, user_name: &str, views: u64) {
+        self.record_at(Utc::now(), user_name, views)
+    }
+
+    fn record_at(&self, now: DateTime<Utc>, user_name: &str, views: u64) {
+        let hour = hour_of(now);
+        let mut rates = self.rates.lock().unwrap();
+        let rate = rates.entry(user_name.to_string()).or_insert(Rate {
+            hour,
+            current: 0,
+            previous: 0,
+            baseline: None,
+            views,
+        });
+
+        rate.roll(hour);
+        rate.current += 1;
+        rate.views = views;
+    }
+
+    /// Flag of the user as of the last analysis.
+    pub fn flag(&self, user_name: &str) -> Option<Flag> {
+        self.flags.lock().unwrap().get(user_name).cloned()
+    }
+
+    /// Flags of every instance, in user name order.
+    pub async fn flags(&self, db: &impl DatastoreOperations) -> Result<Vec<Flag>, DatastoreError> {
+        db.list_flags().await
+    }
+
+    /// Clears the flag of the user, who starts over with a fresh baseline. Other instances
+    /// unfreeze the user with their next analysis.
+    pub async fn clear(
+        &self,
+        db: &impl DatastoreOperations,
+        user_name: &str,
+    ) -> Result<bool, DatastoreError> {
+        let cleared = db.delete_flag(user_name).await?;
+        self.rates.lock().unwrap().remove(user_name);
+        let flagged = self.flags.lock().unwrap().remove(user_name).is_some();
+        Ok(cleared || flagged)
+    }
+
+    pub async fn analyze_loop(&self, db: &impl DatastoreOperations) {
+        let mut interval = tokio::time::interval(ANALYZE_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.analyze_at(db, Utc::now()).await;
+        }
+    }
+
+    /// Stores the flags of users whose views spiked, then picks up the flags of every instance.
+    async fn analyze_at(&self, db: &impl DatastoreOperations, now: DateTime<Utc>) {
+        for flag in self.detect(now) {
+            // a flag failing to be stored is detected again with the next analysis
+            if let Err(err) = db.put_flag(&flag).await {
+                tracing::error!("failed to store flag of user `{}`: {}", flag.user_name, err);
+            }
+        }
+
+        match db.list_flags().await {
+            Ok(listed) => {
+                *self.flags.lock().unwrap() = listed
+                    .into_iter()
+                    .map(|flag| (flag.user_name.clone(), flag))
+                    .collect();
+            }
+            Err(err) => tracing::warn!("failed to list flags, keeping the last ones: {}", err),
+        }
+    }
+
+    /// Flags users whose views spiked since the last analysis, returning the new flags.
+    fn detect(&self, now: DateTime<Utc>) -> Vec<Flag> {
+        let hour = hour_of(now);
+        let mut rates = self.rates.lock().unwrap();
+        let mut flags = self.flags.lock().unwrap();
+        let mut flagged = Vec::new();
+
+        rates.retain(|user_name, rate| {
+            rate.roll(hour);
+            let hourly_views = rate.hourly_views(now);
+            let baseline = rate.baseline.unwrap_or(0.0);
+            let threshold =
+                (baseline * self.config.factor).max(self.config.min_hourly_views as f64);
+
+            if hourly_views as f64 > threshold && !flags.contains_key(user_name) {
+                tracing::warn!(
+                    "flagging user `{}`, {} views over the last hour against a baseline of {:.1}",
+                    user_name,
+                    hourly_views,
+                    baseline
+                );
+                let flag = Flag {
+                    user_name: user_name.to_string(),
+                    flagged_at: now,
+                    hourly_views,
+                    baseline,
+                    views: rate.views,
+                };
+                flags.insert(user_name.to_string(), flag.clone());
+                flagged.push(flag);
+            }
+
+            // users without views for a while are forgotten, their baseline is close to zero
+            hourly_views > 0 || baseline >= 1.0
+        });
+
+        flagged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::Memory;
+    use pretty_assertions::assert_eq;
+
+    const USER_NAME: &str = "octocat";
+
+    fn detector(freeze: bool) -> AnomalyDetector {
+        AnomalyDetector::new(AnomalyConfig {
+            factor: 10.0,
+            min_hourly_views: 50,
+            freeze,
+        })
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        format!("2023-06-01T{:02}:{:02}:00Z", hour, minute)
+            .parse()
+            .unwrap()
+    }
+
+    fn record_views(detector: &AnomalyDetector, now: DateTime<Utc>, count: u64) {
+        for views in 1..=count {
+            detector.record_at(now, USER_NAME, views);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_flags_spikes_above_baseline() {
+        let db = Memory::new();
+        let detector = detector(false);
+        record_views(&detector, at(10, 0), 20);
+        record_views(&detector, at(11, 0), 20);
+        detector.analyze_at(&db, at(11, 30)).await;
+        assert_eq!(detector.flags(&db).await.unwrap(), vec![]);
+
+        record_views(&detector, at(12, 0), 500);
+        detector.analyze_at(&db, at(12, 30)).await;
+
+        let flags = detector.flags(&db).await.unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].user_name, USER_NAME);
+        assert_eq!(flags[0].hourly_views, 510);
+        assert_eq!(detector.flag(USER_NAME), Some(flags[0].clone()));
+        assert_eq!(detector.frozen_views(USER_NAME), None);
+    }
+
+    #[tokio::test]
+    async fn it_freezes_flagged_users_until_cleared() {
+        let db = Memory::new();
+        let detector = detector(true);
+        record_views(&detector, at(10, 0), 100);
+        detector.analyze_at(&db, at(10, 30)).await;
+
+        assert_eq!(detector.frozen_views(USER_NAME), Some(100));
+        assert!(detector.clear(&db, USER_NAME).await.unwrap());
+        assert_eq!(detector.frozen_views(USER_NAME), None);
+        assert_eq!(db.list_flags().await.unwrap(), vec![]);
+        assert!(!detector.clear(&db, USER_NAME).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn it_freezes_users_flagged_by_other_instances() {
+        let db = Memory::new();
+        let flagging = detector(true);
+        let other = detector(true);
+        record_views(&flagging, at(10, 0), 100);
+        flagging.analyze_at(&db, at(10, 30)).await;
+
+        other.analyze_at(&db, at(10, 31)).await;
+        assert_eq!(other.frozen_views(USER_NAME), Some(100));
+
+        assert!(flagging.clear(&db, USER_NAME).await.unwrap());
+        other.analyze_at(&db, at(10, 32)).await;
+        assert_eq!(other.frozen_views(USER_NAME), None);
+    }
+}
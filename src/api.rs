@@ -0,0 +1,455 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State as StateExtractor},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::anomaly::Flag;
+use super::auth::{self, Requirement};
+use super::badge::ShieldsIoFetcher;
+use super::datastore::{DatastoreError, DatastoreOperations, Increment, UserRecord, UserViews};
+use super::error::{ApiError, ErrorCode};
+use super::experiment::ExperimentResults;
+use super::github::GitHubError;
+use super::history::DayViews;
+use super::sampling::RequestSample;
+use super::state::AppState;
+
+const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CountsParams {
+    /// Comma separated user names, at most 100
+    users: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct IncrementRequest {
+    /// GitHub user name, the user must have been onboarded by a badge view
+    user: String,
+    /// Views to add, at least 1
+    count: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct OnboardRequest {
+    /// GitHub user name claimed by the bearer token
+    user_name: String,
+}
+
+/// Onboards a user ahead of their first view, which `ONBOARDING=explicit` requires before views
+/// are counted. The bearer token claims the user name: the user's owner token, a GitHub OAuth
+/// token issued to the user, accepted with `ONBOARDING=explicit`, or the admin token.
+#[utoipa::path(
+    post,
+    path = "/api/onboard",
+    request_body = OnboardRequest,
+    security(("owner_token" = []), ("github_token" = []), ("admin_token" = [])),
+    responses(
+        (status = 201, description = "User onboarded without views", body = UserViews),
+        (status = 400, description = "Invalid user name", body = ErrorBody),
+        (status = 401, description = "Missing token, or a token of another user", body = ErrorBody),
+        (status = 409, description = "User already onboarded", body = ErrorBody),
+        (status = 500, description = "Datastore or GitHub api failure", body = ErrorBody),
+    )
+)]
+pub async fn onboard_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    headers: HeaderMap,
+    Json(request): Json<OnboardRequest>,
+) -> Response {
+    let user_name = request.user_name;
+    // tenant users are keyed `<tenant>/<user>` and onboarded by their first view
+    if user_name.is_empty() || user_name.contains('/') {
+        return ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("invalid user `{}`", user_name),
+        )
+        .into_response();
+    }
+
+    let claimed =
+        match auth::authorize(&state, &headers, Requirement::Owner, Some(&user_name)).await {
+            Ok(()) => true,
+            Err(_) => match (&state.github, auth::bearer_token(&headers)) {
+                (Some(github), Some(token)) => match github.login(token).await {
+                    // GitHub logins are case insensitive
+                    Ok(login) => login.eq_ignore_ascii_case(&user_name),
+                    Err(GitHubError::InvalidToken) => false,
+                    Err(err) => {
+                        tracing::error!("failed to look the token up on github, reason: {}", err);
+                        return ApiError::new(
+                            ErrorCode::GitHubUnavailable,
+                            "failed to look the token up on github",
+                        )
+                        .into_response();
+                    }
+                },
+                _ => false,
+            },
+        };
+    if !claimed {
+        return ApiError::new(
+            ErrorCode::Unauthorized,
+            format!("missing or invalid token of user `{}`", user_name),
+        )
+        .into_response();
+    }
+
+    match state.db.register_user(&user_name).await {
+        Ok(()) => {
+            tracing::info!("user `{}` onboarded", user_name);
+            let user = UserViews {
+                user_name,
+                views: 0,
+            };
+            (StatusCode::CREATED, Json(user)).into_response()
+        }
+        Err(DatastoreError::UserExists(user_name)) => ApiError::new(
+            ErrorCode::UserExists,
+            format!("user `{}` is already onboarded", user_name),
+        )
+        .into_response(),
+        Err(err) => {
+            tracing::error!("failed to onboard user `{}`, reason: {}", user_name, err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to onboard user").into_response()
+        }
+    }
+}
+
+/// Adds views counted elsewhere, e.g. on a personal website, to the users' totals. The batch is
+/// applied in a single transaction: either every user gets incremented or none.
+#[utoipa::path(
+    post,
+    path = "/api/increments",
+    request_body = [IncrementRequest],
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "New views of the incremented users", body = [UserViews]),
+        (status = 400, description = "Empty or too large batch, or an invalid increment", body = ErrorBody),
+        (status = 401, description = "Missing or invalid api key", body = ErrorBody),
+        (status = 404, description = "A user is not onboarded, or api routes are disabled", body = ErrorBody),
+        (status = 410, description = "A user is deleted", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn increments_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Json(requests): Json<Vec<IncrementRequest>>,
+) -> Response {
+    let increments = match increments(requests) {
+        Ok(increments) => increments,
+        Err(err) => return err.into_response(),
+    };
+
+    match state.db.increment_views(&increments).await {
+        Ok(users) => {
+            tracing::info!("incremented views of {} users", users.len());
+            Json(users).into_response()
+        }
+        Err(DatastoreError::UserNotFound(user_name)) => ApiError::new(
+            ErrorCode::UserNotFound,
+            format!("user `{}` not found", user_name),
+        )
+        .into_response(),
+        Err(DatastoreError::UserDeleted(user_name)) => ApiError::new(
+            ErrorCode::UserDeleted,
+            format!("user `{}` is deleted", user_name),
+        )
+        .into_response(),
+        Err(err @ DatastoreError::UserRenamed(_, _)) => {
+            ApiError::new(ErrorCode::UserDeleted, err.to_string()).into_response()
+        }
+        Err(err) => {
+            tracing::error!("failed to increment views, reason: {}", err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to increment views")
+                .into_response()
+        }
+    }
+}
+
+/// Views of up to 100 users in one call, without counting a view. Unknown and deleted users are
+/// left out of the response.
+#[utoipa::path(
+    get,
+    path = "/api/counts",
+    params(CountsParams),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Views of the known users, in the order asked for", body = [UserViews]),
+        (status = 400, description = "No or too many users", body = ErrorBody),
+        (status = 401, description = "Missing or invalid api key", body = ErrorBody),
+        (status = 404, description = "Api routes are disabled", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn counts_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Query(params): Query<CountsParams>,
+) -> Response {
+    let user_names = match user_names(&params.users) {
+        Ok(user_names) => user_names,
+        Err(err) => return err.into_response(),
+    };
+
+    match state.db.get_many(&user_names).await {
+        Ok(users) => Json(users).into_response(),
+        Err(err) => {
+            tracing::error!("failed to get views of users, reason: {}", err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to get views").into_response()
+        }
+    }
+}
+
+/// Badges of each variant served for the user's A/B experiment, see the `b_` params of the
+/// counter.svg route.
+#[utoipa::path(
+    get,
+    path = "/api/experiments/{user_name}",
+    params(("user_name" = String, Path, description = "GitHub user name running the experiment")),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, description = "Badges served per variant", body = ExperimentResults),
+        (status = 401, description = "Missing or invalid api key", body = ErrorBody),
+        (status = 404, description = "Api routes are disabled", body = ErrorBody),
+    )
+)]
+pub async fn experiment_results_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(user_name): Path<String>,
+) -> Json<ExperimentResults> {
+    Json(state.experiments.results(&user_name).await)
+}
+
+/// Everything the server holds about a user. Daily views and samples live in the memory of the
+/// instance answering, so they cover its uptime alone; so do experiments without a shared cache.
+#[derive(Serialize, ToSchema)]
+pub struct UserExport {
+    user_name: String,
+    exported_at: DateTime<Utc>,
+    /// Views and timestamps as stored in the datastore
+    record: UserRecord,
+    /// Most views counted on a single UTC day; `null` for deleted users
+    peak_day_views: Option<u64>,
+    /// Views per UTC day over the last two months, days without views left out
+    daily_views: Vec<DayViews>,
+    /// Flag for unusual views; `null` unless the user is flagged
+    flag: Option<Flag>,
+    experiment: ExperimentResults,
+    /// Sampled requests for the user's routes, with their referrers and user agents
+    requests: Vec<RequestSample>,
+}
+
+/// Exports all data held about the user as a json file, for its owner to download. Users have no
+/// settings of their own: badges are styled by their params alone.
+#[utoipa::path(
+    get,
+    path = "/api/users/{user_name}/export",
+    params(("user_name" = String, Path, description = "GitHub user name whose data is exported")),
+    security(("owner_token" = []), ("admin_token" = [])),
+    responses(
+        (status = 200, description = "Data held about the user, as an attachment", body = UserExport),
+        (status = 401, description = "Missing or invalid owner token", body = ErrorBody),
+        (status = 404, description = "User not found, or owner tokens are disabled", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn export_user_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(user_name): Path<String>,
+) -> Response {
+    let failed = |err: DatastoreError| {
+        tracing::error!("failed to export user `{}`, reason: {}", user_name, err);
+        ApiError::new(ErrorCode::DatastoreUnavailable, "failed to export user").into_response()
+    };
+    let record = match state.db.get_user(&user_name).await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            return ApiError::new(
+                ErrorCode::UserNotFound,
+                format!("user `{}` not found", user_name),
+            )
+            .into_response()
+        }
+        Err(err) => return failed(err),
+    };
+    let peak_day_views = match state.db.get_peak_day_views(&user_name).await {
+        Ok(peak_day_views) => peak_day_views,
+        Err(DatastoreError::UserDeleted(_)) => None,
+        Err(err) => return failed(err),
+    };
+
+    let export = UserExport {
+        user_name: user_name.clone(),
+        exported_at: Utc::now(),
+        record,
+        peak_day_views,
+        daily_views: state.history.daily_views(&user_name),
+        flag: state
+            .anomalies
+            .as_ref()
+            .and_then(|anomalies| anomalies.flag(&user_name)),
+        experiment: state.experiments.results(&user_name).await,
+        requests: state
+            .sampler
+            .as_ref()
+            .map(|sampler| sampler.user_samples(&user_name))
+            .unwrap_or_default(),
+    };
+    tracing::info!("exported data of user `{}`", user_name);
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}-export.json\"", user_name),
+        )],
+        Json(export),
+    )
+        .into_response()
+}
+
+fn user_names(users: &str) -> Result<Vec<String>, ApiError> {
+    let mut user_names: Vec<String> = Vec::new();
+    for user_name in users
+        .split(',')
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+    {
+        if !user_names.iter().any(|known| known == user_name) {
+            user_names.push(user_name.to_string());
+        }
+    }
+
+    match user_names.len() {
+        1..=MAX_BATCH_SIZE => Ok(user_names),
+        _ => Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("expected between 1 and {} users", MAX_BATCH_SIZE),
+        )),
+    }
+}
+
+/// Validates the batch, merging increments of the same user.
+fn increments(requests: Vec<IncrementRequest>) -> Result<Vec<Increment>, ApiError> {
+    let invalid = |message: String| ApiError::new(ErrorCode::InvalidRequest, message);
+
+    if requests.is_empty() || requests.len() > MAX_BATCH_SIZE {
+        return Err(invalid(format!(
+            "expected between 1 and {} increments",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let mut increments: Vec<Increment> = Vec::with_capacity(requests.len());
+    for request in requests {
+        // tenant users are keyed `<tenant>/<user>` and only counted through tenant routes
+        if request.user.is_empty() || request.user.contains('/') {
+            return Err(invalid(format!("invalid user `{}`", request.user)));
+        }
+        if request.count == 0 {
+            return Err(invalid(format!("zero count for user `{}`", request.user)));
+        }
+
+        match increments
+            .iter_mut()
+            .find(|increment| increment.user_name == request.user)
+        {
+            Some(increment) => increment.views += request.count,
+            None => increments.push(Increment {
+                user_name: request.user,
+                views: request.count,
+            }),
+        }
+    }
+
+    Ok(increments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::{Memory, UserViews};
+    use pretty_assertions::assert_eq;
+
+    fn request(user: &str, count: u64) -> IncrementRequest {
+        IncrementRequest {
+            user: user.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn it_merges_increments_of_same_user() {
+        let increments = increments(vec![request("a", 3), request("b", 1), request("a", 2)]);
+
+        assert_eq!(
+            increments.unwrap(),
+            vec![
+                Increment {
+                    user_name: "a".to_string(),
+                    views: 5,
+                },
+                Increment {
+                    user_name: "b".to_string(),
+                    views: 1,
+                },
+            ]
+        );
+        assert!(super::increments(vec![]).is_err());
+        assert!(super::increments(vec![request("a", 0)]).is_err());
+        assert!(super::increments(vec![request("rust/a", 1)]).is_err());
+    }
+
+    #[test]
+    fn it_parses_user_names() {
+        assert_eq!(
+            user_names("a, b,,a,c").unwrap(),
+            vec!["a".to_string(), "b".to_string(), "c".to_string()]
+        );
+        assert!(user_names(" , ").is_err());
+    }
+
+    #[tokio::test]
+    async fn it_increments_all_users_or_none() {
+        let db = Memory::new();
+        db.onboard_user("a").await.unwrap();
+        db.onboard_user("b").await.unwrap();
+
+        let unknown = increments(vec![request("a", 3), request("c", 1)]).unwrap();
+        assert!(matches!(
+            db.increment_views(&unknown).await,
+            Err(DatastoreError::UserNotFound(user_name)) if user_name == "c"
+        ));
+
+        let batch = increments(vec![request("a", 3), request("b", 1)]).unwrap();
+        assert_eq!(
+            db.increment_views(&batch).await.unwrap(),
+            vec![
+                UserViews {
+                    user_name: "a".to_string(),
+                    views: 4,
+                },
+                UserViews {
+                    user_name: "b".to_string(),
+                    views: 2,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rust_embed::RustEmbed;
+
+/// Static files compiled into the binary, so deployments only need to ship the executable.
+#[derive(RustEmbed)]
+#[folder = "assets/"]
+struct Assets;
+
+/// Static asset used by the html pages.
+#[utoipa::path(
+    get,
+    path = "/assets/{path}",
+    params(("path" = String, Path, description = "Asset file name")),
+    responses(
+        (status = 200, description = "Asset"),
+        (status = 404, description = "Unknown asset"),
+    )
+)]
+pub async fn asset_handler(Path(path): Path<String>) -> Response {
+    serve_asset(&path)
+}
+
+#[utoipa::path(
+    get,
+    path = "/favicon.ico",
+    responses((status = 200, description = "Favicon", content_type = "image/svg+xml"))
+)]
+pub async fn favicon_handler() -> Response {
+    serve_asset("favicon.svg")
+}
+
+fn serve_asset(path: &str) -> Response {
+    match Assets::get(path) {
+        Some(asset) => (
+            [
+                (header::CONTENT_TYPE, asset.metadata.mimetype().to_string()),
+                (header::CACHE_CONTROL, "public, max-age=86400".to_string()),
+            ],
+            asset.data,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
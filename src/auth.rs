@@ -0,0 +1,340 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::{
+    extract::{MatchedPath, Request, State as StateExtractor},
+    http::{header, HeaderMap, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::badge::ShieldsIoFetcher;
+use super::datastore::DatastoreOperations;
+use super::error::{ApiError, ErrorCode};
+use super::secrets;
+use super::state::AppState;
+
+// requirements of the routes guarded by the static tokens, applied after the `AUTH_POLICY`
+// rules so those can override them; onboarding checks the claim on a user name itself, as the
+// name comes in the body
+const DEFAULT_POLICY: &str = "/admin/*=admin,/:user_name/debug=admin,\
+    /api/users/:user_name/export=owner,/api/onboard=public,/api/*=api-key";
+
+/// What a request needs to reach a route.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Requirement {
+    Public,
+    /// The `API_KEY` bearer token or a jwt granting the api scope
+    ApiKey,
+    /// The `ADMIN_TOKEN` bearer token or a jwt granting the admin scope
+    Admin,
+    /// A jwt of the identity provider, whatever its scopes
+    Jwt,
+    /// The [`owner_token`] of the user the route is about, or what admin routes accept
+    Owner,
+}
+
+impl FromStr for Requirement {
+    type Err = String;
+
+    fn from_str(requirement: &str) -> Result<Self, Self::Err> {
+        match requirement {
+            "public" => Ok(Requirement::Public),
+            "api-key" => Ok(Requirement::ApiKey),
+            "admin" => Ok(Requirement::Admin),
+            "jwt" => Ok(Requirement::Jwt),
+            "owner" => Ok(Requirement::Owner),
+            _ => Err(format!(
+                "`{}` is not one of `public`, `api-key`, `admin`, `jwt`, `owner`",
+                requirement
+            )),
+        }
+    }
+}
+
+/// Requirements of the routes, from `AUTH_POLICY` rules like `/status=admin,/api/*=jwt`. Rules
+/// name routes as listed in the api docs, e.g. `/{user_name}/debug`, or groups of routes with a
+/// trailing `*`. The first matching rule applies; admin and api routes require their tokens
+/// unless a rule says otherwise, every other route is public.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthPolicy {
+    rules: Vec<(String, Requirement)>,
+}
+
+impl Default for AuthPolicy {
+    fn default() -> AuthPolicy {
+        AuthPolicy::parse("").expect("default policy is valid")
+    }
+}
+
+impl AuthPolicy {
+    pub fn parse(policy: &str) -> Result<AuthPolicy, String> {
+        let rules = policy
+            .split(',')
+            .chain(DEFAULT_POLICY.split(','))
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .map(|rule| {
+                let (pattern, requirement) = rule.split_once('=').ok_or_else(|| {
+                    format!("`{}` is not of the form `<route>=<requirement>`", rule)
+                })?;
+                if !pattern.starts_with('/') {
+                    return Err(format!("`{}` is not a route starting with `/`", pattern));
+                }
+                Ok((route_template(pattern), requirement.parse()?))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(AuthPolicy { rules })
+    }
+
+    /// Requirement of the route, given as matched by the router, e.g. `/:user_name/debug`.
+    pub fn requirement(&self, route: &str) -> Requirement {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| match pattern.strip_suffix('*') {
+                Some(prefix) => route.starts_with(prefix),
+                None => route == pattern,
+            })
+            .map_or(Requirement::Public, |(_, requirement)| *requirement)
+    }
+
+    /// Whether a rule requires jwts.
+    pub fn requires_jwt(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|(_, requirement)| *requirement == Requirement::Jwt)
+    }
+}
+
+// `/{user_name}/debug` as `/:user_name/debug`
+fn route_template(pattern: &str) -> String {
+    pattern
+        .split('/')
+        .map(|segment| {
+            match segment
+                .strip_prefix('{')
+                .and_then(|segment| segment.strip_suffix('}'))
+            {
+                Some(param) => format!(":{}", param),
+                None => segment.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Enforces the requirement of the matched route before its handler runs, so handlers don't
+/// check tokens themselves.
+pub async fn enforce_policy<T, F>(
+    StateExtractor(state): StateExtractor<Arc<AppState<T, F>>>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    // preflights carry no credentials, they are answered by the cors layers of the routes
+    if request.method() == Method::OPTIONS {
+        return next.run(request).await;
+    }
+
+    let (requirement, user_name) = match &matched_path {
+        Some(route) => (
+            state.config.auth_policy.requirement(route.as_str()),
+            path_param(route.as_str(), request.uri().path(), "user_name"),
+        ),
+        None => (Requirement::Public, None),
+    };
+    match authorize(&state, request.headers(), requirement, user_name).await {
+        Ok(()) => next.run(request).await,
+        Err(err) => err.into_response(),
+    }
+}
+
+pub(crate) async fn authorize<T, F>(
+    state: &AppState<T, F>,
+    headers: &HeaderMap,
+    requirement: Requirement,
+    user_name: Option<&str>,
+) -> Result<(), ApiError>
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    let owner_secret = state.config.secrets.get(secrets::OWNER_TOKEN_SECRET);
+    if let (Requirement::Owner, Some(secret), Some(user_name), Some(token)) =
+        (requirement, &owner_secret, user_name, bearer_token(headers))
+    {
+        if constant_time_eq(token.as_bytes(), owner_token(secret, user_name).as_bytes()) {
+            return Ok(());
+        }
+    }
+
+    let jwt = state.config.jwt.as_ref();
+    let (secret, scope, denied) = match requirement {
+        Requirement::Public => return Ok(()),
+        // admins act on behalf of any owner
+        Requirement::Owner => (
+            Some(secrets::ADMIN_TOKEN),
+            jwt.map(|jwt| jwt.admin_scope.as_str()),
+            "missing or invalid owner token",
+        ),
+        Requirement::Admin => (
+            Some(secrets::ADMIN_TOKEN),
+            jwt.map(|jwt| jwt.admin_scope.as_str()),
+            "missing or invalid admin token",
+        ),
+        Requirement::ApiKey => (
+            Some(secrets::API_KEY),
+            jwt.map(|jwt| jwt.api_scope.as_str()),
+            "missing or invalid api key",
+        ),
+        Requirement::Jwt => (None, None, "missing or invalid jwt"),
+    };
+
+    let static_token = secret.and_then(|secret| state.config.secrets.get(secret));
+    // admin and api routes don't exist unless a token or jwts are configured for them
+    if static_token.is_none() && jwt.is_none() {
+        match requirement {
+            Requirement::Admin => {
+                return Err(ApiError::new(
+                    ErrorCode::AdminDisabled,
+                    "admin routes are disabled",
+                ))
+            }
+            Requirement::ApiKey => {
+                return Err(ApiError::new(
+                    ErrorCode::ApiDisabled,
+                    "api routes are disabled",
+                ))
+            }
+            Requirement::Owner if owner_secret.is_none() => {
+                return Err(ApiError::new(
+                    ErrorCode::OwnerTokensDisabled,
+                    "owner routes are disabled",
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let authorized = match bearer_token(headers) {
+        None => false,
+        Some(token)
+            if static_token.is_some_and(|expected| {
+                constant_time_eq(token.as_bytes(), expected.as_bytes())
+            }) =>
+        {
+            true
+        }
+        Some(token) => is_valid_jwt(state, token, scope).await,
+    };
+    match authorized {
+        true => Ok(()),
+        false => Err(ApiError::new(ErrorCode::Unauthorized, denied)),
+    }
+}
+
+#[cfg_attr(not(feature = "jwt"), allow(unused_variables))]
+async fn is_valid_jwt<T, F>(state: &AppState<T, F>, token: &str, scope: Option<&str>) -> bool
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    #[cfg(feature = "jwt")]
+    if let Some(validator) = &state.jwt {
+        match validator.validate(token, scope).await {
+            Ok(()) => return true,
+            Err(err) => tracing::debug!("rejected jwt: {}", err),
+        }
+    }
+    false
+}
+
+/// Token granting the owner routes of the user, e.g. the export of their data, and those of no
+/// other user. Tokens are issued by admins and stay valid until `OWNER_TOKEN_SECRET` changes.
+pub fn owner_token(secret: &str, user_name: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(b"owner:");
+    mac.update(user_name.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// segment of the path in place of the route's `:<name>` segment, e.g. `octocat` for
+// `/:user_name/debug` and `/octocat/debug`
+fn path_param<'a>(route: &str, path: &'a str, name: &str) -> Option<&'a str> {
+    route
+        .split('/')
+        .zip(path.split('/'))
+        .find(|(segment, _)| segment.strip_prefix(':') == Some(name))
+        .map(|(_, value)| value)
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_applies_the_first_matching_rule() {
+        let policy =
+            AuthPolicy::parse("/status=admin, /api/counts=public, /{user_name}/debug=jwt").unwrap();
+        assert_eq!(policy.requirement("/status"), Requirement::Admin);
+        assert_eq!(policy.requirement("/api/counts"), Requirement::Public);
+        assert_eq!(policy.requirement("/api/increments"), Requirement::ApiKey);
+        assert_eq!(policy.requirement("/api/onboard"), Requirement::Public);
+        assert_eq!(policy.requirement("/:user_name/debug"), Requirement::Jwt);
+        assert_eq!(
+            policy.requirement("/admin/users/:user_name"),
+            Requirement::Admin
+        );
+        assert_eq!(
+            policy.requirement("/:user_name/counter.svg"),
+            Requirement::Public
+        );
+
+        assert_eq!(
+            AuthPolicy::parse("/status=root"),
+            Err("`root` is not one of `public`, `api-key`, `admin`, `jwt`, `owner`".to_string())
+        );
+    }
+
+    #[test]
+    fn it_grants_owner_tokens_for_one_user() {
+        let policy = AuthPolicy::default();
+        assert_eq!(
+            policy.requirement("/api/users/:user_name/export"),
+            Requirement::Owner
+        );
+        assert_eq!(
+            path_param(
+                "/api/users/:user_name/export",
+                "/api/users/octocat/export",
+                "user_name"
+            ),
+            Some("octocat")
+        );
+        assert_eq!(path_param("/api/counts", "/api/counts", "user_name"), None);
+
+        let token = owner_token("secret", "octocat");
+        assert_eq!(token, owner_token("secret", "octocat"));
+        assert_ne!(token, owner_token("secret", "monalisa"));
+        assert_ne!(token, owner_token("rotated", "octocat"));
+    }
+}
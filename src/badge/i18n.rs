@@ -0,0 +1,56 @@
+// "profile views" by language, for `label_lang`
+const PROFILE_VIEWS: &[(&str, &str)] = &[
+    ("ar", "مشاهدات الملف الشخصي"),
+    ("bn", "প্রোফাইল ভিউ"),
+    ("de", "Profilaufrufe"),
+    ("en", "profile views"),
+    ("es", "visitas al perfil"),
+    ("fa", "بازدیدهای پروفایل"),
+    ("fr", "vues du profil"),
+    ("he", "צפיות בפרופיל"),
+    ("hi", "प्रोफ़ाइल व्यूज़"),
+    ("id", "kunjungan profil"),
+    ("it", "visite al profilo"),
+    ("ja", "プロフィール閲覧数"),
+    ("ko", "프로필 조회수"),
+    ("nl", "profielweergaven"),
+    ("pl", "wyświetlenia profilu"),
+    ("pt", "visualizações do perfil"),
+    ("ru", "просмотры профиля"),
+    ("sv", "profilvisningar"),
+    ("th", "ยอดเข้าชมโปรไฟล์"),
+    ("tr", "profil görüntülenmeleri"),
+    ("uk", "перегляди профілю"),
+    ("vi", "lượt xem hồ sơ"),
+    ("zh", "个人资料浏览量"),
+    ("zh-tw", "個人檔案瀏覽次數"),
+];
+
+/// "profile views" in the language, e.g. `es` or `zh-TW`; tags are matched case-insensitively
+/// and fall back to their language.
+pub fn profile_views_label(lang: &str) -> Option<&'static str> {
+    let lang = lang.replace('_', "-").to_ascii_lowercase();
+    let language = lang.split('-').next()?;
+
+    [lang.as_str(), language].iter().find_map(|lang| {
+        PROFILE_VIEWS
+            .iter()
+            .find(|(name, _)| name == lang)
+            .map(|(_, label)| *label)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_translates_profile_views_label() {
+        assert_eq!(profile_views_label("es"), Some("visitas al perfil"));
+        assert_eq!(profile_views_label("FR-ca"), Some("vues du profil"));
+        assert_eq!(profile_views_label("zh_TW"), Some("個人檔案瀏覽次數"));
+        assert_eq!(profile_views_label("zh-CN"), Some("个人资料浏览量"));
+        assert_eq!(profile_views_label("xx"), None);
+    }
+}
@@ -0,0 +1,114 @@
+/// Strips what doesn't change how a badge looks from its svg: comments, indentation between
+/// elements and extra whitespace between attributes. Text and attribute values are kept as they
+/// are, so the message placeholder of a template survives.
+pub fn minify(svg: &str) -> String {
+    let mut minified = String::with_capacity(svg.len());
+    let mut rest = svg;
+
+    while let Some(start) = rest.find('<') {
+        push_text(&mut minified, &rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+        } else if rest.starts_with("<![CDATA[") {
+            let end = rest.find("]]>").map_or(rest.len(), |end| end + 3);
+            minified.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else {
+            let end = tag_len(rest);
+            push_tag(&mut minified, &rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    push_text(&mut minified, rest);
+
+    minified
+}
+
+// text between tags, dropped when it only lines up the elements
+fn push_text(minified: &mut String, text: &str) {
+    if !(text.trim().is_empty() && text.contains('\n')) {
+        minified.push_str(text);
+    }
+}
+
+// length of the tag starting the text, up to its `>` outside of quoted values
+fn tag_len(text: &str) -> usize {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+// the tag with whitespace outside of quoted values collapsed to the spaces separating attributes
+fn push_tag(minified: &mut String, tag: &str) {
+    let mut quote = None;
+    let mut space = false;
+
+    for c in tag.chars() {
+        match quote {
+            Some(open) => {
+                minified.push(c);
+                if c == open {
+                    quote = None;
+                }
+            }
+            None if c.is_ascii_whitespace() => space = true,
+            None => {
+                if space && !matches!(c, '>' | '/' | '=') && !minified.ends_with(['<', '=']) {
+                    minified.push(' ');
+                }
+                space = false;
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                minified.push(c);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_strips_comments_and_indentation() {
+        let svg = r##"<?xml version="1.0" encoding="UTF-8"?>
+<!-- generated badge -->
+<svg xmlns="http://www.w3.org/2000/svg"
+     width="90"   height="20" role="img">
+  <title>views: **</title>
+  <g   clip-path = "url(#r)" >
+    <rect width="37" height="20" fill="#555" />
+  </g>
+</svg>
+"##;
+
+        assert_eq!(
+            minify(svg),
+            r##"<?xml version="1.0" encoding="UTF-8"?><svg xmlns="http://www.w3.org/2000/svg" width="90" height="20" role="img"><title>views: **</title><g clip-path="url(#r)"><rect width="37" height="20" fill="#555"/></g></svg>"##
+        );
+    }
+
+    #[test]
+    fn it_keeps_text_and_attribute_values() {
+        let svg = concat!(
+            r#"<svg aria-label="profile  views: ~~"><text xml:space="preserve">  views:  ~~ </text>"#,
+            r#"<text> </text><style><![CDATA[ text  >  tspan { fill: #fff } ]]></style>"#,
+            r#"<text title='a > b'>a &gt; b</text></svg>"#
+        );
+
+        assert_eq!(minify(svg), svg);
+        // minified badges are left as they are
+        assert_eq!(minify(&minify(svg)), minify(svg));
+    }
+}
@@ -0,0 +1,821 @@
+mod i18n;
+mod minify;
+
+pub use minify::minify;
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use super::badge_params::BadgeQuery;
+use super::cache::{CacheStats, CacheStores};
+use super::config::{Config, UpstreamConfig};
+use super::http_client;
+use super::metrics::{self, UpstreamRequest};
+use super::shutdown::Shutdown;
+use super::status;
+
+// fetches of a template missing the message placeholder before giving up
+const TEMPLATE_FETCH_ATTEMPTS: usize = 2;
+// how long templates are kept past their ttl, served while shields.io is unreachable
+const STALE_TEMPLATE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Served in place of the counter whenever views can't be shown.
+pub const UNAVAILABLE_BADGE: &str = include_str!("../../assets/unavailable.svg");
+
+#[async_trait]
+pub trait ShieldsIoFetcher: Shutdown {
+    async fn fetch(&self, params: &ShieldsIoParams, views: u64) -> Result<String, Error> {
+        self.fetch_message(params, &views.to_string()).await
+    }
+
+    /// Badge with any message, e.g. views along with their recent gain.
+    async fn fetch_message(&self, params: &ShieldsIoParams, message: &str)
+        -> Result<String, Error>;
+
+    /// Resolves and connects to the badge service ahead of the first request.
+    async fn warm_up(&self) {}
+
+    /// Whether a badge with the message would be served from the template cache, if the fetcher
+    /// caches templates.
+    async fn is_cached(&self, _params: &ShieldsIoParams, _message: &str) -> Option<bool> {
+        None
+    }
+
+    /// Stats of the badge template cache, if the fetcher caches templates.
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Drops the cached badge templates, which get fetched again on their next use.
+    async fn clear_cache(&self) {}
+}
+
+const STYLES: &[&str] = &["flat", "flat-square", "plastic", "for-the-badge", "social"];
+
+// characters standing in for the message in templates, by preference; templates get the first
+// one missing from the label, as the message replaces it throughout the template
+const PADDING_CHARS: &[char] = &['*', '~', '^'];
+
+// advance widths in pixels of the printable ascii characters in 11px Verdana, the font shields.io
+// sizes badges in; digits are as wide as `*`, so counts are padded a character per digit
+const VERDANA_WIDTHS: [f32; 95] = [
+    3.87, 4.33, 5.05, 9.0, 6.99, 11.84, 7.99, 2.95, 4.99, 4.99, 6.99, 9.0, 4.0, 4.99, 4.0, 4.99,
+    6.99, 6.99, 6.99, 6.99, 6.99, 6.99, 6.99, 6.99, 6.99, 6.99, 4.99, 4.99, 9.0, 9.0, 9.0, 6.0,
+    11.0, 7.52, 7.54, 7.68, 8.48, 6.96, 6.32, 8.53, 8.27, 4.63, 5.0, 7.62, 6.12, 9.27, 8.23, 8.66,
+    6.63, 8.66, 7.65, 7.52, 6.78, 8.05, 7.52, 10.88, 7.54, 6.77, 7.54, 4.99, 4.99, 4.99, 9.0, 6.99,
+    6.99, 6.61, 6.85, 5.73, 6.85, 6.55, 3.87, 6.85, 6.96, 3.02, 3.79, 6.51, 3.02, 10.75, 6.96,
+    6.68, 6.85, 6.85, 4.69, 5.73, 4.33, 6.96, 6.51, 9.0, 6.51, 6.51, 5.78, 6.98, 4.99, 6.98, 9.0,
+];
+
+// width of the text as shields.io measures it; characters past ascii count as wide as a digit
+fn verdana_width(text: &str) -> f32 {
+    text.chars()
+        .map(|c| match c {
+            ' '..='~' => VERDANA_WIDTHS[c as usize - ' ' as usize],
+            _ => VERDANA_WIDTHS['0' as usize - ' ' as usize],
+        })
+        .sum()
+}
+
+/// Hex value of a shields.io named color, e.g. `007ec6` for `blue`.
+pub fn named_color(color: &str) -> Option<&'static str> {
+    let hex = match color {
+        "brightgreen" | "success" => "4c1",
+        "green" => "97ca00",
+        "yellow" => "dfb317",
+        "yellowgreen" => "a4a61d",
+        "orange" | "important" => "fe7d37",
+        "red" | "critical" => "e05d44",
+        "blue" | "informational" => "007ec6",
+        "blueviolet" => "8a2be2",
+        "grey" | "gray" => "555",
+        "lightgrey" | "lightgray" | "inactive" => "9f9f9f",
+        _ => return None,
+    };
+    Some(hex)
+}
+
+fn is_hex_color(color: &str) -> bool {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_css_color_function(color: &str) -> bool {
+    ["rgb(", "rgba(", "hsl(", "hsla("]
+        .iter()
+        .any(|function| color.starts_with(function))
+        && color.ends_with(')')
+}
+
+/// Sets the title and aria label of an svg badge, adding a title when it has none.
+pub fn with_title(svg: &str, title: &str) -> String {
+    let title = escape(title);
+    let svg = match replace_between(svg, "<title>", "</title>", &title) {
+        Some(svg) => svg,
+        None => match svg
+            .find("<svg")
+            .and_then(|start| svg[start..].find('>').map(|end| start + end + 1))
+        {
+            Some(end) => format!("{}<title>{}</title>{}", &svg[..end], title, &svg[end..]),
+            None => return svg.to_string(),
+        },
+    };
+    replace_between(&svg, r#"aria-label=""#, "\"", &title).unwrap_or(svg)
+}
+
+fn replace_between(text: &str, start: &str, end: &str, replacement: &str) -> Option<String> {
+    let from = text.find(start)? + start.len();
+    let to = from + text[from..].find(end)?;
+    Some(format!("{}{}{}", &text[..from], replacement, &text[to..]))
+}
+
+/// Escapes text for svg elements and attributes.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Clone, Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShieldsIoParams {
+    /// Required unless `label_lang` is given
+    label: Option<String>,
+    /// Language of a translated "profile views" label used when `label` is missing, e.g. `es`,
+    /// `ja` or `zh-TW`
+    label_lang: Option<String>,
+    color: String,
+    style: String,
+}
+
+impl ShieldsIoParams {
+    pub fn new(label: &str, color: &str, style: &str) -> ShieldsIoParams {
+        ShieldsIoParams {
+            label: Some(label.to_string()),
+            label_lang: None,
+            color: color.to_string(),
+            style: style.to_string(),
+        }
+    }
+
+    /// Params of a badge query, describing the first missing one otherwise; the label may be
+    /// missing when `label_lang` is given.
+    pub fn from_query(query: &BadgeQuery) -> Result<ShieldsIoParams, String> {
+        if query.label.is_none() && query.label_lang.is_none() {
+            return Err("missing label".to_string());
+        }
+        Ok(ShieldsIoParams {
+            label: query.label.clone(),
+            label_lang: query.label_lang.clone(),
+            color: query.color.clone().ok_or("missing color")?,
+            style: query.style.clone().ok_or("missing style")?,
+        })
+    }
+
+    /// Checks that there is a label, that the color is a shields.io named color, a hex code or a
+    /// css color function and the style a known one, describing the first mistake otherwise.
+    pub fn validate(&self) -> Result<(), String> {
+        match (&self.label, &self.label_lang) {
+            (Some(_), _) => {}
+            (None, Some(lang)) if i18n::profile_views_label(lang).is_none() => {
+                return Err(format!("unknown label_lang {}", lang))
+            }
+            (None, Some(_)) => {}
+            (None, None) => return Err("missing label".to_string()),
+        }
+
+        let color = self.color();
+        if named_color(color).is_none() && !is_hex_color(color) && !is_css_color_function(color) {
+            return Err(format!("unknown color {}", color));
+        }
+        if !STYLES.contains(&self.style()) {
+            return Err(format!("unknown style {}", self.style()));
+        }
+        Ok(())
+    }
+
+    /// Same params with another color, e.g. one picked by the views' trend.
+    pub fn with_color(&self, color: &str) -> ShieldsIoParams {
+        ShieldsIoParams::new(self.label(), color, self.style())
+    }
+
+    /// Same params with the given label, color and style replacing the current ones.
+    pub fn with_overrides(
+        &self,
+        label: Option<&str>,
+        color: Option<&str>,
+        style: Option<&str>,
+    ) -> ShieldsIoParams {
+        ShieldsIoParams::new(
+            label.unwrap_or(self.label()),
+            color.unwrap_or(self.color()),
+            style.unwrap_or(self.style()),
+        )
+    }
+
+    pub fn label(&self) -> &str {
+        match (&self.label, &self.label_lang) {
+            (Some(label), _) => label,
+            (None, Some(lang)) => i18n::profile_views_label(lang).unwrap_or_default(),
+            (None, None) => "",
+        }
+    }
+
+    pub fn color(&self) -> &str {
+        self.color.as_ref()
+    }
+
+    pub fn style(&self) -> &str {
+        self.style.as_ref()
+    }
+
+    fn to_query_string_template(&self, message: &str) -> (String, String) {
+        let label = self.label();
+        // labels holding every preferred padding character fall back to block elements
+        let padding_char = PADDING_CHARS
+            .iter()
+            .copied()
+            .chain('\u{2580}'..=char::MAX)
+            .find(|c| !label.contains(*c))
+            .unwrap_or('*');
+        // shields.io stretches the text to the width of the padding, so the padding is as wide as
+        // the message and badges of the same width share templates
+        let padding_width = verdana_width(padding_char.encode_utf8(&mut [0; 4]));
+        let padding_len = (verdana_width(message) / padding_width).round().max(1.0) as usize;
+        let padding = padding_char.to_string().repeat(padding_len);
+        // encoded, so labels and colors holding `&` or `#` neither end up in another parameter
+        // nor share the cache key of other params
+        let query_string_template = form_urlencoded::Serializer::new(String::new())
+            .append_pair("label", label)
+            .append_pair("color", self.color())
+            .append_pair("style", self.style())
+            .append_pair("message", &padding)
+            .finish();
+
+        (query_string_template, padding)
+    }
+}
+
+impl std::fmt::Display for ShieldsIoParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{{ label: {}, color: {}, style: {} }}",
+            self.label(),
+            self.color(),
+            self.style()
+        )
+    }
+}
+
+pub struct Shields {
+    client: reqwest::Client,
+    upstream: UpstreamConfig,
+    service_url: String,
+    // templates by query params; the shared store holds templates fetched by any instance,
+    // looked up before fetching from shields.io
+    caches: CacheStores,
+    template_ttl: Duration,
+}
+
+/// Template with the time it was fetched at, so any instance reading it from the shared store
+/// knows when to revalidate it.
+struct CachedTemplate {
+    template: String,
+    fetched_at: DateTime<Utc>,
+}
+
+impl CachedTemplate {
+    fn encode(&self) -> String {
+        format!("{}\n{}", self.fetched_at.timestamp_millis(), self.template)
+    }
+
+    fn decode(value: &str) -> Option<CachedTemplate> {
+        let (fetched_at, template) = value.split_once('\n')?;
+        Some(CachedTemplate {
+            template: template.to_string(),
+            fetched_at: DateTime::from_timestamp_millis(fetched_at.parse().ok()?)?,
+        })
+    }
+
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        (Utc::now() - self.fetched_at)
+            .to_std()
+            .map_or(true, |age| age < ttl)
+    }
+}
+
+impl Shields {
+    pub fn new(config: &Config, caches: CacheStores) -> Result<Self, Error> {
+        // default headers
+        let mut cache_control = HeaderMap::new();
+        cache_control.insert(
+            "Cache-Control",
+            HeaderValue::from_static("max-age=0, no-cache, no-store, must-revalidate"),
+        );
+
+        let client = http_client::upstream_builder(config, &config.shields)?
+            .default_headers(cache_control)
+            .build()?;
+        metrics::record_pool_size("shields", config.shields.pool_max_idle);
+
+        Ok(Shields {
+            client,
+            upstream: config.shields.clone(),
+            service_url: "https://shields.io/static/v1".to_string(),
+            caches,
+            template_ttl: config.badge_template_ttl,
+        })
+    }
+
+    /// Same client, fetching templates from another shields.io, e.g. a mock one.
+    #[cfg(feature = "bench")]
+    pub fn with_service_url(self, service_url: &str) -> Shields {
+        Shields {
+            service_url: service_url.to_string(),
+            ..self
+        }
+    }
+
+    async fn update_cache(
+        &self,
+        params: &ShieldsIoParams,
+        message: &str,
+        template: &CachedTemplate,
+    ) {
+        let local = &self.caches.local;
+        let (key, _) = params.to_query_string_template(message);
+
+        // delete the old key if present; the old key is the one of the message a character
+        // shorter, e.g. of the count before it gained a digit
+        let mut shorter = message.chars();
+        shorter.next_back();
+        let (old_key, _) = params.to_query_string_template(shorter.as_str());
+        local.remove(&old_key).await;
+        tracing::info!("removed old key: {}", old_key);
+
+        // insert the new key
+        tracing::info!("inserting key: {}", key);
+        local
+            .set(
+                &key,
+                &template.encode(),
+                self.template_ttl + STALE_TEMPLATE_TTL,
+            )
+            .await;
+    }
+
+    /// Fetches a template holding the padding the message replaces; templates without it would
+    /// render the padding instead of the message, so they are fetched again and never cached.
+    async fn fetch_valid_template(
+        &self,
+        params: &ShieldsIoParams,
+        query_params: &str,
+        padding: &str,
+    ) -> Result<String, Error> {
+        for attempt in 1..=TEMPLATE_FETCH_ATTEMPTS {
+            let template = self.fetch_template(query_params).await?;
+            if template.contains(padding) {
+                return Ok(template);
+            }
+
+            tracing::error!(
+                params = %params,
+                padding_len = padding.len(),
+                attempt,
+                template_len = template.len(),
+                "badge template is missing the message placeholder"
+            );
+        }
+
+        Err(anyhow!(
+            "badge template for {} is missing the message placeholder",
+            params
+        ))
+    }
+
+    async fn fetch_template(&self, query_params: &str) -> Result<String, Error> {
+        let url = format!("{}?{}", self.service_url, query_params);
+        let response = {
+            let _request = UpstreamRequest::start("shields", self.upstream.slow_threshold);
+            let result = self.upstream.send(self.client.get(url)).await;
+            status::record_upstream("shields", &result);
+            result?
+        };
+        // minified before it gets cached, every badge of the template is served smaller
+        Ok(minify(&response.text().await?))
+    }
+}
+
+// templates are written through to the shared cache as they are fetched, nothing is left over
+impl Shutdown for Shields {}
+
+#[async_trait]
+impl ShieldsIoFetcher for Shields {
+    async fn fetch_message(
+        &self,
+        params: &ShieldsIoParams,
+        message: &str,
+    ) -> Result<String, Error> {
+        let (query_params, padding) = params.to_query_string_template(message);
+
+        let cached = self.caches.local.get(&query_params).await;
+        // templates past their ttl are fetched again, so shields.io style changes show up
+        let mut stale_template = match cached.as_deref().and_then(CachedTemplate::decode) {
+            Some(badge) if badge.is_fresh(self.template_ttl) => {
+                tracing::info!("cache hit, params: {}, message: {}", params, message);
+                return Ok(badge.template.replace(&padding, message));
+            }
+            Some(badge) => Some(badge.template),
+            None => None,
+        };
+
+        // another instance may have fetched the template already
+        let shared_key = format!("badge:{}", query_params);
+        if let Some(shared) = &self.caches.shared {
+            let shared_template = shared.get(&shared_key).await;
+            match shared_template.as_deref().and_then(CachedTemplate::decode) {
+                Some(badge) if badge.template.contains(&padding) => {
+                    if badge.is_fresh(self.template_ttl) {
+                        tracing::info!(
+                            "shared cache hit, params: {}, message: {}",
+                            params,
+                            message
+                        );
+                        self.update_cache(params, message, &badge).await;
+                        return Ok(badge.template.replace(&padding, message));
+                    }
+                    stale_template.get_or_insert(badge.template);
+                }
+                _ => {}
+            }
+        }
+
+        tracing::info!(
+            "cache miss, fetching badge, params: {}, message: {}",
+            params,
+            message
+        );
+        let fetched = self
+            .fetch_valid_template(params, &query_params, &padding)
+            .await;
+        let badge_template = match (fetched, stale_template) {
+            (Ok(badge_template), _) => badge_template,
+            // a stale template beats no badge while shields.io is unreachable
+            (Err(err), Some(stale_template)) => {
+                tracing::warn!(
+                    "failed to revalidate badge template, serving stale: {}",
+                    err
+                );
+                return Ok(stale_template.replace(&padding, message));
+            }
+            (Err(err), None) => return Err(err),
+        };
+
+        let badge = badge_template.replace(&padding, message);
+        let fetched = CachedTemplate {
+            template: badge_template,
+            fetched_at: Utc::now(),
+        };
+        if let Some(shared) = &self.caches.shared {
+            shared
+                .set(
+                    &shared_key,
+                    &fetched.encode(),
+                    self.template_ttl + STALE_TEMPLATE_TTL,
+                )
+                .await;
+        }
+        self.update_cache(params, message, &fetched).await;
+
+        Ok(badge)
+    }
+
+    async fn warm_up(&self) {
+        // any response leaves a pooled connection behind, the status doesn't matter
+        match self.client.head(&self.service_url).send().await {
+            Ok(_) => tracing::info!("connection to shields.io warmed up"),
+            Err(err) => tracing::warn!("failed to warm up connection to shields.io: {}", err),
+        }
+    }
+
+    async fn is_cached(&self, params: &ShieldsIoParams, message: &str) -> Option<bool> {
+        let (query_params, _) = params.to_query_string_template(message);
+        let cached = self.caches.local.peek(&query_params).await;
+        Some(
+            cached
+                .as_deref()
+                .and_then(CachedTemplate::decode)
+                .is_some_and(|badge| badge.is_fresh(self.template_ttl)),
+        )
+    }
+
+    async fn cache_stats(&self) -> Option<CacheStats> {
+        self.caches.local.stats().await
+    }
+
+    // the shared store is left alone, other instances keep serving from it
+    async fn clear_cache(&self) {
+        self.caches.local.clear().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cache::{CacheStore, MemoryStore};
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn shields() -> Shields {
+        let config = Config::from_env();
+        Shields::new(&config, CacheStores::new(&config)).unwrap()
+    }
+
+    #[test]
+    fn it_sets_badge_title() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" aria-label="views: 42"><title>views: 42</title><g/></svg>"#;
+        assert_eq!(
+            with_title(svg, "42 profile views for <octocat>"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" aria-label="42 profile views for &lt;octocat&gt;"><title>42 profile views for &lt;octocat&gt;</title><g/></svg>"#
+        );
+
+        let untitled = r#"<svg xmlns="http://www.w3.org/2000/svg"><g/></svg>"#;
+        assert_eq!(
+            with_title(untitled, "42 profile views for octocat"),
+            r#"<svg xmlns="http://www.w3.org/2000/svg"><title>42 profile views for octocat</title><g/></svg>"#
+        );
+    }
+    #[tokio::test]
+    async fn it_counts_template_cache_hits() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/static/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<svg>**</svg>"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let mut shields = shields();
+        shields.service_url = format!("{}/static/v1", server.uri());
+        shields.template_ttl = Duration::from_secs(60);
+        let params = ShieldsIoParams::new("views", "blue", "flat");
+
+        assert_eq!(shields.fetch(&params, 12).await.unwrap(), "<svg>12</svg>");
+        assert_eq!(shields.fetch(&params, 34).await.unwrap(), "<svg>34</svg>");
+        let stats = shields.cache_stats().await.unwrap();
+        assert_eq!((stats.size, stats.hits, stats.misses), (1, 1, 1));
+        assert_eq!(stats.top_keys[0].hits, 1);
+        // templates are cached per message length
+        assert_eq!(shields.is_cached(&params, "99").await, Some(true));
+        assert_eq!(shields.is_cached(&params, "100").await, Some(false));
+
+        shields.clear_cache().await;
+        assert_eq!(shields.is_cached(&params, "99").await, Some(false));
+        assert_eq!(shields.fetch(&params, 56).await.unwrap(), "<svg>56</svg>");
+    }
+
+    #[tokio::test]
+    async fn it_shares_templates_between_instances() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/static/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<svg>**</svg>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let shared: Arc<dyn CacheStore> = Arc::new(MemoryStore::new());
+        let instance = || {
+            let mut shields = shields();
+            shields.service_url = format!("{}/static/v1", server.uri());
+            shields.caches.shared = Some(shared.clone());
+            shields
+        };
+        let params = ShieldsIoParams::new("views", "blue", "flat");
+
+        assert_eq!(
+            instance().fetch(&params, 12).await.unwrap(),
+            "<svg>12</svg>"
+        );
+        let other = instance();
+        assert_eq!(other.fetch(&params, 34).await.unwrap(), "<svg>34</svg>");
+        assert_eq!(other.is_cached(&params, "56").await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn it_revalidates_templates_past_ttl() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/static/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<svg>**</svg>"))
+            .mount(&server)
+            .await;
+        let mut shields = shields();
+        shields.service_url = format!("{}/static/v1", server.uri());
+        shields.template_ttl = Duration::ZERO;
+        let params = ShieldsIoParams::new("views", "blue", "flat");
+
+        assert_eq!(shields.fetch(&params, 12).await.unwrap(), "<svg>12</svg>");
+        server.reset().await;
+        Mock::given(method("GET"))
+            .and(path("/static/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<svg class=\"new\">**</svg>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        assert_eq!(
+            shields.fetch(&params, 34).await.unwrap(),
+            "<svg class=\"new\">34</svg>"
+        );
+        server.verify().await;
+
+        // the stale template is served while shields.io is unreachable
+        shields.service_url = "http://127.0.0.1:1/static/v1".to_string();
+        assert_eq!(
+            shields.fetch(&params, 56).await.unwrap(),
+            "<svg class=\"new\">56</svg>"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_minifies_templates_before_caching() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/static/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "<!-- badge -->\n<svg  width=\"90\" >\n  <text>**</text>\n</svg>\n",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let shared: Arc<dyn CacheStore> = Arc::new(MemoryStore::new());
+        let mut shields = shields();
+        shields.service_url = format!("{}/static/v1", server.uri());
+        shields.caches.shared = Some(shared.clone());
+        let params = ShieldsIoParams::new("views", "blue", "flat");
+
+        let minified = r#"<svg width="90"><text>12</text></svg>"#;
+        assert_eq!(shields.fetch(&params, 12).await.unwrap(), minified);
+        assert_eq!(shields.fetch(&params, 12).await.unwrap(), minified);
+        let (query_params, _) = params.to_query_string_template("12");
+        let cached = shared.get(&format!("badge:{}", query_params)).await;
+        assert_eq!(
+            CachedTemplate::decode(&cached.unwrap()).unwrap().template,
+            r#"<svg width="90"><text>**</text></svg>"#
+        );
+    }
+
+    #[tokio::test]
+    async fn it_refetches_templates_missing_the_placeholder() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/static/v1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<svg>views</svg>"))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let mut shields = shields();
+        shields.service_url = format!("{}/static/v1", server.uri());
+        let params = ShieldsIoParams::new("views", "blue", "flat");
+
+        assert!(shields.fetch(&params, 12).await.is_err());
+        server.verify().await;
+        assert_eq!(shields.cache_stats().await.unwrap().size, 0);
+    }
+
+    #[test]
+    fn it_pads_templates_as_wide_as_the_message() {
+        let params = ShieldsIoParams::new("views", "blue", "flat");
+        let padding = |message| params.to_query_string_template(message).1;
+
+        assert_eq!(padding("12345"), "*****");
+        assert_eq!(padding("12 days"), "******");
+        assert_eq!(padding("+321 this week"), "*".repeat(12));
+        assert_eq!(padding("WWW"), "*****");
+        assert_eq!(padding("iii"), "*");
+
+        // the fallback padding is wider than a digit
+        let params = ShieldsIoParams::new("5 * views", "blue", "flat");
+        assert_eq!(params.to_query_string_template("12345").1, "~~~~");
+    }
+
+    #[test]
+    fn it_validates_color_and_style() {
+        let validate = |color, style| ShieldsIoParams::new("views", color, style).validate();
+
+        assert_eq!(validate("blue", "flat"), Ok(()));
+        assert_eq!(validate("ff69b4", "for-the-badge"), Ok(()));
+        assert_eq!(validate("#abc", "social"), Ok(()));
+        assert_eq!(validate("rgb(0,128,255)", "plastic"), Ok(()));
+        assert_eq!(
+            validate("bleu", "flat"),
+            Err("unknown color bleu".to_string())
+        );
+        assert_eq!(
+            validate("12345", "flat"),
+            Err("unknown color 12345".to_string())
+        );
+        assert_eq!(
+            validate("blue", "flatt"),
+            Err("unknown style flatt".to_string())
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_translated_label() {
+        let params: ShieldsIoParams =
+            serde_json::from_str(r#"{"label_lang": "es", "color": "blue", "style": "flat"}"#)
+                .unwrap();
+        assert_eq!(params.label(), "visitas al perfil");
+        assert_eq!(params.validate(), Ok(()));
+
+        let params: ShieldsIoParams = serde_json::from_str(
+            r#"{"label": "views", "label_lang": "es", "color": "blue", "style": "flat"}"#,
+        )
+        .unwrap();
+        assert_eq!(params.label(), "views");
+
+        let params: ShieldsIoParams =
+            serde_json::from_str(r#"{"label_lang": "xx", "color": "blue", "style": "flat"}"#)
+                .unwrap();
+        assert_eq!(params.validate(), Err("unknown label_lang xx".to_string()));
+    }
+
+    // shields.io-like template showing the label and message in text and attributes alike
+    fn render(label: &str, message: &str) -> String {
+        let (label, message) = (escape(label), escape(message));
+        format!(
+            r#"<svg aria-label="{label}: {message}"><title>{label}: {message}</title><text>{label}</text><text>{message}</text></svg>"#
+        )
+    }
+
+    fn labels() -> impl Strategy<Value = String> {
+        prop_oneof!["[a-z *~^&=#+%<>\"]{0,12}", "\\PC{0,24}"]
+    }
+
+    fn colors() -> impl Strategy<Value = String> {
+        prop_oneof![
+            prop::sample::select(vec!["blue", "red", "brightgreen", "lightgrey"])
+                .prop_map(str::to_string),
+            "#?[0-9a-f]{6}",
+            "rgba?\\([0-9a-z,&=#()]{0,12}\\)",
+        ]
+    }
+
+    fn params() -> impl Strategy<Value = ShieldsIoParams> {
+        (labels(), colors(), prop::sample::select(STYLES))
+            .prop_map(|(label, color, style)| ShieldsIoParams::new(&label, &color, style))
+    }
+
+    proptest! {
+        #[test]
+        fn it_substitutes_only_the_padding(params in params(), message in "[0-9a-z ~*.]{0,12}") {
+            let (_, padding) = params.to_query_string_template(&message);
+            let template = render(params.label(), &padding);
+
+            prop_assert_eq!(template.replace(&padding, &message), render(params.label(), &message));
+        }
+
+        #[test]
+        fn it_encodes_params_in_cache_keys(params in params(), views: u64) {
+            let (key, padding) = params.to_query_string_template(&views.to_string());
+
+            let decoded: Vec<(String, String)> = form_urlencoded::parse(key.as_bytes())
+                .into_owned()
+                .collect();
+            let expected = [
+                ("label", params.label()),
+                ("color", params.color()),
+                ("style", params.style()),
+                ("message", &padding),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+            prop_assert_eq!(decoded, expected.to_vec());
+        }
+
+        #[test]
+        fn it_never_shares_cache_keys_between_badges(
+            first in params(),
+            second in params(),
+            first_message in "[0-9]{0,4}",
+            second_message in "[0-9]{0,4}",
+        ) {
+            let (first_key, first_padding) = first.to_query_string_template(&first_message);
+            let (second_key, second_padding) = second.to_query_string_template(&second_message);
+
+            let same_badge = (first.label(), first.color(), first.style(), first_padding)
+                == (second.label(), second.color(), second.style(), second_padding);
+            prop_assert_eq!(first_key == second_key, same_badge);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Query, RawPathParams},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use super::badge::{ShieldsIoFetcher, ShieldsIoParams};
+use super::datastore::DatastoreOperations;
+use super::handler::{badge_response, check_scale, error_badge, raster_response};
+use super::raster::{RasterBadge, RasterFormat};
+use super::state::AppState;
+
+// style of error badges when the request has none
+const ERROR_BADGE_STYLE: &str = "flat";
+
+/// Badge params as given in the query; on tenant routes, missing ones fall back to the tenant's
+/// defaults.
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BadgeQuery {
+    /// Required unless `label_lang` is given or the tenant has a default label
+    pub label: Option<String>,
+    /// Language of a translated "profile views" label used when `label` is missing, e.g. `es`,
+    /// `ja` or `zh-TW`
+    pub label_lang: Option<String>,
+    /// Required unless the tenant has a default color
+    pub color: Option<String>,
+    /// Required unless the tenant has a default style
+    pub style: Option<String>,
+}
+
+// pixel density of raster badges, checked here so error badges can tell about it
+#[derive(Deserialize)]
+struct ScaleQuery {
+    scale: Option<f32>,
+}
+
+/// Validated badge params of a badge route, with the tenant's defaults applied on tenant routes.
+/// Requests with missing or invalid params get a badge describing the mistake, in the image
+/// format of the route, so embeds show what's wrong instead of a broken image.
+pub struct BadgeParams(pub ShieldsIoParams);
+
+impl Deref for BadgeParams {
+    type Target = ShieldsIoParams;
+
+    fn deref(&self) -> &ShieldsIoParams {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<T, F> FromRequestParts<Arc<AppState<T, F>>> for BadgeParams
+where
+    T: DatastoreOperations + Send + Sync,
+    F: ShieldsIoFetcher + Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState<T, F>>,
+    ) -> Result<Self, Self::Rejection> {
+        let query = match Query::<BadgeQuery>::try_from_uri(&parts.uri) {
+            Ok(Query(query)) => query,
+            Err(rejection) => {
+                let mistake = rejection.body_text();
+                return Err(reject(state, parts, &BadgeQuery::default(), &mistake).await);
+            }
+        };
+        if let Err(mistake) = check_scale(scale_of(parts)) {
+            return Err(reject(state, parts, &query, &mistake).await);
+        }
+
+        let tenant = RawPathParams::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|path| {
+                path.iter()
+                    .find(|(name, _)| *name == "tenant")
+                    .map(|(_, tenant)| tenant.to_string())
+            });
+        let params = match tenant {
+            Some(tenant) => {
+                let tenant = state
+                    .tenants
+                    .get(&tenant)
+                    .map_err(|err| err.into_response())?;
+                tenant.badge_params(&query)
+            }
+            None => ShieldsIoParams::from_query(&query),
+        };
+
+        let params = match params {
+            Ok(params) => params,
+            Err(mistake) => return Err(reject(state, parts, &query, &mistake).await),
+        };
+        if !state.config.permissive_badge_params {
+            if let Err(mistake) = params.validate() {
+                return Err(reject(state, parts, &query, &mistake).await);
+            }
+        }
+        Ok(BadgeParams(params))
+    }
+}
+
+async fn reject(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    parts: &Parts,
+    query: &BadgeQuery,
+    mistake: &str,
+) -> Response {
+    let style = query.style.as_deref().unwrap_or(ERROR_BADGE_STYLE);
+    let badge = error_badge(state, style, mistake);
+
+    let path = parts.uri.path();
+    let raster_format = if path.ends_with(".png") {
+        Some(RasterFormat::Png)
+    } else if path.ends_with(".webp") {
+        Some(RasterFormat::Webp)
+    } else {
+        None
+    };
+    match raster_format {
+        Some(format) => {
+            let scale = scale_of(parts).filter(|scale| scale.is_finite());
+            raster_response(&state.raster, Ok(RasterBadge::new(badge)), format, scale).await
+        }
+        None => badge_response(badge),
+    }
+}
+
+fn scale_of(parts: &Parts) -> Option<f32> {
+    Query::<ScaleQuery>::try_from_uri(&parts.uri)
+        .ok()
+        .and_then(|Query(query)| query.scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, http::StatusCode, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::badge::Shields;
+    use crate::cache::CacheStores;
+    use crate::config::Config;
+    use crate::datastore::Memory;
+    use crate::runtime::TokioSpawner;
+    use pretty_assertions::assert_eq;
+
+    async fn extract(uri: &str) -> (StatusCode, String, String) {
+        let config = Config::from_env();
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        let state = Arc::new(AppState::new(
+            Memory::new(),
+            shields,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        ));
+        let label = |BadgeParams(params): BadgeParams| async move { params.label().to_string() };
+        let router = Router::new()
+            .route("/:user_name/counter.svg", get(label))
+            .route("/:user_name/counter.png", get(label))
+            .with_state(state);
+
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8_lossy(&body).to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn it_answers_invalid_params_with_error_badges() {
+        let (_, _, label) = extract("/octocat/counter.svg?label=views&color=blue&style=flat").await;
+        assert_eq!(label, "views");
+
+        let (status, content_type, badge) =
+            extract("/octocat/counter.svg?label=views&style=flat").await;
+        assert_eq!(
+            (status, content_type.as_str()),
+            (StatusCode::OK, "image/svg+xml")
+        );
+        assert!(badge.contains("missing color"), "{}", badge);
+
+        let (status, content_type, _) =
+            extract("/octocat/counter.png?label=views&color=nope&style=flat").await;
+        assert_eq!(
+            (status, content_type.as_str()),
+            (StatusCode::OK, "image/png")
+        );
+    }
+
+    #[tokio::test]
+    async fn it_answers_non_finite_scales_with_error_badges() {
+        for scale in ["NaN", "inf", "-inf"] {
+            let uri = format!(
+                "/octocat/counter.svg?label=views&color=blue&style=flat&scale={}",
+                scale
+            );
+            let (status, _, badge) = extract(&uri).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(badge.contains("invalid scale"), "{}", badge);
+        }
+
+        let (status, content_type, _) =
+            extract("/octocat/counter.png?label=views&color=blue&style=flat&scale=NaN").await;
+        assert_eq!(
+            (status, content_type.as_str()),
+            (StatusCode::OK, "image/png")
+        );
+    }
+}
@@ -0,0 +1,6 @@
+use github_profile_views_counter::{start, Host};
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    start(Host::Lambda).await
+}
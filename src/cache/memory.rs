@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+
+use super::{CacheCounters, CacheStats, CacheStore};
+
+struct Entry {
+    value: String,
+    // `None` for ttls too long to represent, which never expire
+    expires_at: Option<Instant>,
+    hits: u64,
+}
+
+impl Entry {
+    fn new(value: &str, ttl: Duration) -> Entry {
+        Entry {
+            value: value.to_string(),
+            expires_at: Instant::now().checked_add(ttl),
+            hits: 0,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Instant::now())
+    }
+
+    /// Counter of the entry, zero when it expired or holds no number.
+    fn count(&self) -> u64 {
+        match self.is_expired() {
+            true => 0,
+            false => self.value.parse().unwrap_or(0),
+        }
+    }
+}
+
+/// Store in the instance's memory, holding every entry until it expires.
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, Entry>>,
+    counters: CacheCounters,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> Option<String> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(key) {
+            Some(entry) if !entry.is_expired() => {
+                entry.hits += 1;
+                self.counters.hit();
+                Some(entry.value.clone())
+            }
+            Some(_) => {
+                entries.remove(key);
+                self.counters.miss();
+                None
+            }
+            None => {
+                self.counters.miss();
+                None
+            }
+        }
+    }
+
+    async fn peek(&self, key: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.value.clone())
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key.to_string(), Entry::new(value, ttl));
+    }
+
+    async fn increment(&self, key: &str, by: u64, ttl: Duration) -> Option<u64> {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.get(key).map_or(0, Entry::count) + by;
+        entries.insert(key.to_string(), Entry::new(&count.to_string(), ttl));
+        Some(count)
+    }
+
+    async fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    async fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    async fn stats(&self) -> Option<CacheStats> {
+        let entries = self.entries.lock().unwrap();
+        let entries = entries.iter().map(|(key, entry)| (key.clone(), entry.hits));
+        Some(self.counters.stats(entries))
+    }
+}
+
+/// Store in the instance's memory holding up to `capacity` entries, evicting the least recently
+/// used one past it.
+pub struct LruStore {
+    lru: Mutex<Lru<Entry>>,
+    counters: CacheCounters,
+}
+
+/// Values by key up to a capacity, evicting the least recently used one past it; the bookkeeping
+/// of [`LruStore`], for caches of values other than strings, e.g. rendered images.
+pub struct Lru<V> {
+    capacity: usize,
+    // values with the tick they were last used at
+    entries: HashMap<String, (V, u64)>,
+    // keys by the tick they were last used at, least recently used first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl<V> Lru<V> {
+    pub fn new(capacity: usize) -> Lru<V> {
+        Lru {
+            capacity: capacity.max(1),
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// The value, marked as the most recently used one.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        let (_, used) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        let key = self.recency.remove(used).unwrap_or_else(|| key.to_string());
+        *used = self.tick;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.get_mut(&key).map(|(value, _)| value)
+    }
+
+    /// The value, leaving its recency as it is.
+    pub fn peek(&self, key: &str) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Stores the value as the most recently used one, evicting the least recently used values
+    /// past the capacity.
+    pub fn insert(&mut self, key: String, value: V) {
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let Some((_, evicted)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&evicted);
+        }
+
+        self.tick += 1;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let (value, used) = self.entries.remove(key)?;
+        self.recency.remove(&used);
+        Some(value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.iter().map(|(key, (value, _))| (key, value))
+    }
+}
+
+impl LruStore {
+    pub fn new(capacity: usize) -> LruStore {
+        LruStore {
+            lru: Mutex::new(Lru::new(capacity)),
+            counters: CacheCounters::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for LruStore {
+    async fn get(&self, key: &str) -> Option<String> {
+        let mut lru = self.lru.lock().unwrap();
+        if lru.peek(key).is_some_and(Entry::is_expired) {
+            lru.remove(key);
+        }
+        match lru.get_mut(key) {
+            Some(entry) => {
+                entry.hits += 1;
+                self.counters.hit();
+                Some(entry.value.clone())
+            }
+            None => {
+                self.counters.miss();
+                None
+            }
+        }
+    }
+
+    async fn peek(&self, key: &str) -> Option<String> {
+        let lru = self.lru.lock().unwrap();
+        lru.peek(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.value.clone())
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.insert(key.to_string(), Entry::new(value, ttl));
+    }
+
+    async fn increment(&self, key: &str, by: u64, ttl: Duration) -> Option<u64> {
+        let mut lru = self.lru.lock().unwrap();
+        let count = lru.peek(key).map_or(0, Entry::count) + by;
+        lru.insert(key.to_string(), Entry::new(&count.to_string(), ttl));
+        Some(count)
+    }
+
+    async fn remove(&self, key: &str) {
+        self.lru.lock().unwrap().remove(key);
+    }
+
+    async fn clear(&self) {
+        self.lru.lock().unwrap().clear();
+    }
+
+    async fn stats(&self) -> Option<CacheStats> {
+        let lru = self.lru.lock().unwrap();
+        let entries = lru.iter().map(|(key, entry)| (key.clone(), entry.hits));
+        Some(self.counters.stats(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[tokio::test]
+    async fn it_expires_entries() {
+        let store = MemoryStore::new();
+        store.set("fresh", "1", TTL).await;
+        store.set("expired", "2", Duration::ZERO).await;
+
+        assert_eq!(store.get("fresh").await, Some("1".to_string()));
+        assert_eq!(store.get("expired").await, None);
+        store.remove("fresh").await;
+        assert_eq!(store.peek("fresh").await, None);
+
+        let stats = store.stats().await.unwrap();
+        assert_eq!((stats.size, stats.hits, stats.misses), (0, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn it_evicts_least_recently_used_entries() {
+        let store = LruStore::new(2);
+        store.set("a", "1", TTL).await;
+        store.set("b", "2", TTL).await;
+        // `a` is used after `b`, so `b` makes room for `c`
+        assert_eq!(store.get("a").await, Some("1".to_string()));
+        store.set("c", "3", TTL).await;
+
+        assert_eq!(store.peek("a").await, Some("1".to_string()));
+        assert_eq!(store.peek("b").await, None);
+        assert_eq!(store.peek("c").await, Some("3".to_string()));
+        assert_eq!(store.stats().await.unwrap().size, 2);
+    }
+
+    #[tokio::test]
+    async fn it_increments_counters() {
+        let stores: [Box<dyn CacheStore>; 2] =
+            [Box::new(MemoryStore::new()), Box::new(LruStore::new(2))];
+        for store in stores {
+            assert_eq!(store.increment("views", 2, TTL).await, Some(2));
+            assert_eq!(store.increment("views", 1, TTL).await, Some(3));
+            assert_eq!(store.peek("views").await, Some("3".to_string()));
+
+            store.set("expired", "5", Duration::ZERO).await;
+            assert_eq!(store.increment("expired", 1, TTL).await, Some(1));
+        }
+    }
+}
@@ -0,0 +1,170 @@
+mod memory;
+#[cfg(feature = "redis")]
+mod redis;
+
+pub use memory::{Lru, LruStore, MemoryStore};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::config::{Config, SharedCacheConfig};
+
+// most hit keys reported per cache
+const TOP_KEYS: usize = 10;
+
+/// Key-value store for cached data, e.g. badge templates. Stores are best effort: failures are
+/// logged and read as misses, so a broken store only costs the lookups it would have saved.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    /// The value unless it expired, counted as a hit or a miss.
+    async fn get(&self, key: &str) -> Option<String>;
+
+    /// Like `get`, without counting as a hit or a miss.
+    async fn peek(&self, key: &str) -> Option<String> {
+        self.get(key).await
+    }
+
+    /// Stores the value for `ttl`, replacing the current one.
+    async fn set(&self, key: &str, value: &str, ttl: Duration);
+
+    /// Adds `by` to the counter stored as the value, starting from zero, and keeps it for `ttl`.
+    /// The count after adding, `None` when the store failed.
+    async fn increment(&self, key: &str, by: u64, ttl: Duration) -> Option<u64>;
+
+    async fn remove(&self, key: &str);
+
+    /// Drops every entry.
+    async fn clear(&self);
+
+    /// Stats since the server started, `None` for stores which can't tell.
+    async fn stats(&self) -> Option<CacheStats>;
+}
+
+/// Stores the server caches in: its own memory, and the store shared by all instances when one is
+/// configured. Built once and handed to everything caching, see `AppState`.
+#[derive(Clone)]
+pub struct CacheStores {
+    pub local: Arc<dyn CacheStore>,
+    pub shared: Option<Arc<dyn CacheStore>>,
+}
+
+impl CacheStores {
+    pub fn new(config: &Config) -> CacheStores {
+        let local: Arc<dyn CacheStore> = match config.cache_capacity {
+            Some(capacity) => Arc::new(LruStore::new(capacity)),
+            None => Arc::new(MemoryStore::new()),
+        };
+        CacheStores {
+            local,
+            shared: shared_store(config.shared_cache.as_ref()),
+        }
+    }
+}
+
+/// Store shared by all instances, `None` unless one is configured.
+fn shared_store(config: Option<&SharedCacheConfig>) -> Option<Arc<dyn CacheStore>> {
+    let config = config?;
+
+    #[cfg(feature = "redis")]
+    match redis::RedisStore::new(config) {
+        Ok(store) => {
+            tracing::info!("sharing cached data through redis");
+            Some(Arc::new(store))
+        }
+        Err(err) => {
+            tracing::error!("invalid REDIS_URL, caching in memory only: {}", err);
+            None
+        }
+    }
+
+    #[cfg(not(feature = "redis"))]
+    {
+        tracing::error!(
+            "REDIS_URL `{}` is set, but the server needs to be built with the `redis` feature",
+            config.url
+        );
+        None
+    }
+}
+
+/// Hits and misses of a cache since the server started.
+#[derive(Default)]
+pub struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheCounters {
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Stats of the cache holding entries with the given hits.
+    pub fn stats(&self, entries: impl Iterator<Item = (String, u64)>) -> CacheStats {
+        let mut keys: Vec<KeyStats> = entries.map(|(key, hits)| KeyStats { key, hits }).collect();
+        let size = keys.len();
+        keys.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.key.cmp(&b.key)));
+        keys.truncate(TOP_KEYS);
+
+        CacheStats {
+            size,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            top_keys: keys,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct CacheStats {
+    /// Entries currently cached
+    pub size: usize,
+    /// Lookups served from the cache since the server started
+    pub hits: u64,
+    /// Lookups which missed the cache since the server started
+    pub misses: u64,
+    /// Most hit entries, with their hits since they got cached
+    pub top_keys: Vec<KeyStats>,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct KeyStats {
+    pub key: String,
+    pub hits: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_reports_most_hit_keys() {
+        let counters = CacheCounters::default();
+        counters.hit();
+        counters.hit();
+        counters.miss();
+
+        let entries = (0..12).map(|key| (format!("key-{:02}", key), key % 4));
+        let stats = counters.stats(entries);
+
+        assert_eq!((stats.size, stats.hits, stats.misses), (12, 2, 1));
+        assert_eq!(stats.top_keys.len(), 10);
+        assert_eq!(
+            stats.top_keys[0],
+            KeyStats {
+                key: "key-03".to_string(),
+                hits: 3
+            }
+        );
+    }
+}
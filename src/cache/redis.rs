@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client, RedisError};
+use tokio::sync::OnceCell;
+
+use super::{CacheStats, CacheStore};
+use crate::config::SharedCacheConfig;
+
+// a slow cache must not slow down badges, which would be served without it in time
+const COMMAND_TIMEOUT: Duration = Duration::from_millis(250);
+// clearing scans every key of the prefix, which takes longer than a single command
+const CLEAR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Store on a redis server shared by all instances; connects on first use, retrying on the next
+/// command if that fails, and reconnects whenever the connection drops.
+pub struct RedisStore {
+    client: Client,
+    connection: OnceCell<ConnectionManager>,
+    key_prefix: String,
+}
+
+impl RedisStore {
+    pub fn new(config: &SharedCacheConfig) -> Result<RedisStore, RedisError> {
+        Ok(RedisStore {
+            client: Client::open(config.url.as_str())?,
+            connection: OnceCell::new(),
+            key_prefix: config.key_prefix.clone(),
+        })
+    }
+
+    async fn connection(&self) -> Result<ConnectionManager, RedisError> {
+        self.connection
+            .get_or_try_init(|| self.client.get_connection_manager())
+            .await
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl CacheStore for RedisStore {
+    async fn get(&self, key: &str) -> Option<String> {
+        let key = format!("{}{}", self.key_prefix, key);
+        let get = async {
+            let mut connection = self.connection().await?;
+            connection.get::<_, Option<String>>(&key).await
+        };
+
+        match tokio::time::timeout(COMMAND_TIMEOUT, get).await {
+            Ok(Ok(value)) => value,
+            Ok(Err(err)) => {
+                tracing::warn!("failed to get `{}` from redis: {}", key, err);
+                None
+            }
+            Err(_) => {
+                tracing::warn!("timed out getting `{}` from redis", key);
+                None
+            }
+        }
+    }
+
+    async fn set(&self, key: &str, value: &str, ttl: Duration) {
+        let key = format!("{}{}", self.key_prefix, key);
+        let set = async {
+            let mut connection = self.connection().await?;
+            connection
+                .set_ex::<_, _, ()>(&key, value, ttl.as_secs().max(1))
+                .await
+        };
+
+        match tokio::time::timeout(COMMAND_TIMEOUT, set).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::warn!("failed to set `{}` on redis: {}", key, err),
+            Err(_) => tracing::warn!("timed out setting `{}` on redis", key),
+        }
+    }
+
+    async fn increment(&self, key: &str, by: u64, ttl: Duration) -> Option<u64> {
+        let key = format!("{}{}", self.key_prefix, key);
+        let increment = async {
+            let mut connection = self.connection().await?;
+            // one transaction, so no counter is left without its ttl
+            redis::pipe()
+                .atomic()
+                .incr(&key, by)
+                .expire(&key, ttl.as_secs().max(1) as i64)
+                .ignore()
+                .query_async::<_, (u64,)>(&mut connection)
+                .await
+        };
+
+        match tokio::time::timeout(COMMAND_TIMEOUT, increment).await {
+            Ok(Ok((count,))) => Some(count),
+            Ok(Err(err)) => {
+                tracing::warn!("failed to increment `{}` on redis: {}", key, err);
+                None
+            }
+            Err(_) => {
+                tracing::warn!("timed out incrementing `{}` on redis", key);
+                None
+            }
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        let key = format!("{}{}", self.key_prefix, key);
+        let remove = async {
+            let mut connection = self.connection().await?;
+            connection.del::<_, ()>(&key).await
+        };
+
+        match tokio::time::timeout(COMMAND_TIMEOUT, remove).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::warn!("failed to remove `{}` from redis: {}", key, err),
+            Err(_) => tracing::warn!("timed out removing `{}` from redis", key),
+        }
+    }
+
+    async fn clear(&self) {
+        let pattern = format!("{}*", self.key_prefix);
+        let clear = async {
+            let mut connection = self.connection().await?;
+            let keys: Vec<String> = {
+                let mut scan = connection.scan_match::<_, String>(&pattern).await?;
+                let mut keys = Vec::new();
+                while let Some(key) = scan.next_item().await {
+                    keys.push(key);
+                }
+                keys
+            };
+            for keys in keys.chunks(100) {
+                connection.del::<_, ()>(keys).await?;
+            }
+            Ok::<_, RedisError>(())
+        };
+
+        match tokio::time::timeout(CLEAR_TIMEOUT, clear).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::warn!("failed to clear `{}` on redis: {}", pattern, err),
+            Err(_) => tracing::warn!("timed out clearing `{}` on redis", pattern),
+        }
+    }
+
+    // hits depend on every instance, which only redis itself could tell
+    async fn stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
@@ -0,0 +1,105 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use axum::http::HeaderMap;
+
+pub const DEFAULT_IPV4_PREFIX: u8 = 32;
+// carriers assign a /64 per device and rotate the address within it, often hourly
+pub const DEFAULT_IPV6_PREFIX: u8 = 64;
+
+/// Lengths of the network prefixes telling clients apart, read from `CLIENT_IPV4_PREFIX` and
+/// `CLIENT_IPV6_PREFIX`; every address within a prefix counts as the same client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubnetPrefixes {
+    pub ipv4: u8,
+    pub ipv6: u8,
+}
+
+impl Default for SubnetPrefixes {
+    fn default() -> SubnetPrefixes {
+        SubnetPrefixes {
+            ipv4: DEFAULT_IPV4_PREFIX,
+            ipv6: DEFAULT_IPV6_PREFIX,
+        }
+    }
+}
+
+/// Address of the client behind the proxy in front of the server.
+pub fn client_address(headers: &HeaderMap) -> Option<String> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    // fly.io sets the client address, `x-forwarded-for` covers other proxies
+    header("fly-client-ip")
+        .or_else(|| header("x-forwarded-for").and_then(|addrs| addrs.split(',').next()))
+        .map(|addr| addr.trim().to_string())
+}
+
+/// Client the request came from, as its subnet, e.g. `2001:db8:0:1::/64`; addresses are kept as
+/// they are when the prefix covers all of them or they don't parse.
+pub fn client_identity(headers: &HeaderMap, prefixes: SubnetPrefixes) -> Option<String> {
+    let address = client_address(headers)?;
+    // some proxies forward the client's port as well
+    let ip = address
+        .parse::<IpAddr>()
+        .or_else(|_| address.parse::<SocketAddr>().map(|addr| addr.ip()));
+    match ip {
+        Ok(ip) => Some(subnet(ip.to_canonical(), prefixes)),
+        Err(_) => Some(address),
+    }
+}
+
+fn subnet(ip: IpAddr, prefixes: SubnetPrefixes) -> String {
+    match ip {
+        IpAddr::V4(ip) if prefixes.ipv4 < 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefixes.ipv4 as u32).unwrap_or(0);
+            format!("{}/{}", Ipv4Addr::from(u32::from(ip) & mask), prefixes.ipv4)
+        }
+        IpAddr::V6(ip) if prefixes.ipv6 < 128 => {
+            let mask = u128::MAX
+                .checked_shl(128 - prefixes.ipv6 as u32)
+                .unwrap_or(0);
+            format!(
+                "{}/{}",
+                Ipv6Addr::from(u128::from(ip) & mask),
+                prefixes.ipv6
+            )
+        }
+        ip => ip.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_collapses_clients_to_their_subnet() {
+        let identity = |address: &str, prefixes| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-for", address.parse().unwrap());
+            client_identity(&headers, prefixes)
+        };
+        let defaults = SubnetPrefixes::default();
+
+        assert_eq!(
+            identity("2001:db8:0:1:aaaa::1, 10.0.0.1", defaults),
+            identity("2001:db8:0:1:bbbb::2", defaults)
+        );
+        assert_eq!(
+            identity("[2001:db8:0:1::7]:443", defaults).as_deref(),
+            Some("2001:db8:0:1::/64")
+        );
+        assert_eq!(
+            identity("203.0.113.7", defaults).as_deref(),
+            Some("203.0.113.7")
+        );
+        let ipv4_24 = SubnetPrefixes {
+            ipv4: 24,
+            ..defaults
+        };
+        assert_eq!(
+            identity("::ffff:203.0.113.7", ipv4_24).as_deref(),
+            Some("203.0.113.0/24")
+        );
+        assert_eq!(identity("unknown", defaults).as_deref(), Some("unknown"));
+    }
+}
@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::TenantConfig;
+use crate::auth::AuthPolicy;
+use crate::secrets::{self, file_variable, read_secret_file};
+
+/// A variable the server can't start with, along with how to fix it.
+#[derive(Debug, PartialEq)]
+pub struct ConfigProblem {
+    pub variable: String,
+    pub problem: String,
+    pub hint: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}; {}", self.variable, self.problem, self.hint)
+    }
+}
+
+/// Checks every configuration variable, missing, unparsable and conflicting ones alike, so they
+/// can be fixed in one go rather than one failed start at a time. `PORT` is only required by
+/// servers binding it themselves.
+pub fn check_env(requires_port: bool) -> Vec<ConfigProblem> {
+    check(&std::env::vars().collect(), requires_port)
+}
+
+fn check(env: &HashMap<String, String>, requires_port: bool) -> Vec<ConfigProblem> {
+    let mut check = Check {
+        env,
+        problems: Vec::new(),
+    };
+
+    for name in ["XATA_DB_ENDPOINT", "XATA_TABLE_NAME"] {
+        check.required(
+            name,
+            "copy it from the database settings of the Xata console",
+        );
+    }
+    // a key missing from the provider fails the startup once the provider was asked for it
+    if !check.is_set(secrets::XATA_API_KEY)
+        && !check.is_set(&file_variable(secrets::XATA_API_KEY))
+        && !check.is_set("SECRETS_PROVIDER")
+    {
+        check.problem(
+            secrets::XATA_API_KEY,
+            "is not set",
+            "create an api key in the account settings of the Xata console, set it or the path \
+             of a file holding it in `XATA_API_KEY_FILE`",
+        );
+    }
+    for name in secrets::SECRETS {
+        check.secret(name);
+    }
+    check.secrets_provider();
+    if requires_port {
+        check.required("PORT", "set the port to listen on, e.g. `8080`");
+    }
+    check.number::<u16>("PORT", "a port", |_| true);
+
+    for name in [
+        "DNS_CACHE_TTL",
+        "SHED_RETRY_AFTER",
+        "BADGE_TEMPLATE_TTL",
+        "RETENTION_INTERVAL",
+        "COUNT_FLUSH_INTERVAL",
+        "COUNT_REVALIDATE_AFTER",
+        "COUNT_MAX_STALENESS",
+        "FALLBACK_RECONCILE_INTERVAL",
+        "SHUTDOWN_TIMEOUT",
+        "SECRETS_RELOAD_INTERVAL",
+    ] {
+        check.number::<u64>(name, "a number of seconds", |_| true);
+    }
+    for prefix in ["XATA", "SHIELDS", "GITHUB"] {
+        check.number::<u64>(
+            &format!("{}_CONNECT_TIMEOUT_MS", prefix),
+            "a number of milliseconds",
+            |_| true,
+        );
+        check.number::<u64>(
+            &format!("{}_TIMEOUT_MS", prefix),
+            "a number of milliseconds",
+            |_| true,
+        );
+        check.number::<usize>(&format!("{}_POOL_MAX_IDLE", prefix), "a count", |_| true);
+        check.number::<u32>(&format!("{}_RETRIES", prefix), "a count", |_| true);
+        check.number::<u64>(
+            &format!("{}_SLOW_MS", prefix),
+            "a number of milliseconds above 0",
+            |threshold| *threshold > 0,
+        );
+        check.headers(&format!("{}_HEADERS", prefix));
+    }
+    for name in [
+        "DATASTORE_FAULT_LATENCY_MS",
+        "DATASTORE_FAULT_TIMEOUT_MS",
+        "SHED_WAIT_MS",
+    ] {
+        check.number::<u64>(name, "a number of milliseconds", |_| true);
+    }
+    for name in ["DATASTORE_FAULT_ERROR_RATE", "DATASTORE_FAULT_TIMEOUT_RATE"] {
+        check.number::<f64>(name, "a fraction from 0 up to 1", |rate| {
+            (0.0..=1.0).contains(rate)
+        });
+    }
+    check.number::<u64>("ANOMALY_MIN_HOURLY_VIEWS", "a count", |_| true);
+    for name in [
+        "MAX_CONCURRENT_REQUESTS",
+        "DAILY_VIEW_QUOTA",
+        "CACHE_PRIMING_TOP_N",
+        "CACHE_CAPACITY",
+        "RETENTION_DORMANT_MONTHS",
+    ] {
+        check.number::<u64>(name, "a count above 0", |count| *count > 0);
+    }
+    check.number::<f64>(
+        "TREND_THRESHOLD",
+        "a percentage of 0 or more",
+        |threshold| *threshold >= 0.0,
+    );
+    check.number::<f64>(
+        "ANALYTICS_SAMPLE_RATE",
+        "a fraction above 0, up to 1",
+        |rate| *rate > 0.0 && *rate <= 1.0,
+    );
+    check.number::<u8>("CLIENT_IPV4_PREFIX", "a prefix length up to 32", |bits| {
+        *bits <= 32
+    });
+    check.number::<u8>("CLIENT_IPV6_PREFIX", "a prefix length up to 128", |bits| {
+        *bits <= 128
+    });
+    check.countries("BLOCKED_COUNTRIES");
+    check.number::<usize>(
+        "XATA_CAPTURE_FAILED_REQUESTS",
+        "a count above 0",
+        |capacity| *capacity > 0,
+    );
+    for name in ["REQUEST_BUDGET", "REQUEST_BUDGET_BURST", "ANOMALY_FACTOR"] {
+        check.number::<f64>(name, "a number above 0", |number| *number > 0.0);
+    }
+
+    check.one_of("FALLBACK_DATASTORE", &["memory"]);
+    check.one_of("COUNT_CONSISTENCY", &["strict", "optimistic"]);
+    check.one_of("BADGE_PARAMS_VALIDATION", &["strict", "permissive"]);
+    check.one_of("HEAD_REQUESTS", &["count", "skip"]);
+    check.one_of("ONBOARDING", &["implicit", "explicit"]);
+    check.one_of("USER_VERIFICATION", &["github", "none"]);
+    check.one_of("CACHED_BADGES", &["live", "muted"]);
+    check.one_of("ANOMALY_DETECTION", &["flag", "freeze"]);
+    check.one_of("REUSE_PORT", &["true", "false"]);
+    check.tenants();
+    check.auth_policy();
+
+    check.needs_feature("REDIS_URL", cfg!(feature = "redis"), &["redis"]);
+    check.needs_feature(
+        "EVENTS_URL",
+        cfg!(any(feature = "nats", feature = "kafka")),
+        &["nats", "kafka"],
+    );
+    check.needs_feature("SENTRY_DSN", cfg!(feature = "sentry"), &["sentry"]);
+    check.needs_feature("JWT_JWKS_URL", cfg!(feature = "jwt"), &["jwt"]);
+
+    for name in [
+        "COUNT_FLUSH_INTERVAL",
+        "COUNT_REVALIDATE_AFTER",
+        "COUNT_MAX_STALENESS",
+    ] {
+        if check.is_set(name) && check.value("COUNT_CONSISTENCY") == Some("strict") {
+            check.problem(
+                name,
+                "has no effect with `COUNT_CONSISTENCY=strict`",
+                "unset one of them",
+            );
+        }
+    }
+    check.needs("FALLBACK_RECONCILE_INTERVAL", "FALLBACK_DATASTORE");
+    check.needs("REQUEST_BUDGET_BURST", "REQUEST_BUDGET");
+    for name in [
+        "RETENTION_INTERVAL",
+        "RETENTION_ARCHIVE",
+        "RETENTION_ALLOWLIST",
+    ] {
+        check.needs(name, "RETENTION_DORMANT_MONTHS");
+    }
+    if check.is_set("RETENTION_DORMANT_MONTHS") {
+        check.retention_archive();
+    }
+    check.needs("DATASTORE_FAULT_TIMEOUT_MS", "DATASTORE_FAULT_TIMEOUT_RATE");
+    for name in ["ANOMALY_FACTOR", "ANOMALY_MIN_HOURLY_VIEWS"] {
+        check.needs(name, "ANOMALY_DETECTION");
+    }
+    check.needs("EVENTS_TOPIC", "EVENTS_URL");
+    if check.is_set("JWT_JWKS_URL") {
+        for name in ["JWT_ISSUER", "JWT_AUDIENCE"] {
+            check.required(
+                name,
+                "copy it from the identity provider, tokens of other issuers or apps are rejected",
+            );
+        }
+    }
+    for name in [
+        "JWT_ISSUER",
+        "JWT_AUDIENCE",
+        "JWT_ADMIN_SCOPE",
+        "JWT_API_SCOPE",
+    ] {
+        check.needs(name, "JWT_JWKS_URL");
+    }
+    if check.is_set("SECRETS_RELOAD_INTERVAL")
+        && !check.is_set("SECRETS_PROVIDER")
+        && !secrets::SECRETS
+            .iter()
+            .any(|name| check.is_set(&file_variable(name)))
+    {
+        check.problem(
+            "SECRETS_RELOAD_INTERVAL",
+            "has no effect without secrets read from files or a provider",
+            "set e.g. `XATA_API_KEY_FILE` or `SECRETS_PROVIDER`, or unset it",
+        );
+    }
+    check.needs("REDIS_KEY_PREFIX", "REDIS_URL");
+    if check.value("REUSE_PORT") == Some("true") && check.is_set("LISTEN_FDS") {
+        check.problem(
+            "REUSE_PORT",
+            "has no effect on the socket passed by systemd",
+            "unset it or configure `ReusePort=` in the socket unit",
+        );
+    }
+
+    check.problems
+}
+
+struct Check<'a> {
+    env: &'a HashMap<String, String>,
+    problems: Vec<ConfigProblem>,
+}
+
+impl Check<'_> {
+    fn value(&self, name: &str) -> Option<&str> {
+        self.env
+            .get(name)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    fn is_set(&self, name: &str) -> bool {
+        self.value(name).is_some()
+    }
+
+    fn problem(&mut self, name: &str, problem: &str, hint: &str) {
+        self.problems.push(ConfigProblem {
+            variable: name.to_string(),
+            problem: problem.to_string(),
+            hint: hint.to_string(),
+        });
+    }
+
+    fn required(&mut self, name: &str, hint: &str) {
+        if !self.is_set(name) {
+            self.problem(name, "is not set", hint);
+        }
+    }
+
+    fn number<T: FromStr>(&mut self, name: &str, expected: &str, valid: impl Fn(&T) -> bool) {
+        let Some(value) = self.value(name) else {
+            return;
+        };
+        if !value.parse::<T>().is_ok_and(|number| valid(&number)) {
+            let problem = format!("`{}` is not {}", value, expected);
+            self.problem(name, &problem, "fix the value or unset it for the default");
+        }
+    }
+
+    fn one_of(&mut self, name: &str, values: &[&str]) {
+        let Some(value) = self.value(name) else {
+            return;
+        };
+        if !values.contains(&value) {
+            let problem = format!("`{}` is not supported", value);
+            let hint = format!("use one of `{}`", values.join("`, `"));
+            self.problem(name, &problem, &hint);
+        }
+    }
+
+    fn secret(&mut self, name: &str) {
+        let file = file_variable(name);
+        let Some(path) = self.value(&file) else {
+            return;
+        };
+        if self.is_set(name) {
+            let problem = format!("is set along with `{}`", file);
+            self.problem(name, &problem, "unset one of them");
+            return;
+        }
+
+        match read_secret_file(Path::new(path)) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                let problem = format!("`{}` is empty", path);
+                self.problem(&file, &problem, "write the secret to the file");
+            }
+            Err(err) => {
+                let problem = format!("`{}` can't be read: {}", path, err);
+                self.problem(
+                    &file,
+                    &problem,
+                    "check that the secret is mounted at this path and readable by the server",
+                );
+            }
+        }
+    }
+
+    fn secrets_provider(&mut self) {
+        let (feature, enabled, variables): (_, _, &[&str]) = match self.value("SECRETS_PROVIDER") {
+            None => return,
+            Some("vault") => (
+                "vault",
+                cfg!(feature = "vault"),
+                &["VAULT_ADDR", "VAULT_TOKEN", "VAULT_SECRET_PATH"],
+            ),
+            Some("aws-secrets-manager") => (
+                "aws-secrets-manager",
+                cfg!(feature = "aws-secrets-manager"),
+                // region and credentials are resolved like by the AWS cli, e.g. from a role
+                &["AWS_SECRET_ID"],
+            ),
+            Some(_) => {
+                self.one_of("SECRETS_PROVIDER", &["vault", "aws-secrets-manager"]);
+                return;
+            }
+        };
+
+        self.needs_feature("SECRETS_PROVIDER", enabled, &[feature]);
+        let hint = format!("set it to read secrets from `SECRETS_PROVIDER={}`", feature);
+        for name in variables {
+            self.required(name, &hint);
+        }
+    }
+
+    fn needs(&mut self, name: &str, enabling: &str) {
+        if self.is_set(name) && !self.is_set(enabling) {
+            let problem = format!("has no effect without `{}`", enabling);
+            let hint = format!("set `{}` or unset `{}`", enabling, name);
+            self.problem(name, &problem, &hint);
+        }
+    }
+
+    // removed users only survive in the archive, which the container's working directory
+    // doesn't keep across deploys
+    fn retention_archive(&mut self) {
+        let hint = "point it at a file on a persistent volume, removed users are archived there";
+        match self.value("RETENTION_ARCHIVE").map(Path::new) {
+            None => self.problem("RETENTION_ARCHIVE", "is not set", hint),
+            Some(path) if !path.is_absolute() || !path.parent().is_some_and(|dir| dir.is_dir()) => {
+                let problem = format!(
+                    "`{}` is not an absolute path in an existing directory",
+                    path.display()
+                );
+                self.problem("RETENTION_ARCHIVE", &problem, hint);
+            }
+            Some(_) => {}
+        }
+    }
+
+    fn needs_feature(&mut self, name: &str, enabled: bool, features: &[&str]) {
+        if self.is_set(name) && !enabled {
+            let problem = format!(
+                "needs a build with the `{}` feature",
+                features.join("` or `")
+            );
+            let hint = format!(
+                "rebuild with `--features {}` or unset it",
+                features.join("` or `--features ")
+            );
+            self.problem(name, &problem, &hint);
+        }
+    }
+
+    fn auth_policy(&mut self) {
+        let Some(policy) = self.value("AUTH_POLICY") else {
+            return;
+        };
+        match AuthPolicy::parse(policy) {
+            Ok(policy) if policy.requires_jwt() && !self.is_set("JWT_JWKS_URL") => {
+                self.problem(
+                    "AUTH_POLICY",
+                    "requires jwts without `JWT_JWKS_URL`",
+                    "set `JWT_JWKS_URL` or require other credentials",
+                );
+            }
+            Ok(_) => {}
+            Err(err) => {
+                self.problem(
+                    "AUTH_POLICY",
+                    &err,
+                    "write rules like `/status=admin,/api/*=jwt`, requiring `public`, `api-key`, `admin` or `jwt`",
+                );
+            }
+        }
+    }
+
+    fn countries(&mut self, name: &str) {
+        let Some(countries) = self.value(name) else {
+            return;
+        };
+        let invalid: Vec<&str> = countries
+            .split(',')
+            .map(str::trim)
+            .filter(|country| {
+                country.len() != 2 || !country.chars().all(|c| c.is_ascii_alphanumeric())
+            })
+            .collect();
+        if !invalid.is_empty() {
+            let problem = format!("has invalid country codes `{}`", invalid.join("`, `"));
+            self.problem(
+                name,
+                &problem,
+                "list ISO 3166-1 alpha-2 codes, e.g. `DE,FR`",
+            );
+        }
+    }
+
+    fn headers(&mut self, name: &str) {
+        let Some(headers) = self.value(name) else {
+            return;
+        };
+        if let Err(err) = super::parse_headers(headers) {
+            let problem = format!("is not a valid json object of headers: {}", err);
+            self.problem(name, &problem, r#"e.g. `{"Authorization": "Bearer ..."}`"#);
+        }
+    }
+
+    fn tenants(&mut self) {
+        let Some(tenants) = self.value("TENANTS") else {
+            return;
+        };
+        match serde_json::from_str::<HashMap<String, TenantConfig>>(tenants) {
+            Ok(tenants) => {
+                let mut invalid: Vec<&str> = tenants
+                    .keys()
+                    .filter(|name| !super::is_valid_tenant_name(name))
+                    .map(String::as_str)
+                    .collect();
+                invalid.sort();
+                if !invalid.is_empty() {
+                    let problem = format!("has invalid tenant names `{}`", invalid.join("`, `"));
+                    self.problem("TENANTS", &problem, "name tenants with [a-z0-9-]+");
+                }
+            }
+            Err(err) => {
+                let problem = format!("is not a valid json object of tenants: {}", err);
+                self.problem(
+                    "TENANTS",
+                    &problem,
+                    r#"e.g. `{"rustaceans": {"api_key": "..."}}`"#,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn problems(vars: &[(&str, &str)], requires_port: bool) -> Vec<String> {
+        let mut env: HashMap<String, String> = [
+            ("XATA_DB_ENDPOINT", "https://example.xata.sh/db/views:main"),
+            ("XATA_API_KEY", "key"),
+            ("XATA_TABLE_NAME", "profile_views"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        for (name, value) in vars {
+            env.insert(name.to_string(), value.to_string());
+        }
+
+        check(&env, requires_port)
+            .into_iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn it_accepts_valid_config() {
+        let archive = std::env::temp_dir().join("retention-archive.csv");
+        let vars = [
+            ("PORT", "8080"),
+            ("COUNT_CONSISTENCY", "strict"),
+            ("ANALYTICS_SAMPLE_RATE", "0.5"),
+            ("RETENTION_DORMANT_MONTHS", "12"),
+            ("RETENTION_INTERVAL", "3600"),
+            ("RETENTION_ARCHIVE", archive.to_str().unwrap()),
+        ];
+        assert_eq!(problems(&vars, true), Vec::<String>::new());
+    }
+
+    #[test]
+    fn it_reports_every_problem_at_once() {
+        let vars = [
+            ("XATA_API_KEY", ""),
+            ("MAX_CONCURRENT_REQUESTS", "lots"),
+            ("ANALYTICS_SAMPLE_RATE", "2"),
+            ("HEAD_REQUESTS", "always"),
+            ("COUNT_CONSISTENCY", "strict"),
+            ("COUNT_FLUSH_INTERVAL", "10"),
+            ("REQUEST_BUDGET_BURST", "10"),
+        ];
+        assert_eq!(
+            problems(&vars, true),
+            vec![
+                "XATA_API_KEY is not set; create an api key in the account settings of the Xata console, set it or the path of a file holding it in `XATA_API_KEY_FILE`",
+                "PORT is not set; set the port to listen on, e.g. `8080`",
+                "MAX_CONCURRENT_REQUESTS `lots` is not a count above 0; fix the value or unset it for the default",
+                "ANALYTICS_SAMPLE_RATE `2` is not a fraction above 0, up to 1; fix the value or unset it for the default",
+                "HEAD_REQUESTS `always` is not supported; use one of `count`, `skip`",
+                "COUNT_FLUSH_INTERVAL has no effect with `COUNT_CONSISTENCY=strict`; unset one of them",
+                "REQUEST_BUDGET_BURST has no effect without `REQUEST_BUDGET`; set `REQUEST_BUDGET` or unset `REQUEST_BUDGET_BURST`",
+            ]
+        );
+        // servers listening on an inherited socket don't bind a port
+        assert_eq!(problems(&[("PORT", "")], false), Vec::<String>::new());
+
+        assert_eq!(
+            problems(&[("RETENTION_DORMANT_MONTHS", "12")], false),
+            vec!["RETENTION_ARCHIVE is not set; point it at a file on a persistent volume, removed users are archived there"]
+        );
+        let vars = [
+            ("RETENTION_DORMANT_MONTHS", "12"),
+            ("RETENTION_ARCHIVE", "retention-archive.csv"),
+        ];
+        assert_eq!(
+            problems(&vars, false),
+            vec!["RETENTION_ARCHIVE `retention-archive.csv` is not an absolute path in an existing directory; point it at a file on a persistent volume, removed users are archived there"]
+        );
+
+        assert_eq!(
+            problems(&[("BLOCKED_COUNTRIES", "DE, Germany")], false),
+            vec!["BLOCKED_COUNTRIES has invalid country codes `Germany`; list ISO 3166-1 alpha-2 codes, e.g. `DE,FR`"]
+        );
+        assert_eq!(
+            problems(&[("SHIELDS_HEADERS", r#"{"x-token": "a\nb"}"#)], false),
+            vec![
+                r#"SHIELDS_HEADERS is not a valid json object of headers: the value of `x-token` is not a header value; e.g. `{"Authorization": "Bearer ..."}`"#
+            ]
+        );
+
+        let vars = [
+            ("ADMIN_TOKEN", "token"),
+            ("ADMIN_TOKEN_FILE", "/nonexistent/admin-token"),
+        ];
+        assert_eq!(
+            problems(&vars, false),
+            vec!["ADMIN_TOKEN is set along with `ADMIN_TOKEN_FILE`; unset one of them"]
+        );
+
+        let vars = [
+            ("XATA_API_KEY", ""),
+            ("SECRETS_PROVIDER", "vault"),
+            ("VAULT_ADDR", "https://vault.example.dev:8200"),
+        ];
+        let mut expected = vec![
+            "VAULT_TOKEN is not set; set it to read secrets from `SECRETS_PROVIDER=vault`",
+            "VAULT_SECRET_PATH is not set; set it to read secrets from `SECRETS_PROVIDER=vault`",
+        ];
+        if !cfg!(feature = "vault") {
+            expected.insert(0, "SECRETS_PROVIDER needs a build with the `vault` feature; rebuild with `--features vault` or unset it");
+        }
+        assert_eq!(problems(&vars, false), expected);
+    }
+}
@@ -0,0 +1,641 @@
+mod check;
+
+pub use check::check_env;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    RequestBuilder, Response,
+};
+use serde::Deserialize;
+
+use super::auth::AuthPolicy;
+use super::client_identity::{SubnetPrefixes, DEFAULT_IPV4_PREFIX, DEFAULT_IPV6_PREFIX};
+use super::secrets::Secrets;
+
+const DEFAULT_DNS_CACHE_TTL: u64 = 300;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 512;
+const DEFAULT_SHED_RETRY_AFTER: u64 = 5;
+const DEFAULT_SHED_WAIT_MS: u64 = 100;
+const DEFAULT_TREND_THRESHOLD: f64 = 10.0;
+const DEFAULT_BADGE_TEMPLATE_TTL: u64 = 24 * 60 * 60;
+
+pub struct Config {
+    /// Xata api key, the `ADMIN_TOKEN` bearer token guarding the admin routes and the `API_KEY`
+    /// one guarding the integration api routes; admin and api routes are disabled when their
+    /// token is unset. Each may be read from a file named by `<NAME>_FILE` or, when set in
+    /// neither, from the `SECRETS_PROVIDER`: `vault` or `aws-secrets-manager`.
+    pub secrets: Arc<Secrets>,
+    /// How often secrets read from files or the provider are read again, read from
+    /// `SECRETS_RELOAD_INTERVAL` in seconds; they are read once at startup when unset.
+    /// `/admin/reload-secrets` reads them again on demand either way.
+    pub secrets_reload_interval: Option<Duration>,
+    /// Jwts of an identity provider accepted on admin and api routes along with the static
+    /// tokens, enabled by `JWT_JWKS_URL`; needs a build with the `jwt` feature.
+    pub jwt: Option<JwtConfig>,
+    /// Requirements of the routes, read from `AUTH_POLICY`, see [`AuthPolicy`].
+    pub auth_policy: AuthPolicy,
+    /// Public base url of the server used in generated links, e.g. `https://views.example.dev`.
+    /// Derived from the `Host` header when unset.
+    pub public_url: Option<String>,
+    /// Origins browsers may call the json routes from, read from `CORS_ALLOWED_ORIGINS` as a comma
+    /// separated list, `*` allowing any; badge routes allow any origin regardless.
+    pub cors_allowed_origins: Vec<String>,
+    /// Proxy for requests to xata.io and shields.io; requests go out directly when unset.
+    pub proxy: Option<ProxyConfig>,
+    /// How long resolved upstream addresses are reused, read from `DNS_CACHE_TTL` in seconds.
+    pub dns_cache_ttl: Duration,
+    /// Requests handled at once, read from `MAX_CONCURRENT_REQUESTS`, defaults to 512; requests
+    /// beyond wait up to `shed_wait` for their turn before being shed.
+    pub max_concurrent_requests: usize,
+    /// How long requests beyond `max_concurrent_requests` wait, read from `SHED_WAIT_MS`,
+    /// defaults to 100ms.
+    pub shed_wait: Duration,
+    /// Seconds shed requests are asked to wait before retrying, read from `SHED_RETRY_AFTER`,
+    /// defaults to 5.
+    pub shed_retry_after: u64,
+    /// How long shields.io badge templates are served from the cache before being fetched again,
+    /// read from `BADGE_TEMPLATE_TTL` in seconds, defaults to 24h.
+    pub badge_template_ttl: Duration,
+    /// Whether badge colors and styles go to shields.io unchecked, i.e.
+    /// `BADGE_PARAMS_VALIDATION=permissive`; unknown ones get an error badge by default (`strict`).
+    pub permissive_badge_params: bool,
+    /// Whether HEAD requests on counter routes count a view, i.e. `HEAD_REQUESTS=count`; they
+    /// only return the headers by default (`skip`).
+    pub count_head_requests: bool,
+    /// Whether users must be onboarded through `POST /api/onboard` before their views are
+    /// counted, i.e. `ONBOARDING=explicit`; badges of unknown users read "not registered" then.
+    /// Users are onboarded by their first view by default (`implicit`). Tenant users are always
+    /// onboarded by their first view.
+    pub explicit_onboarding: bool,
+    /// Whether users are looked up on the GitHub api before being onboarded by their first
+    /// view, i.e. `USER_VERIFICATION=github`, so names which aren't GitHub users or organizations
+    /// get a "not registered" badge instead of a record. Users are onboarded unverified by
+    /// default (`none`). Lookups use the `GITHUB_TOKEN` if set, for its higher rate limit.
+    pub verify_github_users: bool,
+    /// Serving of views from local counts, the default (`COUNT_CONSISTENCY=optimistic`); `None`
+    /// with `COUNT_CONSISTENCY=strict`, which counts every view on the datastore before its badge
+    /// is served.
+    pub optimistic_counts: Option<OptimisticConfig>,
+    /// Views counted per user and day, read from `DAILY_VIEW_QUOTA`; views past it are served
+    /// but not counted. Unlimited when unset.
+    pub daily_view_quota: Option<u64>,
+    /// Views counted per second across all users; views past it are served from the views last
+    /// counted without reaching the datastore. Unlimited when `REQUEST_BUDGET` is unset.
+    pub request_budget: Option<BudgetConfig>,
+    /// Whether badges of views served past the daily quota or request budget are greyed out
+    /// and titled `(cached)`, i.e. `CACHED_BADGES=muted`; they look like live ones by default
+    /// (`live`).
+    pub mute_cached_badges: bool,
+    /// Directory of fonts the png and webp badges fall back to for glyphs the embedded fonts
+    /// lack, e.g. CJK ones, read from `RASTER_FONT_DIR`.
+    pub raster_font_dir: Option<String>,
+    /// Number of most viewed users whose badges are fetched on boot, read from
+    /// `CACHE_PRIMING_TOP_N`; nothing is primed when unset.
+    pub cache_priming_top_n: Option<usize>,
+    /// Entries each instance caches in memory, read from `CACHE_CAPACITY`; the least recently
+    /// used ones are evicted past it. Unbounded when unset.
+    pub cache_capacity: Option<usize>,
+    /// Change in percent within which trend badges stay grey, read from `TREND_THRESHOLD`,
+    /// defaults to 10; tenants and badges may override it.
+    pub trend_threshold: f64,
+    /// Fraction of requests whose metadata is sampled for operators, read from
+    /// `ANALYTICS_SAMPLE_RATE`, e.g. `0.01` for 1%; nothing is sampled when unset.
+    pub analytics_sample_rate: Option<f64>,
+    /// Network prefixes telling clients apart when hashing, sampling and splitting experiments,
+    /// read from `CLIENT_IPV4_PREFIX` and `CLIENT_IPV6_PREFIX`, default to /32 and /64 so a phone
+    /// rotating through its IPv6 addresses stays one client.
+    pub client_prefixes: SubnetPrefixes,
+    /// Countries whose viewers are served badges without anything about them being stored,
+    /// read from `BLOCKED_COUNTRIES` as ISO 3166-1 alpha-2 codes, e.g. `DE,FR`. Countries are
+    /// told by the `cf-ipcountry` header of Cloudflare or its CloudFront and Vercel equivalents.
+    pub blocked_countries: HashSet<String>,
+    /// Failed requests to xata.io kept in memory for `/admin/debug/requests`, read from
+    /// `XATA_CAPTURE_FAILED_REQUESTS`, e.g. `50`; nothing is captured when unset.
+    pub xata_capture_failed_requests: Option<usize>,
+    /// Flagging of users with unusual view spikes, enabled by `ANOMALY_DETECTION` set to `flag`
+    /// or `freeze`.
+    pub anomaly: Option<AnomalyConfig>,
+    /// Tenants by name, read from `TENANTS` as a json object, e.g.
+    /// `{"rustaceans": {"api_key": "...", "color": "orange"}}`; tenant routes 404 when unset.
+    pub tenants: HashMap<String, TenantConfig>,
+    /// Cache shared by all instances, `REDIS_URL` e.g. `redis://cache.internal:6379`; needs a
+    /// build with the `redis` feature. Each instance only caches in its own memory when unset.
+    pub shared_cache: Option<SharedCacheConfig>,
+    /// Broker every counted view is published to, enabled by `EVENTS_URL`.
+    pub events: Option<EventsConfig>,
+    /// Removal of users not viewed for a while, enabled by `RETENTION_DORMANT_MONTHS`.
+    pub retention: Option<RetentionConfig>,
+    /// Faults injected into every datastore operation, enabled by `DATASTORE_FAULT_LATENCY_MS`,
+    /// `DATASTORE_FAULT_ERROR_RATE` or `DATASTORE_FAULT_TIMEOUT_RATE`; for trying out how
+    /// fallbacks cope with a misbehaving datastore, never meant for production.
+    pub datastore_faults: Option<FaultConfig>,
+    /// Datastore counting views whenever xata.io fails, enabled by `FALLBACK_DATASTORE=memory`.
+    pub fallback: Option<FallbackConfig>,
+    /// Client settings for xata.io, read from `XATA_*` variables.
+    pub xata: UpstreamConfig,
+    /// Client settings for shields.io, read from `SHIELDS_*` variables.
+    pub shields: UpstreamConfig,
+    /// Client settings for the GitHub api, read from `GITHUB_*` variables.
+    pub github: UpstreamConfig,
+}
+
+#[derive(Clone, Debug)]
+pub struct UpstreamConfig {
+    /// `<PREFIX>_CONNECT_TIMEOUT_MS`, defaults to 5s.
+    pub connect_timeout: Duration,
+    /// Timeout of a whole request including reading the response, `<PREFIX>_TIMEOUT_MS`,
+    /// defaults to 5s.
+    pub timeout: Duration,
+    /// Idle connections kept open, `<PREFIX>_POOL_MAX_IDLE`, defaults to 5.
+    pub pool_max_idle: usize,
+    /// Retries of requests which failed to connect, `<PREFIX>_RETRIES`, defaults to 0. Nothing
+    /// reached the upstream in that case, so retrying is safe for increments too.
+    pub retries: u32,
+    /// Requests answered slower than this are logged as warnings and counted in
+    /// `upstream_slow_requests_total`, `<PREFIX>_SLOW_MS`; unset by default.
+    pub slow_threshold: Option<Duration>,
+    /// Headers sent along with every request, `<PREFIX>_HEADERS` as a json object, e.g.
+    /// `{"Authorization": "Bearer ..."}`; values are treated as secrets and never logged.
+    pub headers: HeaderMap,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnomalyConfig {
+    /// Users are flagged when their views over the last hour exceed their usual hourly views by
+    /// this factor, `ANOMALY_FACTOR`, defaults to 10.
+    pub factor: f64,
+    /// Hourly views below which users are never flagged, `ANOMALY_MIN_HOURLY_VIEWS`, defaults
+    /// to 100.
+    pub min_hourly_views: u64,
+    /// Whether flagged users stop counting views until their flag is cleared, i.e.
+    /// `ANOMALY_DETECTION=freeze`.
+    pub freeze: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct OptimisticConfig {
+    /// How often views served from local counts are written to the datastore,
+    /// `COUNT_FLUSH_INTERVAL` in seconds, defaults to 5.
+    pub flush_interval: Duration,
+    /// Age of a local count past which serving it refreshes it from the datastore in the
+    /// background, `COUNT_REVALIDATE_AFTER` in seconds, defaults to 30.
+    pub revalidate_after: Duration,
+    /// Age of a local count past which views are counted on the datastore before the badge is
+    /// served, `COUNT_MAX_STALENESS` in seconds, defaults to 300.
+    pub max_staleness: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct BudgetConfig {
+    /// Views counted per second on average, `REQUEST_BUDGET`
+    pub per_second: f64,
+    /// Views counted at once after a quiet period, `REQUEST_BUDGET_BURST`, defaults to a
+    /// second's worth
+    pub burst: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct RetentionConfig {
+    /// Users not viewed for this many months are removed, `RETENTION_DORMANT_MONTHS`
+    pub dormant_months: u32,
+    /// How often dormant users are looked for, `RETENTION_INTERVAL` in seconds, defaults to a
+    /// day
+    pub interval: Duration,
+    /// CSV file removed users are appended to before their removal, `RETENTION_ARCHIVE`; an
+    /// absolute path on a persistent volume, required along with `RETENTION_DORMANT_MONTHS`
+    pub archive: String,
+    /// Users never removed however long they weren't viewed, `RETENTION_ALLOWLIST` as a comma
+    /// separated list; tenant users are given as `<tenant>/<user>`
+    pub allowlist: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FallbackConfig {
+    /// How often views counted by the fallback are replayed on xata.io,
+    /// `FALLBACK_RECONCILE_INTERVAL` in seconds, defaults to 60.
+    pub reconcile_interval: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FaultConfig {
+    /// Latency added to every operation, `DATASTORE_FAULT_LATENCY_MS`
+    pub latency: Duration,
+    /// Share of operations failing right away, `DATASTORE_FAULT_ERROR_RATE`
+    pub error_rate: f64,
+    /// Share of operations failing only after hanging for `timeout`,
+    /// `DATASTORE_FAULT_TIMEOUT_RATE`
+    pub timeout_rate: f64,
+    /// How long timed out operations hang, `DATASTORE_FAULT_TIMEOUT_MS`, defaults to 10s
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TenantConfig {
+    /// Bearer token for the tenant's api routes
+    pub api_key: String,
+    /// Badge label used when the request doesn't give one
+    pub label: Option<String>,
+    /// Badge color used when the request doesn't give one
+    pub color: Option<String>,
+    /// Badge style used when the request doesn't give one
+    pub style: Option<String>,
+    /// Views counted per user and day within the tenant; unlimited when unset
+    pub daily_view_quota: Option<u64>,
+    /// Trend threshold of the tenant's badges in percent; defaults to the server's
+    pub trend_threshold: Option<f64>,
+    /// Custom domains serving the tenant's routes without the `/t/<tenant>` prefix, e.g.
+    /// `views.example.dev`
+    #[serde(default)]
+    pub domains: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SharedCacheConfig {
+    pub url: String,
+    /// Prepended to every key, `REDIS_KEY_PREFIX`, defaults to `profile-views:`; lets
+    /// deployments share a server
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub key_prefix: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct JwtConfig {
+    /// Keys of the identity provider, e.g. `https://idp.example.dev/.well-known/jwks.json`
+    #[cfg_attr(not(feature = "jwt"), allow(dead_code))]
+    pub jwks_url: String,
+    /// Required `iss` claim, `JWT_ISSUER`
+    #[cfg_attr(not(feature = "jwt"), allow(dead_code))]
+    pub issuer: String,
+    /// Required `aud` claim, `JWT_AUDIENCE`
+    #[cfg_attr(not(feature = "jwt"), allow(dead_code))]
+    pub audience: String,
+    /// Scope granting the admin routes, `JWT_ADMIN_SCOPE`, defaults to `views:admin`
+    pub admin_scope: String,
+    /// Scope granting the api routes, `JWT_API_SCOPE`, defaults to `views:api`
+    pub api_scope: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct EventsConfig {
+    /// `nats://host:4222` or `kafka://host:9092`; needs a build with the `nats` or `kafka`
+    /// feature
+    pub url: String,
+    /// NATS subject or kafka topic, `EVENTS_TOPIC`, defaults to `profile-views`
+    pub topic: String,
+}
+
+pub struct ProxyConfig {
+    /// Proxy url, e.g. `http://proxy.corp.example:3128`.
+    pub url: String,
+    /// Comma separated hosts, domains and ip ranges bypassing the proxy.
+    pub no_proxy: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        Config {
+            secrets: Arc::new(Secrets::from_env()),
+            secrets_reload_interval: std::env::var("SECRETS_RELOAD_INTERVAL")
+                .ok()
+                .and_then(|interval| interval.parse().ok())
+                .filter(|interval| *interval > 0)
+                .map(Duration::from_secs),
+            jwt: env_var_any(&["JWT_JWKS_URL"]).map(|jwks_url| JwtConfig {
+                jwks_url,
+                issuer: std::env::var("JWT_ISSUER").unwrap_or_default(),
+                audience: std::env::var("JWT_AUDIENCE").unwrap_or_default(),
+                admin_scope: env_var_any(&["JWT_ADMIN_SCOPE"])
+                    .unwrap_or_else(|| "views:admin".to_string()),
+                api_scope: env_var_any(&["JWT_API_SCOPE"])
+                    .unwrap_or_else(|| "views:api".to_string()),
+            }),
+            auth_policy: std::env::var("AUTH_POLICY")
+                .ok()
+                .and_then(|policy| AuthPolicy::parse(&policy).ok())
+                .unwrap_or_default(),
+            public_url: std::env::var("PUBLIC_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+            cors_allowed_origins: std::env::var("CORS_ALLOWED_ORIGINS")
+                .map(|origins| {
+                    origins
+                        .split(',')
+                        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                        .filter(|origin| !origin.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            proxy: env_var_any(&["HTTPS_PROXY", "https_proxy"]).map(|url| ProxyConfig {
+                url,
+                no_proxy: env_var_any(&["NO_PROXY", "no_proxy"]),
+            }),
+            dns_cache_ttl: Duration::from_secs(
+                std::env::var("DNS_CACHE_TTL")
+                    .ok()
+                    .and_then(|ttl| ttl.parse().ok())
+                    .unwrap_or(DEFAULT_DNS_CACHE_TTL),
+            ),
+            max_concurrent_requests: std::env::var("MAX_CONCURRENT_REQUESTS")
+                .ok()
+                .and_then(|limit| limit.parse().ok())
+                .filter(|limit| *limit > 0)
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS),
+            shed_wait: Duration::from_millis(
+                std::env::var("SHED_WAIT_MS")
+                    .ok()
+                    .and_then(|wait| wait.parse().ok())
+                    .unwrap_or(DEFAULT_SHED_WAIT_MS),
+            ),
+            shed_retry_after: std::env::var("SHED_RETRY_AFTER")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .unwrap_or(DEFAULT_SHED_RETRY_AFTER),
+            badge_template_ttl: Duration::from_secs(
+                std::env::var("BADGE_TEMPLATE_TTL")
+                    .ok()
+                    .and_then(|ttl| ttl.parse().ok())
+                    .unwrap_or(DEFAULT_BADGE_TEMPLATE_TTL),
+            ),
+            permissive_badge_params: std::env::var("BADGE_PARAMS_VALIDATION")
+                .is_ok_and(|validation| validation == "permissive"),
+            count_head_requests: std::env::var("HEAD_REQUESTS").is_ok_and(|head| head == "count"),
+            explicit_onboarding: std::env::var("ONBOARDING")
+                .is_ok_and(|onboarding| onboarding == "explicit"),
+            verify_github_users: std::env::var("USER_VERIFICATION")
+                .is_ok_and(|verification| verification == "github"),
+            optimistic_counts: OptimisticConfig::from_env(),
+            daily_view_quota: std::env::var("DAILY_VIEW_QUOTA")
+                .ok()
+                .and_then(|quota| quota.parse().ok())
+                .filter(|quota| *quota > 0),
+            request_budget: BudgetConfig::from_env(),
+            mute_cached_badges: std::env::var("CACHED_BADGES")
+                .is_ok_and(|badges| badges == "muted"),
+            raster_font_dir: env_var_any(&["RASTER_FONT_DIR"]),
+            cache_priming_top_n: std::env::var("CACHE_PRIMING_TOP_N")
+                .ok()
+                .and_then(|top_n| top_n.parse().ok())
+                .filter(|top_n| *top_n > 0),
+            cache_capacity: std::env::var("CACHE_CAPACITY")
+                .ok()
+                .and_then(|capacity| capacity.parse().ok())
+                .filter(|capacity| *capacity > 0),
+            trend_threshold: std::env::var("TREND_THRESHOLD")
+                .ok()
+                .and_then(|threshold| threshold.parse().ok())
+                .filter(|threshold: &f64| *threshold >= 0.0)
+                .unwrap_or(DEFAULT_TREND_THRESHOLD),
+            analytics_sample_rate: std::env::var("ANALYTICS_SAMPLE_RATE")
+                .ok()
+                .and_then(|rate| rate.parse().ok())
+                .filter(|rate: &f64| *rate > 0.0 && *rate <= 1.0),
+            client_prefixes: SubnetPrefixes {
+                ipv4: env_var_or("CLIENT", "IPV4_PREFIX", DEFAULT_IPV4_PREFIX).min(32),
+                ipv6: env_var_or("CLIENT", "IPV6_PREFIX", DEFAULT_IPV6_PREFIX).min(128),
+            },
+            blocked_countries: std::env::var("BLOCKED_COUNTRIES")
+                .map(|countries| {
+                    countries
+                        .split(',')
+                        .map(|country| country.trim().to_ascii_uppercase())
+                        .filter(|country| !country.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            xata_capture_failed_requests: std::env::var("XATA_CAPTURE_FAILED_REQUESTS")
+                .ok()
+                .and_then(|capacity| capacity.parse().ok())
+                .filter(|capacity| *capacity > 0),
+            anomaly: AnomalyConfig::from_env(),
+            tenants: tenants_from_env(),
+            shared_cache: env_var_any(&["REDIS_URL"]).map(|url| SharedCacheConfig {
+                url,
+                key_prefix: std::env::var("REDIS_KEY_PREFIX")
+                    .unwrap_or_else(|_| "profile-views:".to_string()),
+            }),
+            events: env_var_any(&["EVENTS_URL"]).map(|url| EventsConfig {
+                url,
+                topic: std::env::var("EVENTS_TOPIC")
+                    .unwrap_or_else(|_| "profile-views".to_string()),
+            }),
+            retention: RetentionConfig::from_env(),
+            datastore_faults: FaultConfig::from_env(),
+            fallback: FallbackConfig::from_env(),
+            xata: UpstreamConfig::from_env("XATA"),
+            shields: UpstreamConfig::from_env("SHIELDS"),
+            github: UpstreamConfig::from_env("GITHUB"),
+        }
+    }
+}
+
+impl OptimisticConfig {
+    fn from_env() -> Option<OptimisticConfig> {
+        if std::env::var("COUNT_CONSISTENCY").is_ok_and(|consistency| consistency == "strict") {
+            return None;
+        }
+
+        let seconds =
+            |name, default: u64| Duration::from_secs(env_var_or("COUNT", name, default).max(1));
+        Some(OptimisticConfig {
+            flush_interval: seconds("FLUSH_INTERVAL", 5),
+            revalidate_after: seconds("REVALIDATE_AFTER", 30),
+            max_staleness: seconds("MAX_STALENESS", 300),
+        })
+    }
+}
+
+impl BudgetConfig {
+    fn from_env() -> Option<BudgetConfig> {
+        let per_second: f64 = std::env::var("REQUEST_BUDGET")
+            .ok()
+            .and_then(|budget| budget.parse().ok())
+            .filter(|budget: &f64| *budget > 0.0)?;
+
+        Some(BudgetConfig {
+            per_second,
+            burst: env_var_or("REQUEST_BUDGET", "BURST", per_second).max(1.0),
+        })
+    }
+}
+
+impl FallbackConfig {
+    fn from_env() -> Option<FallbackConfig> {
+        if std::env::var("FALLBACK_DATASTORE").map_or(true, |fallback| fallback != "memory") {
+            return None;
+        }
+
+        Some(FallbackConfig {
+            reconcile_interval: Duration::from_secs(
+                env_var_or("FALLBACK", "RECONCILE_INTERVAL", 60).max(1),
+            ),
+        })
+    }
+}
+
+impl FaultConfig {
+    fn from_env() -> Option<FaultConfig> {
+        let rate = |name| env_var_or("DATASTORE_FAULT", name, 0.0_f64).clamp(0.0, 1.0);
+        let faults = FaultConfig {
+            latency: Duration::from_millis(env_var_or("DATASTORE_FAULT", "LATENCY_MS", 0)),
+            error_rate: rate("ERROR_RATE"),
+            timeout_rate: rate("TIMEOUT_RATE"),
+            timeout: Duration::from_millis(env_var_or("DATASTORE_FAULT", "TIMEOUT_MS", 10_000)),
+        };
+        let enabled =
+            !faults.latency.is_zero() || faults.error_rate > 0.0 || faults.timeout_rate > 0.0;
+        enabled.then_some(faults)
+    }
+}
+
+impl RetentionConfig {
+    fn from_env() -> Option<RetentionConfig> {
+        let dormant_months: u32 = std::env::var("RETENTION_DORMANT_MONTHS")
+            .ok()
+            .and_then(|months| months.parse().ok())
+            .filter(|months| *months > 0)?;
+
+        Some(RetentionConfig {
+            dormant_months,
+            interval: Duration::from_secs(
+                env_var_or("RETENTION", "INTERVAL", 24 * 60 * 60).max(60),
+            ),
+            archive: std::env::var("RETENTION_ARCHIVE").ok()?,
+            allowlist: std::env::var("RETENTION_ALLOWLIST")
+                .map(|users| {
+                    users
+                        .split(',')
+                        .map(|user| user.trim().to_string())
+                        .filter(|user| !user.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl AnomalyConfig {
+    fn from_env() -> Option<AnomalyConfig> {
+        let freeze = match std::env::var("ANOMALY_DETECTION").ok()?.as_str() {
+            "flag" => false,
+            "freeze" => true,
+            _ => return None,
+        };
+
+        Some(AnomalyConfig {
+            factor: env_var_or("ANOMALY", "FACTOR", 10.0),
+            min_hourly_views: env_var_or("ANOMALY", "MIN_HOURLY_VIEWS", 100),
+            freeze,
+        })
+    }
+}
+
+impl UpstreamConfig {
+    fn from_env(prefix: &str) -> UpstreamConfig {
+        UpstreamConfig {
+            connect_timeout: Duration::from_millis(env_var_or(prefix, "CONNECT_TIMEOUT_MS", 5000)),
+            timeout: Duration::from_millis(env_var_or(prefix, "TIMEOUT_MS", 5000)),
+            pool_max_idle: env_var_or(prefix, "POOL_MAX_IDLE", 5),
+            retries: env_var_or(prefix, "RETRIES", 0),
+            slow_threshold: std::env::var(format!("{}_SLOW_MS", prefix))
+                .ok()
+                .and_then(|threshold| threshold.parse().ok())
+                .filter(|threshold| *threshold > 0)
+                .map(Duration::from_millis),
+            headers: std::env::var(format!("{}_HEADERS", prefix))
+                .map(|headers| {
+                    parse_headers(&headers).unwrap_or_else(|err| {
+                        tracing::error!("invalid {}_HEADERS, none are sent: {}", prefix, err);
+                        HeaderMap::new()
+                    })
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Sends the request, retrying up to `retries` times while the upstream can't be connected to.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        let mut attempt = 0;
+        loop {
+            // requests with streaming bodies can't be cloned and are sent only once
+            let retry = match request.try_clone() {
+                Some(retry) if attempt < self.retries => retry,
+                _ => return request.send().await,
+            };
+
+            match retry.send().await {
+                Err(err) if err.is_connect() => {
+                    attempt += 1;
+                    tracing::warn!("failed to connect, attempt {}: {}", attempt, err);
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn tenants_from_env() -> HashMap<String, TenantConfig> {
+    let Ok(tenants) = std::env::var("TENANTS") else {
+        return HashMap::new();
+    };
+
+    match serde_json::from_str::<HashMap<String, TenantConfig>>(&tenants) {
+        Ok(tenants) => tenants
+            .into_iter()
+            .filter(|(name, _)| {
+                let valid = is_valid_tenant_name(name);
+                if !valid {
+                    tracing::error!("invalid tenant name `{}`, expected [a-z0-9-]+", name);
+                }
+                valid
+            })
+            .collect(),
+        Err(err) => {
+            tracing::error!("invalid TENANTS, tenant routes are disabled: {}", err);
+            HashMap::new()
+        }
+    }
+}
+
+// tenant names end up in urls and user keys
+fn is_valid_tenant_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Headers from a json object of names and values, marked sensitive so they're never logged.
+pub(crate) fn parse_headers(headers: &str) -> Result<HeaderMap, String> {
+    let headers: HashMap<String, String> =
+        serde_json::from_str(headers).map_err(|err| err.to_string())?;
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("`{}` is not a header name", name))?;
+            let mut value = HeaderValue::from_str(&value)
+                .map_err(|_| format!("the value of `{}` is not a header value", name))?;
+            value.set_sensitive(true);
+            Ok((name, value))
+        })
+        .collect()
+}
+
+fn env_var_or<T: std::str::FromStr>(prefix: &str, name: &str, default: T) -> T {
+    std::env::var(format!("{}_{}", prefix, name))
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn env_var_any(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .filter(|value| !value.is_empty())
+}
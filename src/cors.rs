@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use axum::http::{header, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+// how long browsers may reuse a preflight response
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// CORS for the json routes, allowing the origins of `CORS_ALLOWED_ORIGINS` only; `*` allows any
+/// origin. Browsers are denied cross-origin access when no origin is configured.
+pub fn json_layer(allowed_origins: &[String]) -> CorsLayer {
+    let allow_origin = match allowed_origins.iter().any(|origin| origin == "*") {
+        true => AllowOrigin::any(),
+        false => AllowOrigin::list(
+            allowed_origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        ),
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::HEAD, Method::POST])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+        .expose_headers([
+            header::HeaderName::from_static("ratelimit-limit"),
+            header::HeaderName::from_static("ratelimit-remaining"),
+            header::HeaderName::from_static("ratelimit-reset"),
+            header::HeaderName::from_static("x-count-freshness"),
+        ])
+        .max_age(PREFLIGHT_MAX_AGE)
+}
+
+/// CORS for the badge routes, which any page may embed or fetch.
+pub fn badge_layer() -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods([Method::GET, Method::HEAD])
+        .expose_headers([header::HeaderName::from_static("x-count-freshness")])
+        .max_age(PREFLIGHT_MAX_AGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    async fn allowed_origin(layer: CorsLayer, method: Method, origin: &str) -> Option<String> {
+        let router = Router::new().route("/", get(|| async { "42" }).layer(layer));
+        let mut request = Request::builder()
+            .method(method)
+            .uri("/")
+            .header(header::ORIGIN, origin);
+        if request.method_ref() == Some(&Method::OPTIONS) {
+            request = request.header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET");
+        }
+
+        let response = router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|origin| origin.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn it_allows_configured_origins_on_json_routes() {
+        let origins = ["https://dash.example.dev".to_string()];
+
+        assert_eq!(
+            allowed_origin(
+                json_layer(&origins),
+                Method::OPTIONS,
+                "https://dash.example.dev"
+            )
+            .await,
+            Some("https://dash.example.dev".to_string())
+        );
+        assert_eq!(
+            allowed_origin(
+                json_layer(&origins),
+                Method::GET,
+                "https://evil.example.dev"
+            )
+            .await,
+            None
+        );
+        assert_eq!(
+            allowed_origin(json_layer(&[]), Method::GET, "https://dash.example.dev").await,
+            None
+        );
+        assert_eq!(
+            allowed_origin(json_layer(&["*".to_string()]), Method::GET, "https://a.dev").await,
+            Some("*".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn it_allows_any_origin_on_badge_routes() {
+        assert_eq!(
+            allowed_origin(badge_layer(), Method::OPTIONS, "https://a.dev").await,
+            Some("*".to_string())
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+// shorter secrets can't be told apart from the words around them, e.g. a key `k` in `peak_views`,
+// and are only left out of sensitive headers
+const MIN_REDACTED_LEN: usize = 8;
+
+/// Request to a backend which failed, along with the response if one came back, in the spirit
+/// of a HAR entry. Secrets are replaced by placeholders such as `${XATA_API_KEY}`, so captures
+/// can be attached to bug reports as they are.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct CapturedRequest {
+    pub started_at: DateTime<Utc>,
+    /// Time until the response headers arrived or the request failed
+    pub duration_ms: u64,
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<CapturedHeader>,
+    pub body: String,
+    /// `None` when no response came back, e.g. on timeouts
+    pub response: Option<CapturedResponse>,
+    /// Why no response came back
+    pub error: Option<String>,
+    /// Command replaying the request, reading the secrets from variables of the same name
+    pub curl: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct CapturedResponse {
+    pub status: u16,
+    pub headers: Vec<CapturedHeader>,
+    pub body: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct CapturedHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Keeps the latest failed requests to a backend in memory; older ones are dropped first.
+pub struct RequestCapture {
+    capacity: usize,
+    requests: Mutex<VecDeque<CapturedRequest>>,
+}
+
+/// Replaces the values of the secrets by placeholders named after them.
+struct Redactor<'a> {
+    secrets: &'a [(&'a str, String)],
+}
+
+impl RequestCapture {
+    pub fn new(capacity: usize) -> RequestCapture {
+        RequestCapture {
+            capacity,
+            requests: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record(&self, request: CapturedRequest) {
+        let mut requests = self.requests.lock().unwrap();
+        if requests.len() >= self.capacity {
+            requests.pop_front();
+        }
+        requests.push_back(request);
+    }
+
+    /// Most recent failed requests, newest first.
+    pub fn requests(&self, limit: usize) -> Vec<CapturedRequest> {
+        let requests = self.requests.lock().unwrap();
+        requests.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+impl CapturedRequest {
+    /// Captures the request sent at `started_at`, leaving out the values of `secrets`, which are
+    /// given by name.
+    pub fn new(
+        request: &reqwest::Request,
+        started_at: DateTime<Utc>,
+        secrets: &[(&str, String)],
+    ) -> CapturedRequest {
+        let redactor = Redactor { secrets };
+        let method = request.method().to_string();
+        let url = redactor.redact(request.url().as_str());
+        let headers = redactor.headers(request.headers());
+        let body = redactor.redact(&String::from_utf8_lossy(
+            request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .unwrap_or_default(),
+        ));
+
+        let mut curl = format!("curl -X {} {}", method, redactor.quote(&url));
+        for header in &headers {
+            curl.push_str(&format!(
+                " -H {}",
+                redactor.quote(&format!("{}: {}", header.name, header.value))
+            ));
+        }
+        if !body.is_empty() {
+            curl.push_str(&format!(" --data-raw {}", redactor.quote(&body)));
+        }
+
+        CapturedRequest {
+            started_at,
+            duration_ms: 0,
+            method,
+            url,
+            headers,
+            body,
+            response: None,
+            error: None,
+            curl,
+        }
+    }
+
+    pub fn with_response(
+        mut self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &[u8],
+        secrets: &[(&str, String)],
+        elapsed: Duration,
+    ) -> CapturedRequest {
+        let redactor = Redactor { secrets };
+        self.duration_ms = elapsed.as_millis() as u64;
+        self.response = Some(CapturedResponse {
+            status: status.as_u16(),
+            headers: redactor.headers(headers),
+            body: redactor.redact(&String::from_utf8_lossy(body)),
+        });
+        self
+    }
+
+    pub fn with_error(
+        mut self,
+        error: &reqwest::Error,
+        secrets: &[(&str, String)],
+        elapsed: Duration,
+    ) -> CapturedRequest {
+        self.duration_ms = elapsed.as_millis() as u64;
+        self.error = Some(Redactor { secrets }.redact(&error.to_string()));
+        self
+    }
+}
+
+impl Redactor<'_> {
+    fn redact(&self, value: &str) -> String {
+        self.secrets
+            .iter()
+            .filter(|(_, secret)| secret.len() >= MIN_REDACTED_LEN)
+            .fold(value.to_string(), |value, (name, secret)| {
+                value.replace(secret.as_str(), &format!("${{{}}}", name))
+            })
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Vec<CapturedHeader> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let raw = String::from_utf8_lossy(value.as_bytes());
+                let redacted = self.redact(&raw);
+                CapturedHeader {
+                    name: name.to_string(),
+                    // sensitive values holding no known secret are left out altogether
+                    value: match value.is_sensitive() && redacted == raw {
+                        true => "[redacted]".to_string(),
+                        false => redacted,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Single quotes the value for a shell, leaving the placeholders of secrets to be expanded.
+    fn quote(&self, value: &str) -> String {
+        let quoted = format!("'{}'", value.replace('\'', r"'\''"));
+        self.secrets.iter().fold(quoted, |quoted, (name, _)| {
+            let placeholder = format!("${{{}}}", name);
+            quoted.replace(&placeholder, &format!("'\"{}\"'", placeholder))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use reqwest::header::{self, HeaderValue};
+
+    #[test]
+    fn it_redacts_secrets_and_replays_with_curl() {
+        let secrets = [
+            ("XATA_API_KEY", "xau_secret".to_string()),
+            ("ADMIN_TOKEN", "it".to_string()),
+        ];
+        let mut auth_header = HeaderValue::from_static("Bearer xau_secret");
+        auth_header.set_sensitive(true);
+        let request = reqwest::Client::new()
+            .post("https://ws.xata.sh/db/views:main/transaction")
+            .header(header::AUTHORIZATION, auth_header)
+            .body(r#"{"operations":[{"note":"it's"}]}"#)
+            .build()
+            .unwrap();
+        let started_at = Utc::now();
+
+        let captured = CapturedRequest::new(&request, started_at, &secrets).with_response(
+            StatusCode::BAD_REQUEST,
+            &HeaderMap::new(),
+            b"{\"message\":\"invalid key xau_secret\"}",
+            &secrets,
+            Duration::from_millis(42),
+        );
+        assert_eq!(
+            captured.headers,
+            vec![CapturedHeader {
+                name: "authorization".to_string(),
+                value: "Bearer ${XATA_API_KEY}".to_string(),
+            }]
+        );
+        assert_eq!(
+            captured.response,
+            Some(CapturedResponse {
+                status: 400,
+                headers: Vec::new(),
+                body: r#"{"message":"invalid key ${XATA_API_KEY}"}"#.to_string(),
+            })
+        );
+        assert_eq!(captured.duration_ms, 42);
+        assert_eq!(
+            captured.curl,
+            r#"curl -X POST 'https://ws.xata.sh/db/views:main/transaction' -H 'authorization: Bearer '"${XATA_API_KEY}"'' --data-raw '{"operations":[{"note":"it'\''s"}]}'"#
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_latest_requests() {
+        let capture = RequestCapture::new(2);
+        let request = reqwest::Client::new()
+            .get("http://localhost")
+            .build()
+            .unwrap();
+        for path in ["/1", "/2", "/3"] {
+            let mut captured = CapturedRequest::new(&request, Utc::now(), &[]);
+            captured.url = path.to_string();
+            capture.record(captured);
+        }
+
+        let urls: Vec<_> = capture
+            .requests(10)
+            .into_iter()
+            .map(|captured| captured.url)
+            .collect();
+        assert_eq!(urls, vec!["/3", "/2"]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use axum::async_trait;
+use tokio::time;
+
+use super::{
+    CapturedRequest, DatastoreError, DatastoreOperations, DatastoreUsage, DayStats, Flag,
+    Freshness, Increment, Op, Page, StoredUser, UserRecord, UserViews,
+};
+use crate::config::FaultConfig;
+use crate::shutdown::Shutdown;
+
+/// What an operation runs into before it reaches the inner datastore.
+enum Fault {
+    Error,
+    Timeout(Duration),
+}
+
+struct Faults {
+    config: Option<FaultConfig>,
+    rng: fastrand::Rng,
+}
+
+/// Injects latency, timeouts and errors into the operations of the inner datastore, so retries
+/// and fallbacks can be tried out against a misbehaving backend. Operations pass straight through
+/// while no faults are configured.
+pub struct Faulty<D: DatastoreOperations> {
+    inner: D,
+    faults: Mutex<Faults>,
+}
+
+impl<D: DatastoreOperations> Faulty<D> {
+    pub fn new(inner: D, faults: Option<FaultConfig>) -> Faulty<D> {
+        Faulty {
+            inner,
+            faults: Mutex::new(Faults {
+                config: faults,
+                rng: fastrand::Rng::new(),
+            }),
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_faults(&self, faults: Option<FaultConfig>) {
+        self.faults.lock().unwrap().config = faults;
+    }
+
+    async fn inject(&self) -> Result<(), DatastoreError> {
+        let (latency, fault) = {
+            let mut faults = self.faults.lock().unwrap();
+            let Faults { config, rng } = &mut *faults;
+            let Some(config) = config else {
+                return Ok(());
+            };
+            let draw = rng.f64();
+            let fault = if draw < config.error_rate {
+                Some(Fault::Error)
+            } else if draw < config.error_rate + config.timeout_rate {
+                Some(Fault::Timeout(config.timeout))
+            } else {
+                None
+            };
+            (config.latency, fault)
+        };
+
+        if !latency.is_zero() {
+            time::sleep(latency).await;
+        }
+        match fault {
+            None => Ok(()),
+            Some(Fault::Error) => Err(DatastoreError::Unexpected(
+                "injected datastore fault".to_string(),
+            )),
+            Some(Fault::Timeout(timeout)) => {
+                time::sleep(timeout).await;
+                Err(DatastoreError::Unexpected(format!(
+                    "injected datastore timeout after {:?}",
+                    timeout
+                )))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<D: DatastoreOperations> Shutdown for Faulty<D> {
+    async fn shutdown(&self) {
+        self.inner.shutdown().await
+    }
+}
+
+#[async_trait]
+impl<D: DatastoreOperations> DatastoreOperations for Faulty<D> {
+    async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        self.inject().await?;
+        self.inner.get_latest_views(user_name).await
+    }
+
+    async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        self.inject().await?;
+        self.inner.onboard_user(user_name).await
+    }
+
+    async fn register_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inject().await?;
+        self.inner.register_user(user_name).await
+    }
+
+    async fn increment_views(
+        &self,
+        increments: &[Increment],
+    ) -> Result<Vec<UserViews>, DatastoreError> {
+        self.inject().await?;
+        self.inner.increment_views(increments).await
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inject().await?;
+        self.inner.delete_user(user_name).await
+    }
+
+    async fn restore_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inject().await?;
+        self.inner.restore_user(user_name).await
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inject().await?;
+        self.inner.purge_user(user_name).await
+    }
+
+    async fn transaction(&self, ops: Vec<Op>) -> Result<Vec<Option<StoredUser>>, DatastoreError> {
+        self.inject().await?;
+        self.inner.transaction(ops).await
+    }
+
+    async fn scan(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.inject().await?;
+        self.inner.scan(cursor, limit).await
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.inject().await?;
+        self.inner.scan_prefix(prefix, cursor, limit).await
+    }
+
+    async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, DatastoreError> {
+        self.inject().await?;
+        self.inner.get_many(user_names).await
+    }
+
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, DatastoreError> {
+        self.inject().await?;
+        self.inner.get_user(user_name).await
+    }
+
+    async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, DatastoreError> {
+        self.inject().await?;
+        self.inner.get_peak_day_views(user_name).await
+    }
+
+    async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, DatastoreError> {
+        self.inject().await?;
+        self.inner.get_day_stats(user_name).await
+    }
+
+    async fn list_users(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserRecord>, DatastoreError> {
+        self.inject().await?;
+        self.inner.list_users(cursor, limit).await
+    }
+
+    async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, DatastoreError> {
+        self.inject().await?;
+        self.inner.top_users(limit).await
+    }
+
+    async fn put_flag(&self, flag: &Flag) -> Result<(), DatastoreError> {
+        self.inject().await?;
+        self.inner.put_flag(flag).await
+    }
+
+    async fn list_flags(&self) -> Result<Vec<Flag>, DatastoreError> {
+        self.inject().await?;
+        self.inner.list_flags().await
+    }
+
+    async fn delete_flag(&self, user_name: &str) -> Result<bool, DatastoreError> {
+        self.inject().await?;
+        self.inner.delete_flag(user_name).await
+    }
+
+    async fn warm_up(&self) {
+        self.inner.warm_up().await
+    }
+
+    async fn maintain(&self) {
+        self.inner.maintain().await
+    }
+
+    async fn pending_views(&self) -> u64 {
+        self.inner.pending_views().await
+    }
+
+    // local, so left alone by faults
+    async fn cached_views(&self, user_name: &str) -> Option<u64> {
+        self.inner.cached_views(user_name).await
+    }
+
+    async fn freshness(&self, user_name: &str) -> Freshness {
+        self.inner.freshness(user_name).await
+    }
+
+    fn usage(&self) -> Vec<DatastoreUsage> {
+        self.inner.usage()
+    }
+
+    fn captured_requests(&self, limit: usize) -> Vec<CapturedRequest> {
+        self.inner.captured_requests(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::datastore::Memory;
+
+    const TEST_USER_NAME: &str = "test-user";
+
+    fn faults(error_rate: f64, timeout_rate: f64) -> Option<FaultConfig> {
+        Some(FaultConfig {
+            error_rate,
+            timeout_rate,
+            timeout: Duration::from_millis(50),
+            ..FaultConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn it_passes_operations_through_without_faults() {
+        let faulty = Faulty::new(Memory::new(), None);
+
+        assert_eq!(faulty.onboard_user(TEST_USER_NAME).await.unwrap(), 1);
+        assert_eq!(faulty.get_latest_views(TEST_USER_NAME).await.unwrap(), 2);
+
+        faulty.set_faults(faults(0.0, 0.0));
+        assert_eq!(faulty.get_latest_views(TEST_USER_NAME).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_fails_operations_at_the_error_rate() {
+        let faulty = Faulty::new(Memory::new(), faults(1.0, 0.0));
+        assert!(matches!(
+            faulty.onboard_user(TEST_USER_NAME).await,
+            Err(DatastoreError::Unexpected(_))
+        ));
+
+        faulty.set_faults(faults(0.3, 0.0));
+        let mut failed = 0;
+        for _ in 0..2000 {
+            if faulty.get_user(TEST_USER_NAME).await.is_err() {
+                failed += 1;
+            }
+        }
+        assert!((450..750).contains(&failed), "{} failed", failed);
+    }
+
+    #[tokio::test]
+    async fn it_delays_operations_and_times_them_out() {
+        let faulty = Faulty::new(
+            Memory::new(),
+            Some(FaultConfig {
+                latency: Duration::from_millis(20),
+                ..FaultConfig::default()
+            }),
+        );
+        let started = Instant::now();
+        assert_eq!(faulty.onboard_user(TEST_USER_NAME).await.unwrap(), 1);
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        faulty.set_faults(faults(0.0, 1.0));
+        let started = Instant::now();
+        assert!(matches!(
+            faulty.get_latest_views(TEST_USER_NAME).await,
+            Err(DatastoreError::Unexpected(_))
+        ));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // timed out operations never reach the inner datastore
+        faulty.set_faults(None);
+        assert_eq!(faulty.get_latest_views(TEST_USER_NAME).await.unwrap(), 2);
+    }
+}
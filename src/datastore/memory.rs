@@ -0,0 +1,669 @@
+use std::cmp::Reverse;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::sync::RwLock;
+
+use super::{
+    DatastoreError, DatastoreOperations, DayStats, Fields, Flag, Increment, Op, Page, StoredUser,
+    UserRecord, UserViews, ViewsChange,
+};
+use crate::shutdown::Shutdown;
+
+#[derive(Clone)]
+struct Record {
+    views: u64,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    deleted_at: Option<DateTime<Utc>>,
+    // `None` for users registered ahead of their first view
+    last_viewed_at: Option<DateTime<Utc>>,
+    renamed_to: Option<String>,
+    day_stats: DayStats,
+}
+
+impl Record {
+    fn new(views: u64, now: DateTime<Utc>) -> Record {
+        Record {
+            views,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            last_viewed_at: (views > 0).then_some(now),
+            renamed_to: None,
+            day_stats: DayStats::new(now.date_naive(), views),
+        }
+    }
+
+    // renamed users are deleted as well, so tell them apart first
+    fn check_counted(&self, user_name: &str) -> Result<(), DatastoreError> {
+        match (&self.renamed_to, self.deleted_at) {
+            (Some(renamed_to), _) => Err(DatastoreError::UserRenamed(
+                user_name.to_string(),
+                renamed_to.clone(),
+            )),
+            (None, Some(_)) => Err(DatastoreError::UserDeleted(user_name.to_string())),
+            (None, None) => Ok(()),
+        }
+    }
+
+    fn add_views(&mut self, views: u64, now: DateTime<Utc>) {
+        self.day_stats = self.day_stats.start_day(now.date_naive());
+        self.views += views;
+        self.day_stats.day_views += views;
+        self.updated_at = now;
+        self.last_viewed_at = Some(now);
+    }
+
+    fn write(&mut self, fields: Fields, now: DateTime<Utc>) {
+        match fields.views {
+            Some(ViewsChange::Add(views)) => self.views += views,
+            Some(ViewsChange::Subtract(views)) => self.views = self.views.saturating_sub(views),
+            None => {}
+        }
+        if let Some(day_stats) = fields.day_stats {
+            self.day_stats = day_stats;
+        }
+        if let Some(last_viewed_at) = fields.last_viewed_at {
+            self.last_viewed_at = Some(last_viewed_at);
+        }
+        if let Some(deleted_at) = fields.deleted_at {
+            self.deleted_at = deleted_at;
+        }
+        if let Some(renamed_to) = fields.renamed_to {
+            self.renamed_to = Some(renamed_to);
+        }
+        self.updated_at = now;
+    }
+
+    fn to_stored_user(&self, user_name: &str) -> StoredUser {
+        StoredUser {
+            user_name: user_name.to_string(),
+            views: self.views,
+            day_stats: Some(self.day_stats.clone()),
+            deleted_at: self.deleted_at,
+            last_viewed_at: self.last_viewed_at,
+            renamed_to: self.renamed_to.clone(),
+        }
+    }
+}
+
+fn run_op(
+    records: &mut BTreeMap<String, Record>,
+    op: Op,
+    now: DateTime<Utc>,
+) -> Result<Option<StoredUser>, DatastoreError> {
+    match op {
+        Op::Get(user_name) => Ok(records
+            .get(&user_name)
+            .map(|record| record.to_stored_user(&user_name))),
+        Op::Insert(user_name, _) if records.contains_key(&user_name) => {
+            Err(DatastoreError::UserExists(user_name))
+        }
+        Op::Insert(user_name, fields) => {
+            let record = records
+                .entry(user_name.clone())
+                .or_insert_with(|| Record::new(0, now));
+            record.write(fields, now);
+            Ok(Some(record.to_stored_user(&user_name)))
+        }
+        Op::Update(user_name, fields) => match records.get_mut(&user_name) {
+            Some(record) => {
+                record.write(fields, now);
+                Ok(Some(record.to_stored_user(&user_name)))
+            }
+            None => Err(DatastoreError::UserNotFound(user_name)),
+        },
+    }
+}
+
+fn count_view(
+    records: &mut BTreeMap<String, Record>,
+    user_name: &str,
+) -> Result<u64, DatastoreError> {
+    match records.get_mut(user_name) {
+        Some(record) => {
+            record.check_counted(user_name)?;
+            record.add_views(1, Utc::now());
+            Ok(record.views)
+        }
+        None => Err(DatastoreError::UserNotFound(user_name.to_string())),
+    }
+}
+
+fn to_user_record(user_name: &str, record: &Record) -> UserRecord {
+    UserRecord {
+        user_name: user_name.to_string(),
+        views: record.views,
+        created_at: record.created_at,
+        updated_at: record.updated_at,
+        deleted_at: record.deleted_at,
+        last_viewed_at: record.last_viewed_at,
+        renamed_to: record.renamed_to.clone(),
+    }
+}
+
+/// Process local datastore; counts are lost when the server restarts.
+#[derive(Default)]
+pub struct Memory {
+    records: RwLock<BTreeMap<String, Record>>,
+    flags: RwLock<BTreeMap<String, Flag>>,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    async fn set_deleted_at(
+        &self,
+        user_name: &str,
+        deleted_at: Option<DateTime<Utc>>,
+    ) -> Result<(), DatastoreError> {
+        let mut records = self.records.write().await;
+        let record = records
+            .get_mut(user_name)
+            .ok_or_else(|| DatastoreError::UserNotFound(user_name.to_string()))?;
+
+        record.deleted_at = deleted_at;
+        record.updated_at = Utc::now();
+        Ok(())
+    }
+
+    async fn page<T>(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+        include_deleted: bool,
+        to_user: impl Fn(&str, &Record) -> T,
+    ) -> Page<T> {
+        let records = self.records.read().await;
+        let start = cursor.map_or(Bound::Included(prefix.to_string()), Bound::Excluded);
+
+        // one extra user tells whether there is a next page
+        let mut users: Vec<(&String, T)> = records
+            .range((start, Bound::Unbounded))
+            .take_while(|(user_name, _)| user_name.starts_with(prefix))
+            .filter(|(_, record)| include_deleted || record.deleted_at.is_none())
+            .take(limit + 1)
+            .map(|(user_name, record)| (user_name, to_user(user_name, record)))
+            .collect();
+
+        let next_cursor = match users.len() > limit {
+            true => {
+                users.truncate(limit);
+                users.last().map(|(user_name, _)| user_name.to_string())
+            }
+            false => None,
+        };
+
+        Page {
+            users: users.into_iter().map(|(_, user)| user).collect(),
+            next_cursor,
+        }
+    }
+}
+
+impl Shutdown for Memory {}
+
+#[async_trait]
+impl DatastoreOperations for Memory {
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let mut records = self.records.write().await;
+        count_view(&mut records, user_name)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let mut records = self.records.write().await;
+        // onboarded by a concurrent request in the meantime
+        if records.contains_key(user_name) {
+            return count_view(&mut records, user_name);
+        }
+
+        records.insert(user_name.to_string(), Record::new(1, Utc::now()));
+        Ok(1)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn register_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        let mut records = self.records.write().await;
+        if records.contains_key(user_name) {
+            return Err(DatastoreError::UserExists(user_name.to_string()));
+        }
+
+        records.insert(user_name.to_string(), Record::new(0, Utc::now()));
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", users = increments.len()))]
+    async fn increment_views(
+        &self,
+        increments: &[Increment],
+    ) -> Result<Vec<UserViews>, DatastoreError> {
+        let mut records = self.records.write().await;
+
+        // all users are checked before any is touched
+        for increment in increments {
+            match records.get(&increment.user_name) {
+                Some(record) => record.check_counted(&increment.user_name)?,
+                None => return Err(DatastoreError::UserNotFound(increment.user_name.clone())),
+            }
+        }
+
+        let now = Utc::now();
+        Ok(increments
+            .iter()
+            .filter_map(|increment| {
+                let record = records.get_mut(&increment.user_name)?;
+                record.add_views(increment.views, now);
+                Some(UserViews {
+                    user_name: increment.user_name.clone(),
+                    views: record.views,
+                })
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn delete_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.set_deleted_at(user_name, Some(Utc::now())).await
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn restore_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.set_deleted_at(user_name, None).await
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn purge_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.records.write().await.remove(user_name);
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", ops = ops.len()))]
+    async fn transaction(&self, ops: Vec<Op>) -> Result<Vec<Option<StoredUser>>, DatastoreError> {
+        let mut records = self.records.write().await;
+        // records as they were before the first operation on them, put back when one fails
+        let mut before: BTreeMap<String, Option<Record>> = BTreeMap::new();
+        let now = Utc::now();
+
+        let mut results = Vec::with_capacity(ops.len());
+        for op in ops {
+            let user_name = op.user_name().to_string();
+            if let Entry::Vacant(entry) = before.entry(user_name) {
+                let record = records.get(entry.key()).cloned();
+                entry.insert(record);
+            }
+
+            match run_op(&mut records, op, now) {
+                Ok(result) => results.push(result),
+                Err(err) => {
+                    for (user_name, record) in before {
+                        match record {
+                            Some(record) => records.insert(user_name, record),
+                            None => records.remove(&user_name),
+                        };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", limit = limit))]
+    async fn scan(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        Ok(self
+            .page("", cursor, limit, false, |user_name, record| UserViews {
+                user_name: user_name.to_string(),
+                views: record.views,
+            })
+            .await)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", prefix = prefix, limit = limit))]
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        Ok(self
+            .page(prefix, cursor, limit, false, |user_name, record| {
+                UserViews {
+                    user_name: user_name.to_string(),
+                    views: record.views,
+                }
+            })
+            .await)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", users = user_names.len()))]
+    async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, DatastoreError> {
+        let records = self.records.read().await;
+        Ok(user_names
+            .iter()
+            .filter_map(|user_name| {
+                let record = records
+                    .get(user_name)
+                    .filter(|record| record.deleted_at.is_none())?;
+                Some(UserViews {
+                    user_name: user_name.clone(),
+                    views: record.views,
+                })
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, DatastoreError> {
+        let records = self.records.read().await;
+        Ok(records
+            .get(user_name)
+            .map(|record| to_user_record(user_name, record)))
+    }
+
+    /// Views of the counted user, the memory being the backing service.
+    async fn cached_views(&self, user_name: &str) -> Option<u64> {
+        let records = self.records.read().await;
+        records
+            .get(user_name)
+            .filter(|record| record.check_counted(user_name).is_ok())
+            .map(|record| record.views)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, DatastoreError> {
+        let records = self.records.read().await;
+        match records.get(user_name) {
+            Some(record) if record.deleted_at.is_some() => {
+                Err(DatastoreError::UserDeleted(user_name.to_string()))
+            }
+            Some(record) => Ok(Some(
+                record.day_stats.peak_views.max(record.day_stats.day_views),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", user = user_name))]
+    async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, DatastoreError> {
+        let records = self.records.read().await;
+        match records.get(user_name) {
+            Some(record) if record.deleted_at.is_some() => {
+                Err(DatastoreError::UserDeleted(user_name.to_string()))
+            }
+            Some(record) => Ok(Some(record.day_stats.clone())),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", limit = limit))]
+    async fn list_users(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserRecord>, DatastoreError> {
+        Ok(self.page("", cursor, limit, true, to_user_record).await)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "memory", limit = limit))]
+    async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, DatastoreError> {
+        let records = self.records.read().await;
+
+        let mut users: Vec<UserViews> = records
+            .iter()
+            .filter(|(_, record)| record.deleted_at.is_none())
+            .map(|(user_name, record)| UserViews {
+                user_name: user_name.to_string(),
+                views: record.views,
+            })
+            .collect();
+        // user names are already sorted, the stable sort keeps them as tie-breaker
+        users.sort_by_key(|user| Reverse(user.views));
+        users.truncate(limit);
+
+        Ok(users)
+    }
+
+    async fn put_flag(&self, flag: &Flag) -> Result<(), DatastoreError> {
+        let mut flags = self.flags.write().await;
+        flags.insert(flag.user_name.clone(), flag.clone());
+        Ok(())
+    }
+
+    async fn list_flags(&self) -> Result<Vec<Flag>, DatastoreError> {
+        Ok(self.flags.read().await.values().cloned().collect())
+    }
+
+    async fn delete_flag(&self, user_name: &str) -> Result<bool, DatastoreError> {
+        Ok(self.flags.write().await.remove(user_name).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_counts_concurrent_onboardings_of_the_same_user() {
+        let memory = Memory::new();
+
+        let (first, second) =
+            tokio::join!(memory.onboard_user("alice"), memory.onboard_user("alice"));
+
+        let mut views = vec![first.unwrap(), second.unwrap()];
+        views.sort();
+        assert_eq!(views, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_onboard_deleted_user_again() {
+        let memory = Memory::new();
+        memory.onboard_user("alice").await.unwrap();
+        memory.delete_user("alice").await.unwrap();
+
+        assert!(matches!(
+            memory.onboard_user("alice").await,
+            Err(DatastoreError::UserDeleted(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_registers_users_without_views() {
+        let memory = Memory::new();
+        memory.register_user("alice").await.unwrap();
+
+        let registered = memory.get_user("alice").await.unwrap().unwrap();
+        assert_eq!((registered.views, registered.last_viewed_at), (0, None));
+        assert_eq!(memory.get_latest_views("alice").await.unwrap(), 1);
+        assert!(matches!(
+            memory.register_user("alice").await,
+            Err(DatastoreError::UserExists(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_merges_users_into_another() {
+        let memory = Memory::new();
+        memory.onboard_user("alice").await.unwrap();
+        memory.get_latest_views("alice").await.unwrap();
+        memory.onboard_user("bob").await.unwrap();
+        memory.onboard_user("carol").await.unwrap();
+        memory.delete_user("carol").await.unwrap();
+
+        let users = ["alice".to_string(), "bob".to_string()];
+        assert!(matches!(
+            memory.merge_users(&users, "carol").await,
+            Err(DatastoreError::UserDeleted(_))
+        ));
+        assert_eq!(memory.merge_users(&users, "alicia").await.unwrap(), 3);
+        assert_eq!(memory.get_peak_day_views("alicia").await.unwrap(), Some(3));
+        let day_stats = memory.get_day_stats("alicia").await.unwrap().unwrap();
+        assert_eq!(day_stats.streak(Utc::now().date_naive()), 1);
+        assert!(matches!(
+            memory.get_latest_views("alice").await,
+            Err(DatastoreError::UserRenamed(user_name, renamed_to))
+                if user_name == "alice" && renamed_to == "alicia"
+        ));
+        assert!(matches!(
+            memory.merge_users(&users[..1], "dave").await,
+            Err(DatastoreError::UserRenamed(_, _))
+        ));
+        assert_eq!(memory.get_latest_views("alicia").await.unwrap(), 4);
+        let users = memory.scan(None, 10).await.unwrap().users;
+        assert_eq!(
+            users,
+            vec![UserViews {
+                user_name: "alicia".to_string(),
+                views: 4
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_finishes_merges_which_failed_to_move_the_views() {
+        let memory = Memory::new();
+        memory.onboard_user("alice").await.unwrap();
+        memory.get_latest_views("alice").await.unwrap();
+        memory.onboard_user("bob").await.unwrap();
+        // renamed, but the views are still to be moved
+        let renamed = Fields {
+            deleted_at: Some(Some(Utc::now())),
+            renamed_to: Some("alicia".to_string()),
+            ..Fields::default()
+        };
+        memory
+            .transaction(vec![Op::Update("alice".to_string(), renamed)])
+            .await
+            .unwrap();
+
+        let users = ["alice".to_string()];
+        assert!(matches!(
+            memory.merge_users(&users, "bob").await,
+            Err(DatastoreError::UserRenamed(_, _))
+        ));
+        assert_eq!(memory.merge_users(&users, "alicia").await.unwrap(), 2);
+        assert_eq!(memory.get_user("alice").await.unwrap().unwrap().views, 0);
+        // merging again moves nothing
+        assert_eq!(memory.merge_users(&users, "alicia").await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_rolls_back_failed_transactions() {
+        let memory = Memory::new();
+        memory.onboard_user("alice").await.unwrap();
+
+        let add_views = Fields {
+            views: Some(ViewsChange::Add(2)),
+            ..Fields::default()
+        };
+        let result = memory
+            .transaction(vec![
+                Op::Update("alice".to_string(), add_views.clone()),
+                Op::Insert("bob".to_string(), add_views.clone()),
+                Op::Update("carol".to_string(), add_views),
+            ])
+            .await;
+
+        assert!(
+            matches!(result, Err(DatastoreError::UserNotFound(user_name)) if user_name == "carol")
+        );
+        let results = memory
+            .transaction(vec![
+                Op::Get("alice".to_string()),
+                Op::Get("bob".to_string()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(results[0].as_ref().map(|user| user.views), Some(1));
+        assert_eq!(results[1], None);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_peak_day_views() {
+        let memory = Memory::new();
+        memory.onboard_user("alice").await.unwrap();
+        let increment = Increment {
+            user_name: "alice".to_string(),
+            views: 4,
+        };
+        memory
+            .increment_views(std::slice::from_ref(&increment))
+            .await
+            .unwrap();
+        assert_eq!(memory.get_peak_day_views("alice").await.unwrap(), Some(5));
+
+        // the views of yesterday stay the peak until today beats them
+        let yesterday = Utc::now() - chrono::Duration::days(1);
+        let mut records = memory.records.write().await;
+        records.get_mut("alice").unwrap().day_stats.day = yesterday.date_naive();
+        drop(records);
+        memory.get_latest_views("alice").await.unwrap();
+        assert_eq!(memory.get_peak_day_views("alice").await.unwrap(), Some(5));
+        memory.increment_views(&[increment]).await.unwrap();
+        assert_eq!(memory.get_peak_day_views("alice").await.unwrap(), Some(5));
+        memory.get_latest_views("alice").await.unwrap();
+        assert_eq!(memory.get_peak_day_views("alice").await.unwrap(), Some(6));
+
+        assert_eq!(memory.get_peak_day_views("bob").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_streaks_of_days_with_views() {
+        let memory = Memory::new();
+        let today = Utc::now().date_naive();
+        let streak = |day_stats: Option<DayStats>| day_stats.unwrap().streak(today);
+        memory.onboard_user("alice").await.unwrap();
+        assert_eq!(streak(memory.get_day_stats("alice").await.unwrap()), 1);
+
+        // viewed on each of the four days up to yesterday
+        let yesterday = today.pred_opt().unwrap();
+        let mut records = memory.records.write().await;
+        records.get_mut("alice").unwrap().day_stats = DayStats {
+            day: yesterday,
+            day_views: 2,
+            peak_views: 2,
+            streak: 3,
+            recent_views: vec![1, 1, 1],
+        };
+        drop(records);
+        // the streak holds until today is over
+        assert_eq!(streak(memory.get_day_stats("alice").await.unwrap()), 4);
+        memory.get_latest_views("alice").await.unwrap();
+        let day_stats = memory.get_day_stats("alice").await.unwrap().unwrap();
+        assert_eq!(day_stats.day, today);
+        assert_eq!(day_stats.streak(today), 5);
+        assert_eq!(day_stats.streak(today.succ_opt().unwrap()), 5);
+        assert_eq!(day_stats.streak(today + chrono::Days::new(2)), 0);
+
+        memory.register_user("bob").await.unwrap();
+        assert_eq!(streak(memory.get_day_stats("bob").await.unwrap()), 0);
+        assert_eq!(memory.get_day_stats("carol").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn it_tracks_when_users_were_last_viewed() {
+        let memory = Memory::new();
+        let before = Utc::now();
+        memory.onboard_user("alice").await.unwrap();
+        let onboarded = memory.get_user("alice").await.unwrap().unwrap();
+        assert!(onboarded.last_viewed_at.unwrap() >= before);
+
+        memory.get_latest_views("alice").await.unwrap();
+        let viewed = memory.get_user("alice").await.unwrap().unwrap();
+        assert!(viewed.last_viewed_at >= onboarded.last_viewed_at);
+        assert_eq!(viewed.last_viewed_at, Some(viewed.updated_at));
+    }
+}
@@ -1,6 +1,24 @@
+pub use capture::{CapturedHeader, CapturedRequest, CapturedResponse};
+pub use faulty::Faulty as FaultyDatastore;
+pub use memory::Memory;
 pub use operations::Error as DatastoreError;
 pub use operations::Operations as DatastoreOperations;
+pub use operations::{
+    DayStats, Fields, Flag, Freshness, Increment, Op, Page, StoredUser, UserRecord, UserRecordPage,
+    UserViews, UserViewsPage, ViewsChange,
+};
+pub use optimistic::Optimistic as OptimisticDatastore;
+pub use shared::Shared as SharedDatastore;
+pub use tiered::Tiered as TieredDatastore;
+pub use usage::{DatastoreUsage, UsageBucket};
 pub use xata::Xata;
 
+mod capture;
+mod faulty;
+mod memory;
 mod operations;
+mod optimistic;
+mod shared;
+mod tiered;
+mod usage;
 mod xata;
@@ -1,9 +1,434 @@
+use std::iter;
+use std::ops::Range;
+
 use axum::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{CapturedRequest, DatastoreUsage};
+use crate::shutdown::Shutdown;
+
+/// Days before the one views were last counted on whose views are kept in [`DayStats`], enough
+/// to compare the last 30 days to the 30 before.
+pub const RECENT_DAYS: usize = 59;
 
+/// Datastore of the views; shut down with the rest of the state, see [`Shutdown`].
 #[async_trait]
-pub trait Operations {
+pub trait Operations: Shutdown {
     async fn get_latest_views(&self, user_name: &str) -> Result<u64, Error>;
+
+    /// Creates the user with their first view. When a concurrent request onboarded the user
+    /// first, the view is counted on top instead of failing.
     async fn onboard_user(&self, user_name: &str) -> Result<u64, Error>;
+
+    /// Creates the user without views, ahead of their first view; fails with
+    /// [`Error::UserExists`] for known users, deleted or not.
+    async fn register_user(&self, user_name: &str) -> Result<(), Error>;
+
+    /// Adds views to several onboarded users at once, returning their new views in the same
+    /// order. Either all users are incremented or none, e.g. when one is unknown or deleted.
+    async fn increment_views(&self, increments: &[Increment]) -> Result<Vec<UserViews>, Error>;
+
+    /// Soft deletes the user; deleted users are neither counted nor exported until restored.
+    async fn delete_user(&self, user_name: &str) -> Result<(), Error>;
+    async fn restore_user(&self, user_name: &str) -> Result<(), Error>;
+
+    /// Removes the user's record for good, deleted or not; unknown users are left as they are.
+    async fn purge_user(&self, user_name: &str) -> Result<(), Error>;
+
+    /// Runs the operations in order, all of them or none, returning the record each operation
+    /// left behind. Fails with [`Error::UserNotFound`] when updating an unknown user and with
+    /// [`Error::UserExists`] when inserting a known one.
+    async fn transaction(&self, ops: Vec<Op>) -> Result<Vec<Option<StoredUser>>, Error>;
+
+    /// Moves the views and day stats of the users over to `merged_into`, registering it when
+    /// unknown, and returns its views. The merged users are kept as deleted aliases: counting
+    /// them fails with [`Error::UserRenamed`] until they're purged. They're renamed before
+    /// their views move, so views counted on them until then move along; merges failing in
+    /// between are finished by merging the users into `merged_into` again.
+    async fn merge_users(&self, user_names: &[String], merged_into: &str) -> Result<u64, Error> {
+        let lookups = user_names
+            .iter()
+            .map(String::as_str)
+            .chain([merged_into])
+            .map(|user_name| Op::Get(user_name.to_string()))
+            .collect();
+        let mut users = self.transaction(lookups).await?;
+        let target = users.pop().flatten();
+        for (user_name, user) in user_names.iter().zip(&users) {
+            let user = user
+                .as_ref()
+                .ok_or_else(|| Error::UserNotFound(user_name.clone()))?;
+            // renamed by a merge which failed to move their views
+            if user.renamed_to.as_deref() != Some(merged_into) {
+                user.check_counted()?;
+            }
+        }
+        if let Some(target) = &target {
+            target.check_counted()?;
+        }
+
+        let now = Utc::now();
+        let renames = user_names
+            .iter()
+            .map(|user_name| {
+                Op::Update(
+                    user_name.clone(),
+                    Fields {
+                        deleted_at: Some(Some(now)),
+                        renamed_to: Some(merged_into.to_string()),
+                        ..Fields::default()
+                    },
+                )
+            })
+            .collect();
+        let renamed = self.transaction(renames).await?;
+
+        let today = now.date_naive();
+        let mut day_stats = target
+            .as_ref()
+            .map_or(DayStats::new(today, 0), |target| target.day_stats_on(today));
+        let mut last_viewed_at = target.as_ref().and_then(|target| target.last_viewed_at);
+        let mut views = 0;
+        let mut moves = Vec::with_capacity(user_names.len());
+        for (user_name, user) in user_names.iter().zip(renamed) {
+            let user = user.ok_or_else(|| {
+                Error::Unexpected(format!("renamed user `{}` is gone", user_name))
+            })?;
+            views += user.views;
+            day_stats.merge(&user.day_stats_on(today));
+            last_viewed_at = last_viewed_at.max(user.last_viewed_at);
+            // views counted after renaming stay for the next merge to move
+            moves.push(Op::Update(
+                user_name.clone(),
+                Fields {
+                    views: Some(ViewsChange::Subtract(user.views)),
+                    day_stats: Some(DayStats::new(today, 0)),
+                    ..Fields::default()
+                },
+            ));
+        }
+
+        let fields = Fields {
+            views: Some(ViewsChange::Add(views)),
+            day_stats: Some(day_stats),
+            last_viewed_at,
+            ..Fields::default()
+        };
+        let mut ops = vec![match target {
+            Some(_) => Op::Update(merged_into.to_string(), fields),
+            None => Op::Insert(merged_into.to_string(), fields),
+        }];
+        ops.extend(moves);
+
+        let merged = self.transaction(ops).await?.swap_remove(0);
+        merged
+            .map(|merged| merged.views)
+            .ok_or_else(|| Error::Unexpected(format!("merged user `{}` is gone", merged_into)))
+    }
+
+    /// Returns up to `limit` users starting after `cursor`; `None` starts from the beginning.
+    async fn scan(&self, cursor: Option<String>, limit: usize) -> Result<Page<UserViews>, Error>;
+
+    /// Same as [`Operations::scan`], limited to users whose name starts with `prefix`.
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, Error>;
+
+    /// Views of the given users in the same order; unknown and deleted users are left out.
+    async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, Error>;
+
+    /// Looks a single user up without counting a view; deleted users are returned as well.
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, Error>;
+
+    /// Most views the user got on a single UTC day, today included; `None` for unknown users.
+    async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, Error>;
+
+    /// Day stats of the user without counting a view; `None` for unknown users and users
+    /// without views since onboarding.
+    async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, Error>;
+
+    /// Same as [`Operations::scan`], additionally returning when users were created and updated.
+    async fn list_users(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserRecord>, Error>;
+
+    /// Returns the `limit` most viewed users, most views first; deleted users are left out.
+    async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, Error>;
+
+    /// Stores the flag in the `flags` table, replacing the user's current flag.
+    async fn put_flag(&self, flag: &Flag) -> Result<(), Error>;
+
+    /// Every flag, in user name order.
+    async fn list_flags(&self) -> Result<Vec<Flag>, Error>;
+
+    /// Removes the user's flag, `false` when the user wasn't flagged.
+    async fn delete_flag(&self, user_name: &str) -> Result<bool, Error>;
+
+    /// Resolves and connects to the backing service ahead of the first request.
+    async fn warm_up(&self) {}
+
+    /// Writes views held in memory to the backing service at regular intervals, for as long as
+    /// the process runs; returns right away for datastores holding none.
+    async fn maintain(&self) {}
+
+    /// Views counted but yet to be written to the backing service.
+    async fn pending_views(&self) -> u64 {
+        0
+    }
+
+    /// Views last served for the user which are known without asking the backing service, e.g.
+    /// local counts; `None` when only the backing service knows them. Doesn't count a view.
+    async fn cached_views(&self, _user_name: &str) -> Option<u64> {
+        None
+    }
+
+    /// Whether the views served for the user are the backing service's, or include views it
+    /// has yet to store; never `Cached`, which only handlers tell.
+    async fn freshness(&self, _user_name: &str) -> Freshness {
+        Freshness::Live
+    }
+
+    /// Operations sent to metered backends; empty for backends which don't bill by operation.
+    fn usage(&self) -> Vec<DatastoreUsage> {
+        Vec::new()
+    }
+
+    /// Latest failed requests to the backing service, newest first; empty unless captured.
+    fn captured_requests(&self, _limit: usize) -> Vec<CapturedRequest> {
+        Vec::new()
+    }
+}
+
+/// Where the views served for a user come from, reported in the `X-Count-Freshness` header.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Freshness {
+    /// Counted on and stored by the datastore
+    Live,
+    /// Counted earlier and served as they were, without counting the view
+    Cached,
+    /// Counted, along with views the datastore has yet to store, e.g. local counts of
+    /// `COUNT_CONSISTENCY=optimistic` or views counted by a fallback datastore
+    Approximate,
+}
+
+impl Freshness {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Freshness::Live => "live",
+            Freshness::Cached => "cached",
+            Freshness::Approximate => "approximate",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Increment {
+    pub user_name: String,
+    pub views: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct UserViews {
+    pub user_name: String,
+    pub views: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct UserRecord {
+    pub user_name: String,
+    pub views: u64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// When the last view was counted; `None` for users not viewed since it's been tracked
+    pub last_viewed_at: Option<DateTime<Utc>>,
+    /// User the views were moved to, for users renamed or merged into another one
+    pub renamed_to: Option<String>,
+}
+
+/// User flagged for unusual views, see [`AnomalyDetector`](crate::anomaly::AnomalyDetector).
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct Flag {
+    pub user_name: String,
+    pub flagged_at: DateTime<Utc>,
+    /// Views counted over the last hour when flagged
+    pub hourly_views: u64,
+    /// Usual views per hour when flagged
+    pub baseline: f64,
+    /// Total views when flagged; served while the user is frozen
+    pub views: u64,
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+#[aliases(UserRecordPage = Page<UserRecord>, UserViewsPage = Page<UserViews>)]
+pub struct Page<T> {
+    pub users: Vec<T>,
+    /// Cursor to fetch the next page with, `None` once all users have been scanned.
+    pub next_cursor: Option<String>,
+}
+
+/// Operation of a [`Operations::transaction`] on the record of a user.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    /// Looks the record up, deleted or not
+    Get(String),
+    /// Creates the record with the fields, the views being its only views
+    Insert(String, Fields),
+    /// Writes the fields of an existing record
+    Update(String, Fields),
+}
+
+impl Op {
+    pub fn user_name(&self) -> &str {
+        match self {
+            Op::Get(user_name) | Op::Insert(user_name, _) | Op::Update(user_name, _) => user_name,
+        }
+    }
+}
+
+/// Fields an operation writes; `None` leaves the field as it is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Fields {
+    pub views: Option<ViewsChange>,
+    pub day_stats: Option<DayStats>,
+    pub last_viewed_at: Option<DateTime<Utc>>,
+    /// `Some(None)` restores the user
+    pub deleted_at: Option<Option<DateTime<Utc>>>,
+    pub renamed_to: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ViewsChange {
+    Add(u64),
+    /// Takes the views away, down to none at most
+    Subtract(u64),
+}
+
+/// Views of the day views were last counted on and of the days before it, the most views of any
+/// earlier day and the streak of days with views leading up to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DayStats {
+    pub day: NaiveDate,
+    pub day_views: u64,
+    pub peak_views: u64,
+    /// Consecutive days with views up to the day before `day`
+    pub streak: u64,
+    /// Views of the [`RECENT_DAYS`] before `day`, the day before first
+    pub recent_views: Vec<u64>,
+}
+
+impl DayStats {
+    /// Stats of a user whose first views were counted on the day.
+    pub fn new(day: NaiveDate, day_views: u64) -> DayStats {
+        DayStats {
+            day,
+            day_views,
+            peak_views: 0,
+            streak: 0,
+            recent_views: Vec::new(),
+        }
+    }
+
+    /// Stats once `today` starts: the views of the day they were last counted on move to the
+    /// recent views, become the peak when they beat it and extend the streak, which breaks after
+    /// a day without views.
+    pub fn start_day(&self, today: NaiveDate) -> DayStats {
+        if self.day >= today {
+            return self.clone();
+        }
+        let extends_streak = self.day_views > 0 && self.day.succ_opt() == Some(today);
+        // days between the two went without views
+        let skipped_days = (today - self.day).num_days() as usize - 1;
+        let recent_views = iter::repeat_n(0, skipped_days.min(RECENT_DAYS))
+            .chain(iter::once(self.day_views))
+            .chain(self.recent_views.iter().copied())
+            .take(RECENT_DAYS)
+            .collect();
+        DayStats {
+            day: today,
+            day_views: 0,
+            peak_views: self.peak_views.max(self.day_views),
+            streak: match extends_streak {
+                true => self.streak + 1,
+                false => 0,
+            },
+            recent_views,
+        }
+    }
+
+    /// Views of the days the given number of days before today, today being 0 days ago; days
+    /// past [`RECENT_DAYS`] are left out.
+    pub fn views_between(&self, today: NaiveDate, days_ago: Range<usize>) -> u64 {
+        let stats = self.start_day(today);
+        iter::once(stats.day_views)
+            .chain(stats.recent_views)
+            .skip(days_ago.start)
+            .take(days_ago.len())
+            .sum()
+    }
+
+    /// Adds the views of a user's stats on the same day, keeping the longest streak.
+    fn merge(&mut self, other: &DayStats) {
+        self.day_views += other.day_views;
+        self.peak_views = self.peak_views.max(other.peak_views);
+        // days with views of several users count once
+        self.streak = self.streak.max(other.streak);
+        if self.recent_views.len() < other.recent_views.len() {
+            self.recent_views.resize(other.recent_views.len(), 0);
+        }
+        for (views, other_views) in self.recent_views.iter_mut().zip(&other.recent_views) {
+            *views += other_views;
+        }
+    }
+
+    /// Consecutive days with views up to today, or up to yesterday while today has none yet, so
+    /// a streak doesn't break before the day is over.
+    pub fn streak(&self, today: NaiveDate) -> u64 {
+        let stats = self.start_day(today);
+        stats.streak + u64::from(stats.day_views > 0)
+    }
+}
+
+/// Record of a user as operations of a transaction see it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredUser {
+    pub user_name: String,
+    pub views: u64,
+    /// `None` until the first views after onboarding
+    pub day_stats: Option<DayStats>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub last_viewed_at: Option<DateTime<Utc>>,
+    pub renamed_to: Option<String>,
+}
+
+impl StoredUser {
+    /// Fails for users whose views aren't counted; renamed users are deleted as well, so
+    /// they're told apart first.
+    pub fn check_counted(&self) -> Result<(), Error> {
+        match (&self.renamed_to, self.deleted_at) {
+            (Some(renamed_to), _) => Err(Error::UserRenamed(
+                self.user_name.clone(),
+                renamed_to.clone(),
+            )),
+            (None, Some(_)) => Err(Error::UserDeleted(self.user_name.clone())),
+            (None, None) => Ok(()),
+        }
+    }
+
+    /// Day stats as of today, without views today unless they were counted already.
+    pub fn day_stats_on(&self, today: NaiveDate) -> DayStats {
+        self.day_stats
+            .as_ref()
+            .map_or(DayStats::new(today, 0), |stats| stats.start_day(today))
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -14,6 +439,98 @@ pub enum Error {
     #[error("user `{0}` not found")]
     UserNotFound(String),
 
+    #[error("user `{0}` is deleted")]
+    UserDeleted(String),
+
+    #[error("user `{0}` already exists")]
+    UserExists(String),
+
+    #[error("user `{0}` is renamed to `{1}`")]
+    UserRenamed(String, String),
+
     #[error("unexpected error: {0}")]
     Unexpected(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap()
+    }
+
+    #[test]
+    fn it_moves_views_of_past_days_to_the_recent_views() {
+        let stats = DayStats {
+            day: day(10),
+            day_views: 3,
+            peak_views: 5,
+            streak: 2,
+            recent_views: vec![2, 5],
+        };
+
+        assert_eq!(stats.start_day(day(10)), stats);
+        assert_eq!(
+            stats.start_day(day(13)),
+            DayStats {
+                day: day(13),
+                day_views: 0,
+                peak_views: 5,
+                streak: 0,
+                recent_views: vec![0, 0, 3, 2, 5],
+            }
+        );
+        let later = stats.start_day(day(10) + chrono::Days::new(100));
+        assert_eq!(later.recent_views, vec![0; RECENT_DAYS]);
+    }
+
+    #[test]
+    fn it_sums_views_between_days() {
+        let stats = DayStats {
+            day: day(10),
+            day_views: 3,
+            peak_views: 5,
+            streak: 2,
+            recent_views: vec![2, 5, 1],
+        };
+
+        assert_eq!(stats.views_between(day(10), 0..1), 3);
+        assert_eq!(stats.views_between(day(10), 0..7), 11);
+        assert_eq!(stats.views_between(day(10), 1..3), 7);
+        // the views of the 10th are 2 days old on the 12th
+        assert_eq!(stats.views_between(day(12), 0..3), 3);
+        assert_eq!(stats.views_between(day(12), 0..7), 11);
+        assert_eq!(stats.views_between(day(30), 0..7), 0);
+    }
+
+    #[test]
+    fn it_merges_day_stats() {
+        let mut stats = DayStats {
+            day: day(10),
+            day_views: 3,
+            peak_views: 5,
+            streak: 2,
+            recent_views: vec![2],
+        };
+        stats.merge(&DayStats {
+            day: day(10),
+            day_views: 1,
+            peak_views: 7,
+            streak: 1,
+            recent_views: vec![1, 4],
+        });
+
+        assert_eq!(
+            stats,
+            DayStats {
+                day: day(10),
+                day_views: 4,
+                peak_views: 7,
+                streak: 2,
+                recent_views: vec![3, 4],
+            }
+        );
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use axum::async_trait;
+use tokio::sync::{Mutex, Notify};
+use tokio::time;
+
+use super::{
+    CapturedRequest, DatastoreError, DatastoreOperations, DatastoreUsage, DayStats, Flag,
+    Freshness, Increment, Op, Page, StoredUser, UserRecord, UserViews,
+};
+use crate::config::OptimisticConfig;
+use crate::shutdown::Shutdown;
+
+// users incremented per transaction while flushing
+const FLUSH_BATCH_SIZE: usize = 100;
+
+struct LocalCount {
+    /// Views last reported by the datastore
+    known: u64,
+    /// Views served since, yet to be written to the datastore
+    pending: u64,
+    /// When the datastore last reported the views
+    synced_at: Instant,
+    /// Whether a flush ahead of the interval was asked for to revalidate the count
+    revalidate: bool,
+}
+
+impl LocalCount {
+    fn new(known: u64) -> LocalCount {
+        LocalCount {
+            known,
+            pending: 0,
+            synced_at: Instant::now(),
+            revalidate: false,
+        }
+    }
+
+    fn take_pending(&mut self, user_name: &str) -> Increment {
+        let views = std::mem::take(&mut self.pending);
+        // served views stay the same until the datastore reports the total
+        self.known += views;
+        Increment {
+            user_name: user_name.to_string(),
+            views,
+        }
+    }
+
+    fn synced(&mut self, views: u64) {
+        self.known = views;
+        self.synced_at = Instant::now();
+        self.revalidate = false;
+    }
+}
+
+/// Serves views of users seen before from a local count right away and writes them to the inner
+/// datastore in batches by [`Optimistic::flush`]. Counts served past `revalidate_after` are
+/// flushed ahead of the interval, and views are counted on the datastore before serving once a
+/// count is older than `max_staleness`. Views not flushed yet are lost when the process dies.
+pub struct Optimistic<D: DatastoreOperations> {
+    inner: D,
+    config: OptimisticConfig,
+    counts: Mutex<HashMap<String, LocalCount>>,
+    // wakes the flush loop ahead of its interval to revalidate stale counts
+    revalidate: Notify,
+}
+
+impl<D> Optimistic<D>
+where
+    D: DatastoreOperations,
+{
+    pub fn new(inner: D, config: &OptimisticConfig) -> Optimistic<D> {
+        Optimistic {
+            inner,
+            config: config.clone(),
+            counts: Mutex::new(HashMap::new()),
+            revalidate: Notify::new(),
+        }
+    }
+
+    async fn remember(&self, user_name: &str, views: u64) {
+        let mut counts = self.counts.lock().await;
+        // a concurrent request may have counted on top already
+        counts
+            .entry(user_name.to_string())
+            .or_insert_with(|| LocalCount::new(views));
+    }
+
+    /// Counts the view of a user whose local count is too stale to be served on the datastore,
+    /// along with the views pending; the stale count is served when the datastore fails.
+    async fn count_stale(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let increment = {
+            let mut counts = self.counts.lock().await;
+            match counts.get_mut(user_name) {
+                Some(count) => {
+                    count.pending += 1;
+                    count.take_pending(user_name)
+                }
+                None => return Err(DatastoreError::UserNotFound(user_name.to_string())),
+            }
+        };
+
+        let result = self.write(std::slice::from_ref(&increment)).await;
+        let counts = self.counts.lock().await;
+        match (result, counts.get(user_name)) {
+            (_, Some(count)) => Ok(count.known + count.pending),
+            (Err(err), None) => Err(err),
+            // deleted on this instance while counting
+            (Ok(()), None) => Err(DatastoreError::UserDeleted(user_name.to_string())),
+        }
+    }
+
+    /// Writes pending views, syncing the local counts with the totals of the datastore. Views
+    /// that fail to be written stay pending for the next flush, except for users deleted or gone
+    /// in the meantime, which are forgotten along with users not viewed for `max_staleness`.
+    pub async fn flush(&self) {
+        let increments: Vec<Increment> = {
+            let mut counts = self.counts.lock().await;
+            let max_staleness = self.config.max_staleness;
+            counts
+                .retain(|_, count| count.pending > 0 || count.synced_at.elapsed() < max_staleness);
+
+            counts
+                .iter_mut()
+                .filter(|(_, count)| count.pending > 0)
+                .map(|(user_name, count)| count.take_pending(user_name))
+                .collect()
+        };
+
+        for batch in increments.chunks(FLUSH_BATCH_SIZE) {
+            if let Err(err) = self.write(batch).await {
+                tracing::error!(
+                    "failed to flush views of {} user(s), reason: {}",
+                    batch.len(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Adds the views to the inner datastore and syncs the local counts with the totals.
+    async fn write(&self, increments: &[Increment]) -> Result<(), DatastoreError> {
+        let result = self.inner.increment_views(increments).await;
+        let mut counts = self.counts.lock().await;
+
+        match result {
+            Ok(users) => {
+                for user in users {
+                    if let Some(count) = counts.get_mut(&user.user_name) {
+                        count.synced(user.views);
+                    }
+                }
+                tracing::info!("flushed views of {} user(s)", increments.len());
+                Ok(())
+            }
+            Err(err) => {
+                let gone = match &err {
+                    DatastoreError::UserNotFound(user_name)
+                    | DatastoreError::UserDeleted(user_name)
+                    | DatastoreError::UserRenamed(user_name, _) => Some(user_name),
+                    _ => None,
+                };
+
+                for increment in increments {
+                    if Some(&increment.user_name) == gone {
+                        counts.remove(&increment.user_name);
+                    } else if let Some(count) = counts.get_mut(&increment.user_name) {
+                        count.known -= increment.views;
+                        count.pending += increment.views;
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn flush_loop(&self) {
+        let mut interval = time::interval(self.config.flush_interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = self.revalidate.notified() => {}
+            }
+            self.flush().await;
+        }
+    }
+}
+
+#[async_trait]
+impl<D> Shutdown for Optimistic<D>
+where
+    D: DatastoreOperations,
+{
+    /// Flushes the views served from local counts before the inner datastore shuts down.
+    async fn shutdown(&self) {
+        self.flush().await;
+        self.inner.shutdown().await
+    }
+}
+
+#[async_trait]
+impl<D> DatastoreOperations for Optimistic<D>
+where
+    D: DatastoreOperations + Send + Sync,
+{
+    async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let stale = {
+            let mut counts = self.counts.lock().await;
+            match counts.get_mut(user_name) {
+                Some(count) if count.synced_at.elapsed() < self.config.max_staleness => {
+                    count.pending += 1;
+                    if count.synced_at.elapsed() >= self.config.revalidate_after
+                        && !count.revalidate
+                    {
+                        count.revalidate = true;
+                        self.revalidate.notify_one();
+                    }
+                    return Ok(count.known + count.pending);
+                }
+                Some(_) => true,
+                None => false,
+            }
+        };
+        if stale {
+            return self.count_stale(user_name).await;
+        }
+
+        // first view of the user on this instance, served on top of views known to the inner
+        // datastore without asking it, e.g. counted by other instances, and revalidated ahead of
+        // the interval
+        if let Some(known) = self.inner.cached_views(user_name).await {
+            let mut counts = self.counts.lock().await;
+            let count = counts.entry(user_name.to_string()).or_insert_with(|| {
+                let mut count = LocalCount::new(known);
+                count.revalidate = true;
+                self.revalidate.notify_one();
+                count
+            });
+            count.pending += 1;
+            return Ok(count.known + count.pending);
+        }
+
+        // which also tells unknown and deleted users
+        let views = self.inner.get_latest_views(user_name).await?;
+        self.remember(user_name, views).await;
+        Ok(views)
+    }
+    async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let views = self.inner.onboard_user(user_name).await?;
+        self.remember(user_name, views).await;
+        Ok(views)
+    }
+
+    async fn register_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.register_user(user_name).await
+    }
+
+    async fn increment_views(
+        &self,
+        increments: &[Increment],
+    ) -> Result<Vec<UserViews>, DatastoreError> {
+        self.inner.increment_views(increments).await
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.delete_user(user_name).await?;
+        // views served since the last flush are dropped along with the user
+        self.counts.lock().await.remove(user_name);
+        Ok(())
+    }
+
+    async fn restore_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.restore_user(user_name).await
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.purge_user(user_name).await?;
+        self.counts.lock().await.remove(user_name);
+        Ok(())
+    }
+
+    // pending views are flushed first so operations see them, the local counts of the users
+    // touched are synced again on their next view
+    async fn transaction(&self, ops: Vec<Op>) -> Result<Vec<Option<StoredUser>>, DatastoreError> {
+        self.flush().await;
+        let users: Vec<String> = ops
+            .iter()
+            .filter(|op| !matches!(op, Op::Get(_)))
+            .map(|op| op.user_name().to_string())
+            .collect();
+        let results = self.inner.transaction(ops).await?;
+        let mut counts = self.counts.lock().await;
+        for user_name in &users {
+            counts.remove(user_name);
+        }
+        Ok(results)
+    }
+
+    async fn scan(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.inner.scan(cursor, limit).await
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.inner.scan_prefix(prefix, cursor, limit).await
+    }
+
+    // pending views are added, so reads agree with the badges served
+    async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, DatastoreError> {
+        let mut users = self.inner.get_many(user_names).await?;
+        let counts = self.counts.lock().await;
+        for user in users.iter_mut() {
+            if let Some(count) = counts.get(&user.user_name) {
+                user.views = user.views.max(count.known) + count.pending;
+            }
+        }
+        Ok(users)
+    }
+
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, DatastoreError> {
+        let mut user = self.inner.get_user(user_name).await?;
+        if let Some(user) = user.as_mut() {
+            if let Some(count) = self.counts.lock().await.get(user_name) {
+                user.views = user.views.max(count.known) + count.pending;
+            }
+        }
+        Ok(user)
+    }
+
+    /// Views pending a flush aren't included, they count towards the peak once flushed.
+    async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, DatastoreError> {
+        self.inner.get_peak_day_views(user_name).await
+    }
+
+    async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, DatastoreError> {
+        self.inner.get_day_stats(user_name).await
+    }
+
+    async fn list_users(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserRecord>, DatastoreError> {
+        self.inner.list_users(cursor, limit).await
+    }
+
+    async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, DatastoreError> {
+        self.inner.top_users(limit).await
+    }
+
+    async fn put_flag(&self, flag: &Flag) -> Result<(), DatastoreError> {
+        self.inner.put_flag(flag).await
+    }
+
+    async fn list_flags(&self) -> Result<Vec<Flag>, DatastoreError> {
+        self.inner.list_flags().await
+    }
+
+    async fn delete_flag(&self, user_name: &str) -> Result<bool, DatastoreError> {
+        self.inner.delete_flag(user_name).await
+    }
+
+    async fn warm_up(&self) {
+        self.inner.warm_up().await
+    }
+
+    async fn maintain(&self) {
+        tokio::join!(self.flush_loop(), self.inner.maintain());
+    }
+
+    async fn pending_views(&self) -> u64 {
+        let pending: u64 = self
+            .counts
+            .lock()
+            .await
+            .values()
+            .map(|count| count.pending)
+            .sum();
+        pending + self.inner.pending_views().await
+    }
+
+    async fn cached_views(&self, user_name: &str) -> Option<u64> {
+        let count = self
+            .counts
+            .lock()
+            .await
+            .get(user_name)
+            .map(|count| count.known + count.pending);
+        match count {
+            Some(views) => Some(views),
+            None => self.inner.cached_views(user_name).await,
+        }
+    }
+
+    async fn freshness(&self, user_name: &str) -> Freshness {
+        let pending = self
+            .counts
+            .lock()
+            .await
+            .get(user_name)
+            .is_some_and(|count| count.pending > 0);
+        match pending {
+            true => Freshness::Approximate,
+            false => self.inner.freshness(user_name).await,
+        }
+    }
+
+    fn usage(&self) -> Vec<DatastoreUsage> {
+        self.inner.usage()
+    }
+
+    fn captured_requests(&self, limit: usize) -> Vec<CapturedRequest> {
+        self.inner.captured_requests(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::datastore::Memory;
+    use pretty_assertions::assert_eq;
+
+    static TEST_USER_NAME: &str = "test_user";
+
+    fn optimistic(revalidate_after: u64, max_staleness: u64) -> Optimistic<Memory> {
+        Optimistic::new(
+            Memory::new(),
+            &OptimisticConfig {
+                flush_interval: Duration::from_secs(5),
+                revalidate_after: Duration::from_millis(revalidate_after),
+                max_staleness: Duration::from_millis(max_staleness),
+            },
+        )
+    }
+
+    async fn stored_views(optimistic: &Optimistic<Memory>) -> Vec<UserViews> {
+        optimistic
+            .inner
+            .get_many(&[TEST_USER_NAME.to_string()])
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_serves_local_counts_and_flushes_them() {
+        let optimistic = optimistic(60_000, 60_000);
+
+        assert!(matches!(
+            optimistic.get_latest_views(TEST_USER_NAME).await,
+            Err(DatastoreError::UserNotFound(_))
+        ));
+        assert_eq!(optimistic.onboard_user(TEST_USER_NAME).await.unwrap(), 1);
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            3
+        );
+        assert_eq!(stored_views(&optimistic).await[0].views, 1);
+        assert_eq!(optimistic.cached_views(TEST_USER_NAME).await, Some(3));
+
+        // views counted by another instance show up after the flush
+        optimistic
+            .inner
+            .get_latest_views(TEST_USER_NAME)
+            .await
+            .unwrap();
+        optimistic.flush().await;
+        assert_eq!(stored_views(&optimistic).await[0].views, 4);
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn it_serves_first_views_on_top_of_cached_views() {
+        let optimistic = optimistic(60_000, 60_000);
+        // counted by another instance
+        optimistic.inner.onboard_user(TEST_USER_NAME).await.unwrap();
+
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            2
+        );
+        assert_eq!(stored_views(&optimistic).await[0].views, 1);
+        assert!(optimistic.counts.lock().await[TEST_USER_NAME].revalidate);
+
+        optimistic.flush().await;
+        assert_eq!(stored_views(&optimistic).await[0].views, 2);
+    }
+
+    #[tokio::test]
+    async fn it_tells_local_counts_apart_from_stored_views() {
+        let optimistic = optimistic(60_000, 60_000);
+        optimistic.onboard_user(TEST_USER_NAME).await.unwrap();
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            2
+        );
+        assert_eq!(
+            optimistic.freshness(TEST_USER_NAME).await,
+            Freshness::Approximate
+        );
+
+        optimistic.flush().await;
+        assert_eq!(optimistic.freshness(TEST_USER_NAME).await, Freshness::Live);
+    }
+
+    #[tokio::test]
+    async fn it_flushes_pending_views_on_shutdown() {
+        let optimistic = optimistic(60_000, 60_000);
+        optimistic.onboard_user(TEST_USER_NAME).await.unwrap();
+        optimistic.get_latest_views(TEST_USER_NAME).await.unwrap();
+        optimistic.get_latest_views(TEST_USER_NAME).await.unwrap();
+        assert_eq!(optimistic.pending_views().await, 2);
+
+        optimistic.shutdown().await;
+        assert_eq!(optimistic.pending_views().await, 0);
+        assert_eq!(stored_views(&optimistic).await[0].views, 3);
+    }
+
+    #[tokio::test]
+    async fn it_forgets_users_deleted_before_the_flush() {
+        let optimistic = optimistic(60_000, 60_000);
+        optimistic.onboard_user(TEST_USER_NAME).await.unwrap();
+        optimistic.get_latest_views(TEST_USER_NAME).await.unwrap();
+
+        // deleted through another instance
+        optimistic.inner.delete_user(TEST_USER_NAME).await.unwrap();
+        optimistic.flush().await;
+
+        assert!(optimistic.counts.lock().await.is_empty());
+        assert!(matches!(
+            optimistic.get_latest_views(TEST_USER_NAME).await,
+            Err(DatastoreError::UserDeleted(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_revalidates_stale_counts_in_the_background() {
+        let optimistic = optimistic(0, 60_000);
+        optimistic.onboard_user(TEST_USER_NAME).await.unwrap();
+        optimistic.flush().await;
+
+        // counted by another instance
+        optimistic
+            .inner
+            .get_latest_views(TEST_USER_NAME)
+            .await
+            .unwrap();
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            2
+        );
+        assert!(optimistic.counts.lock().await[TEST_USER_NAME].revalidate);
+
+        // woken by the stale count instead of the interval
+        tokio::time::timeout(Duration::from_secs(1), optimistic.revalidate.notified())
+            .await
+            .unwrap();
+        optimistic.flush().await;
+        assert_eq!(stored_views(&optimistic).await[0].views, 3);
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            4
+        );
+    }
+
+    #[tokio::test]
+    async fn it_counts_on_datastore_past_max_staleness() {
+        let optimistic = optimistic(0, 0);
+        optimistic.onboard_user(TEST_USER_NAME).await.unwrap();
+
+        optimistic
+            .inner
+            .get_latest_views(TEST_USER_NAME)
+            .await
+            .unwrap();
+        assert_eq!(
+            optimistic.get_latest_views(TEST_USER_NAME).await.unwrap(),
+            3
+        );
+        assert_eq!(stored_views(&optimistic).await[0].views, 3);
+    }
+}
@@ -0,0 +1,287 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+
+use super::{
+    CapturedRequest, DatastoreError, DatastoreOperations, DatastoreUsage, DayStats, Flag,
+    Freshness, Increment, Op, Page, StoredUser, UserRecord, UserViews,
+};
+use crate::cache::CacheStore;
+use crate::runtime::Spawner;
+use crate::shutdown::Shutdown;
+
+// how long views counted by an instance can be served by the others
+const SHARED_VIEWS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Publishes the views counted on the inner datastore to the cache store shared by all instances,
+/// so every instance knows them without asking the datastore, see
+/// [`DatastoreOperations::cached_views`]. Views are published in the background, counting doesn't
+/// wait for the store; operations pass straight through without a shared store.
+pub struct Shared<D: DatastoreOperations> {
+    inner: D,
+    store: Option<Arc<dyn CacheStore>>,
+    spawner: Arc<dyn Spawner>,
+}
+
+impl<D: DatastoreOperations> Shared<D> {
+    pub fn new(
+        inner: D,
+        store: Option<Arc<dyn CacheStore>>,
+        spawner: Arc<dyn Spawner>,
+    ) -> Shared<D> {
+        Shared {
+            inner,
+            store,
+            spawner,
+        }
+    }
+
+    fn publish(&self, users: impl IntoIterator<Item = (String, u64)>) {
+        let Some(store) = self.store.clone() else {
+            return;
+        };
+        let users: Vec<(String, u64)> = users.into_iter().collect();
+        self.spawner.spawn(Box::pin(async move {
+            for (user_name, views) in users {
+                store
+                    .set(&views_key(&user_name), &views.to_string(), SHARED_VIEWS_TTL)
+                    .await;
+            }
+        }));
+    }
+
+    // views of users changed otherwise than by counting are known again once counted
+    fn forget(&self, user_names: Vec<String>) {
+        let Some(store) = self.store.clone() else {
+            return;
+        };
+        self.spawner.spawn(Box::pin(async move {
+            for user_name in user_names {
+                store.remove(&views_key(&user_name)).await;
+            }
+        }));
+    }
+}
+
+fn views_key(user_name: &str) -> String {
+    format!("views:{}", user_name)
+}
+
+#[async_trait]
+impl<D: DatastoreOperations> Shutdown for Shared<D> {
+    async fn shutdown(&self) {
+        self.inner.shutdown().await
+    }
+}
+
+#[async_trait]
+impl<D: DatastoreOperations> DatastoreOperations for Shared<D> {
+    async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let views = self.inner.get_latest_views(user_name).await?;
+        self.publish([(user_name.to_string(), views)]);
+        Ok(views)
+    }
+
+    async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let views = self.inner.onboard_user(user_name).await?;
+        self.publish([(user_name.to_string(), views)]);
+        Ok(views)
+    }
+
+    async fn register_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.register_user(user_name).await
+    }
+
+    async fn increment_views(
+        &self,
+        increments: &[Increment],
+    ) -> Result<Vec<UserViews>, DatastoreError> {
+        let users = self.inner.increment_views(increments).await?;
+        self.publish(
+            users
+                .iter()
+                .map(|user| (user.user_name.clone(), user.views)),
+        );
+        Ok(users)
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.delete_user(user_name).await?;
+        self.forget(vec![user_name.to_string()]);
+        Ok(())
+    }
+
+    async fn restore_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.restore_user(user_name).await
+    }
+
+    async fn purge_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.inner.purge_user(user_name).await?;
+        self.forget(vec![user_name.to_string()]);
+        Ok(())
+    }
+
+    async fn transaction(&self, ops: Vec<Op>) -> Result<Vec<Option<StoredUser>>, DatastoreError> {
+        let users: Vec<String> = ops
+            .iter()
+            .filter(|op| !matches!(op, Op::Get(_)))
+            .map(|op| op.user_name().to_string())
+            .collect();
+        let results = self.inner.transaction(ops).await?;
+        self.forget(users);
+        Ok(results)
+    }
+
+    async fn scan(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.inner.scan(cursor, limit).await
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.inner.scan_prefix(prefix, cursor, limit).await
+    }
+
+    async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, DatastoreError> {
+        self.inner.get_many(user_names).await
+    }
+
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, DatastoreError> {
+        self.inner.get_user(user_name).await
+    }
+
+    async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, DatastoreError> {
+        self.inner.get_peak_day_views(user_name).await
+    }
+
+    async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, DatastoreError> {
+        self.inner.get_day_stats(user_name).await
+    }
+
+    async fn list_users(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserRecord>, DatastoreError> {
+        self.inner.list_users(cursor, limit).await
+    }
+
+    async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, DatastoreError> {
+        self.inner.top_users(limit).await
+    }
+
+    async fn put_flag(&self, flag: &Flag) -> Result<(), DatastoreError> {
+        self.inner.put_flag(flag).await
+    }
+
+    async fn list_flags(&self) -> Result<Vec<Flag>, DatastoreError> {
+        self.inner.list_flags().await
+    }
+
+    async fn delete_flag(&self, user_name: &str) -> Result<bool, DatastoreError> {
+        self.inner.delete_flag(user_name).await
+    }
+
+    async fn warm_up(&self) {
+        self.inner.warm_up().await
+    }
+
+    async fn maintain(&self) {
+        self.inner.maintain().await
+    }
+
+    async fn pending_views(&self) -> u64 {
+        self.inner.pending_views().await
+    }
+
+    // views known locally are at least as recent as the ones published by other instances
+    async fn cached_views(&self, user_name: &str) -> Option<u64> {
+        if let Some(views) = self.inner.cached_views(user_name).await {
+            return Some(views);
+        }
+        let views = self.store.as_ref()?.get(&views_key(user_name)).await?;
+        views.parse().ok()
+    }
+
+    async fn freshness(&self, user_name: &str) -> Freshness {
+        self.inner.freshness(user_name).await
+    }
+
+    fn usage(&self) -> Vec<DatastoreUsage> {
+        self.inner.usage()
+    }
+
+    fn captured_requests(&self, limit: usize) -> Vec<CapturedRequest> {
+        self.inner.captured_requests(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::cache::MemoryStore;
+    use crate::datastore::Memory;
+    use crate::runtime::TokioSpawner;
+
+    const TEST_USER_NAME: &str = "test-user";
+
+    fn shared(store: &Arc<dyn CacheStore>) -> Shared<Memory> {
+        Shared::new(Memory::new(), Some(store.clone()), Arc::new(TokioSpawner))
+    }
+
+    // views are published in the background
+    async fn published(store: &Arc<dyn CacheStore>, user_name: &str) -> Option<String> {
+        for _ in 0..100 {
+            if let Some(views) = store.peek(&views_key(user_name)).await {
+                return Some(views);
+            }
+            tokio::task::yield_now().await;
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn it_serves_views_counted_by_other_instances() {
+        let store: Arc<dyn CacheStore> = Arc::new(MemoryStore::new());
+        let counting = shared(&store);
+        let other = shared(&store);
+
+        counting.onboard_user(TEST_USER_NAME).await.unwrap();
+        counting.get_latest_views(TEST_USER_NAME).await.unwrap();
+
+        assert_eq!(
+            published(&store, TEST_USER_NAME).await.as_deref(),
+            Some("2")
+        );
+        assert_eq!(other.cached_views(TEST_USER_NAME).await, Some(2));
+        assert_eq!(other.cached_views("other-user").await, None);
+    }
+
+    #[tokio::test]
+    async fn it_forgets_views_of_deleted_users() {
+        let store: Arc<dyn CacheStore> = Arc::new(MemoryStore::new());
+        let shared = shared(&store);
+        shared.onboard_user(TEST_USER_NAME).await.unwrap();
+        assert_eq!(
+            published(&store, TEST_USER_NAME).await.as_deref(),
+            Some("1")
+        );
+
+        shared.delete_user(TEST_USER_NAME).await.unwrap();
+        for _ in 0..100 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(store.peek(&views_key(TEST_USER_NAME)).await, None);
+    }
+}
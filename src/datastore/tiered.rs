@@ -0,0 +1,563 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use axum::async_trait;
+use tokio::sync::Mutex;
+use tokio::time;
+use tokio_stream::{wrappers::IntervalStream, StreamExt};
+
+use super::{
+    CapturedRequest, DatastoreError, DatastoreOperations, DatastoreUsage, DayStats, Flag,
+    Freshness, Increment, Op, Page, StoredUser, UserRecord, UserViews,
+};
+use crate::cache::{CacheStore, LruStore};
+use crate::shutdown::Shutdown;
+
+// users whose last views on the primary are remembered for outages
+const REMEMBERED_USERS: usize = 10_000;
+const DEFAULT_RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Writes to the primary datastore and falls back to the secondary one when the primary fails.
+/// Views counted by the secondary are replayed on the primary by [`Tiered::reconcile`].
+pub struct Tiered<P: DatastoreOperations, S: DatastoreOperations> {
+    primary: P,
+    secondary: S,
+    // user name -> views counted by the secondary which are yet to be replayed on the primary
+    pending: Mutex<HashMap<String, u64>>,
+    // user name -> views last counted on the primary, served on top of pending views during
+    // outages since the secondary only counts views from the start of the outage
+    last_views: LruStore,
+    reconcile_interval: Duration,
+}
+
+impl<P, S> Tiered<P, S>
+where
+    P: DatastoreOperations,
+    S: DatastoreOperations,
+{
+    pub fn new(primary: P, secondary: S) -> Tiered<P, S> {
+        Tiered {
+            primary,
+            secondary,
+            pending: Mutex::new(HashMap::new()),
+            last_views: LruStore::new(REMEMBERED_USERS),
+            reconcile_interval: DEFAULT_RECONCILE_INTERVAL,
+        }
+    }
+
+    /// Replays pending views every `interval` instead of every minute.
+    pub fn with_reconcile_interval(mut self, interval: Duration) -> Tiered<P, S> {
+        self.reconcile_interval = interval;
+        self
+    }
+
+    async fn remember(&self, user_name: &str, views: u64) {
+        self.last_views
+            .set(user_name, &views.to_string(), Duration::MAX)
+            .await;
+    }
+
+    async fn count_on_primary(&self, user_name: &str, views: u64) -> u64 {
+        self.remember(user_name, views).await;
+        let pending = self.pending.lock().await.get(user_name).copied();
+        views + pending.unwrap_or(0)
+    }
+
+    /// Counts the view on the secondary, serving it on top of the views last counted on the
+    /// primary. Users the primary's views aren't known of get the secondary's views.
+    async fn count_on_secondary(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let views = match self.secondary.get_latest_views(user_name).await {
+            Err(DatastoreError::UserNotFound(_)) => self.secondary.onboard_user(user_name).await?,
+            result => result?,
+        };
+
+        let pending = {
+            let mut pending = self.pending.lock().await;
+            let pending = pending.entry(user_name.to_string()).or_insert(0);
+            *pending += 1;
+            *pending
+        };
+
+        let last_views = self.last_views.peek(user_name).await;
+        Ok(
+            match last_views.and_then(|views| views.parse::<u64>().ok()) {
+                Some(last_views) => last_views + pending,
+                None => views,
+            },
+        )
+    }
+
+    /// Replays pending views on the primary datastore in a single batch, keeping them for the
+    /// next attempt when it fails. Views counted while the batch is replayed stay pending.
+    pub async fn reconcile(&self) {
+        let pending = self.pending.lock().await.clone();
+        if pending.is_empty() {
+            return;
+        }
+
+        match self.replay(&pending).await {
+            Ok(replayed) => {
+                {
+                    let mut remaining = self.pending.lock().await;
+                    for (user_name, views) in &pending {
+                        if let Some(left) = remaining.get_mut(user_name) {
+                            *left = left.saturating_sub(*views);
+                            if *left == 0 {
+                                remaining.remove(user_name);
+                            }
+                        }
+                    }
+                }
+                for user in &replayed {
+                    self.remember(&user.user_name, user.views).await;
+                }
+                tracing::info!(
+                    "reconciled {} view(s) of {} user(s)",
+                    pending.values().sum::<u64>(),
+                    replayed.len()
+                );
+            }
+            Err(err) => tracing::error!("failed to reconcile views, reason: {}", err),
+        }
+    }
+
+    // looks the users up and adds their views in one batch: users unknown to the primary are
+    // registered first, views of renamed users go to the user they were renamed to and views of
+    // deleted users are dropped, as the primary wouldn't have counted them
+    async fn replay(
+        &self,
+        pending: &HashMap<String, u64>,
+    ) -> Result<Vec<UserViews>, DatastoreError> {
+        let lookups = pending
+            .keys()
+            .map(|user_name| Op::Get(user_name.clone()))
+            .collect();
+        let users = self.primary.transaction(lookups).await?;
+
+        let mut increments = BTreeMap::new();
+        for ((user_name, views), user) in pending.iter().zip(users) {
+            let counted_on = match user.as_ref().map(StoredUser::check_counted) {
+                None => {
+                    match self.primary.register_user(user_name).await {
+                        Ok(()) | Err(DatastoreError::UserExists(_)) => {}
+                        Err(err) => return Err(err),
+                    }
+                    user_name.clone()
+                }
+                Some(Ok(())) => user_name.clone(),
+                Some(Err(DatastoreError::UserRenamed(_, renamed_to))) => renamed_to,
+                Some(Err(err)) => {
+                    tracing::warn!("dropping {} view(s) to replay, reason: {}", views, err);
+                    continue;
+                }
+            };
+            *increments.entry(counted_on).or_insert(0) += views;
+        }
+
+        let increments: Vec<_> = increments
+            .into_iter()
+            .map(|(user_name, views)| Increment { user_name, views })
+            .collect();
+        if increments.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.primary.increment_views(&increments).await
+    }
+
+    async fn reconcile_loop(&self) {
+        let mut stream = IntervalStream::new(time::interval(self.reconcile_interval));
+
+        while stream.next().await.is_some() {
+            if !self.pending.lock().await.is_empty() {
+                self.reconcile().await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<P, S> Shutdown for Tiered<P, S>
+where
+    P: DatastoreOperations,
+    S: DatastoreOperations,
+{
+    /// Replays the views counted by the secondary one last time, views the primary still fails
+    /// to take are lost.
+    async fn shutdown(&self) {
+        self.reconcile().await;
+        let lost: u64 = self.pending.lock().await.values().sum();
+        if lost > 0 {
+            tracing::error!(
+                "shutting down with {} view(s) not replayed on primary",
+                lost
+            );
+        }
+        tokio::join!(self.primary.shutdown(), self.secondary.shutdown());
+    }
+}
+
+#[async_trait]
+impl<P, S> DatastoreOperations for Tiered<P, S>
+where
+    P: DatastoreOperations + Send + Sync,
+    S: DatastoreOperations + Send + Sync,
+{
+    async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        match self.primary.get_latest_views(user_name).await {
+            Ok(views) => Ok(self.count_on_primary(user_name, views).await),
+            result @ (Err(DatastoreError::UserNotFound(_))
+            | Err(DatastoreError::UserDeleted(_))
+            | Err(DatastoreError::UserRenamed(_, _))) => result,
+            Err(err) => {
+                tracing::warn!(
+                    "primary datastore failed to fetch views for user `{}`, falling back to secondary, reason: {}",
+                    user_name,
+                    err
+                );
+
+                self.count_on_secondary(user_name).await
+            }
+        }
+    }
+
+    async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        match self.primary.onboard_user(user_name).await {
+            Ok(views) => Ok(self.count_on_primary(user_name, views).await),
+            Err(err) => {
+                tracing::warn!(
+                    "primary datastore failed to onboard user `{}`, falling back to secondary, reason: {}",
+                    user_name,
+                    err
+                );
+
+                self.count_on_secondary(user_name).await
+            }
+        }
+    }
+
+    // registrations on the secondary would be lost on the primary
+    async fn register_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.primary.register_user(user_name).await
+    }
+
+    // batches are all or nothing, which the reconcile loop can't guarantee across datastores
+    async fn increment_views(
+        &self,
+        increments: &[Increment],
+    ) -> Result<Vec<UserViews>, DatastoreError> {
+        self.primary.increment_views(increments).await
+    }
+
+    async fn delete_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.primary.delete_user(user_name).await
+    }
+
+    async fn restore_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.primary.restore_user(user_name).await
+    }
+
+    // views counted during an outage would onboard the user again once replayed
+    async fn purge_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.primary.purge_user(user_name).await?;
+        self.secondary.purge_user(user_name).await
+    }
+
+    // views counted on the secondary during an outage are moved once reconciled
+    // all or nothing, which the reconcile loop can't guarantee across datastores
+    async fn transaction(&self, ops: Vec<Op>) -> Result<Vec<Option<StoredUser>>, DatastoreError> {
+        self.primary.transaction(ops).await
+    }
+
+    // the secondary only knows about views counted during outages, so scans are served by the
+    // primary alone
+    async fn scan(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.primary.scan(cursor, limit).await
+    }
+
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        self.primary.scan_prefix(prefix, cursor, limit).await
+    }
+
+    async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, DatastoreError> {
+        self.primary.get_many(user_names).await
+    }
+
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, DatastoreError> {
+        self.primary.get_user(user_name).await
+    }
+
+    async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, DatastoreError> {
+        self.primary.get_peak_day_views(user_name).await
+    }
+
+    async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, DatastoreError> {
+        self.primary.get_day_stats(user_name).await
+    }
+
+    async fn list_users(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserRecord>, DatastoreError> {
+        self.primary.list_users(cursor, limit).await
+    }
+
+    async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, DatastoreError> {
+        self.primary.top_users(limit).await
+    }
+
+    async fn put_flag(&self, flag: &Flag) -> Result<(), DatastoreError> {
+        self.primary.put_flag(flag).await
+    }
+
+    async fn list_flags(&self) -> Result<Vec<Flag>, DatastoreError> {
+        self.primary.list_flags().await
+    }
+
+    async fn delete_flag(&self, user_name: &str) -> Result<bool, DatastoreError> {
+        self.primary.delete_flag(user_name).await
+    }
+
+    async fn warm_up(&self) {
+        self.primary.warm_up().await
+    }
+
+    async fn maintain(&self) {
+        tokio::join!(self.reconcile_loop(), self.primary.maintain());
+    }
+
+    async fn pending_views(&self) -> u64 {
+        let pending: u64 = self.pending.lock().await.values().sum();
+        pending + self.primary.pending_views().await
+    }
+
+    /// Views last counted on the primary along with those pending, as served during outages.
+    async fn cached_views(&self, user_name: &str) -> Option<u64> {
+        let last_views = self.last_views.peek(user_name).await;
+        match last_views.and_then(|views| views.parse::<u64>().ok()) {
+            Some(views) => {
+                let pending = self.pending.lock().await.get(user_name).copied();
+                Some(views + pending.unwrap_or(0))
+            }
+            None => self.primary.cached_views(user_name).await,
+        }
+    }
+
+    async fn freshness(&self, user_name: &str) -> Freshness {
+        // views counted by the secondary are served on top until they are replayed
+        match self.pending.lock().await.contains_key(user_name) {
+            true => Freshness::Approximate,
+            false => self.primary.freshness(user_name).await,
+        }
+    }
+
+    fn usage(&self) -> Vec<DatastoreUsage> {
+        let mut usage = self.primary.usage();
+        usage.extend(self.secondary.usage());
+        usage
+    }
+
+    fn captured_requests(&self, limit: usize) -> Vec<CapturedRequest> {
+        let mut requests = self.primary.captured_requests(limit);
+        requests.extend(self.secondary.captured_requests(limit));
+        requests.sort_by_key(|request| std::cmp::Reverse(request.started_at));
+        requests.truncate(limit);
+        requests
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FaultConfig;
+    use crate::datastore::{FaultyDatastore, Memory};
+    use pretty_assertions::assert_eq;
+
+    static TEST_USER_NAME: &str = "test_user";
+
+    fn flaky() -> FaultyDatastore<Memory> {
+        FaultyDatastore::new(Memory::new(), None)
+    }
+
+    fn outage() -> Option<FaultConfig> {
+        Some(FaultConfig {
+            error_rate: 1.0,
+            ..FaultConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn it_counts_views_on_primary_when_available() {
+        let tiered = Tiered::new(flaky(), Memory::new());
+
+        assert_eq!(tiered.onboard_user(TEST_USER_NAME).await.unwrap(), 1);
+        assert_eq!(tiered.get_latest_views(TEST_USER_NAME).await.unwrap(), 2);
+        assert!(tiered.pending.lock().await.is_empty());
+        assert!(matches!(
+            tiered.secondary.get_latest_views(TEST_USER_NAME).await,
+            Err(DatastoreError::UserNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_to_secondary_and_reconciles_views() {
+        let tiered = Tiered::new(flaky(), Memory::new());
+        tiered.onboard_user(TEST_USER_NAME).await.unwrap();
+
+        // the secondary counts from the primary's last views rather than from scratch
+        tiered.primary.set_faults(outage());
+        assert_eq!(tiered.get_latest_views(TEST_USER_NAME).await.unwrap(), 2);
+        assert_eq!(tiered.get_latest_views(TEST_USER_NAME).await.unwrap(), 3);
+        assert_eq!(tiered.cached_views(TEST_USER_NAME).await, Some(3));
+
+        // primary is still down, nothing gets replayed
+        tiered.reconcile().await;
+        assert_eq!(tiered.pending.lock().await.get(TEST_USER_NAME), Some(&2));
+
+        tiered.primary.set_faults(None);
+        tiered.reconcile().await;
+        assert!(tiered.pending.lock().await.is_empty());
+        assert_eq!(tiered.get_latest_views(TEST_USER_NAME).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_views_counted_while_reconciling() {
+        let tiered = Tiered::new(flaky(), Memory::new());
+        tiered.onboard_user(TEST_USER_NAME).await.unwrap();
+        tiered.primary.set_faults(outage());
+        tiered.get_latest_views(TEST_USER_NAME).await.unwrap();
+
+        // the primary is slow to look the user up, another view fails over in the meantime
+        tiered.primary.set_faults(Some(FaultConfig {
+            latency: Duration::from_millis(50),
+            ..FaultConfig::default()
+        }));
+        let count_during_replay = async {
+            time::sleep(Duration::from_millis(10)).await;
+            tiered.primary.set_faults(outage());
+            // the views being replayed are still served on top of the primary's last views
+            assert_eq!(tiered.get_latest_views(TEST_USER_NAME).await.unwrap(), 3);
+            tiered.primary.set_faults(None);
+        };
+        tokio::join!(tiered.reconcile(), count_during_replay);
+
+        assert_eq!(tiered.pending.lock().await.get(TEST_USER_NAME), Some(&1));
+        assert_eq!(
+            tiered
+                .primary
+                .get_latest_views(TEST_USER_NAME)
+                .await
+                .unwrap(),
+            3
+        );
+
+        // views stay pending when the replay is cut short
+        tiered.primary.set_faults(Some(FaultConfig {
+            latency: Duration::from_millis(50),
+            ..FaultConfig::default()
+        }));
+        let replayed = time::timeout(Duration::from_millis(10), tiered.reconcile()).await;
+        assert!(replayed.is_err());
+        assert_eq!(tiered.pending.lock().await.get(TEST_USER_NAME), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn it_reconciles_views_while_maintained() {
+        let tiered =
+            Tiered::new(flaky(), Memory::new()).with_reconcile_interval(Duration::from_millis(10));
+        tiered.onboard_user(TEST_USER_NAME).await.unwrap();
+
+        tiered.primary.set_faults(outage());
+        tiered.get_latest_views(TEST_USER_NAME).await.unwrap();
+        tiered.primary.set_faults(None);
+
+        let maintained = time::timeout(Duration::from_millis(100), tiered.maintain()).await;
+        assert!(maintained.is_err());
+        assert!(tiered.pending.lock().await.is_empty());
+        assert_eq!(
+            tiered
+                .primary
+                .get_latest_views(TEST_USER_NAME)
+                .await
+                .unwrap(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn it_replays_views_of_renamed_and_deleted_users() {
+        let tiered = Tiered::new(flaky(), Memory::new());
+        for user_name in ["alice", "bob", "carol"] {
+            tiered.onboard_user(user_name).await.unwrap();
+        }
+
+        tiered.primary.set_faults(outage());
+        for user_name in ["alice", "bob", "bob", "carol", "dave"] {
+            tiered.get_latest_views(user_name).await.unwrap();
+        }
+        tiered.primary.set_faults(None);
+        tiered
+            .merge_users(&["bob".to_string()], "alice")
+            .await
+            .unwrap();
+        tiered.delete_user("carol").await.unwrap();
+
+        tiered.reconcile().await;
+        assert!(tiered.pending.lock().await.is_empty());
+        // views of bob went to alice, the ones of carol are dropped and dave got onboarded
+        assert_eq!(tiered.get_latest_views("alice").await.unwrap(), 6);
+        assert_eq!(
+            tiered
+                .primary
+                .get_user("carol")
+                .await
+                .unwrap()
+                .unwrap()
+                .views,
+            1
+        );
+        assert_eq!(tiered.get_latest_views("dave").await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_replays_views_of_secondary_on_shutdown() {
+        let tiered = Tiered::new(flaky(), Memory::new());
+        tiered.onboard_user(TEST_USER_NAME).await.unwrap();
+
+        tiered.primary.set_faults(outage());
+        tiered.get_latest_views(TEST_USER_NAME).await.unwrap();
+        tiered.primary.set_faults(None);
+
+        tiered.shutdown().await;
+        assert!(tiered.pending.lock().await.is_empty());
+        assert_eq!(
+            tiered
+                .primary
+                .get_latest_views(TEST_USER_NAME)
+                .await
+                .unwrap(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn it_onboards_users_on_primary_while_reconciling() {
+        let tiered = Tiered::new(flaky(), Memory::new());
+
+        tiered.primary.set_faults(outage());
+        assert_eq!(tiered.onboard_user(TEST_USER_NAME).await.unwrap(), 1);
+
+        tiered.primary.set_faults(None);
+        tiered.reconcile().await;
+        assert_eq!(tiered.get_latest_views(TEST_USER_NAME).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::metrics;
+
+const HOURS_KEPT: usize = 24;
+const DAYS_KEPT: usize = 31;
+
+/// Operations sent to a datastore backend, for predicting the bill of metered databases.
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct DatastoreUsage {
+    /// Backend the operations went to, e.g. `xata`
+    pub backend: String,
+    /// Operations per UTC hour over the last day, the current hour first
+    pub hours: Vec<UsageBucket>,
+    /// Operations per UTC day over the last month, today first
+    pub days: Vec<UsageBucket>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct UsageBucket {
+    /// Start of the hour or day
+    pub start: DateTime<Utc>,
+    /// Queries looking records up
+    pub reads: u64,
+    /// Operations changing records; each operation of a transaction counts on its own
+    pub writes: u64,
+}
+
+/// Counts the operations of a backend per hour and day. Counts live in process memory, so each
+/// instance reports its own operations and a restart starts over; the `datastore_operations_total`
+/// metric is the one to sum up across instances.
+pub struct UsageCounters {
+    backend: &'static str,
+    buckets: Mutex<(VecDeque<UsageBucket>, VecDeque<UsageBucket>)>,
+}
+
+impl UsageCounters {
+    pub fn new(backend: &'static str) -> UsageCounters {
+        UsageCounters {
+            backend,
+            buckets: Mutex::new((VecDeque::new(), VecDeque::new())),
+        }
+    }
+
+    pub fn read(&self, operations: u64) {
+        metrics::record_datastore_operations(self.backend, "read", operations);
+        self.record_at(Utc::now(), operations, 0);
+    }
+
+    pub fn write(&self, operations: u64) {
+        metrics::record_datastore_operations(self.backend, "write", operations);
+        self.record_at(Utc::now(), 0, operations);
+    }
+
+    pub fn usage(&self) -> DatastoreUsage {
+        let (hours, days) = &*self.buckets.lock().unwrap();
+        DatastoreUsage {
+            backend: self.backend.to_string(),
+            hours: hours.iter().cloned().collect(),
+            days: days.iter().cloned().collect(),
+        }
+    }
+
+    fn record_at(&self, now: DateTime<Utc>, reads: u64, writes: u64) {
+        let hour = now.duration_trunc(Duration::hours(1)).unwrap_or(now);
+        let day = now.date_naive().and_time(Default::default()).and_utc();
+
+        let (hours, days) = &mut *self.buckets.lock().unwrap();
+        add(hours, HOURS_KEPT, hour, reads, writes);
+        add(days, DAYS_KEPT, day, reads, writes);
+    }
+}
+
+// adds the operations to the bucket starting at `start`, which is the newest one
+fn add(
+    buckets: &mut VecDeque<UsageBucket>,
+    kept: usize,
+    start: DateTime<Utc>,
+    reads: u64,
+    writes: u64,
+) {
+    if buckets.front().is_none_or(|bucket| bucket.start < start) {
+        buckets.push_front(UsageBucket {
+            start,
+            reads: 0,
+            writes: 0,
+        });
+        buckets.truncate(kept);
+    }
+
+    if let Some(bucket) = buckets.front_mut() {
+        bucket.reads += reads;
+        bucket.writes += writes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_counts_operations_per_hour_and_day() {
+        let usage = UsageCounters::new("xata");
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        usage.record_at(at("2023-06-01T10:15:00Z"), 1, 0);
+        usage.record_at(at("2023-06-01T10:45:00Z"), 0, 3);
+        usage.record_at(at("2023-06-01T11:05:00Z"), 2, 0);
+        usage.record_at(at("2023-06-02T00:05:00Z"), 0, 1);
+
+        let usage = usage.usage();
+        let bucket = |start: &str, reads, writes| UsageBucket {
+            start: at(start),
+            reads,
+            writes,
+        };
+        assert_eq!(
+            usage.hours,
+            vec![
+                bucket("2023-06-02T00:00:00Z", 0, 1),
+                bucket("2023-06-01T11:00:00Z", 2, 0),
+                bucket("2023-06-01T10:00:00Z", 1, 3),
+            ]
+        );
+        assert_eq!(
+            usage.days,
+            vec![
+                bucket("2023-06-02T00:00:00Z", 0, 1),
+                bucket("2023-06-01T00:00:00Z", 3, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_keeps_a_day_of_hours() {
+        let usage = UsageCounters::new("xata");
+        let start = "2023-06-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        for hour in 0..30 {
+            usage.record_at(start + Duration::hours(hour), 1, 0);
+        }
+
+        let usage = usage.usage();
+        assert_eq!(usage.hours.len(), HOURS_KEPT);
+        assert_eq!(usage.hours[0].start, start + Duration::hours(29));
+        assert_eq!(usage.days.len(), 2);
+    }
+}
@@ -1,45 +1,296 @@
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::Instant;
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use axum::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
 use reqwest::{
-    header::{self, HeaderMap, HeaderValue},
-    Response, StatusCode,
+    header::{self, HeaderValue},
+    Method, RequestBuilder, Response, StatusCode,
 };
-use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use serde::{de::DeserializeOwned, ser::SerializeMap, Deserialize, Serialize, Serializer};
 use serde_json::Value;
 
-use super::{DatastoreError, DatastoreOperations};
+use super::capture::RequestCapture;
+use super::usage::UsageCounters;
+use super::{
+    CapturedRequest, DatastoreError, DatastoreOperations, DatastoreUsage, DayStats, Fields, Flag,
+    Increment, Op, Page, StoredUser, UserRecord, UserViews, ViewsChange,
+};
+use crate::config::{Config, UpstreamConfig};
+use crate::http_client;
+use crate::metrics::{self, UpstreamRequest};
+use crate::secrets::{self, Secrets};
+use crate::shutdown::Shutdown;
+use crate::status;
 
 pub struct Xata {
     client: reqwest::Client,
+    // holds the api key, read for every request so a rotated key applies right away
+    secrets: Arc<Secrets>,
+    upstream: UpstreamConfig,
     db_endpoint: String,
+    query_endpoint: String,
     table_name: String,
+    // records api of the flags table, e.g. `.../tables/flags`
+    flags_endpoint: String,
+    usage: UsageCounters,
+    // failed requests kept for `/admin/debug/requests` when `XATA_CAPTURE_FAILED_REQUESTS` is set
+    capture: Option<RequestCapture>,
 }
 
 impl Xata {
-    pub fn new() -> Result<Xata, Error> {
+    pub fn new(config: &Config) -> Result<Xata, Error> {
         let db_endpoint = std::env::var("XATA_DB_ENDPOINT")?;
-        let api_key = std::env::var("XATA_API_KEY")?;
         let table_name = std::env::var("XATA_TABLE_NAME")?;
+        let flags_table_name =
+            std::env::var("XATA_FLAGS_TABLE_NAME").unwrap_or_else(|_| FLAGS_TABLE_NAME.to_string());
+        Xata::with_tables(config, db_endpoint, table_name, flags_table_name)
+    }
 
-        // all request to xata.io will use bearer auth token
-        let mut auth_header = HeaderMap::new();
-        let mut auth_header_value = HeaderValue::from_str(&format!("Bearer {}", api_key))?;
-        auth_header_value.set_sensitive(true);
-        auth_header.insert(header::AUTHORIZATION, auth_header_value);
+    /// Client of the tables on the branch whose transaction api is at `db_endpoint`.
+    fn with_tables(
+        config: &Config,
+        db_endpoint: String,
+        table_name: String,
+        flags_table_name: String,
+    ) -> Result<Xata, Error> {
+        if config.secrets.get(secrets::XATA_API_KEY).is_none() {
+            return Err(anyhow!("missing XATA_API_KEY"));
+        }
 
-        let client = reqwest::Client::builder()
-            .default_headers(auth_header)
-            .pool_max_idle_per_host(5)
-            .pool_idle_timeout(Duration::from_secs(120))
-            .timeout(Duration::from_secs(5))
-            .build()?;
+        // db endpoint points to the branch transaction api, queries live next to it
+        let branch_endpoint = db_endpoint.trim_end_matches("/transaction");
+        let query_endpoint = format!("{}/tables/{}/query", branch_endpoint, table_name);
+        let flags_endpoint = format!("{}/tables/{}", branch_endpoint, flags_table_name);
+
+        let client = http_client::upstream_builder(config, &config.xata)?.build()?;
+        metrics::record_pool_size("xata", config.xata.pool_max_idle);
 
         Ok(Xata {
             client,
+            secrets: config.secrets.clone(),
+            upstream: config.xata.clone(),
             db_endpoint,
+            query_endpoint,
             table_name,
+            flags_endpoint,
+            usage: UsageCounters::new("xata"),
+            capture: config.xata_capture_failed_requests.map(RequestCapture::new),
+        })
+    }
+
+    /// Request to xata.io, authenticated with the current api key.
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        let Some(api_key) = self.secrets.get(secrets::XATA_API_KEY) else {
+            return request;
+        };
+        match HeaderValue::from_str(&format!("Bearer {}", api_key)) {
+            Ok(mut auth_header) => {
+                auth_header.set_sensitive(true);
+                request.header(header::AUTHORIZATION, auth_header)
+            }
+            Err(_) => {
+                tracing::error!("XATA_API_KEY is not a valid header value");
+                request
+            }
+        }
+    }
+
+    /// Empty transaction on the views table, to add operations to.
+    fn transaction(&self) -> XataTransaction<'_> {
+        XataTransaction::on(&self.table_name)
+    }
+
+    /// Transaction updating the views of every user by their increment.
+    fn batch_transaction<'txn>(
+        &'txn self,
+        increments: &'txn [Increment],
+        op_type: impl Fn(u64) -> OperationType,
+    ) -> XataTransaction<'txn> {
+        increments
+            .iter()
+            .fold(self.transaction(), |transaction, increment| {
+                transaction.with(&increment.user_name, op_type(increment.views))
+            })
+    }
+
+    async fn update(
+        &self,
+        user_name: &str,
+        op_type: OperationType,
+    ) -> Result<ProfileViews, DatastoreError> {
+        let transaction = self.transaction().with(user_name, op_type);
+        let update_txn_resp = self.execute(&transaction).await?;
+
+        // xata returns 400 if transaction fails with some error.
+        // reference - https://xata.io/docs/api-reference/db/db_branch_name/transaction#execute-a-transaction-on-a-branch
+        match update_txn_resp.status() {
+            StatusCode::OK => {
+                let mut profile_views =
+                    ProfileViews::from_response(update_txn_resp, &transaction).await?;
+                Ok(profile_views.swap_remove(0))
+            }
+            StatusCode::BAD_REQUEST => {
+                let txn_error_resp = self.transaction_error(update_txn_resp).await?;
+
+                let txn_error = txn_error_resp
+                    .errors
+                    .iter()
+                    .find(|err| {
+                        err.message.contains(user_name) && err.message.contains("not found")
+                    })
+                    .map(|_| Err(DatastoreError::UserNotFound(user_name.to_string())))
+                    .unwrap_or_else(|| {
+                        Err(DatastoreError::Unexpected(format!(
+                            "failed to update user: `{}`, error: {:?}",
+                            user_name, txn_error_resp
+                        )))
+                    });
+
+                txn_error
+            }
+            _ => Err(self.handle_unexpected_error(update_txn_resp).await),
+        }
+    }
+
+    /// Fails unless all users are known and counted, looking them up in a single transaction.
+    /// Xata can't update records conditionally, so views are only written once this passed;
+    /// users deleted in between still get their views.
+    async fn check_counted(&self, user_names: &[&str]) -> Result<(), DatastoreError> {
+        let lookups = user_names
+            .iter()
+            .map(|user_name| Op::Get(user_name.to_string()))
+            .collect();
+        let users = DatastoreOperations::transaction(self, lookups).await?;
+        for (user_name, user) in user_names.iter().zip(users) {
+            user.ok_or_else(|| DatastoreError::UserNotFound(user_name.to_string()))?
+                .check_counted()?;
+        }
+        Ok(())
+    }
+
+    /// Moves the day stats of users whose views were last counted on an earlier day over to
+    /// today, keeping that day's views as the peak when they beat it and extending the streak
+    /// when it was yesterday. Xata can't update records conditionally, so this takes a second
+    /// transaction once a day per user; views counted concurrently while the day turns may be
+    /// left out of the new day's views.
+    async fn start_days(&self, counted: &[(&str, u64, &ProfileViews)]) {
+        let today = Utc::now().date_naive();
+        let mut transaction = self.transaction();
+        for &(user_name, views, profile_views) in counted {
+            let day_stats = match profile_views.day {
+                Some(day) if day == today => continue,
+                Some(day) => DayStats {
+                    day,
+                    // the views just counted belong to today
+                    day_views: profile_views.day_views.saturating_sub(views),
+                    peak_views: profile_views.peak_views,
+                    streak: profile_views.streak,
+                    recent_views: profile_views.recent_views.clone(),
+                }
+                .start_day(today),
+                None => DayStats::new(today, 0),
+            };
+            transaction.push(
+                user_name,
+                OperationType::StartDay(DayStats {
+                    day_views: views,
+                    ..day_stats
+                }),
+            );
+        }
+        if transaction.operations.is_empty() {
+            return;
+        }
+
+        match self.execute(&transaction).await {
+            Ok(resp) if resp.status() == StatusCode::OK => {}
+            Ok(resp) => tracing::error!(
+                "failed to start day stats, reason: {}",
+                self.handle_unexpected_error(resp).await
+            ),
+            Err(err) => tracing::error!("failed to start day stats, reason: {}", err),
+        }
+    }
+
+    async fn execute(&self, transaction: &XataTransaction<'_>) -> Result<Response, DatastoreError> {
+        let _request = UpstreamRequest::start("xata", self.upstream.slow_threshold);
+        self.usage.write(transaction.operations.len() as u64);
+
+        self.send(
+            self.request(Method::POST, &self.db_endpoint)
+                .json(transaction),
+        )
+        .await
+    }
+
+    /// Sends the request, capturing it when it fails and failed requests are captured.
+    async fn send(&self, request: RequestBuilder) -> Result<Response, DatastoreError> {
+        // captured from a copy, the request itself is sent and retried as usual
+        let sent = self.capture.as_ref().and_then(|capture| {
+            let mut sent = request.try_clone()?.build().ok()?;
+            // the client adds its default headers only once the request is sent
+            for (name, value) in &http_client::default_headers(&self.upstream) {
+                sent.headers_mut()
+                    .entry(name)
+                    .or_insert_with(|| value.clone());
+            }
+            Some((capture, sent))
+        });
+        let (started_at, timer) = (Utc::now(), Instant::now());
+        let result = self.upstream.send(request).await;
+        status::record_upstream("xata", &result);
+        let Some((capture, sent)) = sent else {
+            return result.map_err(DatastoreError::Client);
+        };
+
+        let secrets: Vec<_> = secrets::SECRETS
+            .iter()
+            .filter_map(|name| Some((*name, self.secrets.get(name)?)))
+            .collect();
+        let captured = CapturedRequest::new(&sent, started_at, &secrets);
+        match result {
+            Ok(response) if response.status().is_success() => Ok(response),
+            Ok(response) => {
+                let status = response.status();
+                let headers = response.headers().clone();
+                let body = response.bytes().await.map_err(DatastoreError::Client)?;
+                capture.record(captured.with_response(
+                    status,
+                    &headers,
+                    &body,
+                    &secrets,
+                    timer.elapsed(),
+                ));
+
+                // handed back unread, so the failure is handled as if it wasn't captured
+                let mut response = hyper::http::Response::new(body);
+                *response.status_mut() = status;
+                *response.headers_mut() = headers;
+                Ok(Response::from(response))
+            }
+            Err(err) => {
+                capture.record(captured.with_error(&err, &secrets, timer.elapsed()));
+                Err(DatastoreError::Client(err))
+            }
+        }
+    }
+
+    /// Errors of the operations failing a transaction, which xata answers with 400. Other bad
+    /// requests, e.g. of malformed transactions, are unexpected errors.
+    async fn transaction_error(
+        &self,
+        response: Response,
+    ) -> Result<XataTransactionError, DatastoreError> {
+        let status_code = response.status();
+        let body = response.text().await.map_err(DatastoreError::Client)?;
+        serde_json::from_str(&body).map_err(|_| {
+            DatastoreError::Unexpected(format!(
+                "status code: {}, server error message: {}",
+                status_code, body
+            ))
         })
     }
 
@@ -55,8 +306,100 @@ impl Xata {
 
 #[derive(Clone)]
 pub(crate) enum OperationType {
-    Update,
-    Insert,
+    /// Counts a view at the given time
+    Update(DateTime<Utc>),
+    /// Onboards the user with a view at the given time
+    Insert(DateTime<Utc>),
+    /// Creates the user without views
+    Register,
+    /// Counts views, the last one at the given time
+    IncrementBy(u64, DateTime<Utc>),
+    SoftDelete(DateTime<Utc>),
+    Restore,
+    /// Deletes the record for good
+    Purge,
+    /// Operations of [`DatastoreOperations::transaction`], returning all stored columns
+    Get,
+    Create(Fields),
+    Write(Fields),
+    StartDay(DayStats),
+}
+
+// columns of the records returned by queries
+const VIEWS_COLUMNS: &[&str] = &["count", "deleted_at"];
+const RECORD_COLUMNS: &[&str] = &["count", "deleted_at", "last_viewed_at", "renamed_to"];
+const DAY_STATS_COLUMNS: &[&str] = &[
+    "day",
+    "day_views",
+    "peak_views",
+    "streak",
+    "recent_views",
+    "deleted_at",
+];
+const FLAG_COLUMNS: &[&str] = &["flagged_at", "hourly_views", "baseline", "views"];
+// flags are few, they are listed in pages of the most records xata returns at once
+const FLAGS_PAGE_SIZE: usize = 200;
+/// Table of [`DatastoreOperations::put_flag`] unless `XATA_FLAGS_TABLE_NAME` names another one.
+const FLAGS_TABLE_NAME: &str = "flags";
+
+// columns returned by every operation
+const OPERATION_COLUMNS: &[&str] = &[
+    "count",
+    "deleted_at",
+    "day",
+    "day_views",
+    "peak_views",
+    "streak",
+    "recent_views",
+];
+// columns returned by the operations of `DatastoreOperations::transaction`
+const STORED_COLUMNS: &[&str] = &[
+    "count",
+    "deleted_at",
+    "day",
+    "day_views",
+    "peak_views",
+    "streak",
+    "recent_views",
+    "last_viewed_at",
+    "renamed_to",
+];
+
+/// Fields written by an operation of `DatastoreOperations::transaction`.
+fn fields_json(fields: &Fields) -> serde_json::Map<String, Value> {
+    let mut json = serde_json::Map::new();
+    match fields.views {
+        Some(ViewsChange::Add(views)) => {
+            json.insert("count".into(), serde_json::json!({ "$increment": views }));
+        }
+        Some(ViewsChange::Subtract(views)) => {
+            json.insert("count".into(), serde_json::json!({ "$decrement": views }));
+        }
+        None => {}
+    }
+    if let Some(stats) = &fields.day_stats {
+        json.extend(day_stats_json(stats));
+    }
+    if let Some(last_viewed_at) = fields.last_viewed_at {
+        json.insert("last_viewed_at".into(), serde_json::json!(last_viewed_at));
+    }
+    if let Some(deleted_at) = fields.deleted_at {
+        json.insert("deleted_at".into(), serde_json::json!(deleted_at));
+    }
+    if let Some(renamed_to) = &fields.renamed_to {
+        json.insert("renamed_to".into(), renamed_to.as_str().into());
+    }
+    json
+}
+
+fn day_stats_json(stats: &DayStats) -> serde_json::Map<String, Value> {
+    let mut json = serde_json::Map::new();
+    json.insert("day".into(), serde_json::json!(stats.day));
+    json.insert("day_views".into(), stats.day_views.into());
+    json.insert("peak_views".into(), stats.peak_views.into());
+    json.insert("streak".into(), stats.streak.into());
+    json.insert("recent_views".into(), serde_json::json!(stats.recent_views));
+    json
 }
 
 struct TransactionMetadata<'txn> {
@@ -76,23 +419,67 @@ impl<'txn> Serialize for UserViewsOperation<'txn> {
     {
         let mut operations = serializer.serialize_map(None)?;
         operations.serialize_entry("table", &self.metadata.table)?;
-        match self.metadata.op_type {
-            OperationType::Update => {
+
+        let update_fields = match &self.metadata.op_type {
+            OperationType::Update(viewed_at) => Some(serde_json::json!({
+                "count": { "$increment": 1 },
+                "day_views": { "$increment": 1 },
+                "last_viewed_at": viewed_at,
+            })),
+            OperationType::IncrementBy(views, viewed_at) => Some(serde_json::json!({
+                "count": { "$increment": views },
+                "day_views": { "$increment": views },
+                "last_viewed_at": viewed_at,
+            })),
+            OperationType::SoftDelete(deleted_at) => {
+                Some(serde_json::json!({ "deleted_at": deleted_at }))
+            }
+            OperationType::Restore => Some(serde_json::json!({ "deleted_at": null })),
+            OperationType::Write(fields) => Some(Value::Object(fields_json(fields))),
+            OperationType::StartDay(stats) => Some(Value::Object(day_stats_json(stats))),
+            OperationType::Insert(_)
+            | OperationType::Register
+            | OperationType::Create(_)
+            | OperationType::Get
+            | OperationType::Purge => None,
+        };
+
+        match update_fields {
+            Some(fields) => {
                 operations.serialize_entry("id", &self.metadata.user_name)?;
-                operations.serialize_entry(
-                    "fields",
-                    &serde_json::json!({ "count": { "$increment": 1 } }),
-                )?;
+                operations.serialize_entry("fields", &fields)?;
             }
-            OperationType::Insert => {
-                operations.serialize_entry(
-                    "record",
-                    &serde_json::json!({ "id": &self.metadata.user_name, "count": 1 }),
-                )?;
+            None => {
+                let mut record = serde_json::json!({ "id": &self.metadata.user_name, "count": 1 });
+                match &self.metadata.op_type {
+                    OperationType::Insert(viewed_at) => {
+                        record["last_viewed_at"] = serde_json::json!(viewed_at)
+                    }
+                    OperationType::Register => record["count"] = serde_json::json!(0),
+                    OperationType::Create(fields) => {
+                        record["count"] = serde_json::json!(match fields.views {
+                            Some(ViewsChange::Add(views)) => views,
+                            Some(ViewsChange::Subtract(_)) | None => 0,
+                        });
+                        let fields = fields_json(&Fields {
+                            views: None,
+                            ..fields.clone()
+                        });
+                        for (name, value) in fields {
+                            record[name] = value;
+                        }
+                    }
+                    _ => {}
+                }
+                operations.serialize_entry("record", &record)?;
                 operations.serialize_entry("createOnly", &true)?;
             }
         }
-        operations.serialize_entry("columns", &serde_json::json!(["count"]))?;
+        let columns = match self.metadata.op_type {
+            OperationType::Create(_) | OperationType::Write(_) => STORED_COLUMNS,
+            _ => OPERATION_COLUMNS,
+        };
+        operations.serialize_entry("columns", columns)?;
         operations.end()
     }
 }
@@ -104,139 +491,924 @@ enum Operations<'txn> {
 
     #[serde(rename = "insert")]
     Insert(UserViewsOperation<'txn>),
+
+    #[serde(rename = "delete")]
+    Delete { table: &'txn str, id: &'txn str },
+
+    #[serde(rename = "get")]
+    Get {
+        table: &'txn str,
+        id: &'txn str,
+        columns: &'static [&'static str],
+    },
 }
 
+/// Operations on the records of a table, executed all at once or not at all.
 #[derive(Serialize)]
 pub(crate) struct XataTransaction<'txn> {
-    operations: [Operations<'txn>; 1],
+    #[serde(skip)]
+    table: &'txn str,
+    operations: Vec<Operations<'txn>>,
+}
+
+impl<'txn> XataTransaction<'txn> {
+    pub(crate) fn on(table: &'txn str) -> XataTransaction<'txn> {
+        XataTransaction {
+            table,
+            operations: Vec::new(),
+        }
+    }
+
+    /// Adds an operation on the user's record; its result comes at the same index of the
+    /// transaction's results.
+    pub(crate) fn with(mut self, user_name: &'txn str, op_type: OperationType) -> Self {
+        self.push(user_name, op_type);
+        self
+    }
+
+    pub(crate) fn push(&mut self, user_name: &'txn str, op_type: OperationType) {
+        let metadata = TransactionMetadata {
+            table: self.table,
+            user_name,
+            op_type: op_type.clone(),
+        };
+
+        self.operations.push(match op_type {
+            OperationType::Insert(_) | OperationType::Register | OperationType::Create(_) => {
+                Operations::Insert(UserViewsOperation { metadata })
+            }
+            OperationType::Purge => Operations::Delete {
+                table: self.table,
+                id: user_name,
+            },
+            OperationType::Get => Operations::Get {
+                table: self.table,
+                id: user_name,
+                columns: STORED_COLUMNS,
+            },
+            _ => Operations::Update(UserViewsOperation { metadata }),
+        });
+    }
+
+    /// Users of the operations, in operation order.
+    fn user_names(&self) -> impl Iterator<Item = &'txn str> + '_ {
+        self.operations.iter().map(|operation| match operation {
+            Operations::Update(operation) | Operations::Insert(operation) => {
+                operation.metadata.user_name
+            }
+            Operations::Delete { id, .. } | Operations::Get { id, .. } => id,
+        })
+    }
 }
 
 struct ProfileViews {
     count: u64,
+    deleted: bool,
+    // day the views were last counted on and the views of that day, `None` until the first
+    // views after onboarding
+    day: Option<NaiveDate>,
+    day_views: u64,
+    // most views of any earlier day
+    peak_views: u64,
+    // consecutive days with views up to the day before `day`
+    streak: u64,
+    // views of the days before `day`, the day before first
+    recent_views: Vec<u64>,
 }
 
-impl<'de> Deserialize<'de> for ProfileViews {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let value = Value::deserialize(deserializer)?;
+impl ProfileViews {
+    /// Views of the user's record, `None` unless the operation returned the record's count.
+    fn from_result(result: &OperationResult, user_name: &str) -> Option<ProfileViews> {
+        let columns = match result {
+            OperationResult::Insert { id, rows, columns }
+            | OperationResult::Update { id, rows, columns }
+                if id == user_name && *rows == 1 =>
+            {
+                columns.as_ref()?
+            }
+            OperationResult::Get { columns } => columns.as_ref()?,
+            _ => return None,
+        };
+
+        Some(ProfileViews {
+            count: columns.count?,
+            deleted: columns.deleted_at.is_some(),
+            day: columns.day,
+            day_views: columns.day_views.unwrap_or(0),
+            peak_views: columns.peak_views.unwrap_or(0),
+            streak: columns.streak.unwrap_or(0),
+            recent_views: columns.recent_views.clone().unwrap_or_default(),
+        })
+    }
 
-        let count = value["results"]
-            .get(0)
-            .and_then(|result| result["columns"].get("count"))
-            .and_then(Value::as_u64)
+    /// Views of every operation of the transaction, in operation order.
+    async fn from_response(
+        resp: Response,
+        transaction: &XataTransaction<'_>,
+    ) -> Result<Vec<ProfileViews>, DatastoreError> {
+        let results = resp
+            .json::<TransactionResults>()
+            .await
+            .map_err(DatastoreError::Client)?;
+
+        results
+            .results
+            .iter()
+            .zip(transaction.user_names())
+            .map(|(result, user_name)| ProfileViews::from_result(result, user_name))
+            .collect::<Option<Vec<_>>>()
+            .filter(|views| views.len() == transaction.operations.len())
             .ok_or_else(|| {
-                serde::de::Error::custom(format_args!(
-                    "failed to deserialize server response: {}",
-                    value
+                DatastoreError::Unexpected(format!(
+                    "transaction results without views: {:?}",
+                    results
                 ))
-            })?;
-
-        Ok(ProfileViews { count })
+            })
     }
 }
 
+/// Record of the user an operation of `DatastoreOperations::transaction` left behind, `None`
+/// for deletes and lookups of unknown users.
+fn stored_user(result: &OperationResult, user_name: &str) -> Option<StoredUser> {
+    let columns = match result {
+        OperationResult::Insert { columns, .. }
+        | OperationResult::Update { columns, .. }
+        | OperationResult::Get { columns } => columns.as_ref()?,
+        OperationResult::Delete => return None,
+    };
+
+    Some(StoredUser {
+        user_name: user_name.to_string(),
+        views: columns.count?,
+        day_stats: columns.day.map(|day| DayStats {
+            day,
+            day_views: columns.day_views.unwrap_or(0),
+            peak_views: columns.peak_views.unwrap_or(0),
+            streak: columns.streak.unwrap_or(0),
+            recent_views: columns.recent_views.clone().unwrap_or_default(),
+        }),
+        deleted_at: columns.deleted_at,
+        last_viewed_at: columns.last_viewed_at,
+        renamed_to: columns.renamed_to.clone(),
+    })
+}
+
+/// Results of a successful transaction, one per operation in operation order.
+// reference - https://xata.io/docs/api-reference/db/db_branch_name/transaction#execute-a-transaction-on-a-branch
+#[derive(Debug, Deserialize)]
+struct TransactionResults {
+    results: Vec<OperationResult>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "operation", rename_all = "lowercase")]
+enum OperationResult {
+    Insert {
+        id: String,
+        rows: u64,
+        columns: Option<RecordColumns>,
+    },
+    Update {
+        id: String,
+        rows: u64,
+        columns: Option<RecordColumns>,
+    },
+    Delete,
+    /// Columns are empty when the record doesn't exist
+    Get {
+        columns: Option<RecordColumns>,
+    },
+}
+
+/// Columns an operation asked for; columns missing from older records are `None`.
+#[derive(Debug, Deserialize)]
+struct RecordColumns {
+    count: Option<u64>,
+    deleted_at: Option<DateTime<Utc>>,
+    day: Option<NaiveDate>,
+    day_views: Option<u64>,
+    peak_views: Option<u64>,
+    streak: Option<u64>,
+    recent_views: Option<Vec<u64>>,
+    last_viewed_at: Option<DateTime<Utc>>,
+    renamed_to: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct TransactionError {
+    /// Operation which failed
+    index: Option<usize>,
     message: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct XataTransactionError {
-    errors: [TransactionError; 1],
+    errors: Vec<TransactionError>,
 }
 
-#[async_trait]
-impl DatastoreOperations for Xata {
-    async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
-        let metadata = TransactionMetadata {
-            table: self.table_name.as_str(),
-            user_name,
-            op_type: OperationType::Update,
+#[derive(Serialize)]
+struct QueryPageRequest<'q> {
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<&'q str>,
+}
+
+// reference - https://xata.io/docs/api-reference/db/db_branch_name/tables/table_name/query#query-table
+#[derive(Serialize)]
+struct ScanQuery<'q> {
+    columns: &'q [&'q str],
+    // cursors carry the filter of the query which created them, so it's only sent for the first page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    filter: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<Value>,
+    page: QueryPageRequest<'q>,
+}
+
+#[derive(Deserialize)]
+struct ViewsRecord {
+    id: String,
+    count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordMetadata {
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct DayStatsRecord {
+    #[serde(default)]
+    day: Option<NaiveDate>,
+    day_views: Option<u64>,
+    peak_views: Option<u64>,
+    #[serde(default)]
+    streak: Option<u64>,
+    #[serde(default)]
+    recent_views: Option<Vec<u64>>,
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ViewsRecordWithMetadata {
+    id: String,
+    count: u64,
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    last_viewed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    renamed_to: Option<String>,
+    xata: RecordMetadata,
+}
+
+// reference - https://xata.io/docs/api-reference/db/db_branch_name/tables/table_name/data/record_id#upsert-record-with-id
+#[derive(Serialize, Deserialize)]
+struct FlagRecord {
+    #[serde(skip_serializing)]
+    id: String,
+    flagged_at: DateTime<Utc>,
+    hourly_views: u64,
+    baseline: f64,
+    views: u64,
+}
+
+impl From<&Flag> for FlagRecord {
+    fn from(flag: &Flag) -> FlagRecord {
+        FlagRecord {
+            id: flag.user_name.clone(),
+            flagged_at: flag.flagged_at,
+            hourly_views: flag.hourly_views,
+            baseline: flag.baseline,
+            views: flag.views,
+        }
+    }
+}
+
+impl From<FlagRecord> for Flag {
+    fn from(record: FlagRecord) -> Flag {
+        Flag {
+            user_name: record.id,
+            flagged_at: record.flagged_at,
+            hourly_views: record.hourly_views,
+            baseline: record.baseline,
+            views: record.views,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryPage {
+    cursor: String,
+    more: bool,
+}
+
+#[derive(Deserialize)]
+struct QueryMeta {
+    page: QueryPage,
+}
+
+#[derive(Deserialize)]
+struct QueryResponse<R> {
+    records: Vec<R>,
+    meta: QueryMeta,
+}
+
+impl From<ViewsRecordWithMetadata> for UserRecord {
+    fn from(record: ViewsRecordWithMetadata) -> UserRecord {
+        UserRecord {
+            user_name: record.id,
+            views: record.count,
+            created_at: record.xata.created_at,
+            updated_at: record.xata.updated_at,
+            deleted_at: record.deleted_at,
+            last_viewed_at: record.last_viewed_at,
+            renamed_to: record.renamed_to,
+        }
+    }
+}
+
+impl Xata {
+    async fn query_page<R: DeserializeOwned>(
+        &self,
+        columns: &[&str],
+        cursor: Option<&str>,
+        limit: usize,
+        include_deleted: bool,
+        id_filter: Option<Value>,
+        sort: Option<Value>,
+    ) -> Result<(Vec<R>, Option<String>), DatastoreError> {
+        let mut filter = serde_json::Map::new();
+        if !include_deleted {
+            filter.insert("$notExists".to_string(), "deleted_at".into());
+        }
+        if let Some(id_filter) = id_filter {
+            filter.insert("id".to_string(), id_filter);
+        }
+        let filter = match cursor.is_none() && !filter.is_empty() {
+            true => Some(Value::Object(filter)),
+            false => None,
         };
 
-        let transaction = XataTransaction {
-            operations: [Operations::Update(UserViewsOperation { metadata })],
+        let query = ScanQuery {
+            columns,
+            filter,
+            sort: sort.filter(|_| cursor.is_none()),
+            page: QueryPageRequest {
+                size: limit,
+                after: cursor,
+            },
         };
 
-        let update_txn_resp = self
-            .client
-            .post(self.db_endpoint.as_str())
-            .json(&transaction)
-            .send()
-            .await
-            .map_err(DatastoreError::Client)?;
+        self.query(&self.query_endpoint, &query).await
+    }
 
-        // xata returns 400 if transaction fails with some error.
-        // reference - https://xata.io/docs/api-reference/db/db_branch_name/transaction#execute-a-transaction-on-a-branch
-        match update_txn_resp.status() {
+    /// Sends the query to the query api at `endpoint`, returning the records and the cursor of
+    /// the next page, if any.
+    async fn query<R: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        query: &ScanQuery<'_>,
+    ) -> Result<(Vec<R>, Option<String>), DatastoreError> {
+        let query_resp = {
+            let _request = UpstreamRequest::start("xata", self.upstream.slow_threshold);
+            self.usage.read(1);
+            self.send(self.request(Method::POST, endpoint).json(query))
+                .await?
+        };
+
+        match query_resp.status() {
             StatusCode::OK => {
-                let count = update_txn_resp
-                    .json::<ProfileViews>()
+                let query_result = query_resp
+                    .json::<QueryResponse<R>>()
                     .await
-                    .map_err(DatastoreError::Client)?
-                    .count;
+                    .map_err(DatastoreError::Client)?;
+
+                let next_cursor = match query_result.meta.page.more {
+                    true => Some(query_result.meta.page.cursor),
+                    false => None,
+                };
+
+                Ok((query_result.records, next_cursor))
+            }
+            _ => Err(self.handle_unexpected_error(query_resp).await),
+        }
+    }
+
+    /// Url of the user's record in the flags table; user names are escaped as a path segment.
+    fn flag_url(&self, user_name: &str) -> Result<reqwest::Url, DatastoreError> {
+        let mut url = reqwest::Url::parse(&self.flags_endpoint).map_err(|err| {
+            DatastoreError::Unexpected(format!("invalid flags endpoint: {}", err))
+        })?;
+        url.path_segments_mut()
+            .map_err(|_| DatastoreError::Unexpected("invalid flags endpoint".to_string()))?
+            .extend(["data", user_name]);
+        Ok(url)
+    }
+}
 
-                Ok(count)
+// every operation completes before its response is served, nothing is left to write
+impl Shutdown for Xata {}
+
+#[async_trait]
+impl DatastoreOperations for Xata {
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        self.check_counted(&[user_name]).await?;
+        let profile_views = self
+            .update(user_name, OperationType::Update(Utc::now()))
+            .await?;
+        if profile_views.deleted {
+            tracing::warn!(
+                "user `{}` was deleted while counting their view, the view is kept",
+                user_name
+            );
+            return Err(DatastoreError::UserDeleted(user_name.to_string()));
+        }
+
+        self.start_days(&[(user_name, 1, &profile_views)]).await;
+        Ok(profile_views.count)
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
+        let transaction = self
+            .transaction()
+            .with(user_name, OperationType::Insert(Utc::now()));
+        let insert_txn_resp = self.execute(&transaction).await?;
+
+        match insert_txn_resp.status() {
+            StatusCode::OK => {
+                let profile_views = ProfileViews::from_response(insert_txn_resp, &transaction)
+                    .await?
+                    .swap_remove(0);
+
+                self.start_days(&[(user_name, 1, &profile_views)]).await;
+                Ok(profile_views.count)
             }
             StatusCode::BAD_REQUEST => {
-                let txn_error_resp = update_txn_resp
-                    .json::<XataTransactionError>()
-                    .await
-                    .map_err(DatastoreError::Client)?;
+                let txn_error_resp = self.transaction_error(insert_txn_resp).await?;
 
-                let txn_error = txn_error_resp
+                // a concurrent request onboarded the user first, the view is counted on top
+                match txn_error_resp
                     .errors
                     .iter()
-                    .find(|err| {
-                        err.message.contains(user_name) && err.message.contains("not found")
+                    .any(|err| err.message.contains("already exists"))
+                {
+                    true => {
+                        tracing::info!(
+                            "user `{}` onboarded concurrently, counting view",
+                            user_name
+                        );
+                        self.get_latest_views(user_name).await
+                    }
+                    false => Err(DatastoreError::Unexpected(format!(
+                        "failed to onboard user: `{}`, error: {:?}",
+                        user_name, txn_error_resp
+                    ))),
+                }
+            }
+            _ => Err(self.handle_unexpected_error(insert_txn_resp).await),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn register_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        let transaction = self.transaction().with(user_name, OperationType::Register);
+        let txn_resp = self.execute(&transaction).await?;
+
+        match txn_resp.status() {
+            StatusCode::OK => Ok(()),
+            StatusCode::BAD_REQUEST => {
+                let txn_error_resp = self.transaction_error(txn_resp).await?;
+
+                match txn_error_resp
+                    .errors
+                    .iter()
+                    .any(|err| err.message.contains("already exists"))
+                {
+                    true => Err(DatastoreError::UserExists(user_name.to_string())),
+                    false => Err(DatastoreError::Unexpected(format!(
+                        "failed to register user: `{}`, error: {:?}",
+                        user_name, txn_error_resp
+                    ))),
+                }
+            }
+            _ => Err(self.handle_unexpected_error(txn_resp).await),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, users = increments.len()))]
+    async fn increment_views(
+        &self,
+        increments: &[Increment],
+    ) -> Result<Vec<UserViews>, DatastoreError> {
+        let user_names: Vec<_> = increments
+            .iter()
+            .map(|increment| increment.user_name.as_str())
+            .collect();
+        self.check_counted(&user_names).await?;
+
+        let now = Utc::now();
+        let transaction =
+            self.batch_transaction(increments, |views| OperationType::IncrementBy(views, now));
+        let txn_resp = self.execute(&transaction).await?;
+
+        let views = match txn_resp.status() {
+            StatusCode::OK => ProfileViews::from_response(txn_resp, &transaction).await?,
+            // a failing operation fails the whole transaction, nothing got incremented
+            StatusCode::BAD_REQUEST => {
+                let txn_error_resp = self.transaction_error(txn_resp).await?;
+
+                let not_found = increments.iter().find(|increment| {
+                    txn_error_resp.errors.iter().any(|err| {
+                        err.message.contains(&increment.user_name)
+                            && err.message.contains("not found")
                     })
-                    .map(|_| Err(DatastoreError::UserNotFound(user_name.to_string())))
-                    .unwrap_or_else(|| {
-                        Err(DatastoreError::Unexpected(format!(
-                            "failed to update count for user: `{}`, error: {:?}",
-                            user_name, txn_error_resp
-                        )))
-                    });
+                });
+                return Err(match not_found {
+                    Some(increment) => DatastoreError::UserNotFound(increment.user_name.clone()),
+                    None => DatastoreError::Unexpected(format!(
+                        "failed to increment views, error: {:?}",
+                        txn_error_resp
+                    )),
+                });
+            }
+            _ => return Err(self.handle_unexpected_error(txn_resp).await),
+        };
+
+        let counted: Vec<(&str, u64, &ProfileViews)> = increments
+            .iter()
+            .zip(&views)
+            .map(|(increment, views)| (increment.user_name.as_str(), increment.views, views))
+            .collect();
+        self.start_days(&counted).await;
+
+        Ok(increments
+            .iter()
+            .zip(views)
+            .map(|(increment, views)| UserViews {
+                user_name: increment.user_name.clone(),
+                views: views.count,
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn delete_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.update(user_name, OperationType::SoftDelete(Utc::now()))
+            .await
+            .map(|_| ())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn restore_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        self.update(user_name, OperationType::Restore)
+            .await
+            .map(|_| ())
+    }
+
+    // deletes of unknown records succeed, returning no rows
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn purge_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+        let transaction = self.transaction().with(user_name, OperationType::Purge);
+        let resp = self.execute(&transaction).await?;
+
+        match resp.status() {
+            StatusCode::OK => Ok(()),
+            _ => Err(self.handle_unexpected_error(resp).await),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, ops = ops.len()))]
+    async fn transaction(&self, ops: Vec<Op>) -> Result<Vec<Option<StoredUser>>, DatastoreError> {
+        let mut transaction = self.transaction();
+        for op in &ops {
+            let op_type = match op {
+                Op::Get(_) => OperationType::Get,
+                Op::Insert(_, fields) => OperationType::Create(fields.clone()),
+                Op::Update(_, fields) => OperationType::Write(fields.clone()),
+            };
+            transaction.push(op.user_name(), op_type);
+        }
+        let txn_resp = self.execute(&transaction).await?;
+
+        match txn_resp.status() {
+            StatusCode::OK => {
+                let results = txn_resp
+                    .json::<TransactionResults>()
+                    .await
+                    .map_err(DatastoreError::Client)?;
+                if results.results.len() != ops.len() {
+                    return Err(DatastoreError::Unexpected(format!(
+                        "transaction results don't match its operations: {:?}",
+                        results
+                    )));
+                }
+
+                Ok(results
+                    .results
+                    .iter()
+                    .zip(&ops)
+                    .map(|(result, op)| stored_user(result, op.user_name()))
+                    .collect())
+            }
+            StatusCode::BAD_REQUEST => {
+                let txn_error_resp = self.transaction_error(txn_resp).await?;
+
+                // a failing operation fails the whole transaction, nothing got written
+                let failed = txn_error_resp.errors.iter().find_map(|err| {
+                    let op = err
+                        .index
+                        .and_then(|index| ops.get(index))
+                        .or_else(|| ops.iter().find(|op| err.message.contains(op.user_name())))?;
+                    let user_name = op.user_name().to_string();
+                    match &err.message {
+                        message if message.contains("not found") => {
+                            Some(DatastoreError::UserNotFound(user_name))
+                        }
+                        message if message.contains("already exists") => {
+                            Some(DatastoreError::UserExists(user_name))
+                        }
+                        _ => None,
+                    }
+                });
+                Err(failed.unwrap_or_else(|| {
+                    DatastoreError::Unexpected(format!(
+                        "failed to run transaction, error: {:?}",
+                        txn_error_resp
+                    ))
+                }))
+            }
+            _ => Err(self.handle_unexpected_error(txn_resp).await),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, limit = limit))]
+    async fn scan(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        let (records, next_cursor) = self
+            .query_page::<ViewsRecord>(VIEWS_COLUMNS, cursor.as_deref(), limit, false, None, None)
+            .await?;
+
+        let users = records
+            .into_iter()
+            .map(|record| UserViews {
+                user_name: record.id,
+                views: record.count,
+            })
+            .collect();
+
+        Ok(Page { users, next_cursor })
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, prefix = prefix, limit = limit))]
+    async fn scan_prefix(
+        &self,
+        prefix: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserViews>, DatastoreError> {
+        let (records, next_cursor) = self
+            .query_page::<ViewsRecord>(
+                VIEWS_COLUMNS,
+                cursor.as_deref(),
+                limit,
+                false,
+                Some(serde_json::json!({ "$startsWith": prefix })),
+                None,
+            )
+            .await?;
+
+        let users = records
+            .into_iter()
+            .map(|record| UserViews {
+                user_name: record.id,
+                views: record.count,
+            })
+            .collect();
+
+        Ok(Page { users, next_cursor })
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, users = user_names.len()))]
+    async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, DatastoreError> {
+        let (records, _) = self
+            .query_page::<ViewsRecord>(
+                VIEWS_COLUMNS,
+                None,
+                user_names.len(),
+                false,
+                Some(serde_json::json!({ "$any": user_names })),
+                None,
+            )
+            .await?;
+
+        // records come back in id order, users are returned in the order asked for
+        Ok(user_names
+            .iter()
+            .filter_map(|user_name| {
+                let record = records.iter().find(|record| &record.id == user_name)?;
+                Some(UserViews {
+                    user_name: record.id.clone(),
+                    views: record.count,
+                })
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, DatastoreError> {
+        let (records, _) = self
+            .query_page::<ViewsRecordWithMetadata>(
+                RECORD_COLUMNS,
+                None,
+                1,
+                true,
+                Some(serde_json::json!(user_name)),
+                None,
+            )
+            .await?;
+
+        Ok(records.into_iter().next().map(UserRecord::from))
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, DatastoreError> {
+        let (records, _) = self
+            .query_page::<DayStatsRecord>(
+                DAY_STATS_COLUMNS,
+                None,
+                1,
+                true,
+                Some(serde_json::json!(user_name)),
+                None,
+            )
+            .await?;
+
+        match records.into_iter().next() {
+            Some(record) if record.deleted_at.is_some() => {
+                Err(DatastoreError::UserDeleted(user_name.to_string()))
+            }
+            Some(record) => Ok(Some(
+                record
+                    .peak_views
+                    .unwrap_or(0)
+                    .max(record.day_views.unwrap_or(0)),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, user = user_name))]
+    async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, DatastoreError> {
+        let (records, _) = self
+            .query_page::<DayStatsRecord>(
+                DAY_STATS_COLUMNS,
+                None,
+                1,
+                true,
+                Some(serde_json::json!(user_name)),
+                None,
+            )
+            .await?;
+
+        match records.into_iter().next() {
+            Some(record) if record.deleted_at.is_some() => {
+                Err(DatastoreError::UserDeleted(user_name.to_string()))
+            }
+            Some(record) => Ok(record.day.map(|day| DayStats {
+                day,
+                day_views: record.day_views.unwrap_or(0),
+                peak_views: record.peak_views.unwrap_or(0),
+                streak: record.streak.unwrap_or(0),
+                recent_views: record.recent_views.unwrap_or_default(),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, limit = limit))]
+    async fn list_users(
+        &self,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<UserRecord>, DatastoreError> {
+        let (records, next_cursor) = self
+            .query_page::<ViewsRecordWithMetadata>(
+                RECORD_COLUMNS,
+                cursor.as_deref(),
+                limit,
+                true,
+                None,
+                None,
+            )
+            .await?;
+
+        let users = records.into_iter().map(UserRecord::from).collect();
+
+        Ok(Page { users, next_cursor })
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", table = %self.table_name, limit = limit))]
+    async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, DatastoreError> {
+        let sort = serde_json::json!({ "count": "desc" });
+        let (records, _) = self
+            .query_page::<ViewsRecord>(VIEWS_COLUMNS, None, limit, false, None, Some(sort))
+            .await?;
 
-                txn_error
+        Ok(records
+            .into_iter()
+            .map(|record| UserViews {
+                user_name: record.id,
+                views: record.count,
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata", user = %flag.user_name))]
+    async fn put_flag(&self, flag: &Flag) -> Result<(), DatastoreError> {
+        let url = self.flag_url(&flag.user_name)?;
+        let response = {
+            let _request = UpstreamRequest::start("xata", self.upstream.slow_threshold);
+            self.usage.write(1);
+            self.send(
+                self.request(Method::PUT, url.as_str())
+                    .json(&FlagRecord::from(flag)),
+            )
+            .await?
+        };
+
+        match response.status() {
+            StatusCode::OK | StatusCode::CREATED => Ok(()),
+            _ => Err(self.handle_unexpected_error(response).await),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(backend = "xata"))]
+    async fn list_flags(&self) -> Result<Vec<Flag>, DatastoreError> {
+        let endpoint = format!("{}/query", self.flags_endpoint);
+        let mut flags = Vec::new();
+        let mut cursor = None;
+        loop {
+            let query = ScanQuery {
+                columns: FLAG_COLUMNS,
+                filter: None,
+                sort: cursor.is_none().then(|| serde_json::json!({ "id": "asc" })),
+                page: QueryPageRequest {
+                    size: FLAGS_PAGE_SIZE,
+                    after: cursor.as_deref(),
+                },
+            };
+            let (records, next_cursor) = self.query::<FlagRecord>(&endpoint, &query).await?;
+            flags.extend(records.into_iter().map(Flag::from));
+            match next_cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => return Ok(flags),
             }
-            _ => Err(self.handle_unexpected_error(update_txn_resp).await),
         }
     }
 
-    async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
-        let metadata = TransactionMetadata {
-            table: self.table_name.as_str(),
-            user_name,
-            op_type: OperationType::Insert,
+    #[tracing::instrument(skip_all, fields(backend = "xata", user = user_name))]
+    async fn delete_flag(&self, user_name: &str) -> Result<bool, DatastoreError> {
+        let url = self.flag_url(user_name)?;
+        let response = {
+            let _request = UpstreamRequest::start("xata", self.upstream.slow_threshold);
+            self.usage.write(1);
+            self.send(self.request(Method::DELETE, url.as_str()))
+                .await?
         };
 
-        let transaction = XataTransaction {
-            operations: [Operations::Insert(UserViewsOperation { metadata })],
-        };
+        match response.status() {
+            StatusCode::OK | StatusCode::NO_CONTENT => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            _ => Err(self.handle_unexpected_error(response).await),
+        }
+    }
 
-        let insert_txn_resp = self
-            .client
-            .post(self.db_endpoint.as_str())
-            .json(&transaction)
-            .send()
-            .await
-            .map_err(DatastoreError::Client)?;
+    async fn warm_up(&self) {
+        // any response leaves a pooled connection behind, the status doesn't matter
+        match self.request(Method::HEAD, &self.db_endpoint).send().await {
+            Ok(_) => tracing::info!("connection to xata warmed up"),
+            Err(err) => tracing::warn!("failed to warm up connection to xata: {}", err),
+        }
+    }
 
-        match insert_txn_resp.status() {
-            StatusCode::OK => {
-                let count = insert_txn_resp
-                    .json::<ProfileViews>()
-                    .await
-                    .map_err(DatastoreError::Client)?
-                    .count;
+    fn usage(&self) -> Vec<DatastoreUsage> {
+        vec![self.usage.usage()]
+    }
 
-                Ok(count)
-            }
-            _ => Err(self.handle_unexpected_error(insert_txn_resp).await),
-        }
+    fn captured_requests(&self, limit: usize) -> Vec<CapturedRequest> {
+        self.capture
+            .as_ref()
+            .map(|capture| capture.requests(limit))
+            .unwrap_or_default()
     }
 }
 
@@ -244,17 +1416,18 @@ impl DatastoreOperations for Xata {
 mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
-    use serde_json;
-    use serial_test::serial;
+    use wiremock::matchers::{body_partial_json, body_string, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[test]
     fn test_serialize_update_user_views_operation() {
-        let serialized =
-            serde_json::to_string(&test_helpers::user_views_transaction(OperationType::Update))
-                .unwrap();
+        let serialized = serde_json::to_string(&test_helpers::user_views_transaction(
+            OperationType::Update(test_helpers::viewed_at()),
+        ))
+        .unwrap();
 
         let expected = format!(
-            r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}}}},"columns":["count"]}}}}]}}"#,
+            r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}},"day_views":{{"$increment":1}},"last_viewed_at":"2023-06-01T12:00:00Z"}},"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
             test_helpers::TEST_TABLE_NAME,
             test_helpers::TEST_USER_NAME
         );
@@ -263,72 +1436,250 @@ mod tests {
 
     #[test]
     fn test_serialize_insert_user_views_operation() {
-        let serialized =
-            serde_json::to_string(&test_helpers::user_views_transaction(OperationType::Insert))
-                .unwrap();
+        let serialized = serde_json::to_string(&test_helpers::user_views_transaction(
+            OperationType::Insert(test_helpers::viewed_at()),
+        ))
+        .unwrap();
 
         let expected = format!(
-            r#"{{"operations":[{{"insert":{{"table":"{}","record":{{"count":1,"id":"{}"}},"createOnly":true,"columns":["count"]}}}}]}}"#,
+            r#"{{"operations":[{{"insert":{{"table":"{}","record":{{"count":1,"id":"{}","last_viewed_at":"2023-06-01T12:00:00Z"}},"createOnly":true,"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
             test_helpers::TEST_TABLE_NAME,
             test_helpers::TEST_USER_NAME
         );
         assert_eq!(serialized, expected);
     }
 
+    #[test]
+    fn test_serialize_transaction_of_several_operations() {
+        let transaction = XataTransaction::on(test_helpers::TEST_TABLE_NAME)
+            .with("alice", OperationType::Insert(test_helpers::viewed_at()))
+            .with(
+                "bob",
+                OperationType::IncrementBy(3, test_helpers::viewed_at()),
+            )
+            .with("alice", OperationType::Restore)
+            .with("carol", OperationType::Purge);
+        let serialized = serde_json::to_value(&transaction).unwrap();
+
+        let operations = serialized["operations"].as_array().unwrap();
+        assert_eq!(operations.len(), 4);
+        assert_eq!(operations[0]["insert"]["record"]["id"], "alice");
+        assert_eq!(operations[1]["update"]["id"], "bob");
+        assert_eq!(operations[1]["update"]["fields"]["count"]["$increment"], 3);
+        assert_eq!(
+            operations[1]["update"]["fields"]["last_viewed_at"],
+            "2023-06-01T12:00:00Z"
+        );
+        assert_eq!(operations[2]["update"]["fields"]["deleted_at"], Value::Null);
+        assert_eq!(
+            operations[3],
+            serde_json::json!({ "delete": { "table": test_helpers::TEST_TABLE_NAME, "id": "carol" } })
+        );
+        assert_eq!(
+            transaction.user_names().collect::<Vec<_>>(),
+            vec!["alice", "bob", "alice", "carol"]
+        );
+    }
+
+    #[test]
+    fn test_serialize_operations_of_generic_transaction() {
+        let fields = Fields {
+            views: Some(ViewsChange::Add(5)),
+            day_stats: Some(DayStats {
+                day: "2023-06-01".parse().unwrap(),
+                day_views: 2,
+                peak_views: 4,
+                streak: 3,
+                recent_views: vec![1, 0, 3],
+            }),
+            deleted_at: Some(None),
+            renamed_to: Some("alicia".to_string()),
+            ..Fields::default()
+        };
+        let transaction = XataTransaction::on(test_helpers::TEST_TABLE_NAME)
+            .with("alice", OperationType::Get)
+            .with("alice", OperationType::Write(fields.clone()))
+            .with("bob", OperationType::Create(fields))
+            .with(
+                "carol",
+                OperationType::Write(Fields {
+                    views: Some(ViewsChange::Subtract(2)),
+                    ..Fields::default()
+                }),
+            );
+        let serialized = serde_json::to_value(&transaction).unwrap();
+
+        let operations = serialized["operations"].as_array().unwrap();
+        assert_eq!(
+            operations[0],
+            serde_json::json!({ "get": {
+                "table": test_helpers::TEST_TABLE_NAME,
+                "id": "alice",
+                "columns": STORED_COLUMNS,
+            } })
+        );
+        assert_eq!(
+            operations[1]["update"]["fields"],
+            serde_json::json!({
+                "count": { "$increment": 5 },
+                "day": "2023-06-01",
+                "day_views": 2,
+                "peak_views": 4,
+                "streak": 3,
+                "recent_views": [1, 0, 3],
+                "deleted_at": null,
+                "renamed_to": "alicia",
+            })
+        );
+        assert_eq!(
+            operations[2]["insert"]["record"],
+            serde_json::json!({
+                "id": "bob",
+                "count": 5,
+                "day": "2023-06-01",
+                "day_views": 2,
+                "peak_views": 4,
+                "streak": 3,
+                "recent_views": [1, 0, 3],
+                "deleted_at": null,
+                "renamed_to": "alicia",
+            })
+        );
+        assert_eq!(
+            operations[2]["insert"]["columns"],
+            serde_json::json!(STORED_COLUMNS)
+        );
+        assert_eq!(
+            operations[3]["update"]["fields"],
+            serde_json::json!({ "count": { "$decrement": 2 } })
+        );
+    }
+
+    #[test]
+    fn it_parses_results_of_every_operation() {
+        let results: TransactionResults = serde_json::from_str(
+            r#"{"results":[
+                {"operation":"update","id":"alice","rows":1,"columns":{"count":13,"day":"2023-06-01","day_views":3}},
+                {"operation":"insert","id":"bob","rows":1,"columns":{"count":1}},
+                {"operation":"update","id":"carol","rows":0,"columns":{"count":7}},
+                {"operation":"get","columns":{"count":5,"deleted_at":"2023-06-01T00:00:00Z"}},
+                {"operation":"get","columns":{}},
+                {"operation":"delete","rows":1}
+            ]}"#,
+        )
+        .unwrap();
+        let views = |index: usize, user_name| {
+            ProfileViews::from_result(&results.results[index], user_name)
+                .map(|views| (views.count, views.deleted, views.day_views))
+        };
+
+        assert_eq!(views(0, "alice"), Some((13, false, 3)));
+        assert_eq!(
+            ProfileViews::from_result(&results.results[0], "alice")
+                .unwrap()
+                .day,
+            NaiveDate::from_ymd_opt(2023, 6, 1)
+        );
+        assert_eq!(views(1, "bob"), Some((1, false, 0)));
+        // results are mapped back to their operation's user
+        assert_eq!(views(1, "alice"), None);
+        assert_eq!(views(2, "carol"), None);
+        assert_eq!(views(3, "dave"), Some((5, true, 0)));
+        assert_eq!(views(4, "erin"), None);
+        assert_eq!(views(5, "frank"), None);
+    }
+
     #[tokio::test]
-    #[serial]
     async fn it_gets_latest_views_for_onboarded_user() {
-        let expected_count = 998 as u64;
+        let expected_count = 998_u64;
 
-        let mock = test_helpers::mock_xata_server()
-            .match_body(
-                format!(
-                    r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}}}},"columns":["count"]}}}}]}}"#,
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(
+            &server,
+            &[(test_helpers::TEST_USER_NAME, r#"{"count":10}"#)],
+        )
+        .await;
+        test_helpers::mock_transaction()
+            .and(test_helpers::viewed_body(format!(
+                    r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}},"day_views":{{"$increment":1}},"last_viewed_at":"VIEWED_AT"}},"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
                     test_helpers::TEST_TABLE_NAME,
                     test_helpers::TEST_USER_NAME
-                ).as_str(),
-            )
-            .with_status(200)
-            .with_body(
+                )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
                 format!(r#"{{"results":[{{"columns":{{"count":{}}},"id":"{}","operation":"update","rows":1}}]}}"#, expected_count, test_helpers::TEST_USER_NAME
-                ).as_str())
-            .create_async().await;
+                )))
+            .expect(1)
+            .mount(&server)
+            .await;
 
-        let count = Xata::new()
-            .unwrap()
+        let count = test_helpers::xata(&server)
             .get_latest_views(test_helpers::TEST_USER_NAME)
             .await;
 
-        mock.assert_async().await;
         assert_eq!(count.unwrap(), 998);
     }
 
     #[tokio::test]
-    #[serial]
+    async fn it_starts_day_stats_on_first_view_of_the_day() {
+        let today = Utc::now().date_naive();
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(
+            &server,
+            &[(test_helpers::TEST_USER_NAME, r#"{"count":10}"#)],
+        )
+        .await;
+        test_helpers::mock_transaction()
+            .and(body_partial_json(serde_json::json!(
+                {"operations":[{"update":{"fields":{"count":{"$increment":1}}}}]}
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                format!(r#"{{"results":[{{"columns":{{"count":42,"day":"{}","day_views":8,"peak_views":5,"streak":4,"recent_views":[2,3]}},"id":"{}","operation":"update","rows":1}}]}}"#, today.pred_opt().unwrap(), test_helpers::TEST_USER_NAME
+                )))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // the view just counted starts today, the ones before make yesterday the peak, extend the
+        // streak and become the views of yesterday
+        test_helpers::mock_transaction()
+            .and(body_partial_json(serde_json::json!(
+                {"operations":[{"update":{
+                    "id":test_helpers::TEST_USER_NAME,
+                    "fields":{
+                        "day":today,
+                        "day_views":1,
+                        "peak_views":7,
+                        "streak":5,
+                        "recent_views":[7,2,3]
+                    }
+                }}]}
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"results":[]}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let count = test_helpers::xata(&server)
+            .get_latest_views(test_helpers::TEST_USER_NAME)
+            .await;
+
+        assert_eq!(count.unwrap(), 42);
+    }
+
+    #[tokio::test]
     async fn it_returns_user_not_found_error_for_non_onboarded_user() {
-        let mock = test_helpers::mock_xata_server()
-            .match_body(
-                format!(
-                    r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}}}},"columns":["count"]}}}}]}}"#,
-                    test_helpers::TEST_TABLE_NAME,
-                    test_helpers::TEST_USER_NAME
-                ).as_str(),
-            )
-            .with_status(400)
-            .with_body(
-                format!(r#"{{"errors":[{{"index":0,"message":"table [{}]: record [{}] not found"}}]}}"#,
-                        test_helpers::TEST_TABLE_NAME,
-                        test_helpers::TEST_USER_NAME
-                ).as_str(),
-            )
-            .create_async().await;
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(&server, &[(test_helpers::TEST_USER_NAME, "{}")]).await;
+        // unknown users aren't written to
+        test_helpers::mock_transaction()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
 
-        let count = Xata::new()
-            .unwrap()
+        let count = test_helpers::xata(&server)
             .get_latest_views(test_helpers::TEST_USER_NAME)
             .await;
 
-        mock.assert_async().await;
         assert_eq!(
             count.unwrap_err().to_string(),
             DatastoreError::UserNotFound(test_helpers::TEST_USER_NAME.to_string()).to_string()
@@ -336,26 +1687,81 @@ mod tests {
     }
 
     #[tokio::test]
-    #[serial]
+    async fn it_returns_user_deleted_error_for_deleted_user_without_counting() {
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(
+            &server,
+            &[(
+                test_helpers::TEST_USER_NAME,
+                r#"{"count":10,"deleted_at":"2023-06-01T00:00:00Z"}"#,
+            )],
+        )
+        .await;
+        test_helpers::mock_transaction()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let count = test_helpers::xata(&server)
+            .get_latest_views(test_helpers::TEST_USER_NAME)
+            .await;
+
+        assert_eq!(
+            count.unwrap_err().to_string(),
+            DatastoreError::UserDeleted(test_helpers::TEST_USER_NAME.to_string()).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn it_returns_user_renamed_error_for_renamed_user_without_counting() {
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(
+            &server,
+            &[(
+                test_helpers::TEST_USER_NAME,
+                r#"{"count":0,"deleted_at":"2023-06-01T00:00:00Z","renamed_to":"new_user"}"#,
+            )],
+        )
+        .await;
+
+        let count = test_helpers::xata(&server)
+            .get_latest_views(test_helpers::TEST_USER_NAME)
+            .await;
+
+        assert_eq!(
+            count.unwrap_err().to_string(),
+            DatastoreError::UserRenamed(
+                test_helpers::TEST_USER_NAME.to_string(),
+                "new_user".to_string()
+            )
+            .to_string()
+        );
+    }
+
+    #[tokio::test]
     async fn it_handles_unexpected_error_while_fetching_latest_views() {
-        let mock = test_helpers::mock_xata_server()
-            .match_body(
-                format!(
-                    r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}}}},"columns":["count"]}}}}]}}"#,
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(
+            &server,
+            &[(test_helpers::TEST_USER_NAME, r#"{"count":10}"#)],
+        )
+        .await;
+        test_helpers::mock_transaction()
+            .and(test_helpers::viewed_body(format!(
+                    r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}},"day_views":{{"$increment":1}},"last_viewed_at":"VIEWED_AT"}},"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
                     test_helpers::TEST_TABLE_NAME,
                     test_helpers::TEST_USER_NAME
-                ).as_str(),
-            )
-            .with_status(500)
-            .with_body(r#"unavailable"#)
-            .create_async().await;
+                )))
+            .respond_with(ResponseTemplate::new(500).set_body_string("unavailable"))
+            .expect(1)
+            .mount(&server)
+            .await;
 
-        let count = Xata::new()
-            .unwrap()
+        let count = test_helpers::xata(&server)
             .get_latest_views(test_helpers::TEST_USER_NAME)
             .await;
 
-        mock.assert_async().await;
         assert_eq!(
             count.unwrap_err().to_string(),
             DatastoreError::Unexpected(
@@ -366,53 +1772,201 @@ mod tests {
         );
     }
 
+    // reference - https://xata.io/docs/api-reference/db/db_branch_name/transaction#execute-a-transaction-on-a-branch
+    #[tokio::test]
+    async fn it_maps_every_documented_transaction_error() {
+        let cases = [
+            (
+                400,
+                r#"{"errors":[{"index":0,"message":"table [profile_views]: record [test_user] not found"}]}"#,
+                DatastoreError::UserNotFound(test_helpers::TEST_USER_NAME.to_string()).to_string(),
+            ),
+            (
+                400,
+                r#"{"errors":[{"index":0,"message":"column [count]: type mismatch: expected int"}]}"#,
+                r#"unexpected error: failed to update user: `test_user`, error: XataTransactionError { errors: [TransactionError { index: Some(0), message: "column [count]: type mismatch: expected int" }] }"#.to_string(),
+            ),
+            (
+                400,
+                r#"{"id":"b7ad5c0c","message":"invalid transaction: operation [0]: table [profile_views] not found"}"#,
+                r#"unexpected error: status code: 400 Bad Request, server error message: {"id":"b7ad5c0c","message":"invalid transaction: operation [0]: table [profile_views] not found"}"#.to_string(),
+            ),
+            (
+                401,
+                r#"{"id":"b7ad5c0c","message":"invalid API key"}"#,
+                r#"unexpected error: status code: 401 Unauthorized, server error message: {"id":"b7ad5c0c","message":"invalid API key"}"#.to_string(),
+            ),
+            (
+                404,
+                r#"{"id":"b7ad5c0c","message":"branch [test_branch] not found"}"#,
+                r#"unexpected error: status code: 404 Not Found, server error message: {"id":"b7ad5c0c","message":"branch [test_branch] not found"}"#.to_string(),
+            ),
+            (
+                429,
+                r#"{"id":"b7ad5c0c","message":"rate limit exceeded"}"#,
+                r#"unexpected error: status code: 429 Too Many Requests, server error message: {"id":"b7ad5c0c","message":"rate limit exceeded"}"#.to_string(),
+            ),
+            (
+                503,
+                r#"{"id":"b7ad5c0c","message":"service unavailable"}"#,
+                r#"unexpected error: status code: 503 Service Unavailable, server error message: {"id":"b7ad5c0c","message":"service unavailable"}"#.to_string(),
+            ),
+        ];
+
+        for (status, body, expected) in cases {
+            let server = MockServer::start().await;
+            test_helpers::mock_lookup(
+                &server,
+                &[(test_helpers::TEST_USER_NAME, r#"{"count":10}"#)],
+            )
+            .await;
+            test_helpers::mock_transaction()
+                .respond_with(ResponseTemplate::new(status).set_body_string(body))
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            let count = test_helpers::xata(&server)
+                .get_latest_views(test_helpers::TEST_USER_NAME)
+                .await;
+
+            assert_eq!(
+                count.unwrap_err().to_string(),
+                expected,
+                "{} {}",
+                status,
+                body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn it_maps_failed_operations_of_transactions_to_their_user() {
+        let add_view = Fields {
+            views: Some(ViewsChange::Add(1)),
+            ..Fields::default()
+        };
+        let ops = vec![
+            Op::Update("alice".to_string(), add_view.clone()),
+            Op::Insert("bob".to_string(), add_view),
+        ];
+        let cases = [
+            (
+                r#"{"errors":[{"index":0,"message":"table [profile_views]: record [alice] not found"}]}"#,
+                DatastoreError::UserNotFound("alice".to_string()),
+            ),
+            (
+                r#"{"errors":[{"index":1,"message":"record with ID [bob] already exists"}]}"#,
+                DatastoreError::UserExists("bob".to_string()),
+            ),
+            // errors without an index are told apart by the user they name
+            (
+                r#"{"errors":[{"message":"record with ID [bob] already exists"}]}"#,
+                DatastoreError::UserExists("bob".to_string()),
+            ),
+        ];
+
+        for (body, expected) in cases {
+            let server = MockServer::start().await;
+            test_helpers::mock_transaction()
+                .respond_with(ResponseTemplate::new(400).set_body_string(body))
+                .expect(1)
+                .mount(&server)
+                .await;
+
+            let xata = test_helpers::xata(&server);
+            let result = DatastoreOperations::transaction(&xata, ops.clone()).await;
+
+            assert_eq!(
+                result.unwrap_err().to_string(),
+                expected.to_string(),
+                "{}",
+                body
+            );
+        }
+    }
+
     #[tokio::test]
-    #[serial]
     async fn it_onboards_user_successfully() {
-        let mock = test_helpers::mock_xata_server()
-            .match_body(
-                format!(
-                    r#"{{"operations":[{{"insert":{{"table":"{}","record":{{"count":1,"id":"{}"}},"createOnly":true,"columns":["count"]}}}}]}}"#,
+        let server = MockServer::start().await;
+        test_helpers::mock_transaction()
+            .and(test_helpers::viewed_body(format!(
+                    r#"{{"operations":[{{"insert":{{"table":"{}","record":{{"count":1,"id":"{}","last_viewed_at":"VIEWED_AT"}},"createOnly":true,"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
                     test_helpers::TEST_TABLE_NAME,
                     test_helpers::TEST_USER_NAME
-                ).as_str(),
-            )
-            .with_status(200)
-            .with_body(
+                )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
                 format!(r#"{{"results":[{{"columns":{{"count":1}},"id":"{}","operation":"insert","rows":1}}]}}"#, test_helpers::TEST_USER_NAME
-                ).as_str())
-            .create_async().await;
+                )))
+            .expect(1)
+            .mount(&server)
+            .await;
 
-        let count = Xata::new()
-            .unwrap()
+        let count = test_helpers::xata(&server)
             .onboard_user(test_helpers::TEST_USER_NAME)
             .await;
 
-        mock.assert_async().await;
         assert_eq!(count.unwrap(), 1);
     }
 
     #[tokio::test]
-    #[serial]
+    async fn it_counts_view_of_user_onboarded_concurrently() {
+        let server = MockServer::start().await;
+        test_helpers::mock_transaction()
+            .and(test_helpers::viewed_body(format!(
+                    r#"{{"operations":[{{"insert":{{"table":"{}","record":{{"count":1,"id":"{}","last_viewed_at":"VIEWED_AT"}},"createOnly":true,"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
+                    test_helpers::TEST_TABLE_NAME,
+                    test_helpers::TEST_USER_NAME
+                )))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                format!(r#"{{"errors":[{{"index":0,"message":"record with ID [{}] already exists"}}]}}"#, test_helpers::TEST_USER_NAME
+                )))
+            .expect(1)
+            .mount(&server)
+            .await;
+        test_helpers::mock_lookup(
+            &server,
+            &[(test_helpers::TEST_USER_NAME, r#"{"count":10}"#)],
+        )
+        .await;
+        test_helpers::mock_transaction()
+            .and(test_helpers::viewed_body(format!(
+                    r#"{{"operations":[{{"update":{{"table":"{}","id":"{}","fields":{{"count":{{"$increment":1}},"day_views":{{"$increment":1}},"last_viewed_at":"VIEWED_AT"}},"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
+                    test_helpers::TEST_TABLE_NAME,
+                    test_helpers::TEST_USER_NAME
+                )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                format!(r#"{{"results":[{{"columns":{{"count":2}},"id":"{}","operation":"update","rows":1}}]}}"#, test_helpers::TEST_USER_NAME
+                )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let count = test_helpers::xata(&server)
+            .onboard_user(test_helpers::TEST_USER_NAME)
+            .await;
+
+        assert_eq!(count.unwrap(), 2);
+    }
+
+    #[tokio::test]
     async fn it_handles_unexpected_error_while_onboarding_user() {
-        let mock = test_helpers::mock_xata_server()
-            .match_body(
-                format!(
-                    r#"{{"operations":[{{"insert":{{"table":"{}","record":{{"count":1,"id":"{}"}},"createOnly":true,"columns":["count"]}}}}]}}"#,
+        let server = MockServer::start().await;
+        test_helpers::mock_transaction()
+            .and(test_helpers::viewed_body(format!(
+                    r#"{{"operations":[{{"insert":{{"table":"{}","record":{{"count":1,"id":"{}","last_viewed_at":"VIEWED_AT"}},"createOnly":true,"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
                     test_helpers::TEST_TABLE_NAME,
                     test_helpers::TEST_USER_NAME
-                ).as_str(),
-            )
-            .with_status(500)
-            .with_body(r#"unavailable"#)
-            .create_async().await;
+                )))
+            .respond_with(ResponseTemplate::new(500).set_body_string("unavailable"))
+            .expect(1)
+            .mount(&server)
+            .await;
 
-        let count = Xata::new()
-            .unwrap()
+        let count = test_helpers::xata(&server)
             .onboard_user(test_helpers::TEST_USER_NAME)
             .await;
 
-        mock.assert_async().await;
         assert_eq!(
             count.unwrap_err().to_string(),
             DatastoreError::Unexpected(
@@ -422,49 +1976,481 @@ mod tests {
             .to_string()
         );
     }
+
+    #[tokio::test]
+    async fn it_increments_views_of_users_in_one_transaction() {
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(
+            &server,
+            &[("alice", r#"{"count":10}"#), ("bob", r#"{"count":7}"#)],
+        )
+        .await;
+        test_helpers::mock_transaction()
+            .and(test_helpers::viewed_body(format!(
+                    r#"{{"operations":[{{"update":{{"table":"{0}","id":"alice","fields":{{"count":{{"$increment":3}},"day_views":{{"$increment":3}},"last_viewed_at":"VIEWED_AT"}},"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}},{{"update":{{"table":"{0}","id":"bob","fields":{{"count":{{"$increment":1}},"day_views":{{"$increment":1}},"last_viewed_at":"VIEWED_AT"}},"columns":["count","deleted_at","day","day_views","peak_views","streak","recent_views"]}}}}]}}"#,
+                    test_helpers::TEST_TABLE_NAME,
+                )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"results":[{"columns":{"count":13},"id":"alice","operation":"update","rows":1},{"columns":{"count":8},"id":"bob","operation":"update","rows":1}]}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let increments = [
+            Increment {
+                user_name: "alice".to_string(),
+                views: 3,
+            },
+            Increment {
+                user_name: "bob".to_string(),
+                views: 1,
+            },
+        ];
+        let users = test_helpers::xata(&server)
+            .increment_views(&increments)
+            .await;
+
+        assert_eq!(
+            users.unwrap(),
+            vec![
+                UserViews {
+                    user_name: "alice".to_string(),
+                    views: 13,
+                },
+                UserViews {
+                    user_name: "bob".to_string(),
+                    views: 8,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_rejects_batches_touching_deleted_users_before_writing() {
+        let server = MockServer::start().await;
+        test_helpers::mock_lookup(
+            &server,
+            &[
+                ("alice", r#"{"count":10}"#),
+                ("bob", r#"{"count":7,"deleted_at":"2023-06-01T00:00:00Z"}"#),
+            ],
+        )
+        .await;
+        test_helpers::mock_transaction()
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let increments = [
+            Increment {
+                user_name: "alice".to_string(),
+                views: 3,
+            },
+            Increment {
+                user_name: "bob".to_string(),
+                views: 1,
+            },
+        ];
+        let users = test_helpers::xata(&server)
+            .increment_views(&increments)
+            .await;
+
+        assert_eq!(
+            users.unwrap_err().to_string(),
+            DatastoreError::UserDeleted("bob".to_string()).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn it_gets_many_users_with_one_query() {
+        let server = MockServer::start().await;
+        test_helpers::mock_query()
+            .and(body_string(
+                r#"{"columns":["count","deleted_at"],"filter":{"$notExists":"deleted_at","id":{"$any":["bob","alice","carol"]}},"page":{"size":3}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"alice","count":3,"xata":{"version":2}},{"id":"bob","count":7,"xata":{"version":6}}],"meta":{"page":{"cursor":"next_cursor","more":false}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let users = test_helpers::xata(&server)
+            .get_many(&["bob".to_string(), "alice".to_string(), "carol".to_string()])
+            .await;
+
+        assert_eq!(
+            users.unwrap(),
+            vec![
+                UserViews {
+                    user_name: "bob".to_string(),
+                    views: 7,
+                },
+                UserViews {
+                    user_name: "alice".to_string(),
+                    views: 3,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_scans_users_from_cursor() {
+        let server = MockServer::start().await;
+        test_helpers::mock_query()
+            .and(body_string(
+                r#"{"columns":["count","deleted_at"],"page":{"size":2,"after":"prev_cursor"}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"alice","count":3,"xata":{"version":2}},{"id":"bob","count":7,"xata":{"version":6}}],"meta":{"page":{"cursor":"next_cursor","more":true}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let page = test_helpers::xata(&server)
+            .scan(Some("prev_cursor".to_string()), 2)
+            .await;
+
+        assert_eq!(
+            page.unwrap(),
+            Page {
+                users: vec![
+                    UserViews {
+                        user_name: "alice".to_string(),
+                        views: 3
+                    },
+                    UserViews {
+                        user_name: "bob".to_string(),
+                        views: 7
+                    },
+                ],
+                next_cursor: Some("next_cursor".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_ends_scan_on_last_page() {
+        let server = MockServer::start().await;
+        test_helpers::mock_query()
+            .and(body_string(
+                r#"{"columns":["count","deleted_at"],"filter":{"$notExists":"deleted_at"},"page":{"size":2}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"alice","count":3}],"meta":{"page":{"cursor":"last_cursor","more":false}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let page = test_helpers::xata(&server).scan(None, 2).await.unwrap();
+
+        assert_eq!(page.users.len(), 1);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[tokio::test]
+    async fn it_fetches_most_viewed_users() {
+        let server = MockServer::start().await;
+        test_helpers::mock_query()
+            .and(body_string(
+                r#"{"columns":["count","deleted_at"],"filter":{"$notExists":"deleted_at"},"sort":{"count":"desc"},"page":{"size":2}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"bob","count":7},{"id":"alice","count":3}],"meta":{"page":{"cursor":"next_cursor","more":true}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let users = test_helpers::xata(&server).top_users(2).await.unwrap();
+
+        assert_eq!(
+            users
+                .iter()
+                .map(|user| (user.user_name.as_str(), user.views))
+                .collect::<Vec<_>>(),
+            vec![("bob", 7), ("alice", 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_lists_users_with_timestamps() {
+        let server = MockServer::start().await;
+        test_helpers::mock_query()
+            .and(body_string(r#"{"columns":["count","deleted_at","last_viewed_at","renamed_to"],"page":{"size":1}}"#))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"alice","count":3,"last_viewed_at":"2023-06-01T12:30:00Z","xata":{"createdAt":"2023-03-01T10:00:00Z","updatedAt":"2023-06-01T12:30:00.5Z","version":2}}],"meta":{"page":{"cursor":"next_cursor","more":true}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let page = test_helpers::xata(&server)
+            .list_users(None, 1)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            page,
+            Page {
+                users: vec![UserRecord {
+                    user_name: "alice".to_string(),
+                    views: 3,
+                    created_at: "2023-03-01T10:00:00Z".parse().unwrap(),
+                    updated_at: "2023-06-01T12:30:00.5Z".parse().unwrap(),
+                    deleted_at: None,
+                    last_viewed_at: Some("2023-06-01T12:30:00Z".parse().unwrap()),
+                    renamed_to: None,
+                }],
+                next_cursor: Some("next_cursor".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_gets_deleted_user_by_id() {
+        let server = MockServer::start().await;
+        test_helpers::mock_query()
+            .and(body_string(
+                r#"{"columns":["count","deleted_at","last_viewed_at","renamed_to"],"filter":{"id":"alice"},"page":{"size":1}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"alice","count":3,"deleted_at":"2023-07-01T00:00:00Z","xata":{"createdAt":"2023-03-01T10:00:00Z","updatedAt":"2023-07-01T00:00:00Z","version":3}}],"meta":{"page":{"cursor":"","more":false}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let user = test_helpers::xata(&server).get_user("alice").await.unwrap();
+
+        assert_eq!(
+            user,
+            Some(UserRecord {
+                user_name: "alice".to_string(),
+                views: 3,
+                created_at: "2023-03-01T10:00:00Z".parse().unwrap(),
+                updated_at: "2023-07-01T00:00:00Z".parse().unwrap(),
+                deleted_at: Some("2023-07-01T00:00:00Z".parse().unwrap()),
+                last_viewed_at: None,
+                renamed_to: None,
+            })
+        );
+    }
+
+    fn flag(user_name: &str) -> Flag {
+        Flag {
+            user_name: user_name.to_string(),
+            flagged_at: "2023-06-01T12:00:00Z".parse().unwrap(),
+            hourly_views: 510,
+            baseline: 20.5,
+            views: 530,
+        }
+    }
+
+    #[tokio::test]
+    async fn it_puts_flags_under_the_escaped_user_name() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path(format!(
+                "{}/data/org%2Frepo",
+                test_helpers::TEST_FLAGS_ENDPOINT_PATH
+            )))
+            .and(body_string(
+                r#"{"flagged_at":"2023-06-01T12:00:00Z","hourly_views":510,"baseline":20.5,"views":530}"#,
+            ))
+            .respond_with(ResponseTemplate::new(201).set_body_string(r#"{"id":"org/repo"}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        test_helpers::xata(&server)
+            .put_flag(&flag("org/repo"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_lists_flags_of_every_page() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(format!(
+                "{}/query",
+                test_helpers::TEST_FLAGS_ENDPOINT_PATH
+            )))
+            .and(body_string(
+                r#"{"columns":["flagged_at","hourly_views","baseline","views"],"sort":{"id":"asc"},"page":{"size":200}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"alice","flagged_at":"2023-06-01T12:00:00Z","hourly_views":510,"baseline":20.5,"views":530}],"meta":{"page":{"cursor":"next_cursor","more":true}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!(
+                "{}/query",
+                test_helpers::TEST_FLAGS_ENDPOINT_PATH
+            )))
+            .and(body_string(
+                r#"{"columns":["flagged_at","hourly_views","baseline","views"],"page":{"size":200,"after":"next_cursor"}}"#,
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"{"records":[{"id":"bob","flagged_at":"2023-06-01T12:00:00Z","hourly_views":510,"baseline":20.5,"views":530}],"meta":{"page":{"cursor":"","more":false}}}"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let flags = test_helpers::xata(&server).list_flags().await.unwrap();
+
+        assert_eq!(flags, vec![flag("alice"), flag("bob")]);
+    }
+
+    #[tokio::test]
+    async fn it_deletes_flags_and_tells_unflagged_users() {
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path(format!(
+                "{}/data/alice",
+                test_helpers::TEST_FLAGS_ENDPOINT_PATH
+            )))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path(format!(
+                "{}/data/bob",
+                test_helpers::TEST_FLAGS_ENDPOINT_PATH
+            )))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .set_body_string(r#"{"id":"bob","message":"record not found"}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let xata = test_helpers::xata(&server);
+
+        assert!(xata.delete_flag("alice").await.unwrap());
+        assert!(!xata.delete_flag("bob").await.unwrap());
+    }
 }
 
 #[cfg(test)]
 mod test_helpers {
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Match, Mock, MockBuilder, MockServer, Request, ResponseTemplate};
+
     use super::*;
+    use crate::secrets::Secrets;
 
     pub(crate) static TEST_TABLE_NAME: &str = "profile_views";
+    pub(crate) static TEST_FLAGS_TABLE_NAME: &str = "flags";
     pub(crate) static TEST_USER_NAME: &str = "test_user";
     pub(crate) static TEST_API_KEY: &str = "test_api_key";
     pub(crate) static TEST_DB_ENDPOINT_PATH: &str = "/v1/branch/test_branch/transaction";
+    pub(crate) static TEST_QUERY_ENDPOINT_PATH: &str =
+        "/v1/branch/test_branch/tables/profile_views/query";
+    pub(crate) static TEST_FLAGS_ENDPOINT_PATH: &str = "/v1/branch/test_branch/tables/flags";
 
-    pub(crate) fn user_views_transaction(op: OperationType) -> XataTransaction<'static> {
-        let metadata = TransactionMetadata {
-            table: TEST_TABLE_NAME,
-            user_name: TEST_USER_NAME,
-            op_type: op.clone(),
-        };
+    pub(crate) fn viewed_at() -> DateTime<Utc> {
+        "2023-06-01T12:00:00Z".parse().unwrap()
+    }
 
-        match op {
-            OperationType::Update => XataTransaction {
-                operations: [Operations::Update(UserViewsOperation { metadata })],
-            },
-            OperationType::Insert => XataTransaction {
-                operations: [Operations::Insert(UserViewsOperation { metadata })],
-            },
+    /// Client of the test table on the server, configured without touching the environment so
+    /// tests run in parallel.
+    pub(crate) fn xata(server: &MockServer) -> Xata {
+        let mut config = Config::from_env();
+        config.secrets = Arc::new(Secrets::new(|name| {
+            (name == secrets::XATA_API_KEY).then(|| TEST_API_KEY.to_string())
+        }));
+        let db_endpoint = format!("{}{}", server.uri(), TEST_DB_ENDPOINT_PATH);
+        Xata::with_tables(
+            &config,
+            db_endpoint,
+            TEST_TABLE_NAME.to_string(),
+            TEST_FLAGS_TABLE_NAME.to_string(),
+        )
+        .unwrap()
+    }
+
+    /// Matches the body of a transaction counting views now, standing in for the timestamps
+    /// with `VIEWED_AT`.
+    pub(crate) fn viewed_body(body: String) -> ViewedBody {
+        ViewedBody(serde_json::from_str(&body).unwrap())
+    }
+
+    pub(crate) struct ViewedBody(Value);
+
+    impl Match for ViewedBody {
+        fn matches(&self, request: &Request) -> bool {
+            let Ok(mut body) = serde_json::from_slice::<Value>(&request.body) else {
+                return false;
+            };
+            replace_timestamps(&mut body);
+            body == self.0
+        }
+    }
+
+    fn replace_timestamps(value: &mut Value) {
+        match value {
+            Value::String(text) if text.parse::<DateTime<Utc>>().is_ok() => {
+                *text = "VIEWED_AT".to_string()
+            }
+            Value::Array(values) => values.iter_mut().for_each(replace_timestamps),
+            Value::Object(fields) => fields.values_mut().for_each(replace_timestamps),
+            _ => {}
         }
     }
 
-    pub(crate) fn set_env_variables(db_endpoint: String) {
-        std::env::set_var("XATA_API_KEY", TEST_API_KEY);
-        std::env::set_var("XATA_TABLE_NAME", TEST_TABLE_NAME);
-        std::env::set_var("XATA_DB_ENDPOINT", db_endpoint);
+    pub(crate) fn user_views_transaction(op: OperationType) -> XataTransaction<'static> {
+        XataTransaction::on(TEST_TABLE_NAME).with(TEST_USER_NAME, op)
     }
 
-    pub(crate) fn mock_xata_server() -> mockito::Mock {
-        let mut server = mockito::Server::new();
-        let url = format!("{}{}", server.url(), TEST_DB_ENDPOINT_PATH);
-        set_env_variables(url.clone());
+    pub(crate) fn mock_transaction() -> MockBuilder {
+        Mock::given(method("POST"))
+            .and(path(TEST_DB_ENDPOINT_PATH))
+            .and(header(
+                "Authorization",
+                format!("Bearer {}", TEST_API_KEY).as_str(),
+            ))
+    }
 
-        let mock = server
-            .mock("POST", TEST_DB_ENDPOINT_PATH)
-            .match_header("Authorization", &*format!("Bearer {}", TEST_API_KEY));
+    /// Answers the lookup views are counted after with the columns of each user's record, `{}`
+    /// for unknown users.
+    pub(crate) async fn mock_lookup(server: &MockServer, records: &[(&str, &str)]) {
+        let operations: Vec<_> = records
+            .iter()
+            .map(|(user_name, _)| serde_json::json!({"get":{"table":TEST_TABLE_NAME,"id":user_name}}))
+            .collect();
+        let results: Vec<_> = records
+            .iter()
+            .map(|(_, columns)| format!(r#"{{"operation":"get","columns":{}}}"#, columns))
+            .collect();
+        mock_transaction()
+            .and(body_partial_json(
+                serde_json::json!({ "operations": operations }),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(format!(r#"{{"results":[{}]}}"#, results.join(","))),
+            )
+            .expect(1)
+            .mount(server)
+            .await;
+    }
 
-        mock
+    pub(crate) fn mock_query() -> MockBuilder {
+        Mock::given(method("POST"))
+            .and(path(TEST_QUERY_ENDPOINT_PATH))
+            .and(header(
+                "Authorization",
+                format!("Bearer {}", TEST_API_KEY).as_str(),
+            ))
     }
 }
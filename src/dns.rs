@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+struct Entry {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+}
+
+/// Resolver keeping addresses for `ttl`, so requests after a quiet period don't pay for a lookup.
+/// Stale addresses are served when a refresh fails.
+pub struct CachingResolver {
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl CachingResolver {
+    pub fn new(ttl: Duration) -> CachingResolver {
+        CachingResolver {
+            ttl,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn lookup(
+        cache: &Mutex<HashMap<String, Entry>>,
+        ttl: Duration,
+        host: &str,
+    ) -> Result<Vec<SocketAddr>, std::io::Error> {
+        if let Some(entry) = cache.lock().unwrap().get(host) {
+            if entry.resolved_at.elapsed() < ttl {
+                return Ok(entry.addrs.clone());
+            }
+        }
+
+        match tokio::net::lookup_host((host, 0)).await {
+            Ok(addrs) => {
+                let addrs: Vec<SocketAddr> = addrs.collect();
+                cache.lock().unwrap().insert(
+                    host.to_string(),
+                    Entry {
+                        addrs: addrs.clone(),
+                        resolved_at: Instant::now(),
+                    },
+                );
+                Ok(addrs)
+            }
+            Err(err) => match cache.lock().unwrap().get(host) {
+                Some(entry) => {
+                    tracing::warn!(
+                        "failed to resolve `{}`, using stale addresses: {}",
+                        host,
+                        err
+                    );
+                    Ok(entry.addrs.clone())
+                }
+                None => Err(err),
+            },
+        }
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = self.cache.clone();
+        let ttl = self.ttl;
+
+        Box::pin(async move {
+            let addrs = CachingResolver::lookup(&cache, ttl, name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_serves_cached_addresses_within_ttl() {
+        let resolver = CachingResolver::new(Duration::from_secs(60));
+        let cached = vec![SocketAddr::from(([10, 0, 0, 1], 0))];
+        resolver.cache.lock().unwrap().insert(
+            "xata.test".to_string(),
+            Entry {
+                addrs: cached.clone(),
+                resolved_at: Instant::now(),
+            },
+        );
+
+        let addrs = CachingResolver::lookup(&resolver.cache, resolver.ttl, "xata.test")
+            .await
+            .unwrap();
+
+        assert_eq!(addrs, cached);
+    }
+
+    #[tokio::test]
+    async fn it_refreshes_expired_addresses() {
+        let resolver = CachingResolver::new(Duration::ZERO);
+        resolver.cache.lock().unwrap().insert(
+            "localhost".to_string(),
+            Entry {
+                addrs: vec![SocketAddr::from(([10, 0, 0, 1], 0))],
+                resolved_at: Instant::now(),
+            },
+        );
+
+        let addrs = CachingResolver::lookup(&resolver.cache, resolver.ttl, "localhost")
+            .await
+            .unwrap();
+
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+    }
+}
@@ -0,0 +1,167 @@
+use axum::{
+    extract::Request,
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Stable error codes clients can branch on; renaming a code is a breaking change.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// `label`, `color` or `style` missing for a badge response
+    MissingBadgeParams,
+    /// Request body or params are malformed, the message tells which
+    InvalidRequest,
+    /// Admin token missing or invalid
+    Unauthorized,
+    /// Admin routes are disabled as no admin token is configured
+    AdminDisabled,
+    /// Api routes are disabled as no api key is configured
+    ApiDisabled,
+    /// Owner routes and tokens are disabled as no `OWNER_TOKEN_SECRET` is configured
+    OwnerTokensDisabled,
+    UserNotFound,
+    UserDeleted,
+    /// User is yet to be onboarded through `POST /api/onboard`, with `ONBOARDING=explicit`
+    UserNotRegistered,
+    /// User name was already onboarded
+    UserExists,
+    /// User is not flagged for unusual views
+    FlagNotFound,
+    /// No tenant of this name is configured
+    TenantNotFound,
+    /// Datastore could not be reached or failed to process the request
+    DatastoreUnavailable,
+    /// shields.io could not be reached or failed to render the badge
+    UpstreamBadgeFailed,
+    /// Badge could not be rendered to an image
+    RenderFailed,
+    /// GitHub api could not be reached or failed to tell who holds a token
+    #[serde(rename = "GITHUB_UNAVAILABLE")]
+    GitHubUnavailable,
+    /// Secrets provider could not be reached, its secrets are unchanged
+    SecretsUnavailable,
+    /// Server is at its concurrency limit, retry after the `Retry-After` header; also returned
+    /// for json and text views of users without cached views once the request budget is used up
+    Overloaded,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::MissingBadgeParams | ErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::AdminDisabled
+            | ErrorCode::ApiDisabled
+            | ErrorCode::OwnerTokensDisabled
+            | ErrorCode::UserNotFound
+            | ErrorCode::UserNotRegistered
+            | ErrorCode::FlagNotFound
+            | ErrorCode::TenantNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::UserDeleted => StatusCode::GONE,
+            ErrorCode::UserExists => StatusCode::CONFLICT,
+            ErrorCode::DatastoreUnavailable
+            | ErrorCode::UpstreamBadgeFailed
+            | ErrorCode::RenderFailed
+            | ErrorCode::GitHubUnavailable
+            | ErrorCode::SecretsUnavailable => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Error returned by handlers; rendered as an [`ErrorBody`].
+#[derive(Clone, Debug)]
+pub struct ApiError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn into_response_with(self, request_id: Option<String>) -> Response {
+        let body = ErrorBody {
+            code: self.code,
+            message: self.message.clone(),
+            request_id,
+        };
+
+        let mut response = (self.code.status(), Json(body)).into_response();
+        // kept around so the request id can be filled in by `attach_request_id`
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        self.into_response_with(None)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    code: ErrorCode,
+    /// Human readable description, not meant to be parsed
+    message: String,
+    /// Id of the request, also returned in the `x-request-id` header
+    request_id: Option<String>,
+}
+
+/// Adds the request id to error bodies; handlers don't have access to it.
+pub async fn attach_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|request_id| request_id.to_str().ok())
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+    match (request_id, response.extensions().get::<ApiError>()) {
+        (Some(request_id), Some(err)) => {
+            let mut with_request_id = err.clone().into_response_with(Some(request_id));
+            // headers set by the handler on top of the error, e.g. `Vary`
+            for (name, value) in response.headers() {
+                if name != header::CONTENT_LENGTH && !with_request_id.headers().contains_key(name) {
+                    with_request_id
+                        .headers_mut()
+                        .insert(name.clone(), value.clone());
+                }
+            }
+            with_request_id
+        }
+        _ => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_serializes_error_body() {
+        let body = ErrorBody {
+            code: ErrorCode::DatastoreUnavailable,
+            message: "failed to count views".to_string(),
+            request_id: Some("42".to_string()),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&body).unwrap(),
+            r#"{"code":"DATASTORE_UNAVAILABLE","message":"failed to count views","request_id":"42"}"#
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error};
+use axum::async_trait;
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time;
+
+use super::client_identity::{client_identity, SubnetPrefixes};
+use super::config::EventsConfig;
+use super::runtime::Task;
+use super::shutdown::Shutdown;
+
+// events waiting for the broker; views past it are not published
+const QUEUE_CAPACITY: usize = 1024;
+// how long the publisher gets to take the queued events on shutdown
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ViewEvent {
+    pub user_name: String,
+    pub timestamp: DateTime<Utc>,
+    /// Salted hash of the client's address or subnet, the address itself is never published.
+    /// The salt changes with every restart.
+    pub client: Option<String>,
+    pub referrer: Option<String>,
+}
+
+/// Publishes an event per counted view to NATS or kafka. Events are queued and published in the
+/// background by the `publisher` task, so a slow broker drops events instead of slowing down
+/// badges.
+pub struct ViewEvents {
+    sender: mpsc::Sender<ViewEvent>,
+    client_salt: RandomState,
+    client_prefixes: SubnetPrefixes,
+    config: EventsConfig,
+    // held by the running publisher; a restarted one picks up the events queued meanwhile
+    receiver: Arc<Mutex<mpsc::Receiver<ViewEvent>>>,
+}
+
+impl ViewEvents {
+    pub fn new(config: &EventsConfig, client_prefixes: SubnetPrefixes) -> ViewEvents {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+
+        ViewEvents {
+            sender,
+            client_salt: RandomState::new(),
+            client_prefixes,
+            config: config.clone(),
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
+
+    /// Task publishing the queued events.
+    pub fn publisher(&self) -> Task {
+        Box::pin(publish_loop(self.config.clone(), self.receiver.clone()))
+    }
+
+    /// Events queued for the publisher.
+    pub fn backlog(&self) -> usize {
+        QUEUE_CAPACITY - self.sender.capacity()
+    }
+
+    pub fn publish(&self, user_name: &str, headers: &HeaderMap) {
+        let event = self.event(user_name, headers);
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.sender.try_send(event) {
+            tracing::warn!("view events queue is full, dropping event");
+        }
+    }
+
+    /// Waits for the publisher to take the queued events, for up to `timeout`; events still
+    /// queued then are dropped.
+    async fn drain(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.backlog() > 0 && Instant::now() < deadline {
+            time::sleep(Duration::from_millis(20)).await;
+        }
+
+        match self.backlog() {
+            0 => {}
+            dropped => tracing::warn!("shutting down with {} view event(s) queued", dropped),
+        }
+    }
+
+    fn event(&self, user_name: &str, headers: &HeaderMap) -> ViewEvent {
+        ViewEvent {
+            user_name: user_name.to_string(),
+            timestamp: Utc::now(),
+            client: client_identity(headers, self.client_prefixes)
+                .map(|client| format!("{:016x}", self.client_salt.hash_one(client))),
+            referrer: headers
+                .get(header::REFERER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+#[async_trait]
+impl Shutdown for ViewEvents {
+    async fn shutdown(&self) {
+        self.drain(DRAIN_TIMEOUT).await
+    }
+}
+
+async fn publish_loop(config: EventsConfig, receiver: Arc<Mutex<mpsc::Receiver<ViewEvent>>>) {
+    let sink = match Sink::connect(&config).await {
+        Ok(sink) => sink,
+        Err(err) => {
+            tracing::error!(
+                "failed to connect to `{}`, not publishing views: {}",
+                config.url,
+                err
+            );
+            return;
+        }
+    };
+    tracing::info!("publishing views to `{}` on `{}`", config.topic, config.url);
+
+    let mut receiver = receiver.lock().await;
+    while let Some(event) = receiver.recv().await {
+        if let Err(err) = sink.publish(&event).await {
+            tracing::warn!("failed to publish view of `{}`: {}", event.user_name, err);
+        }
+    }
+}
+
+enum Sink {
+    #[cfg(feature = "nats")]
+    Nats {
+        client: async_nats::Client,
+        subject: String,
+    },
+    #[cfg(feature = "kafka")]
+    Kafka(rskafka::client::partition::PartitionClient),
+}
+
+impl Sink {
+    #[cfg_attr(not(any(feature = "nats", feature = "kafka")), allow(unused_variables))]
+    async fn connect(config: &EventsConfig) -> Result<Sink, Error> {
+        let (scheme, address) = config
+            .url
+            .split_once("://")
+            .ok_or_else(|| anyhow!("expected `nats://` or `kafka://` url"))?;
+
+        match scheme {
+            #[cfg(feature = "nats")]
+            "nats" => Ok(Sink::Nats {
+                client: async_nats::connect(address).await?,
+                subject: config.topic.clone(),
+            }),
+            #[cfg(feature = "kafka")]
+            "kafka" => {
+                use rskafka::client::{partition::UnknownTopicHandling, ClientBuilder};
+
+                let client = ClientBuilder::new(vec![address.to_string()]).build().await?;
+                let partition = client
+                    .partition_client(config.topic.clone(), 0, UnknownTopicHandling::Retry)
+                    .await?;
+                Ok(Sink::Kafka(partition))
+            }
+            _ => Err(anyhow!(
+                    "unsupported scheme `{}`, the server needs to be built with the `nats` or `kafka` feature",
+                scheme
+            )),
+        }
+    }
+
+    // without a broker feature there is no sink to publish to
+    #[cfg_attr(
+        not(any(feature = "nats", feature = "kafka")),
+        allow(unused_variables, unreachable_code)
+    )]
+    async fn publish(&self, event: &ViewEvent) -> Result<(), Error> {
+        match *self {
+            #[cfg(feature = "nats")]
+            Sink::Nats {
+                ref client,
+                ref subject,
+            } => {
+                let payload = serde_json::to_vec(event)?;
+                client.publish(subject.clone(), payload.into()).await?;
+            }
+            #[cfg(feature = "kafka")]
+            Sink::Kafka(ref partition) => {
+                use rskafka::{client::partition::Compression, record::Record};
+
+                let record = Record {
+                    key: Some(event.user_name.clone().into_bytes()),
+                    value: Some(serde_json::to_vec(event)?),
+                    headers: Default::default(),
+                    timestamp: event.timestamp,
+                };
+                partition
+                    .produce(vec![record], Compression::NoCompression)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_hashes_client_address_into_event() {
+        let events = ViewEvents::new(
+            &EventsConfig {
+                url: "unsupported://localhost".to_string(),
+                topic: "profile-views".to_string(),
+            },
+            SubnetPrefixes::default(),
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.1".parse().unwrap());
+        headers.insert(header::REFERER, "https://github.com/".parse().unwrap());
+
+        let event = events.event("octocat", &headers);
+        let same_client = events.event("octocat", &headers);
+
+        assert_eq!(event.referrer.as_deref(), Some("https://github.com/"));
+        assert_eq!(event.client, same_client.client);
+        assert!(!event.client.unwrap().contains("203.0.113.7"));
+    }
+
+    #[tokio::test]
+    async fn it_drains_queued_events_on_shutdown() {
+        let events = ViewEvents::new(
+            &EventsConfig {
+                url: "unsupported://localhost".to_string(),
+                topic: "profile-views".to_string(),
+            },
+            SubnetPrefixes::default(),
+        );
+        events.publish("octocat", &HeaderMap::new());
+        events.publish("octocat", &HeaderMap::new());
+
+        // no publisher is running, the events stay queued
+        events.drain(Duration::from_millis(50)).await;
+        assert_eq!(events.backlog(), 2);
+
+        let receiver = events.receiver.clone();
+        let publisher = tokio::spawn(async move {
+            let mut receiver = receiver.lock().await;
+            while receiver.recv().await.is_some() {}
+        });
+        events.drain(Duration::from_secs(5)).await;
+        assert_eq!(events.backlog(), 0);
+        publisher.abort();
+    }
+}
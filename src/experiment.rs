@@ -0,0 +1,273 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use super::badge::ShieldsIoParams;
+use super::cache::{CacheStore, Lru};
+use super::client_identity::{client_identity, SubnetPrefixes};
+use super::runtime::Spawner;
+
+const DEFAULT_SPLIT: u8 = 50;
+// users whose served variants an instance counts itself, the least recently served are dropped
+const MAX_USERS: usize = 10_000;
+// experiments served nothing for this long are forgotten by the shared store
+const SHARED_RESULTS_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Second badge variant served to a share of the viewers; the experiment runs as soon as one of
+/// the `b_` params is given.
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExperimentParams {
+    /// Label of variant B, defaults to the label of variant A
+    b_label: Option<String>,
+    /// Color of variant B, defaults to the color of variant A
+    b_color: Option<String>,
+    /// Style of variant B, defaults to the style of variant A
+    b_style: Option<String>,
+    /// Percentage of viewers served variant B, defaults to 50
+    split: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    A,
+    B,
+}
+
+const VARIANTS: [Variant; 2] = [Variant::A, Variant::B];
+
+impl Variant {
+    fn results_key(self, user_name: &str) -> String {
+        let variant = match self {
+            Variant::A => "a",
+            Variant::B => "b",
+        };
+        format!("experiment:{}:{}", user_name, variant)
+    }
+}
+
+impl ExperimentParams {
+    /// Badge params of variant B, `None` when no experiment runs.
+    pub fn variant_b(&self, params: &ShieldsIoParams) -> Option<ShieldsIoParams> {
+        if self.b_label.is_none() && self.b_color.is_none() && self.b_style.is_none() {
+            return None;
+        }
+
+        Some(params.with_overrides(
+            self.b_label.as_deref(),
+            self.b_color.as_deref(),
+            self.b_style.as_deref(),
+        ))
+    }
+
+    /// Variant served to the viewer. Viewers are told apart by their address or subnet, so a
+    /// viewer keeps getting the same variant across requests and server instances.
+    pub fn pick(&self, user_name: &str, headers: &HeaderMap, prefixes: SubnetPrefixes) -> Variant {
+        let viewer = client_identity(headers, prefixes).unwrap_or_default();
+        self.pick_for(user_name, &viewer)
+    }
+
+    fn pick_for(&self, user_name: &str, viewer: &str) -> Variant {
+        let split = self.split.unwrap_or(DEFAULT_SPLIT).min(100);
+        match viewer_hash(user_name, viewer) % 100 < split as u64 {
+            true => Variant::B,
+            false => Variant::A,
+        }
+    }
+}
+
+/// FNV-1a, which unlike the std hashers is stable across processes and releases.
+fn viewer_hash(user_name: &str, viewer: &str) -> u64 {
+    let bytes = user_name.bytes().chain([0]).chain(viewer.bytes());
+    bytes.fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct ExperimentResults {
+    pub user_name: String,
+    /// Badges of variant A served by every instance, or by the answering one since it started
+    /// when no shared cache is configured
+    pub a: u64,
+    /// Badges of variant B served, like `a`
+    pub b: u64,
+}
+
+/// Variants served per user. Counted in the cache store shared by all instances when one is
+/// configured, so results survive restarts; each instance also counts the users it served most
+/// recently itself, which it reports when the shared store can't tell.
+pub struct Experiments {
+    served: Mutex<Lru<[u64; 2]>>,
+    shared: Option<Arc<dyn CacheStore>>,
+    spawner: Arc<dyn Spawner>,
+}
+
+impl Experiments {
+    pub fn new(shared: Option<Arc<dyn CacheStore>>, spawner: Arc<dyn Spawner>) -> Experiments {
+        Experiments::with_capacity(MAX_USERS, shared, spawner)
+    }
+
+    fn with_capacity(
+        capacity: usize,
+        shared: Option<Arc<dyn CacheStore>>,
+        spawner: Arc<dyn Spawner>,
+    ) -> Experiments {
+        Experiments {
+            served: Mutex::new(Lru::new(capacity)),
+            shared,
+            spawner,
+        }
+    }
+
+    /// Counts the served variant; the shared store is counted on in the background.
+    pub fn record(&self, user_name: &str, variant: Variant) {
+        {
+            let mut served = self.served.lock().unwrap();
+            match served.get_mut(user_name) {
+                Some(counts) => counts[variant as usize] += 1,
+                None => {
+                    let mut counts = [0; 2];
+                    counts[variant as usize] = 1;
+                    served.insert(user_name.to_string(), counts);
+                }
+            }
+        }
+
+        let Some(shared) = self.shared.clone() else {
+            return;
+        };
+        let key = variant.results_key(user_name);
+        self.spawner.spawn(Box::pin(async move {
+            shared.increment(&key, 1, SHARED_RESULTS_TTL).await;
+        }));
+    }
+
+    pub async fn results(&self, user_name: &str) -> ExperimentResults {
+        let mut counts = self
+            .served
+            .lock()
+            .unwrap()
+            .peek(user_name)
+            .copied()
+            .unwrap_or_default();
+        if let Some(shared) = &self.shared {
+            for variant in VARIANTS {
+                let key = variant.results_key(user_name);
+                if let Some(count) = shared.peek(&key).await.and_then(|count| count.parse().ok()) {
+                    counts[variant as usize] = count;
+                }
+            }
+        }
+
+        let [a, b] = counts;
+        ExperimentResults {
+            user_name: user_name.to_string(),
+            a,
+            b,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryStore;
+    use crate::runtime::TokioSpawner;
+    use pretty_assertions::assert_eq;
+
+    fn experiment(split: u8) -> ExperimentParams {
+        ExperimentParams {
+            b_label: None,
+            b_color: Some("green".to_string()),
+            b_style: None,
+            split: Some(split),
+        }
+    }
+
+    #[test]
+    fn it_splits_viewers_deterministically() {
+        let viewers: Vec<String> = (0..1000)
+            .map(|i| format!("10.0.{}.{}", i / 256, i % 256))
+            .collect();
+        let picks: Vec<Variant> = viewers
+            .iter()
+            .map(|viewer| experiment(30).pick_for("octocat", viewer))
+            .collect();
+
+        let b = picks
+            .iter()
+            .filter(|variant| **variant == Variant::B)
+            .count();
+        assert!((250..350).contains(&b), "{} of 1000 viewers got B", b);
+        assert_eq!(
+            viewers
+                .iter()
+                .map(|viewer| experiment(30).pick_for("octocat", viewer))
+                .collect::<Vec<_>>(),
+            picks
+        );
+        assert_eq!(experiment(0).pick_for("octocat", "10.0.0.1"), Variant::A);
+        assert_eq!(experiment(100).pick_for("octocat", "10.0.0.1"), Variant::B);
+    }
+
+    #[tokio::test]
+    async fn it_records_served_variants() {
+        let experiments = Experiments::new(None, Arc::new(TokioSpawner));
+        experiments.record("octocat", Variant::B);
+        experiments.record("octocat", Variant::B);
+        experiments.record("octocat", Variant::A);
+
+        assert_eq!(
+            experiments.results("octocat").await,
+            ExperimentResults {
+                user_name: "octocat".to_string(),
+                a: 1,
+                b: 2,
+            }
+        );
+        assert_eq!(experiments.results("hubot").await.a, 0);
+    }
+
+    #[tokio::test]
+    async fn it_forgets_least_recently_served_users_past_its_capacity() {
+        let experiments = Experiments::with_capacity(2, None, Arc::new(TokioSpawner));
+        experiments.record("octocat", Variant::A);
+        experiments.record("hubot", Variant::A);
+        experiments.record("octocat", Variant::B);
+        experiments.record("monalisa", Variant::A);
+
+        assert_eq!(experiments.results("hubot").await.a, 0);
+        assert_eq!(experiments.results("octocat").await.b, 1);
+        assert_eq!(experiments.results("monalisa").await.a, 1);
+    }
+
+    #[tokio::test]
+    async fn it_reports_variants_served_by_every_instance() {
+        let store: Arc<dyn CacheStore> = Arc::new(MemoryStore::new());
+        let serving = Experiments::new(Some(store.clone()), Arc::new(TokioSpawner));
+        let other = Experiments::new(Some(store.clone()), Arc::new(TokioSpawner));
+        serving.record("octocat", Variant::B);
+        other.record("octocat", Variant::A);
+        other.record("octocat", Variant::B);
+
+        // the shared store is counted on in the background
+        for _ in 0..100 {
+            if serving.results("octocat").await.b == 2 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            serving.results("octocat").await,
+            ExperimentResults {
+                user_name: "octocat".to_string(),
+                a: 1,
+                b: 2,
+            }
+        );
+    }
+}
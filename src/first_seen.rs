@@ -0,0 +1,87 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+
+use super::cache::Lru;
+use super::datastore::{DatastoreError, DatastoreOperations};
+
+// users whose onboarding time is remembered, the least recently viewed are forgotten first
+const MAX_FIRST_SEEN_USERS: usize = 100_000;
+
+/// When users were onboarded, for badges counting views since then. Onboarding times never
+/// change, so each is looked up once and kept in process memory.
+pub struct FirstSeen {
+    onboarded: Mutex<Lru<DateTime<Utc>>>,
+}
+
+impl Default for FirstSeen {
+    fn default() -> FirstSeen {
+        FirstSeen {
+            onboarded: Mutex::new(Lru::new(MAX_FIRST_SEEN_USERS)),
+        }
+    }
+}
+
+impl FirstSeen {
+    pub fn new() -> FirstSeen {
+        FirstSeen::default()
+    }
+
+    /// When the user was onboarded, `None` for unknown users.
+    pub async fn get(
+        &self,
+        db: &impl DatastoreOperations,
+        user_name: &str,
+    ) -> Result<Option<DateTime<Utc>>, DatastoreError> {
+        if let Some(onboarded_at) = self.onboarded.lock().unwrap().get_mut(user_name) {
+            return Ok(Some(*onboarded_at));
+        }
+
+        let Some(user) = db.get_user(user_name).await? else {
+            return Ok(None);
+        };
+        self.onboarded
+            .lock()
+            .unwrap()
+            .insert(user_name.to_string(), user.created_at);
+        Ok(Some(user.created_at))
+    }
+}
+
+/// Describes when views started being counted in a badge message, e.g. `since Mar 2023`.
+pub fn since(onboarded_at: DateTime<Utc>) -> String {
+    format!("since {}", onboarded_at.format("%b %Y"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::Memory;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_remembers_when_users_were_onboarded() {
+        let db = Memory::new();
+        let first_seen = FirstSeen::new();
+        db.onboard_user("alice").await.unwrap();
+
+        let onboarded_at = first_seen.get(&db, "alice").await.unwrap();
+        assert_eq!(
+            onboarded_at,
+            Some(db.get_user("alice").await.unwrap().unwrap().created_at)
+        );
+        assert_eq!(first_seen.get(&db, "bob").await.unwrap(), None);
+
+        // looked up once only
+        db.purge_user("alice").await.unwrap();
+        assert_eq!(first_seen.get(&db, "alice").await.unwrap(), onboarded_at);
+    }
+
+    #[test]
+    fn it_describes_since_when_views_are_counted() {
+        assert_eq!(
+            since("2023-03-14T10:00:00Z".parse().unwrap()),
+            "since Mar 2023"
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+
+use axum::http::HeaderMap;
+
+// set by Cloudflare, CloudFront and Vercel respectively; the server itself has no geoip database
+const COUNTRY_HEADERS: [&str; 3] = [
+    "cf-ipcountry",
+    "cloudfront-viewer-country",
+    "x-vercel-ip-country",
+];
+
+/// Country of the viewer as an ISO 3166-1 alpha-2 code, e.g. `DE`, as told by the CDN in front of
+/// the server; `None` when there's no CDN or it couldn't tell.
+pub fn viewer_country(headers: &HeaderMap) -> Option<String> {
+    COUNTRY_HEADERS
+        .iter()
+        .find_map(|name| headers.get(*name).and_then(|value| value.to_str().ok()))
+        .map(|country| country.trim().to_ascii_uppercase())
+        // Cloudflare's code for addresses it has no country for
+        .filter(|country| !country.is_empty() && country != "XX")
+}
+
+/// Whether the viewer is in one of the `BLOCKED_COUNTRIES`. Nothing is stored about blocked
+/// viewers: their views are served but neither counted nor sampled.
+pub fn is_blocked(blocked_countries: &HashSet<String>, headers: &HeaderMap) -> bool {
+    !blocked_countries.is_empty()
+        && viewer_country(headers).is_some_and(|country| blocked_countries.contains(&country))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_blocks_viewers_from_blocked_countries() {
+        let from = |name: &'static str, country: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, country.parse().unwrap());
+            headers
+        };
+        let blocked = HashSet::from(["DE".to_string(), "T1".to_string()]);
+
+        assert_eq!(
+            viewer_country(&from("cloudfront-viewer-country", "de")).as_deref(),
+            Some("DE")
+        );
+        assert!(is_blocked(&blocked, &from("cf-ipcountry", "DE")));
+        assert!(is_blocked(&blocked, &from("cf-ipcountry", "T1")));
+        assert!(!is_blocked(&blocked, &from("cf-ipcountry", "US")));
+        assert!(!is_blocked(&blocked, &from("cf-ipcountry", "XX")));
+        assert!(!is_blocked(&blocked, &HeaderMap::new()));
+    }
+}
@@ -0,0 +1,274 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Client, Response, StatusCode,
+};
+use serde::Deserialize;
+
+use super::cache::Lru;
+use super::config::{Config, UpstreamConfig};
+use super::http_client;
+use super::metrics::{self, UpstreamRequest};
+use super::secrets::{self, Secrets};
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+// names may be taken by new accounts at any time, existing accounts are rarely renamed
+const MISSING_USER_TTL: Duration = Duration::from_secs(60 * 60);
+const EXISTING_USER_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// user names whose existence is remembered, the least recently looked up are forgotten first
+const MAX_VERIFIED_USERS: usize = 100_000;
+const MAX_LOGIN_LEN: usize = 39;
+
+#[derive(thiserror::Error, Debug)]
+pub enum GitHubError {
+    #[error("token is invalid or expired")]
+    InvalidToken,
+
+    #[error("rate limited until {0}")]
+    RateLimited(DateTime<Utc>),
+
+    #[error(transparent)]
+    Client(#[from] reqwest::Error),
+}
+
+/// Client of the GitHub rest api, telling who users are by their OAuth tokens and whether user
+/// names exist. Lookups of user names are cached and paused while the rate limit is used up;
+/// they count against the limit of the `GITHUB_TOKEN` if set, which is higher than the one of
+/// the server's address.
+pub struct GitHub {
+    client: Client,
+    upstream: UpstreamConfig,
+    secrets: Arc<Secrets>,
+    api_url: String,
+    verified: Mutex<Lru<(bool, Instant)>>,
+    rate_limited_until: Mutex<Option<DateTime<Utc>>>,
+}
+
+#[derive(Deserialize)]
+struct GitHubUser {
+    login: String,
+}
+
+impl GitHub {
+    pub fn new(config: &Config) -> GitHub {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/vnd.github+json"),
+        );
+        headers.insert(
+            "x-github-api-version",
+            HeaderValue::from_static("2022-11-28"),
+        );
+
+        let client = http_client::upstream_builder(config, &config.github)
+            .and_then(|builder| builder.default_headers(headers).build())
+            .expect("failed to initialize github client");
+        metrics::record_pool_size("github", config.github.pool_max_idle);
+
+        GitHub {
+            client,
+            upstream: config.github.clone(),
+            secrets: config.secrets.clone(),
+            api_url: GITHUB_API_URL.to_string(),
+            verified: Mutex::new(Lru::new(MAX_VERIFIED_USERS)),
+            rate_limited_until: Mutex::new(None),
+        }
+    }
+
+    /// Login of the user the OAuth token was issued to; tokens need no scopes for it.
+    pub async fn login(&self, token: &str) -> Result<String, GitHubError> {
+        let response = {
+            let _request = UpstreamRequest::start("github", self.upstream.slow_threshold);
+            self.client
+                .get(format!("{}/user", self.api_url))
+                .bearer_auth(token)
+                .send()
+                .await?
+        };
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED => Err(GitHubError::InvalidToken),
+            _ => Ok(response
+                .error_for_status()?
+                .json::<GitHubUser>()
+                .await?
+                .login),
+        }
+    }
+
+    /// Whether a GitHub user or organization of this name exists.
+    pub async fn user_exists(&self, user_name: &str) -> Result<bool, GitHubError> {
+        // e.g. `wp-login.php` of scanners, which can't be a login
+        if !is_login(user_name) {
+            return Ok(false);
+        }
+        // GitHub logins are case insensitive
+        let key = user_name.to_lowercase();
+        if let Some((exists, verified_at)) = self.verified.lock().unwrap().get_mut(&key) {
+            let ttl = match exists {
+                true => EXISTING_USER_TTL,
+                false => MISSING_USER_TTL,
+            };
+            if verified_at.elapsed() < ttl {
+                return Ok(*exists);
+            }
+        }
+        if let Some(until) = *self.rate_limited_until.lock().unwrap() {
+            if Utc::now() < until {
+                return Err(GitHubError::RateLimited(until));
+            }
+        }
+
+        let response = {
+            let _request = UpstreamRequest::start("github", self.upstream.slow_threshold);
+            let mut request = self.client.get(format!("{}/users/{}", self.api_url, key));
+            if let Some(token) = self.secrets.get(secrets::GITHUB_TOKEN) {
+                request = request.bearer_auth(token);
+            }
+            request.send().await?
+        };
+        let reset = rate_limit_reset(&response);
+        if let Some(until) = reset {
+            tracing::warn!(
+                "github rate limit used up, not verifying users until {}",
+                until
+            );
+            *self.rate_limited_until.lock().unwrap() = Some(until);
+        }
+
+        let exists = match (response.status(), reset) {
+            (StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS, Some(until)) => {
+                return Err(GitHubError::RateLimited(until))
+            }
+            (StatusCode::NOT_FOUND, _) => false,
+            _ => {
+                response.error_for_status()?;
+                true
+            }
+        };
+
+        self.verified
+            .lock()
+            .unwrap()
+            .insert(key, (exists, Instant::now()));
+        Ok(exists)
+    }
+}
+
+// alphanumerics and single hyphens between them
+/// Whether the name is made of the characters GitHub allows in logins, ASCII alphanumerics and
+/// hyphens, within their length. Unlike [`is_login`], hyphens may lead, trail or repeat, as in
+/// some logins of old accounts.
+pub fn is_user_name(user_name: &str) -> bool {
+    (1..=MAX_LOGIN_LEN).contains(&user_name.len())
+        && user_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn is_login(user_name: &str) -> bool {
+    (1..=MAX_LOGIN_LEN).contains(&user_name.len())
+        && user_name
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+// when lookups may go on again if the response used up the rate limit, see
+// https://docs.github.com/en/rest/using-the-rest-api/rate-limits-for-the-rest-api
+fn rate_limit_reset(response: &Response) -> Option<DateTime<Utc>> {
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<i64>().ok())
+    };
+    // secondary rate limits tell how long to wait instead
+    if let Some(seconds) = header("retry-after") {
+        return Some(Utc::now() + chrono::Duration::seconds(seconds));
+    }
+    match header("x-ratelimit-remaining") {
+        Some(0) => header("x-ratelimit-reset")
+            .and_then(|reset| DateTime::from_timestamp(reset, 0))
+            .or_else(|| Some(Utc::now() + chrono::Duration::minutes(1))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_tells_the_login_of_a_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .and(header("authorization", "Bearer gho_valid"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"login": "octocat", "id": 1}"#),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/user"))
+            .and(header("authorization", "Bearer gho_expired"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        let mut github = GitHub::new(&Config::from_env());
+        github.api_url = server.uri();
+
+        assert_eq!(github.login("gho_valid").await.unwrap(), "octocat");
+        assert!(matches!(
+            github.login("gho_expired").await,
+            Err(GitHubError::InvalidToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_caches_lookups_and_pauses_them_while_rate_limited() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/users/octocat"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"login": "octocat"}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/users/wp-admin"))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .insert_header("x-ratelimit-remaining", "0")
+                    .insert_header("x-ratelimit-reset", "4102444800"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mut github = GitHub::new(&Config::from_env());
+        github.api_url = server.uri();
+
+        assert!(github.user_exists("octocat").await.unwrap());
+        assert!(github.user_exists("Octocat").await.unwrap());
+        assert!(!github.user_exists("wp-login.php").await.unwrap());
+        assert!(!github.user_exists("wp-admin").await.unwrap());
+        // cached lookups go on while the rate limit is used up, others wait for its reset
+        assert!(!github.user_exists("wp-admin").await.unwrap());
+        assert_eq!(
+            github
+                .user_exists("monalisa")
+                .await
+                .unwrap_err()
+                .to_string(),
+            "rate limited until 2100-01-01 00:00:00 UTC"
+        );
+    }
+}
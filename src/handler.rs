@@ -1,71 +1,2062 @@
+use std::borrow::Cow;
 use std::sync::Arc;
 
 use axum::{
-    extract::{Path, Query, State as StateExtractor},
-    http::StatusCode,
+    async_trait,
+    extract::{Extension, FromRequestParts, Path, Query, State as StateExtractor},
+    http::{header, request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-use super::badge::{ShieldsIoFetcher, ShieldsIoParams};
-use super::datastore::{DatastoreError, DatastoreOperations};
+use super::anomaly::Flag;
+use super::badge::{self, ShieldsIoFetcher, ShieldsIoParams, UNAVAILABLE_BADGE};
+use super::badge_params::{BadgeParams, BadgeQuery};
+use super::datastore::{
+    DatastoreError, DatastoreOperations, DayStats, Freshness, UserRecord, UserViews,
+};
+use super::error::{ApiError, ErrorCode};
+use super::experiment::{ExperimentParams, Variant};
+use super::first_seen;
+use super::geo;
+use super::github::{self, GitHub};
+use super::history::{DeltaPeriod, Trend};
+use super::locale::{self, Locale};
+use super::metrics;
+use super::quota::{DailyQuota, RateLimit, RequestBudget};
+use super::raster::{self, Font, FontFamily, FontWeight, RasterBadge, RasterFormat, Rasterizer};
 use super::state::AppState;
+use super::tenant::{Tenant, TenantPathParams};
+
+const MAX_RASTER_SCALE: f32 = 4.0;
+// shields.io's grey for inactive badges
+const CACHED_BADGE_COLOR: &str = "inactive";
+// renames followed from the name in the path, e.g. for users renamed twice
+const MAX_RENAMES: usize = 5;
+const COUNT_FRESHNESS_HEADER: HeaderName = HeaderName::from_static("x-count-freshness");
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
 pub struct PathParams {
+    /// GitHub user name the views are counted for
     user_name: String,
 }
 
+/// Rejects user names GitHub wouldn't allow, e.g. a percent-encoded `acme%2Falice` reaching the
+/// `<tenant>/<user>` key of a tenant user through a plain route.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for PathParams {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(path_params) = Path::<PathParams>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| ApiError::new(ErrorCode::InvalidRequest, rejection.body_text()))?;
+        if !github::is_user_name(&path_params.user_name) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("invalid user name `{}`", path_params.user_name),
+            ));
+        }
+        Ok(path_params)
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RasterParams {
+    /// Pixel density of the image, between 1 and 4; defaults to 1
+    scale: Option<f32>,
+    /// Lays the badge out locally in `sans`, `serif` or `mono` instead of using shields.io's
+    #[param(value_type = Option<String>)]
+    font: Option<FontFamily>,
+    /// `normal` or `bold`, lays the badge out locally like `font`
+    #[param(value_type = Option<String>)]
+    font_weight: Option<FontWeight>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DisplayParams {
+    /// Groups the views' digits the way the locale does, e.g. `1.234.567` for `de-DE` or
+    /// `12,34,567` for `hi-IN`; digits are not grouped by default
+    #[param(value_type = Option<String>)]
+    locale: Option<Locale>,
+    /// Adds the views gained over the last `day`, `week` or `month` to the badge, e.g.
+    /// `12345 (+321)`; gains are read from the views per day stored with the user
+    #[param(value_type = Option<String>)]
+    delta: Option<DeltaPeriod>,
+    /// Shows only the gained views instead of the total, e.g. `+321 this week`
+    delta_only: Option<bool>,
+    /// Colors the badge by the views gained over the last `day`, `week` or `month` compared to
+    /// the period before: green when up, grey when flat and red when down
+    #[param(value_type = Option<String>)]
+    trend: Option<DeltaPeriod>,
+    /// Change in percent within which the trend is flat; defaults to the tenant's or server's
+    trend_threshold: Option<f64>,
+    /// Shows the views against a goal instead of `delta`, e.g. `12345 / 100k`
+    goal: Option<u64>,
+    /// Shows the progress towards `goal` in percent instead, e.g. `12%`
+    goal_percent: Option<bool>,
+    /// Fills the badge's message up to the progress towards `goal`, as a progress bar; such
+    /// badges are laid out locally
+    goal_bar: Option<bool>,
+    /// Appends when the views started being counted, e.g. `12345 since Mar 2023`
+    since: Option<bool>,
+}
+
+#[utoipa::path(head, path = "/healthz", responses((status = 200, description = "Server is up")))]
 pub async fn health_check_handler() -> Response {
     StatusCode::OK.into_response()
 }
 
+/// Health of the background tasks; unready while one of them crashed and waits to be restarted.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Background tasks are running", body = Readiness),
+        (status = 503, description = "A background task crashed and waits to be restarted", body = Readiness),
+    )
+)]
+pub async fn readiness_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+) -> Response {
+    let readiness = state.supervisor.readiness();
+    let status = match readiness.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(readiness)).into_response()
+}
+
+/// Prometheus metrics.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Metrics in prometheus text format", content_type = "text/plain"))
+)]
+pub async fn metrics_handler(Extension(metrics_handle): Extension<PrometheusHandle>) -> String {
+    metrics_handle.render()
+}
+
+/// Counts a profile view and returns the views badge.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/counter.svg",
+    params(PathParams, ShieldsIoParams, DisplayParams, ExperimentParams),
+    responses(
+        (status = 200, description = "Views badge; deleted users get an \"unavailable\" badge, users yet to be onboarded with `ONBOARDING=explicit` a \"not registered\" one", content_type = "image/svg+xml", body = String),
+        (status = 500, description = "Datastore or shields.io failure", body = ErrorBody),
+    )
+)]
 pub async fn profile_views_handler(
     StateExtractor(state): StateExtractor<
         Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
     >,
-    query: Query<ShieldsIoParams>,
-    path_params: Path<PathParams>,
+    query: BadgeParams,
+    display_params: Query<DisplayParams>,
+    experiment: Query<ExperimentParams>,
+    path_params: PathParams,
+    headers: HeaderMap,
+) -> Response {
+    let variant_b = experiment.variant_b(&query);
+    let invalid_badge = variant_b
+        .as_ref()
+        .and_then(|variant_b| invalid_params_badge(&state, variant_b));
+    if let Some(badge) = invalid_badge {
+        return badge_response(badge);
+    }
+
+    let counted = count_view(&state, &path_params.user_name, &headers).await;
+    let freshness = counted.as_ref().ok().and_then(Views::freshness);
+    let response = match counted {
+        Ok(served @ (Views::Counted(views) | Views::Approximate(views) | Views::Cached(views))) => {
+            let user_name = &path_params.user_name;
+            let params = match &variant_b {
+                Some(variant_b) => {
+                    let variant =
+                        experiment.pick(user_name, &headers, state.config.client_prefixes);
+                    state.experiments.record(user_name, variant);
+                    match variant {
+                        Variant::A => &query.0,
+                        Variant::B => variant_b,
+                    }
+                }
+                None => &query.0,
+            };
+            let contents = badge_contents(
+                &state,
+                user_name,
+                views,
+                served.is_cached(),
+                params,
+                &display_params,
+            )
+            .await;
+            svg_response(&state, &contents).await
+        }
+        Ok(Views::UserDeleted | Views::Unavailable) => {
+            badge_response(UNAVAILABLE_BADGE.to_string())
+        }
+        Ok(Views::NotRegistered) => badge_response(not_registered_badge(&state, query.style())),
+        Err(err) => err.into_response(),
+    };
+    let response = with_freshness_header(freshness, response);
+    with_quota_headers(state.quota.as_ref(), &path_params.user_name, response)
+}
+
+/// Counts a profile view within a tenant and returns the views badge; badge params missing from
+/// the query fall back to the tenant's defaults.
+#[utoipa::path(
+    get,
+    path = "/t/{tenant}/{user_name}/counter.svg",
+    params(TenantPathParams, PathParams, BadgeQuery, DisplayParams),
+    responses(
+        (status = 200, description = "Views badge; deleted users get an \"unavailable\" badge, badge params missing from both the query and the tenant an error badge", content_type = "image/svg+xml", body = String),
+        (status = 404, description = "Tenant not found", body = ErrorBody),
+        (status = 500, description = "Datastore or shields.io failure", body = ErrorBody),
+    )
+)]
+pub async fn tenant_views_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    BadgeParams(params): BadgeParams,
+    Query(mut display_params): Query<DisplayParams>,
+    Path((tenant, user_name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let tenant = match state.tenants.get(&tenant) {
+        Ok(tenant) => tenant,
+        Err(err) => return err.into_response(),
+    };
+
+    display_params.trend_threshold = display_params.trend_threshold.or(tenant.trend_threshold());
+
+    let user_key = match tenant.user_key(&user_name) {
+        Ok(user_key) => user_key,
+        Err(err) => return err.into_response(),
+    };
+    let counted = count_view_within(&state, Some(tenant), &user_key, &headers).await;
+    let freshness = counted.as_ref().ok().and_then(Views::freshness);
+    let response = match counted {
+        Ok(served @ (Views::Counted(views) | Views::Approximate(views) | Views::Cached(views))) => {
+            let mut contents = badge_contents(
+                &state,
+                &user_key,
+                views,
+                served.is_cached(),
+                &params,
+                &display_params,
+            )
+            .await;
+            contents.title = alt_text(views, &user_name);
+            svg_response(&state, &contents).await
+        }
+        Ok(Views::UserDeleted | Views::Unavailable) => {
+            badge_response(UNAVAILABLE_BADGE.to_string())
+        }
+        Ok(Views::NotRegistered) => badge_response(not_registered_badge(&state, params.style())),
+        Err(err) => err.into_response(),
+    };
+    let response = with_freshness_header(freshness, response);
+    with_quota_headers(tenant.quota.as_ref(), &user_key, response)
+}
+
+/// Describes the user's views badge without counting a view, e.g. `42 profile views for
+/// octocat`, for the badge's alt text.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/alt.txt",
+    params(PathParams),
+    responses(
+        (status = 200, description = "Alt text of the views badge", content_type = "text/plain", body = String),
+        (status = 404, description = "User not found or deleted", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn alt_text_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    path_params: PathParams,
+) -> Response {
+    let user_name = &path_params.user_name;
+    // named like the badge's title, which keeps the name it's embedded with after a rename
+    match get_renamed_user(&state.db, user_name).await {
+        Ok(Some(user)) if user.deleted_at.is_none() => {
+            uncached_response("text/plain; charset=utf-8", alt_text(user.views, user_name))
+        }
+        Ok(_) => ApiError::new(
+            ErrorCode::UserNotFound,
+            format!("user `{}` not found", user_name),
+        )
+        .into_response(),
+        Err(err) => {
+            tracing::error!("failed to get views of {}, reason: {}", user_name, err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to get views").into_response()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct ViewsSummary {
+    pub user_name: String,
+    pub views: u64,
+    /// When the user was onboarded, i.e. views started being counted
+    pub created_at: DateTime<Utc>,
+    /// When the last view was counted, `null` for views counted before it was tracked
+    pub last_viewed_at: Option<DateTime<Utc>>,
+}
+
+/// Returns the user's views and when they were last viewed, without counting a view.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/count.json",
+    params(PathParams),
+    responses(
+        (status = 200, description = "Views of the user", body = ViewsSummary),
+        (status = 404, description = "User not found or deleted", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn count_json_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    path_params: PathParams,
+) -> Response {
+    let user_name = &path_params.user_name;
+    match get_renamed_user(&state.db, user_name).await {
+        Ok(Some(user)) if user.deleted_at.is_none() => uncached_response(
+            "application/json",
+            Json(ViewsSummary {
+                user_name: user.user_name,
+                views: user.views,
+                created_at: user.created_at,
+                last_viewed_at: user.last_viewed_at,
+            }),
+        ),
+        Ok(_) => ApiError::new(
+            ErrorCode::UserNotFound,
+            format!("user `{}` not found", user_name),
+        )
+        .into_response(),
+        Err(err) => {
+            tracing::error!("failed to get user {}, reason: {}", user_name, err);
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to get views").into_response()
+        }
+    }
+}
+
+/// Returns a badge of the user's current streak of days with views, e.g. `12 days`, without
+/// counting a view. Streaks are stored with the user's day stats, so every instance serves the
+/// same one.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/streak.svg",
+    params(PathParams, ShieldsIoParams),
+    responses(
+        (status = 200, description = "Streak badge; deleted users get an \"unavailable\" badge", content_type = "image/svg+xml", body = String),
+        (status = 500, description = "Datastore or shields.io failure", body = ErrorBody),
+    )
+)]
+pub async fn streak_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    query: BadgeParams,
+    path_params: PathParams,
+) -> Response {
+    let user_name = &path_params.user_name;
+    let today = Utc::now().date_naive();
+    let streak = match state.db.get_day_stats(user_name).await {
+        Ok(day_stats) => day_stats.map_or(0, |day_stats| day_stats.streak(today)),
+        Err(DatastoreError::UserDeleted(_)) => {
+            return badge_response(UNAVAILABLE_BADGE.to_string())
+        }
+        Err(err) => {
+            tracing::error!("failed to get streak of {}, reason: {}", user_name, err);
+            return ApiError::new(ErrorCode::DatastoreUnavailable, "failed to get views")
+                .into_response();
+        }
+    };
+    let days = match streak {
+        1 => "1 day".to_string(),
+        _ => format!("{} days", streak),
+    };
+    let contents = BadgeContents {
+        params: Cow::Borrowed(&query),
+        title: format!("{} streak of profile views for {}", days, user_name),
+        message: days,
+        progress: None,
+        cached: false,
+    };
+    svg_response(&state, &contents).await
+}
+
+/// Returns a badge of the most views the user got on a single UTC day, without counting a view.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/record.svg",
+    params(PathParams, ShieldsIoParams),
+    responses(
+        (status = 200, description = "Record badge; deleted users get an \"unavailable\" badge", content_type = "image/svg+xml", body = String),
+        (status = 500, description = "Datastore or shields.io failure", body = ErrorBody),
+    )
+)]
+pub async fn record_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    query: BadgeParams,
+    path_params: PathParams,
+) -> Response {
+    let user_name = &path_params.user_name;
+    let peak_views = match state.db.get_peak_day_views(user_name).await {
+        Ok(peak_views) => peak_views.unwrap_or(0),
+        Err(DatastoreError::UserDeleted(_)) => {
+            return badge_response(UNAVAILABLE_BADGE.to_string())
+        }
+        Err(err) => {
+            tracing::error!("failed to get peak views of {}, reason: {}", user_name, err);
+            return ApiError::new(ErrorCode::DatastoreUnavailable, "failed to get views")
+                .into_response();
+        }
+    };
+
+    let contents = BadgeContents {
+        params: Cow::Borrowed(&query),
+        message: peak_views.to_string(),
+        title: format!(
+            "{} profile views on the best day of {}",
+            peak_views, user_name
+        ),
+        progress: None,
+        cached: false,
+    };
+    svg_response(&state, &contents).await
+}
+
+/// What a badge request for the user would do, without the view being counted.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewOutcome {
+    /// The view is counted
+    Counted,
+    /// The user is onboarded with their first view
+    Onboarded,
+    /// The user is flagged for unusual views, the views at flagging are served uncounted
+    Frozen,
+    /// The viewer is in one of the `BLOCKED_COUNTRIES`, the views are served uncounted
+    GeoBlocked,
+    /// Today's quota is used up, the views at reaching it are served uncounted
+    OverQuota,
+    /// The request budget is used up, the views cached for the user are served uncounted
+    OverBudget,
+    /// The user is unknown and isn't onboarded, see `ONBOARDING` and `USER_VERIFICATION`
+    NotRegistered,
+    /// The user is deleted and gets the "unavailable" badge
+    Deleted,
+}
+
+/// Explains how views of a user are currently handled, e.g. why they aren't increasing.
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct ViewDiagnosis {
+    user_name: String,
+    outcome: ViewOutcome,
+    /// User the view would be counted on, the user renamed users were renamed to
+    counted_on: Option<String>,
+    /// Views the next badge would show; `None` when it shows none, e.g. for deleted users
+    views: Option<u64>,
+    /// Where the views of the next badge come from, see the `X-Count-Freshness` header; `None`
+    /// when it shows none
+    freshness: Option<Freshness>,
+    /// Set while the user is flagged for unusual views
+    flag: Option<Flag>,
+    /// Today's quota of the user, when a daily quota is configured
+    rate_limit: Option<RateLimit>,
+    /// Whether the counted view would be published as an event
+    publishes_event: bool,
+    /// Whether the badge for the given badge params would be served from the template cache;
+    /// `None` without badge params or when templates aren't cached
+    badge_template_cached: Option<bool>,
+}
+
+/// Reports how the next badge request for the user, with the same headers, would be handled
+/// without counting a view.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/debug",
+    params(PathParams, ShieldsIoParams),
+    security(("admin_token" = [])),
+    responses(
+        (status = 200, description = "How the user's views are handled", body = ViewDiagnosis),
+        (status = 401, description = "Missing or invalid admin token", body = ErrorBody),
+        (status = 500, description = "Datastore or GitHub failure", body = ErrorBody),
+    )
+)]
+pub async fn debug_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    path_params: PathParams,
+    headers: HeaderMap,
+    params: Option<Query<ShieldsIoParams>>,
+) -> Response {
+    let user_name = &path_params.user_name;
+    let mut diagnosis = match diagnose(&state, user_name, &headers).await {
+        Ok(diagnosis) => diagnosis,
+        Err(err) => return err.into_response(),
+    };
+
+    diagnosis.badge_template_cached = match (params, diagnosis.views) {
+        (Some(Query(params)), Some(views)) if params.validate().is_ok() => {
+            state.badge.is_cached(&params, &views.to_string()).await
+        }
+        _ => None,
+    };
+    Json(diagnosis).into_response()
+}
+
+/// Diagnoses the decision [`count_view_within`] takes, looking the user up where it would count
+/// the view.
+async fn diagnose(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    user_name: &str,
+    headers: &HeaderMap,
+) -> Result<ViewDiagnosis, ApiError> {
+    let (outcome, views, counted_on) = match decide_view(state, None, user_name, headers).await? {
+        Decision::Frozen(views) => (ViewOutcome::Frozen, Some(views), None),
+        Decision::GeoBlocked(views) => (ViewOutcome::GeoBlocked, views.count(), None),
+        Decision::OverQuota(views) => (ViewOutcome::OverQuota, Some(views), None),
+        Decision::OverBudget(views) => (ViewOutcome::OverBudget, views, None),
+        Decision::Count { onboard, verifier } => {
+            let (counted_on, user) =
+                lookup_renamed_user(&state.db, user_name)
+                    .await
+                    .map_err(|err| {
+                        tracing::error!("failed to get user {}, reason: {}", user_name, err);
+                        ApiError::new(ErrorCode::DatastoreUnavailable, "failed to get user")
+                    })?;
+            match user {
+                Some(user) if user.deleted_at.is_some() => (ViewOutcome::Deleted, None, None),
+                Some(user) => (ViewOutcome::Counted, Some(user.views + 1), Some(counted_on)),
+                None if onboards(&counted_on, onboard, verifier).await? => {
+                    (ViewOutcome::Onboarded, Some(1), Some(counted_on))
+                }
+                None => (ViewOutcome::NotRegistered, None, None),
+            }
+        }
+    };
+
+    let freshness = match outcome {
+        ViewOutcome::Frozen
+        | ViewOutcome::GeoBlocked
+        | ViewOutcome::OverQuota
+        | ViewOutcome::OverBudget => views.map(|_| Freshness::Cached),
+        ViewOutcome::NotRegistered | ViewOutcome::Deleted => None,
+        ViewOutcome::Counted | ViewOutcome::Onboarded => Some(state.db.freshness(user_name).await),
+    };
+
+    Ok(ViewDiagnosis {
+        user_name: user_name.to_string(),
+        outcome,
+        counted_on: counted_on.map(Cow::into_owned),
+        views,
+        freshness,
+        flag: state
+            .anomalies
+            .as_ref()
+            .and_then(|anomalies| anomalies.flag(user_name)),
+        rate_limit: state
+            .quota
+            .as_ref()
+            .map(|quota| quota.rate_limit(user_name)),
+        publishes_event: state.events.is_some()
+            && matches!(outcome, ViewOutcome::Counted | ViewOutcome::Onboarded),
+        badge_template_cached: None,
+    })
+}
+
+/// Counts a profile view and returns the views badge as png.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/counter.png",
+    params(PathParams, ShieldsIoParams, RasterParams, DisplayParams),
+    responses(
+        (status = 200, description = "Views badge; deleted users get an \"unavailable\" badge", content_type = "image/png"),
+        (status = 500, description = "Datastore, shields.io or rendering failure", body = ErrorBody),
+    )
+)]
+pub async fn counter_png_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    query: BadgeParams,
+    raster_params: Query<RasterParams>,
+    display_params: Query<DisplayParams>,
+    path_params: PathParams,
+    headers: HeaderMap,
 ) -> Response {
-    let views = match state.db.get_latest_views(&path_params.user_name).await {
+    raster_counter_response(
+        &state,
+        &query,
+        &display_params,
+        &path_params.user_name,
+        &headers,
+        RasterFormat::Png,
+        &raster_params,
+    )
+    .await
+}
+
+/// Counts a profile view and returns the views badge as webp.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/counter.webp",
+    params(PathParams, ShieldsIoParams, RasterParams, DisplayParams),
+    responses(
+        (status = 200, description = "Views badge; deleted users get an \"unavailable\" badge", content_type = "image/webp"),
+        (status = 500, description = "Datastore, shields.io or rendering failure", body = ErrorBody),
+    )
+)]
+pub async fn counter_webp_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    query: BadgeParams,
+    raster_params: Query<RasterParams>,
+    display_params: Query<DisplayParams>,
+    path_params: PathParams,
+    headers: HeaderMap,
+) -> Response {
+    raster_counter_response(
+        &state,
+        &query,
+        &display_params,
+        &path_params.user_name,
+        &headers,
+        RasterFormat::Webp,
+        &raster_params,
+    )
+    .await
+}
+
+/// Counts a profile view and returns the views as a badge, json or plain text depending on the
+/// `format` query param or, when not given, the `Accept` header.
+#[utoipa::path(
+    get,
+    path = "/{user_name}/counter",
+    params(
+        PathParams,
+        ShieldsIoParams,
+        FormatParams,
+        DisplayParams,
+        ("Accept" = Option<String>, Header, description = "`image/svg+xml` (default), `image/webp`, `image/png`, `application/json` or `text/plain`"),
+    ),
+    responses(
+        (status = 200, description = "Views in the negotiated format", body = UserViews, content_type = ["image/svg+xml", "image/webp", "image/png", "application/json", "text/plain"]),
+        (status = 400, description = "Badge params missing for a badge response", body = ErrorBody),
+        (status = 410, description = "User is deleted (json and plain text only)", body = ErrorBody),
+        (status = 500, description = "Datastore, shields.io or rendering failure", body = ErrorBody),
+    )
+)]
+pub async fn counter_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    query: Option<Query<ShieldsIoParams>>,
+    format_params: Query<FormatParams>,
+    display_params: Query<DisplayParams>,
+    path_params: PathParams,
+    headers: HeaderMap,
+) -> Response {
+    let format = format_params
+        .format
+        .unwrap_or_else(|| ResponseFormat::from_accept(&headers));
+
+    // badge params are only needed for badges, so they are checked before counting the view
+    if format.is_badge() && query.is_none() {
+        return ApiError::new(
+            ErrorCode::MissingBadgeParams,
+            "missing badge params: label, color and style",
+        )
+        .into_response();
+    }
+
+    let invalid_params_badge = query.as_ref().and_then(|query| {
+        invalid_params_badge(&state, query).or_else(|| {
+            let mistake = check_scale(format_params.scale).err()?;
+            Some(error_badge(&state, query.style(), &mistake))
+        })
+    });
+    match (format, invalid_params_badge) {
+        (ResponseFormat::Svg, Some(badge)) => return badge_response(badge),
+        (ResponseFormat::Raster(raster_format), Some(badge)) => {
+            let badge = Ok(RasterBadge::new(badge));
+            return raster_response(&state.raster, badge, raster_format, format_params.scale).await;
+        }
+        _ => {}
+    }
+
+    let views = match count_view(&state, &path_params.user_name, &headers).await {
         Ok(views) => views,
+        Err(err) => return err.into_response(),
+    };
+    let freshness = views.freshness();
+
+    let mut response = match (format, views, query) {
+        (
+            ResponseFormat::Svg,
+            served @ (Views::Counted(views) | Views::Approximate(views) | Views::Cached(views)),
+            Some(query),
+        ) => {
+            let user_name = &path_params.user_name;
+            let contents = badge_contents(
+                &state,
+                user_name,
+                views,
+                served.is_cached(),
+                &query,
+                &display_params,
+            )
+            .await;
+            svg_response(&state, &contents).await
+        }
+        (ResponseFormat::Svg, Views::NotRegistered, Some(query)) => {
+            badge_response(not_registered_badge(&state, query.style()))
+        }
+        (ResponseFormat::Svg, _, _) => badge_response(UNAVAILABLE_BADGE.to_string()),
+        (ResponseFormat::Raster(raster_format), views, query) => {
+            let badge = match (query, views) {
+                (
+                    Some(query),
+                    served @ (Views::Counted(views)
+                    | Views::Approximate(views)
+                    | Views::Cached(views)),
+                ) => {
+                    let user_name = &path_params.user_name;
+                    let contents = badge_contents(
+                        &state,
+                        user_name,
+                        views,
+                        served.is_cached(),
+                        &query,
+                        &display_params,
+                    )
+                    .await;
+                    let font = Font::from_params(format_params.font, format_params.font_weight);
+                    raster_badge(&state, &contents, font).await
+                }
+                (Some(query), Views::NotRegistered) => Ok(RasterBadge::new(not_registered_badge(
+                    &state,
+                    query.style(),
+                ))),
+                _ => Ok(RasterBadge::new(UNAVAILABLE_BADGE.to_string())),
+            };
+            raster_response(&state.raster, badge, raster_format, format_params.scale).await
+        }
+        (_, Views::UserDeleted, _) => ApiError::new(
+            ErrorCode::UserDeleted,
+            format!("user `{}` is deleted", path_params.user_name),
+        )
+        .into_response(),
+        (_, Views::NotRegistered, _) => not_registered(&path_params.user_name).into_response(),
+        (_, Views::Unavailable, _) => budget_used_up().into_response(),
+        (
+            ResponseFormat::Json,
+            Views::Counted(views) | Views::Approximate(views) | Views::Cached(views),
+            _,
+        ) => uncached_response(
+            format.content_type(),
+            Json(UserViews {
+                user_name: path_params.user_name.clone(),
+                views,
+            }),
+        ),
+        (
+            ResponseFormat::Text,
+            Views::Counted(views) | Views::Approximate(views) | Views::Cached(views),
+            _,
+        ) => uncached_response(format.content_type(), views.to_string()),
+    };
+
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    let response = with_freshness_header(freshness, response);
+    with_quota_headers(state.quota.as_ref(), &path_params.user_name, response)
+}
+
+/// Returns the headers of the views badge as svg; no view is counted unless configured.
+#[utoipa::path(
+    head,
+    path = "/{user_name}/counter.svg",
+    params(PathParams),
+    responses(
+        (status = 200, description = "Headers of the views badge", content_type = "image/svg+xml"),
+    )
+)]
+pub async fn profile_views_head_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    path_params: PathParams,
+    headers: HeaderMap,
+) -> Response {
+    head_response(
+        &state,
+        None,
+        &path_params.user_name,
+        &headers,
+        "image/svg+xml",
+    )
+    .await
+}
+
+/// Returns the headers of the views in the negotiated format; no view is counted unless
+/// configured.
+#[utoipa::path(
+    head,
+    path = "/{user_name}/counter",
+    params(PathParams, FormatParams),
+    responses(
+        (status = 200, description = "Headers of the views in the negotiated format"),
+    )
+)]
+pub async fn counter_head_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    format_params: Query<FormatParams>,
+    path_params: PathParams,
+    headers: HeaderMap,
+) -> Response {
+    let format = format_params
+        .format
+        .unwrap_or_else(|| ResponseFormat::from_accept(&headers));
+
+    let user_name = &path_params.user_name;
+    let mut response =
+        head_response(&state, None, user_name, &headers, format.content_type()).await;
+    response
+        .headers_mut()
+        .insert(header::VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+/// Returns the headers of the views badge as png; no view is counted unless configured.
+#[utoipa::path(
+    head,
+    path = "/{user_name}/counter.png",
+    params(PathParams),
+    responses(
+        (status = 200, description = "Headers of the views badge", content_type = "image/png"),
+    )
+)]
+pub async fn counter_png_head_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    path_params: PathParams,
+    headers: HeaderMap,
+) -> Response {
+    let content_type = RasterFormat::Png.content_type();
+    head_response(&state, None, &path_params.user_name, &headers, content_type).await
+}
+
+/// Returns the headers of the views badge as webp; no view is counted unless configured.
+#[utoipa::path(
+    head,
+    path = "/{user_name}/counter.webp",
+    params(PathParams),
+    responses(
+        (status = 200, description = "Headers of the views badge", content_type = "image/webp"),
+    )
+)]
+pub async fn counter_webp_head_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    path_params: PathParams,
+    headers: HeaderMap,
+) -> Response {
+    let content_type = RasterFormat::Webp.content_type();
+    head_response(&state, None, &path_params.user_name, &headers, content_type).await
+}
+
+/// Returns the headers of the views badge within a tenant; no view is counted unless configured.
+#[utoipa::path(
+    head,
+    path = "/t/{tenant}/{user_name}/counter.svg",
+    params(TenantPathParams, PathParams),
+    responses(
+        (status = 200, description = "Headers of the views badge", content_type = "image/svg+xml"),
+        (status = 404, description = "Tenant not found"),
+    )
+)]
+pub async fn tenant_views_head_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path((tenant, user_name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let tenant = match state.tenants.get(&tenant) {
+        Ok(tenant) => tenant,
+        Err(err) => return err.into_response(),
+    };
+
+    let user_key = match tenant.user_key(&user_name) {
+        Ok(user_key) => user_key,
+        Err(err) => return err.into_response(),
+    };
+    head_response(&state, Some(tenant), &user_key, &headers, "image/svg+xml").await
+}
+
+/// Headers of a counter route without its body, of the `tenant` route when given. Uptime checkers
+/// and link previews send HEAD requests, which only count a view with `HEAD_REQUESTS=count`.
+async fn head_response(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    tenant: Option<&Tenant>,
+    user_name: &str,
+    headers: &HeaderMap,
+    content_type: &'static str,
+) -> Response {
+    let freshness = match state.config.count_head_requests {
+        true => match count_view_within(state, tenant, user_name, headers).await {
+            Ok(views) => views.freshness(),
+            Err(err) => return err.into_response(),
+        },
+        // the freshness a counted view would have been served with
+        false => Some(state.db.freshness(user_name).await),
+    };
+    let response = with_freshness_header(freshness, uncached_response(content_type, ()));
+    with_quota_headers(quota_of(state, tenant), user_name, response)
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FormatParams {
+    /// Response format overriding the `Accept` header: `svg`, `webp`, `png`, `json` or `text`
+    #[param(value_type = Option<String>)]
+    format: Option<ResponseFormat>,
+    /// Pixel density of webp and png images, between 1 and 4; defaults to 1
+    scale: Option<f32>,
+    /// Lays webp and png badges out locally in `sans`, `serif` or `mono` instead of using shields.io's
+    #[param(value_type = Option<String>)]
+    font: Option<FontFamily>,
+    /// `normal` or `bold`, lays the badge out locally like `font`
+    #[param(value_type = Option<String>)]
+    font_weight: Option<FontWeight>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ResponseFormat {
+    Svg,
+    Json,
+    Text,
+    #[serde(untagged)]
+    Raster(RasterFormat),
+}
+
+impl ResponseFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ResponseFormat::Svg => "image/svg+xml",
+            ResponseFormat::Json => "application/json",
+            ResponseFormat::Text => "text/plain; charset=utf-8",
+            ResponseFormat::Raster(raster_format) => raster_format.content_type(),
+        }
+    }
+
+    fn is_badge(&self) -> bool {
+        matches!(self, ResponseFormat::Svg | ResponseFormat::Raster(_))
+    }
+
+    /// Formats matching equally well are picked in this order, vector badges being the cheapest.
+    fn preference(&self) -> u8 {
+        match self {
+            ResponseFormat::Svg => 0,
+            ResponseFormat::Raster(RasterFormat::Webp) => 1,
+            ResponseFormat::Raster(RasterFormat::Png) => 2,
+            ResponseFormat::Json => 3,
+            ResponseFormat::Text => 4,
+        }
+    }
+
+    /// Picks the format with the highest quality in the `Accept` header, svg when none matches.
+    fn from_accept(headers: &HeaderMap) -> ResponseFormat {
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default();
+
+        let mut best: Option<(ResponseFormat, f32)> = None;
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';').map(str::trim);
+            let format = match parts.next().unwrap_or_default() {
+                "image/svg+xml" | "image/*" | "*/*" => ResponseFormat::Svg,
+                "image/webp" => ResponseFormat::Raster(RasterFormat::Webp),
+                "image/png" => ResponseFormat::Raster(RasterFormat::Png),
+                "application/json" => ResponseFormat::Json,
+                "text/plain" => ResponseFormat::Text,
+                _ => continue,
+            };
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let is_better = best.is_none_or(|(best_format, best_quality)| {
+                quality > best_quality
+                    || (quality == best_quality && format.preference() < best_format.preference())
+            });
+            if quality > 0.0 && is_better {
+                best = Some((format, quality));
+            }
+        }
+
+        best.map_or(ResponseFormat::Svg, |(format, _)| format)
+    }
+}
+
+enum Views {
+    Counted(u64),
+    /// Views counted, along with views the datastore has yet to store
+    Approximate(u64),
+    /// Views counted earlier, served as they were since the user's quota or the request budget
+    /// is used up
+    Cached(u64),
+    UserDeleted,
+    /// User unknown with `ONBOARDING=explicit`, nothing was counted
+    NotRegistered,
+    /// The request budget is used up and no views of the user are cached, nothing was counted
+    Unavailable,
+}
+
+impl Views {
+    fn is_cached(&self) -> bool {
+        matches!(self, Views::Cached(_))
+    }
+
+    /// Views the badge shows, `None` when it shows none.
+    fn count(&self) -> Option<u64> {
+        match self {
+            Views::Counted(views) | Views::Approximate(views) | Views::Cached(views) => {
+                Some(*views)
+            }
+            Views::UserDeleted | Views::NotRegistered | Views::Unavailable => None,
+        }
+    }
+
+    fn freshness(&self) -> Option<Freshness> {
+        match self {
+            Views::Counted(_) => Some(Freshness::Live),
+            Views::Approximate(_) => Some(Freshness::Approximate),
+            Views::Cached(_) => Some(Freshness::Cached),
+            Views::UserDeleted | Views::NotRegistered | Views::Unavailable => None,
+        }
+    }
+}
+
+async fn count_view(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    user_name: &str,
+    headers: &HeaderMap,
+) -> Result<Views, ApiError> {
+    count_view_within(state, None, user_name, headers).await
+}
+
+/// The daily quota views are counted within, the tenant's on tenant routes.
+fn quota_of<'a>(
+    state: &'a AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    tenant: Option<&'a Tenant>,
+) -> Option<&'a DailyQuota> {
+    tenant.map_or(state.quota.as_ref(), |tenant| tenant.quota.as_ref())
+}
+
+/// How a view of the user is handled ahead of the datastore counting it, see [`decide_view`].
+enum Decision<'a> {
+    /// Flagged for unusual views, the views at flagging are served as if counted
+    Frozen(u64),
+    /// Viewed from one of the `BLOCKED_COUNTRIES`, served like any other view without counting
+    GeoBlocked(Views),
+    /// Today's quota is used up, the views at reaching it are served
+    OverQuota(u64),
+    /// The request budget is used up, the views cached for the user are served when known
+    OverBudget(Option<u64>),
+    /// Counted by the datastore; unknown users are onboarded when `onboard` is set, once
+    /// `verifier` found them on GitHub
+    Count {
+        onboard: bool,
+        verifier: Option<&'a GitHub>,
+    },
+}
+
+/// Decides how a view of the user, or of the `tenant` user stored under `user_name`, is handled.
+/// Nothing is counted or spent, so `debug_handler` reports the decision badge requests take.
+async fn decide_view<'a>(
+    state: &'a AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    tenant: Option<&Tenant>,
+    user_name: &str,
+    headers: &HeaderMap,
+) -> Result<Decision<'a>, ApiError> {
+    // tenant users are vouched for by their tenant, which only the route tells apart
+    let is_tenant_user = tenant.is_some();
+    let onboard = !state.config.explicit_onboarding || is_tenant_user;
+    let verifier = state
+        .github
+        .as_ref()
+        .filter(|_| state.config.verify_github_users && !is_tenant_user);
+
+    if let Some(views) = state
+        .anomalies
+        .as_ref()
+        .and_then(|anomalies| anomalies.frozen_views(user_name))
+    {
+        return Ok(Decision::Frozen(views));
+    }
+    if geo::is_blocked(&state.config.blocked_countries, headers) {
+        let views = match get_renamed_user(&state.db, user_name).await {
+            Ok(Some(user)) if user.deleted_at.is_some() => Views::UserDeleted,
+            Ok(None) if !onboard => Views::NotRegistered,
+            Ok(user) => Views::Counted(user.map_or(0, |user| user.views)),
+            Err(err) => {
+                tracing::error!("failed to fetch views from database, reason: {}", err);
+                return Err(datastore_unavailable());
+            }
+        };
+        return Ok(Decision::GeoBlocked(views));
+    }
+    if let Some(views) = quota_of(state, tenant).and_then(|quota| quota.capped_views(user_name)) {
+        return Ok(Decision::OverQuota(views));
+    }
+    if state.budget.as_ref().is_some_and(RequestBudget::is_used_up) {
+        return Ok(Decision::OverBudget(state.db.cached_views(user_name).await));
+    }
+    Ok(Decision::Count { onboard, verifier })
+}
+
+/// Counts a view of the user, or of the `tenant` user stored under `user_name`.
+async fn count_view_within(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    tenant: Option<&Tenant>,
+    user_name: &str,
+    headers: &HeaderMap,
+) -> Result<Views, ApiError> {
+    let traced = state.traces.is_traced(user_name);
+    let (onboard, verifier) = match decide_view(state, tenant, user_name, headers).await? {
+        Decision::Frozen(views) => {
+            trace_decision(traced, user_name, "frozen", Some(views));
+            // served like counted views, down to their freshness, so the freeze doesn't show
+            return Ok(Views::Counted(views));
+        }
+        // served like any other view, so blocked viewers can't tell
+        Decision::GeoBlocked(views) => {
+            trace_decision(traced, user_name, "geo_blocked", None);
+            return Ok(views);
+        }
+        Decision::OverQuota(views) => {
+            trace_decision(traced, user_name, "over_quota", Some(views));
+            return Ok(Views::Cached(views));
+        }
+        Decision::OverBudget(views) => return Ok(over_budget(traced, user_name, views)),
+        Decision::Count { onboard, verifier } => (onboard, verifier),
+    };
+    if state
+        .budget
+        .as_ref()
+        .is_some_and(|budget| !budget.try_spend())
+    {
+        // used up by concurrent requests since deciding
+        let views = state.db.cached_views(user_name).await;
+        return Ok(over_budget(traced, user_name, views));
+    }
+
+    let counted = count_view_on(&state.db, user_name, onboard, verifier).await;
+    let views = counted.as_ref().map(|(views, _)| views);
+    match &views {
+        // the datastore always counts the view
+        Ok(Views::Counted(views) | Views::Approximate(views) | Views::Cached(views)) => {
+            trace_decision(traced, user_name, "counted", Some(*views))
+        }
+        Ok(Views::UserDeleted) => trace_decision(traced, user_name, "deleted", None),
+        Ok(Views::NotRegistered) => trace_decision(traced, user_name, "not_registered", None),
+        Ok(Views::Unavailable) => trace_decision(traced, user_name, "over_budget", None),
+        Err(_) => trace_decision(traced, user_name, "datastore_failed", None),
+    }
+    let (views, counted_on) = counted?;
+    if let Views::Counted(views) | Views::Approximate(views) = views {
+        if let Some(quota) = quota_of(state, tenant) {
+            quota.record(user_name, views);
+        }
+        if let Some(anomalies) = &state.anomalies {
+            anomalies.record(user_name, views);
+        }
+        if let Some(events) = &state.events {
+            events.publish(user_name, headers);
+            trace_decision(traced, user_name, "event_published", Some(views));
+        }
+        state.history.record(&counted_on);
+    }
+    Ok(views)
+}
+
+fn over_budget(traced: bool, user_name: &str, views: Option<u64>) -> Views {
+    metrics::record_over_budget();
+    trace_decision(traced, user_name, "over_budget", views);
+    // badges of users without cached views show as unavailable, like badges of shed requests
+    views.map_or(Views::Unavailable, Views::Cached)
+}
+
+fn budget_used_up() -> ApiError {
+    ApiError::new(ErrorCode::Overloaded, "request budget used up, retry later")
+}
+
+/// Logs a decision taken for a request of a user traced through `POST /admin/debug/:user_name`.
+fn trace_decision(traced: bool, user_name: &str, decision: &str, views: Option<u64>) {
+    if traced {
+        tracing::info!(
+            traced_user = user_name,
+            decision,
+            views,
+            "traced view decision"
+        );
+    }
+}
+
+/// Reports the user's daily quota in `RateLimit-*` headers, when a quota is configured.
+fn with_quota_headers(
+    quota: Option<&DailyQuota>,
+    user_name: &str,
+    mut response: Response,
+) -> Response {
+    if let Some(quota) = quota {
+        quota
+            .rate_limit(user_name)
+            .insert_headers(response.headers_mut());
+    }
+    response
+}
+
+/// Tells where the served views come from in the `X-Count-Freshness` header: `live` from the
+/// datastore, `cached` when counted earlier or `approximate` from local counts.
+fn with_freshness_header(freshness: Option<Freshness>, mut response: Response) -> Response {
+    if let Some(freshness) = freshness {
+        response.headers_mut().insert(
+            COUNT_FRESHNESS_HEADER,
+            HeaderValue::from_static(freshness.as_str()),
+        );
+    }
+    response
+}
+
+/// Counts the view, returning the user it was counted on: renamed users are counted on the
+/// user they were renamed to.
+async fn count_view_on<'a>(
+    db: &impl DatastoreOperations,
+    user_name: &'a str,
+    onboard: bool,
+    verifier: Option<&GitHub>,
+) -> Result<(Views, Cow<'a, str>), ApiError> {
+    let mut counted_on = Cow::Borrowed(user_name);
+    let mut result = db.get_latest_views(user_name).await;
+    for _ in 0..MAX_RENAMES {
+        let Err(DatastoreError::UserRenamed(user, renamed_to)) = &result else {
+            break;
+        };
+        tracing::info!(
+            "user `{}` is renamed to `{}`, counting view",
+            user,
+            renamed_to
+        );
+        counted_on = Cow::Owned(renamed_to.clone());
+        result = db.get_latest_views(&counted_on).await;
+    }
+
+    let views = match result {
+        Ok(views) => Ok(counted(db, &counted_on, views).await),
+        Err(DatastoreError::UserNotFound(user)) if !onboards(&user, onboard, verifier).await? => {
+            Ok(Views::NotRegistered)
+        }
         Err(DatastoreError::UserNotFound(user)) => {
             tracing::info!("user `{}` not found, onboarding", &user);
 
-            match state.db.onboard_user(&user).await {
+            match db.onboard_user(&user).await {
                 Ok(views) => {
                     tracing::info!("user `{}` onboarded", &user);
-                    views
+                    Ok(counted(db, &user, views).await)
                 }
                 Err(err) => {
                     tracing::error!("failed to onboard user `{}`, reason: {}", &user, err);
-                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    Err(datastore_unavailable())
                 }
             }
         }
+        Err(DatastoreError::UserDeleted(user)) => {
+            tracing::info!("user `{}` is deleted, not counting views", &user);
+            Ok(Views::UserDeleted)
+        }
         Err(err) => {
             tracing::error!("failed to fetch views from database, reason: {}", err);
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            Err(datastore_unavailable())
         }
+    }?;
+    Ok((views, counted_on))
+}
+
+/// Whether the unknown user gets onboarded: not with `ONBOARDING=explicit`, nor when they aren't
+/// found on GitHub with `USER_VERIFICATION=github`.
+async fn onboards(
+    user_name: &str,
+    onboard: bool,
+    verifier: Option<&GitHub>,
+) -> Result<bool, ApiError> {
+    if !onboard {
+        tracing::info!("user `{}` not found, not registered", user_name);
+        return Ok(false);
+    }
+    let Some(github) = verifier else {
+        return Ok(true);
     };
+    match github.user_exists(user_name).await {
+        Ok(true) => Ok(true),
+        Ok(false) => {
+            tracing::info!("user `{}` is not on github, not onboarding", user_name);
+            Ok(false)
+        }
+        Err(err) => {
+            tracing::error!("failed to verify user `{}`, reason: {}", user_name, err);
+            Err(ApiError::new(
+                ErrorCode::GitHubUnavailable,
+                "failed to verify user on github",
+            ))
+        }
+    }
+}
 
-    match state.badge.fetch(&query, views).await {
-        Ok(badge) => (
-            // docs - https://docs.rs/axum/latest/axum/response/index.html
-            StatusCode::OK,
-            [
-                (
-                    "Cache-Control",
-                    "max-age=0, no-cache, no-store, must-revalidate",
-                ),
-                ("Content-Type", "image/svg+xml"),
-            ],
-            badge,
-        )
-            .into_response(),
+// views just counted, approximate when they include views the datastore has yet to store
+async fn counted(db: &impl DatastoreOperations, user_name: &str, views: u64) -> Views {
+    match db.freshness(user_name).await {
+        Freshness::Approximate => Views::Approximate(views),
+        Freshness::Live | Freshness::Cached => Views::Counted(views),
+    }
+}
+
+/// Looks the user up without counting a view, following renames like counted views do.
+async fn get_renamed_user(
+    db: &impl DatastoreOperations,
+    user_name: &str,
+) -> Result<Option<UserRecord>, DatastoreError> {
+    Ok(lookup_renamed_user(db, user_name).await?.1)
+}
+
+/// Same as [`get_renamed_user`], additionally returning the user views would be counted on.
+async fn lookup_renamed_user<'a>(
+    db: &impl DatastoreOperations,
+    user_name: &'a str,
+) -> Result<(Cow<'a, str>, Option<UserRecord>), DatastoreError> {
+    let mut counted_on = Cow::Borrowed(user_name);
+    let mut user = db.get_user(user_name).await?;
+    for _ in 0..MAX_RENAMES {
+        match user.as_ref().and_then(|user| user.renamed_to.clone()) {
+            Some(renamed_to) => {
+                user = db.get_user(&renamed_to).await?;
+                counted_on = Cow::Owned(renamed_to);
+            }
+            None => break,
+        }
+    }
+    Ok((counted_on, user))
+}
+
+fn datastore_unavailable() -> ApiError {
+    ApiError::new(ErrorCode::DatastoreUnavailable, "failed to count views")
+}
+
+fn not_registered(user_name: &str) -> ApiError {
+    ApiError::new(
+        ErrorCode::UserNotRegistered,
+        format!("user `{}` is not registered", user_name),
+    )
+}
+
+/// Error badge describing a mistake in the badge params, checked before counting the view so
+/// typos get noticed on the first look at the badge.
+pub fn invalid_params_badge(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    params: &ShieldsIoParams,
+) -> Option<String> {
+    if state.config.permissive_badge_params {
+        return None;
+    }
+
+    let mistake = params.validate().err()?;
+    Some(error_badge(state, params.style(), &mistake))
+}
+
+/// Mistake in the pixel density of raster badges; nan and infinity parse as numbers but can't be
+/// clamped to the supported range.
+pub fn check_scale(scale: Option<f32>) -> Result<(), String> {
+    match scale {
+        Some(scale) if !scale.is_finite() => Err(format!(
+            "invalid scale {}, expected 1 to {}",
+            scale, MAX_RASTER_SCALE
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Badge describing a mistake in the badge params, in the requested style.
+pub fn error_badge(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    style: &str,
+    mistake: &str,
+) -> String {
+    let error_params = ShieldsIoParams::new("invalid badge", "red", style);
+    local_badge(state, &error_params, mistake)
+}
+
+/// Badge of users yet to be onboarded with `ONBOARDING=explicit`, in the requested style.
+fn not_registered_badge(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    style: &str,
+) -> String {
+    let params = ShieldsIoParams::new("profile views", "lightgrey", style);
+    local_badge(state, &params, "not registered")
+}
+
+// laid out locally, sparing a request to shields.io
+fn local_badge(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    params: &ShieldsIoParams,
+    message: &str,
+) -> String {
+    match state.raster.layout(params, message, Font::default()) {
+        Ok(badge) => badge,
+        Err(err) => {
+            tracing::error!("failed to lay out badge, reason: {}", err);
+            UNAVAILABLE_BADGE.to_string()
+        }
+    }
+}
+
+/// What a views badge shows.
+struct BadgeContents<'a> {
+    /// Badge params, colored by the views' trend when asked for
+    params: Cow<'a, ShieldsIoParams>,
+    /// Views formatted for the locale, with or replaced by their gain or goal when asked for
+    message: String,
+    /// Title of the svg read out by screen readers, see [`alt_text`]
+    title: String,
+    /// Progress towards the goal, between 0 and 1, when drawn as a progress bar
+    progress: Option<f32>,
+    /// Whether the badge is muted for serving views counted earlier
+    cached: bool,
+}
+
+impl BadgeContents<'_> {
+    /// Title of the svg, telling muted badges apart from live ones.
+    fn svg_title(&self) -> Cow<'_, str> {
+        match self.cached {
+            true => Cow::Owned(format!("{} (cached)", self.title)),
+            false => Cow::Borrowed(&self.title),
+        }
+    }
+}
+
+/// Day stats of the user for badges showing gains or trends; `None` when they can't be read, the
+/// views are still worth serving without them.
+async fn recent_day_stats(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    user_name: &str,
+) -> Option<DayStats> {
+    match state.db.get_day_stats(user_name).await {
+        Ok(day_stats) => day_stats,
+        Err(err) => {
+            tracing::warn!("failed to get day stats of {}, reason: {}", user_name, err);
+            None
+        }
+    }
+}
+
+async fn badge_contents<'a>(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    user_name: &str,
+    views: u64,
+    cached: bool,
+    params: &'a ShieldsIoParams,
+    display_params: &DisplayParams,
+) -> BadgeContents<'a> {
+    let cached = cached && state.config.mute_cached_badges;
+    // a goal of 0 would always be reached, so it's ignored
+    let goal = display_params.goal.filter(|goal| *goal > 0);
+    let today = Utc::now().date_naive();
+    let shows_recent_views = (display_params.trend.is_some() && !cached)
+        || (display_params.delta.is_some() && goal.is_none());
+    let day_stats = match shows_recent_views {
+        true => recent_day_stats(state, user_name).await,
+        false => None,
+    };
+    let gained = |period: DeltaPeriod| {
+        day_stats.as_ref().map_or(0, |day_stats| {
+            day_stats.views_between(today, 0..period.days())
+        })
+    };
+
+    let params = match display_params.trend {
+        _ if cached => Cow::Owned(params.with_color(CACHED_BADGE_COLOR)),
+        Some(period) => {
+            let threshold = display_params
+                .trend_threshold
+                .unwrap_or(state.config.trend_threshold);
+            let trend = day_stats.as_ref().map_or(Trend::Flat, |day_stats| {
+                Trend::of(day_stats, today, period, threshold)
+            });
+            Cow::Owned(params.with_color(trend.color()))
+        }
+        None => Cow::Borrowed(params),
+    };
+
+    let format = |views: u64| match display_params.locale {
+        Some(locale) => locale.format(views),
+        None => views.to_string(),
+    };
+    let message = match (
+        goal,
+        display_params.delta,
+        display_params.delta_only.unwrap_or(false),
+    ) {
+        (Some(goal), _, _) if display_params.goal_percent.unwrap_or(false) => {
+            // percent is floored, so 100% means the goal is reached
+            format!("{}%", views as u128 * 100 / goal as u128)
+        }
+        (Some(goal), _, _) => format!("{} / {}", format(views), locale::compact(goal)),
+        (None, Some(period), true) => format!("+{} {}", format(gained(period)), period.describe()),
+        (None, Some(period), false) => format!("{} (+{})", format(views), format(gained(period))),
+        (None, None, _) => format(views),
+    };
+    let message = match display_params.since.unwrap_or(false) {
+        true => match state.first_seen.get(&state.db, user_name).await {
+            Ok(Some(onboarded_at)) => format!("{} {}", message, first_seen::since(onboarded_at)),
+            Ok(None) => message,
+            Err(err) => {
+                // the views are still worth serving without it
+                tracing::warn!(
+                    "failed to get when {} was onboarded, reason: {}",
+                    user_name,
+                    err
+                );
+                message
+            }
+        },
+        false => message,
+    };
+    let progress = goal
+        .filter(|_| display_params.goal_bar.unwrap_or(false))
+        .map(|goal| (views as f64 / goal as f64).min(1.0) as f32);
+
+    if state.traces.is_traced(user_name) {
+        tracing::info!(
+            traced_user = user_name,
+            decision = "badge",
+            params = %params,
+            badge_message = %message,
+            "traced view decision"
+        );
+    }
+
+    BadgeContents {
+        params,
+        message,
+        title: alt_text(views, user_name),
+        progress,
+        cached,
+    }
+}
+
+/// Describes a views badge, e.g. `42 profile views for octocat`.
+fn alt_text(views: u64, user_name: &str) -> String {
+    format!("{} profile views for {}", views, user_name)
+}
+
+async fn svg_response(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    contents: &BadgeContents<'_>,
+) -> Response {
+    // shields.io has no progress bars, so those badges are laid out locally
+    let badge = match contents.progress {
+        Some(_) => layout_badge(state, contents),
+        None => fetch_badge(&state.badge, contents).await,
+    };
+    match badge {
+        Ok(badge) => badge_response(badge),
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn fetch_badge(
+    badge: &impl ShieldsIoFetcher,
+    contents: &BadgeContents<'_>,
+) -> Result<String, ApiError> {
+    match badge
+        .fetch_message(&contents.params, &contents.message)
+        .await
+    {
+        Ok(svg) => Ok(badge::with_title(&svg, &contents.svg_title())),
         Err(err) => {
             tracing::error!("failed to fetch badge from shields.io, reason: {}", err);
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            Err(ApiError::new(
+                ErrorCode::UpstreamBadgeFailed,
+                "failed to fetch badge from shields.io",
+            ))
         }
     }
 }
+
+async fn raster_counter_response(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    params: &ShieldsIoParams,
+    display_params: &DisplayParams,
+    user_name: &str,
+    headers: &HeaderMap,
+    format: RasterFormat,
+    raster_params: &RasterParams,
+) -> Response {
+    let counted = count_view(state, user_name, headers).await;
+    let freshness = counted.as_ref().ok().and_then(Views::freshness);
+    let badge = match counted {
+        Ok(served @ (Views::Counted(views) | Views::Approximate(views) | Views::Cached(views))) => {
+            let contents = badge_contents(
+                state,
+                user_name,
+                views,
+                served.is_cached(),
+                params,
+                display_params,
+            )
+            .await;
+            let font = Font::from_params(raster_params.font, raster_params.font_weight);
+            raster_badge(state, &contents, font).await
+        }
+        Ok(Views::UserDeleted | Views::Unavailable) => {
+            Ok(RasterBadge::new(UNAVAILABLE_BADGE.to_string()))
+        }
+        Ok(Views::NotRegistered) => Ok(RasterBadge::new(not_registered_badge(
+            state,
+            params.style(),
+        ))),
+        Err(err) => Err(err),
+    };
+
+    let response = raster_response(&state.raster, badge, format, raster_params.scale).await;
+    let response = with_freshness_header(freshness, response);
+    with_quota_headers(state.quota.as_ref(), user_name, response)
+}
+
+/// Badge to rasterize, laid out locally for custom fonts, progress bars and text shields.io
+/// can't measure; the layout is left to the render, off the threads serving requests.
+async fn raster_badge(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    contents: &BadgeContents<'_>,
+    font: Option<Font>,
+) -> Result<RasterBadge, ApiError> {
+    let (params, message) = (&contents.params, &contents.message);
+    let font = match font {
+        Some(font) => font,
+        None if contents.progress.is_none()
+            && raster::has_shields_widths(params.label())
+            && raster::has_shields_widths(message) =>
+        {
+            let svg = fetch_badge(&state.badge, contents).await?;
+            return Ok(RasterBadge::with_message(svg, message));
+        }
+        None => Font::default(),
+    };
+
+    Ok(RasterBadge::Layout {
+        params: params.clone().into_owned(),
+        message: message.clone(),
+        font,
+        progress: contents.progress,
+    })
+}
+
+/// Svg of a badge laid out locally, for progress bars shields.io doesn't have.
+fn layout_badge(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    contents: &BadgeContents<'_>,
+) -> Result<String, ApiError> {
+    let layout = state.raster.layout_progress(
+        &contents.params,
+        &contents.message,
+        Font::default(),
+        contents.progress,
+    );
+    layout
+        .map(|svg| badge::with_title(&svg, &contents.svg_title()))
+        .map_err(|err| {
+            tracing::error!("failed to lay out badge, reason: {}", err);
+            ApiError::new(ErrorCode::RenderFailed, "failed to render badge")
+        })
+}
+
+pub async fn raster_response(
+    raster: &Arc<Rasterizer>,
+    badge: Result<RasterBadge, ApiError>,
+    format: RasterFormat,
+    scale: Option<f32>,
+) -> Response {
+    let badge = match badge {
+        Ok(badge) => badge,
+        Err(err) => return err.into_response(),
+    };
+
+    let scale = scale.unwrap_or(1.0).clamp(1.0, MAX_RASTER_SCALE);
+    match raster.render(badge, format, scale).await {
+        Ok(image) => uncached_response(format.content_type(), image),
+        Err(err) => {
+            tracing::error!("failed to render {:?} badge, reason: {}", format, err);
+            ApiError::new(ErrorCode::RenderFailed, "failed to render badge").into_response()
+        }
+    }
+}
+
+pub fn badge_response(badge: String) -> Response {
+    uncached_response("image/svg+xml", badge)
+}
+
+fn uncached_response(content_type: &'static str, body: impl IntoResponse) -> Response {
+    (
+        // docs - https://docs.rs/axum/latest/axum/response/index.html
+        StatusCode::OK,
+        [
+            (
+                "Cache-Control",
+                "max-age=0, no-cache, no-store, must-revalidate",
+            ),
+            ("Content-Type", content_type),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn format_for(accept: &str) -> ResponseFormat {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        ResponseFormat::from_accept(&headers)
+    }
+
+    #[test]
+    fn it_negotiates_response_format_from_accept_header() {
+        assert_eq!(
+            ResponseFormat::from_accept(&HeaderMap::new()),
+            ResponseFormat::Svg
+        );
+        assert_eq!(format_for("*/*"), ResponseFormat::Svg);
+        assert_eq!(format_for("application/json"), ResponseFormat::Json);
+        assert_eq!(format_for("text/plain"), ResponseFormat::Text);
+        assert_eq!(format_for("text/html"), ResponseFormat::Svg);
+        assert_eq!(
+            format_for("image/avif,image/webp,image/svg+xml,image/*,*/*;q=0.8"),
+            ResponseFormat::Svg
+        );
+        assert_eq!(
+            format_for("image/webp,image/png,*/*;q=0.8"),
+            ResponseFormat::Raster(RasterFormat::Webp)
+        );
+        assert_eq!(
+            format_for("image/png"),
+            ResponseFormat::Raster(RasterFormat::Png)
+        );
+        assert_eq!(
+            format_for("text/plain;q=0.5, application/json;q=0.9"),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            format_for("application/json;q=0, text/plain"),
+            ResponseFormat::Text
+        );
+    }
+
+    #[test]
+    fn it_reads_response_format_from_query() {
+        let format_of = |query: &str| {
+            Query::<FormatParams>::try_from_uri(
+                &format!("/user/counter?{}", query).parse().unwrap(),
+            )
+            .map(|params| params.0.format)
+            .ok()
+        };
+
+        assert_eq!(format_of("format=svg"), Some(Some(ResponseFormat::Svg)));
+        assert_eq!(
+            format_of("format=webp&scale=2"),
+            Some(Some(ResponseFormat::Raster(RasterFormat::Webp)))
+        );
+        assert_eq!(
+            format_of("format=png"),
+            Some(Some(ResponseFormat::Raster(RasterFormat::Png)))
+        );
+        assert_eq!(format_of("format=json"), Some(Some(ResponseFormat::Json)));
+        assert_eq!(format_of("label=views"), Some(None));
+        assert_eq!(format_of("format=gif"), None);
+    }
+
+    #[test]
+    fn it_checks_raster_scales_are_finite() {
+        assert!(check_scale(None).is_ok());
+        assert!(check_scale(Some(2.0)).is_ok());
+        // out of range scales are clamped rather than rejected
+        assert!(check_scale(Some(10.0)).is_ok());
+        assert!(check_scale(Some(f32::NAN)).is_err());
+        assert!(check_scale(Some(f32::INFINITY)).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_counts_views_of_renamed_users_on_their_new_name() {
+        let db = crate::datastore::Memory::new();
+        db.onboard_user("alice").await.unwrap();
+        db.merge_users(&["alice".to_string()], "alicia")
+            .await
+            .unwrap();
+        db.merge_users(&["alicia".to_string()], "ali")
+            .await
+            .unwrap();
+
+        let (views, counted_on) = count_view_on(&db, "alice", true, None).await.unwrap();
+        assert!(matches!(views, Views::Counted(2)));
+        assert_eq!(counted_on, "ali");
+        let user = get_renamed_user(&db, "alice").await.unwrap().unwrap();
+        assert_eq!((user.user_name.as_str(), user.views), ("ali", 2));
+    }
+
+    #[tokio::test]
+    async fn it_reports_the_freshness_of_counted_views() {
+        use std::time::Duration;
+
+        use crate::config::OptimisticConfig;
+        use crate::datastore::{Memory, OptimisticDatastore};
+
+        let db = OptimisticDatastore::new(
+            Memory::new(),
+            &OptimisticConfig {
+                flush_interval: Duration::from_secs(5),
+                revalidate_after: Duration::from_secs(60),
+                max_staleness: Duration::from_secs(60),
+            },
+        );
+        let (views, _) = count_view_on(&db, "alice", true, None).await.unwrap();
+        assert!(matches!(views, Views::Counted(1)));
+        let (views, _) = count_view_on(&db, "alice", true, None).await.unwrap();
+        assert!(matches!(views, Views::Approximate(2)));
+
+        let response = with_freshness_header(views.freshness(), ().into_response());
+        assert_eq!(response.headers()[COUNT_FRESHNESS_HEADER], "approximate");
+        let response = with_freshness_header(Views::Cached(2).freshness(), ().into_response());
+        assert_eq!(response.headers()[COUNT_FRESHNESS_HEADER], "cached");
+        let response = with_freshness_header(Views::UserDeleted.freshness(), ().into_response());
+        assert!(!response.headers().contains_key(COUNT_FRESHNESS_HEADER));
+    }
+
+    #[tokio::test]
+    async fn it_counts_views_on_the_fallback_while_the_datastore_fails() {
+        use crate::config::FaultConfig;
+        use crate::datastore::{FaultyDatastore, Memory, TieredDatastore};
+
+        let outage = Some(FaultConfig {
+            error_rate: 1.0,
+            ..FaultConfig::default()
+        });
+        let db = FaultyDatastore::new(Memory::new(), outage.clone());
+        let Err(err) = count_view_on(&db, "alice", true, None).await else {
+            panic!("views got counted on a failing datastore");
+        };
+        assert_eq!(
+            err.into_response().status(),
+            ErrorCode::DatastoreUnavailable.status()
+        );
+
+        let db = TieredDatastore::new(FaultyDatastore::new(Memory::new(), outage), Memory::new());
+        let (views, counted_on) = count_view_on(&db, "alice", true, None).await.unwrap();
+        // counted by the fallback, the datastore is yet to store the view
+        assert!(matches!(views, Views::Approximate(1)));
+        assert_eq!(counted_on, "alice");
+    }
+
+    #[tokio::test]
+    async fn it_serves_cached_views_past_the_request_budget() {
+        use axum::{body::Body, extract::Request, routing::get, Router};
+        use tower::ServiceExt;
+
+        use crate::badge::Shields;
+        use crate::cache::CacheStores;
+        use crate::config::{BudgetConfig, Config};
+        use crate::datastore::Memory;
+        use crate::routes;
+        use crate::runtime::TokioSpawner;
+
+        let mut config = Config::from_env();
+        config.request_budget = Some(BudgetConfig {
+            per_second: 0.001,
+            burst: 1.0,
+        });
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        let state = Arc::new(AppState::new(
+            Memory::new(),
+            shields,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        ));
+        state.db.onboard_user("alice").await.unwrap();
+
+        let views = count_view(&state, "alice", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert!(matches!(views, Views::Counted(2)));
+        let views = count_view(&state, "alice", &HeaderMap::new())
+            .await
+            .unwrap();
+        assert!(matches!(views, Views::Cached(2)));
+        let views = count_view(&state, "bob", &HeaderMap::new()).await.unwrap();
+        assert!(matches!(views, Views::Unavailable));
+
+        // badges of users without cached views show as unavailable, other formats fail
+        let app = Router::new()
+            .route(routes::COUNTER, get(profile_views_handler))
+            .route(routes::COUNTER_NEGOTIATED, get(counter_handler))
+            .with_state(state);
+        let get = |uri: &str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request)
+        };
+        let response = get("/bob/counter.svg?label=views&color=blue&style=flat")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, UNAVAILABLE_BADGE.as_bytes());
+        let response = get("/bob/counter?format=json").await.unwrap();
+        assert_eq!(response.status(), ErrorCode::Overloaded.status());
+    }
+
+    #[tokio::test]
+    async fn it_diagnoses_the_views_badge_requests_serve() {
+        use crate::badge::Shields;
+        use crate::cache::CacheStores;
+        use crate::config::Config;
+        use crate::datastore::Memory;
+        use crate::runtime::TokioSpawner;
+
+        let mut config = Config::from_env();
+        config.explicit_onboarding = true;
+        config.daily_view_quota = Some(2);
+        config.blocked_countries = ["DE".to_string()].into();
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        let state = AppState::new(
+            Memory::new(),
+            shields,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        );
+        for user_name in ["alice", "bob", "carol"] {
+            state.db.onboard_user(user_name).await.unwrap();
+        }
+        state
+            .db
+            .merge_users(&["bob".to_string()], "robert")
+            .await
+            .unwrap();
+        state.db.delete_user("carol").await.unwrap();
+        let mut blocked = HeaderMap::new();
+        blocked.insert("cf-ipcountry", HeaderValue::from_static("DE"));
+
+        let cases = [
+            ("alice", HeaderMap::new(), ViewOutcome::Counted),
+            ("alice", HeaderMap::new(), ViewOutcome::Counted),
+            ("alice", blocked, ViewOutcome::GeoBlocked),
+            ("alice", HeaderMap::new(), ViewOutcome::OverQuota),
+            ("bob", HeaderMap::new(), ViewOutcome::Counted),
+            ("carol", HeaderMap::new(), ViewOutcome::Deleted),
+            ("dave", HeaderMap::new(), ViewOutcome::NotRegistered),
+            // only tenant routes vouch for tenant users
+            ("acme/dave", HeaderMap::new(), ViewOutcome::NotRegistered),
+        ];
+        for (user_name, headers, outcome) in cases {
+            let diagnosis = diagnose(&state, user_name, &headers).await.unwrap();
+            let views = count_view(&state, user_name, &headers).await.unwrap();
+            assert_eq!(diagnosis.outcome, outcome, "{}", user_name);
+            assert_eq!(diagnosis.views, views.count(), "{}", user_name);
+        }
+
+        let diagnosis = diagnose(&state, "bob", &HeaderMap::new()).await.unwrap();
+        assert_eq!(diagnosis.counted_on.as_deref(), Some("robert"));
+    }
+
+    #[tokio::test]
+    async fn it_verifies_users_unless_the_route_is_a_tenants() {
+        use crate::badge::Shields;
+        use crate::cache::CacheStores;
+        use crate::config::{Config, TenantConfig};
+        use crate::datastore::Memory;
+        use crate::runtime::TokioSpawner;
+
+        let mut config = Config::from_env();
+        config.verify_github_users = true;
+        config.tenants.insert(
+            "acme".to_string(),
+            TenantConfig {
+                api_key: "key".to_string(),
+                label: None,
+                color: None,
+                style: None,
+                daily_view_quota: None,
+                trend_threshold: None,
+                domains: vec![],
+            },
+        );
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        let state = AppState::new(
+            Memory::new(),
+            shields,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        );
+        let tenant = state.tenants.get("acme").unwrap();
+        let verified = |decision| match decision {
+            Decision::Count { verifier, .. } => verifier.is_some(),
+            _ => panic!("view isn't counted"),
+        };
+
+        // a name alone, e.g. a percent-encoded `acme%2Falice`, doesn't make a tenant user
+        let headers = HeaderMap::new();
+        let decision = decide_view(&state, None, "acme/alice", &headers);
+        assert!(verified(decision.await.unwrap()));
+        let decision = decide_view(&state, Some(tenant), "acme/alice", &headers);
+        assert!(!verified(decision.await.unwrap()));
+    }
+}
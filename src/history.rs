@@ -0,0 +1,326 @@
+use std::sync::Mutex;
+
+use chrono::{Datelike, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::cache::Lru;
+use super::datastore::DayStats;
+
+// longest period views are kept for, in days
+const HISTORY_DAYS: usize = 60;
+// users whose history is kept, 240 bytes of views each; the least recently viewed ones are
+// forgotten past it
+const HISTORY_USERS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeltaPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl DeltaPeriod {
+    pub fn days(&self) -> usize {
+        match self {
+            DeltaPeriod::Day => 1,
+            DeltaPeriod::Week => 7,
+            DeltaPeriod::Month => 30,
+        }
+    }
+
+    /// Describes the period in a badge message, e.g. `+321 this week`.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            DeltaPeriod::Day => "today",
+            DeltaPeriod::Week => "this week",
+            DeltaPeriod::Month => "this month",
+        }
+    }
+}
+
+/// Growth of a user's views over a period compared to the period before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trend {
+    Up,
+    Flat,
+    Down,
+}
+
+impl Trend {
+    /// Trend of the views of the period up to today compared to the period before, from the
+    /// views per day of the user's day stats.
+    pub fn of(
+        day_stats: &DayStats,
+        today: NaiveDate,
+        period: DeltaPeriod,
+        threshold: f64,
+    ) -> Trend {
+        let days = period.days();
+        let previous = day_stats.views_between(today, days..2 * days);
+        let current = day_stats.views_between(today, 0..days);
+        Trend::between(previous, current, threshold)
+    }
+
+    /// Trend of the views, flat while they changed by no more than `threshold` percent.
+    fn between(previous: u64, current: u64, threshold: f64) -> Trend {
+        let (previous, current) = (previous as f64, current as f64);
+        let margin = previous * threshold / 100.0;
+        if current > previous + margin {
+            Trend::Up
+        } else if current < previous - margin {
+            Trend::Down
+        } else {
+            Trend::Flat
+        }
+    }
+
+    /// shields.io color of badges showing the trend.
+    pub fn color(&self) -> &'static str {
+        match self {
+            Trend::Up => "green",
+            Trend::Flat => "lightgrey",
+            Trend::Down => "red",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct DayViews {
+    /// UTC day
+    pub day: NaiveDate,
+    pub views: u32,
+}
+
+/// Views counted per user and UTC day over the last two months, for exports of the user's data.
+/// History lives in the instance's memory: it only holds the views this instance counted, starts
+/// over from zero when the server restarts, and forgets the least recently viewed users past ten
+/// thousand, so exports served by several instances differ from one another.
+pub struct ViewHistory {
+    users: Mutex<Lru<DailyViews>>,
+}
+
+impl Default for ViewHistory {
+    fn default() -> ViewHistory {
+        ViewHistory::with_capacity(HISTORY_USERS)
+    }
+}
+
+struct DailyViews {
+    // day of the most recent slot, in days since the common era
+    day: i32,
+    // views by day, indexed by day modulo `HISTORY_DAYS`
+    views: [u32; HISTORY_DAYS],
+}
+
+impl DailyViews {
+    fn roll(&mut self, day: i32) {
+        let elapsed = (day - self.day).clamp(0, HISTORY_DAYS as i32);
+        for offset in 1..=elapsed {
+            self.views[slot(self.day + offset)] = 0;
+        }
+        self.day = self.day.max(day);
+    }
+}
+
+// views of the user rolled over to the day, starting from none for users without history
+fn daily_views_of<'a>(
+    users: &'a mut Lru<DailyViews>,
+    day: i32,
+    user_name: &str,
+) -> &'a mut DailyViews {
+    if users.peek(user_name).is_none() {
+        let views = DailyViews {
+            day,
+            views: [0; HISTORY_DAYS],
+        };
+        users.insert(user_name.to_string(), views);
+    }
+    let daily_views = users.get_mut(user_name).expect("history was just inserted");
+    daily_views.roll(day);
+    daily_views
+}
+
+fn slot(day: i32) -> usize {
+    day.rem_euclid(HISTORY_DAYS as i32) as usize
+}
+
+fn today() -> i32 {
+    Utc::now().date_naive().num_days_from_ce()
+}
+
+impl ViewHistory {
+    pub fn new() -> ViewHistory {
+        ViewHistory::default()
+    }
+
+    fn with_capacity(users: usize) -> ViewHistory {
+        ViewHistory {
+            users: Mutex::new(Lru::new(users)),
+        }
+    }
+
+    /// Records a counted view.
+    pub fn record(&self, user_name: &str) {
+        self.record_on(today(), user_name)
+    }
+
+    /// Views of the days in the history with views, oldest first.
+    pub fn daily_views(&self, user_name: &str) -> Vec<DayViews> {
+        self.daily_views_on(today(), user_name)
+    }
+
+    /// Adds the history of the users to the one of `merged_into`, forgetting theirs.
+    pub fn merge(&self, user_names: &[String], merged_into: &str) {
+        self.merge_on(today(), user_names, merged_into)
+    }
+
+    fn merge_on(&self, day: i32, user_names: &[String], merged_into: &str) {
+        let mut users = self.users.lock().unwrap();
+        let merged: Vec<DailyViews> = user_names
+            .iter()
+            .filter_map(|user_name| users.remove(user_name))
+            .collect();
+        if merged.is_empty() {
+            return;
+        }
+
+        let daily_views = daily_views_of(&mut users, day, merged_into);
+        for mut other in merged {
+            other.roll(day);
+            for (views, other_views) in daily_views.views.iter_mut().zip(other.views) {
+                *views += other_views;
+            }
+        }
+    }
+
+    fn record_on(&self, day: i32, user_name: &str) {
+        let mut users = self.users.lock().unwrap();
+        let daily_views = daily_views_of(&mut users, day, user_name);
+        daily_views.views[slot(day)] += 1;
+    }
+
+    fn daily_views_on(&self, day: i32, user_name: &str) -> Vec<DayViews> {
+        let mut users = self.users.lock().unwrap();
+        let Some(daily_views) = users.get_mut(user_name) else {
+            return Vec::new();
+        };
+
+        daily_views.roll(day);
+        (day - HISTORY_DAYS as i32 + 1..=day)
+            .filter(|day| daily_views.views[slot(*day)] > 0)
+            .filter_map(|day| {
+                Some(DayViews {
+                    day: NaiveDate::from_num_days_from_ce_opt(day)?,
+                    views: daily_views.views[slot(day)],
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const USER_NAME: &str = "octocat";
+
+    // views of the user over the history
+    fn views_on(history: &ViewHistory, day: i32, user_name: &str) -> u32 {
+        history
+            .daily_views_on(day, user_name)
+            .iter()
+            .map(|day_views| day_views.views)
+            .sum()
+    }
+
+    #[test]
+    fn it_forgets_views_older_than_history() {
+        let history = ViewHistory::new();
+        history.record_on(100, USER_NAME);
+
+        assert_eq!(views_on(&history, 160, USER_NAME), 0);
+        history.record_on(200, USER_NAME);
+        assert_eq!(views_on(&history, 200, USER_NAME), 1);
+    }
+
+    #[test]
+    fn it_merges_histories_of_users() {
+        let history = ViewHistory::new();
+        history.record_on(100, USER_NAME);
+        history.record_on(101, "old_name");
+        history.record_on(102, "other_name");
+
+        let merged = ["old_name".to_string(), "other_name".to_string()];
+        history.merge_on(103, &merged, USER_NAME);
+        assert_eq!(views_on(&history, 103, USER_NAME), 3);
+        assert_eq!(views_on(&history, 103, "old_name"), 0);
+    }
+
+    #[test]
+    fn it_lists_days_with_views() {
+        let history = ViewHistory::new();
+        let first_day = NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .num_days_from_ce();
+        for (day, views) in [(first_day, 2), (first_day + 3, 1)] {
+            for _ in 0..views {
+                history.record_on(day, USER_NAME);
+            }
+        }
+
+        assert_eq!(
+            history.daily_views_on(first_day + 3, USER_NAME),
+            vec![
+                DayViews {
+                    day: NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+                    views: 2,
+                },
+                DayViews {
+                    day: NaiveDate::from_ymd_opt(2024, 5, 4).unwrap(),
+                    views: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            history.daily_views_on(first_day + 63, USER_NAME),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn it_forgets_least_recently_viewed_users() {
+        let history = ViewHistory::with_capacity(2);
+        history.record_on(100, USER_NAME);
+        history.record_on(100, "other_user");
+        history.record_on(101, USER_NAME);
+        history.record_on(101, "third_user");
+
+        assert_eq!(views_on(&history, 101, USER_NAME), 2);
+        assert_eq!(views_on(&history, 101, "third_user"), 1);
+        assert_eq!(views_on(&history, 101, "other_user"), 0);
+    }
+
+    #[test]
+    fn it_compares_views_to_previous_period() {
+        let day = |day| NaiveDate::from_ymd_opt(2024, 5, day).unwrap();
+        // 10 views on the 2nd and 12 on the 9th
+        let day_stats = DayStats {
+            day: day(9),
+            day_views: 12,
+            peak_views: 10,
+            streak: 0,
+            recent_views: vec![0, 0, 0, 0, 0, 0, 10],
+        };
+
+        let trend =
+            |today, threshold| Trend::of(&day_stats, day(today), DeltaPeriod::Week, threshold);
+        assert_eq!(trend(9, 10.0), Trend::Up);
+        assert_eq!(trend(9, 25.0), Trend::Flat);
+        assert_eq!(trend(15, 10.0), Trend::Up);
+        assert_eq!(trend(16, 10.0), Trend::Down);
+        assert_eq!(trend(30, 10.0), Trend::Flat);
+    }
+}
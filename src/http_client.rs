@@ -0,0 +1,86 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Client, ClientBuilder, NoProxy, Proxy,
+};
+
+use super::config::{Config, UpstreamConfig};
+use super::dns::CachingResolver;
+
+/// `User-Agent` of every outbound request, so upstreams can tell the server's requests apart.
+pub const USER_AGENT: &str = concat!("profile-views-counter/", env!("CARGO_PKG_VERSION"));
+
+/// Client builder identifying as this server, for services other than the upstreams.
+#[cfg_attr(not(feature = "jwt"), allow(dead_code))]
+pub fn builder() -> ClientBuilder {
+    Client::builder().user_agent(USER_AGENT)
+}
+
+/// Headers sent along with every request to the upstream; its `<PREFIX>_HEADERS` may override
+/// the user agent.
+pub fn default_headers(upstream: &UpstreamConfig) -> HeaderMap {
+    let mut headers = upstream.headers.clone();
+    headers
+        .entry(header::USER_AGENT)
+        .or_insert(HeaderValue::from_static(USER_AGENT));
+    headers
+}
+
+/// Client builder for an upstream with the outbound network settings and its extra headers
+/// applied; the proxy environment is only honoured through this config, so every upstream
+/// behaves the same.
+pub fn upstream_builder(
+    config: &Config,
+    upstream: &UpstreamConfig,
+) -> Result<ClientBuilder, reqwest::Error> {
+    let builder = Client::builder()
+        .default_headers(default_headers(upstream))
+        .dns_resolver(Arc::new(CachingResolver::new(config.dns_cache_ttl)))
+        .connect_timeout(upstream.connect_timeout)
+        .timeout(upstream.timeout)
+        .pool_max_idle_per_host(upstream.pool_max_idle)
+        .pool_idle_timeout(Duration::from_secs(120));
+
+    match &config.proxy {
+        Some(proxy) => {
+            let no_proxy = proxy.no_proxy.as_deref().and_then(NoProxy::from_string);
+            Ok(builder.proxy(Proxy::https(&proxy.url)?.no_proxy(no_proxy)))
+        }
+        None => Ok(builder.no_proxy()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::config::parse_headers;
+
+    #[tokio::test]
+    async fn it_sends_the_user_agent_and_extra_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .and(header("user-agent", USER_AGENT))
+            .and(header("authorization", "Bearer shields-token"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let config = Config::from_env();
+        let upstream = UpstreamConfig {
+            headers: parse_headers(r#"{"Authorization": "Bearer shields-token"}"#).unwrap(),
+            ..config.shields.clone()
+        };
+
+        let client = upstream_builder(&config, &upstream)
+            .unwrap()
+            .build()
+            .unwrap();
+        client.get(server.uri()).send().await.unwrap();
+    }
+}
@@ -4,6 +4,8 @@ use reqwest::{Client, StatusCode};
 use tokio::time;
 use tokio_stream::{wrappers::IntervalStream, StreamExt};
 
+use super::routes;
+
 pub struct KeepAlive {
     http_client: Client,
     port: u16,
@@ -31,7 +33,7 @@ impl KeepAlive {
         while stream.next().await.is_some() {
             let response = self
                 .http_client
-                .head(format!("http://127.0.0.1:{}/healthz", self.port))
+                .head(format!("http://127.0.0.1:{}{}", self.port, routes::HEALTHZ))
                 .send()
                 .await;
 
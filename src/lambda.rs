@@ -0,0 +1,13 @@
+use axum::Router;
+
+/// Serves the app as an AWS Lambda function, answering the API Gateway or load balancer events
+/// of each invocation with the router. Background tasks only make progress while an invocation
+/// is running, since Lambda freezes the instance between invocations; views counted locally
+/// (`OPTIMISTIC_COUNTS`) may therefore be written late or, if the instance is reclaimed, never.
+pub async fn serve(app: Router) -> Result<(), anyhow::Error> {
+    tracing::info!("serving lambda invocations");
+
+    lambda_http::run(app)
+        .await
+        .map_err(anyhow::Error::from_boxed)
+}
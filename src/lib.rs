@@ -0,0 +1,479 @@
+//! The profile views counter, served by the `github-profile-views-counter` binary, or by the
+//! `lambda` one on AWS Lambda with the `lambda` feature.
+//!
+//! Receivers of requests signed by the server can check them with [`signing::verify`].
+
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use listenfd::ListenFd;
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::signal;
+use tokio::sync::oneshot;
+
+use badge::{Shields, ShieldsIoFetcher};
+use cache::CacheStores;
+use config::Config;
+use datastore::{
+    DatastoreOperations, FaultyDatastore, Memory, OptimisticDatastore, SharedDatastore,
+    TieredDatastore, Xata,
+};
+use router::build_router;
+use runtime::{Spawner, TokioSpawner};
+use shutdown::Shutdown;
+use state::AppState;
+
+mod admin;
+mod anomaly;
+mod api;
+mod assets;
+mod auth;
+mod badge;
+mod badge_params;
+mod cache;
+mod client_identity;
+mod config;
+mod cors;
+mod datastore;
+mod dns;
+mod error;
+mod events;
+mod experiment;
+mod first_seen;
+mod geo;
+mod github;
+mod handler;
+mod history;
+mod http_client;
+#[cfg(feature = "jwt")]
+mod jwt;
+#[cfg(feature = "lambda")]
+mod lambda;
+mod locale;
+// mod keepalive;
+mod metrics;
+mod openapi;
+mod overload;
+mod pages;
+mod panic;
+mod priming;
+mod quota;
+mod raster;
+mod retention;
+mod router;
+mod routes;
+mod runtime;
+mod sampling;
+mod secrets;
+mod self_test;
+mod shutdown;
+pub mod signing;
+mod state;
+mod status;
+mod supervisor;
+mod telemetry;
+mod tenant;
+mod user_trace;
+
+/// Internals measured by the benchmarks in `benches/`, not part of the api.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::badge::{Shields, ShieldsIoFetcher, ShieldsIoParams};
+    pub use crate::cache::{CacheStore, CacheStores, LruStore, MemoryStore};
+    pub use crate::config::Config;
+    pub use crate::raster::{Font, Rasterizer};
+}
+
+// seconds in-flight requests have to finish on shutdown unless `SHUTDOWN_TIMEOUT` is set
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+// pending connections of sockets bound with `REUSE_PORT`, as many as `TcpListener::bind` queues
+const LISTEN_BACKLOG: u32 = 1024;
+
+/// Where the app is served from.
+#[derive(Clone, Copy)]
+pub enum Host {
+    /// Long-lived http server listening on `PORT`.
+    Server,
+    /// AWS Lambda function behind API Gateway or an application load balancer, see `lambda`.
+    #[cfg(feature = "lambda")]
+    Lambda,
+}
+
+/// Sets up the app from the environment and serves it from the host until it shuts down.
+pub async fn start(host: Host) -> Result<(), anyhow::Error> {
+    let is_production_env = std::env::var("PRODUCTION").is_ok();
+    let _telemetry = telemetry::setup(is_production_env);
+    panic::install_hook();
+
+    if std::env::args().skip(1).any(|arg| arg == "--self-test") {
+        return self_test::run().await;
+    }
+
+    // servers listening on a socket passed by systemd don't bind `PORT`
+    let requires_port = matches!(host, Host::Server) && std::env::var("LISTEN_FDS").is_err();
+    let problems = config::check_env(requires_port);
+    if !problems.is_empty() {
+        let problems: Vec<String> = problems
+            .iter()
+            .map(|problem| format!("  - {}", problem))
+            .collect();
+        return Err(anyhow::anyhow!(
+            "invalid configuration:\n{}",
+            problems.join("\n")
+        ));
+    }
+
+    // install prometheus recorder before any client gets instrumented
+    let metrics_handle = metrics::setup_recorder()?;
+
+    let config = Config::from_env();
+
+    // secrets not set in the environment are needed before any client is set up
+    config
+        .secrets
+        .fetch()
+        .await
+        .map_err(|err| anyhow::anyhow!("failed to fetch secrets: {}", err))?;
+
+    // setup xata serverless db client, misbehaving on purpose when faults are configured
+    if let Some(faults) = &config.datastore_faults {
+        tracing::warn!("injecting faults into datastore operations: {:?}", faults);
+    }
+    let db = FaultyDatastore::new(Xata::new(&config)?, config.datastore_faults.clone());
+
+    // stores everything caching shares, in memory and across instances
+    let caches = CacheStores::new(&config);
+    let spawner: Arc<dyn Spawner> = Arc::new(TokioSpawner);
+
+    // views counted by any instance are known to all of them through the shared cache
+    let db = SharedDatastore::new(db, caches.shared.clone(), spawner.clone());
+
+    // initialize shields io badge
+    let shields_io_badge = Shields::new(&config, caches.clone())?;
+
+    let startup = Startup {
+        badge: shields_io_badge,
+        config,
+        caches,
+        spawner,
+        metrics_handle,
+        host,
+        is_production_env,
+    };
+
+    // views are counted in memory whenever xata is unavailable, then replayed on it
+    match startup.config.fallback.clone() {
+        Some(fallback) => {
+            let db = TieredDatastore::new(db, Memory::new())
+                .with_reconcile_interval(fallback.reconcile_interval);
+            startup.run_optimistic(db).await
+        }
+        None => startup.run_optimistic(db).await,
+    }
+}
+
+/// Everything the state is built from besides its datastore, whose layers depend on the config.
+struct Startup {
+    badge: Shields,
+    config: Config,
+    caches: CacheStores,
+    spawner: Arc<dyn Spawner>,
+    metrics_handle: PrometheusHandle,
+    host: Host,
+    is_production_env: bool,
+}
+
+impl Startup {
+    /// Serves views of known users from local counts on top of the datastore, unless counts
+    /// are strict.
+    async fn run_optimistic<T>(self, db: T) -> Result<(), anyhow::Error>
+    where
+        T: DatastoreOperations + Send + Sync + 'static,
+    {
+        match self.config.optimistic_counts.clone() {
+            Some(optimistic) => self.run(OptimisticDatastore::new(db, &optimistic)).await,
+            None => self.run(db).await,
+        }
+    }
+
+    async fn run<T>(self, db: T) -> Result<(), anyhow::Error>
+    where
+        T: DatastoreOperations + Send + Sync + 'static,
+    {
+        let app_state = Arc::new(AppState::new(
+            db,
+            self.badge,
+            self.config,
+            self.caches,
+            self.spawner,
+        ));
+        run(
+            app_state,
+            self.metrics_handle,
+            self.host,
+            self.is_production_env,
+        )
+        .await
+    }
+}
+
+/// Serves the app until the host shuts it down, then shuts down the state.
+async fn run<T, F>(
+    app_state: Arc<AppState<T, F>>,
+    metrics_handle: PrometheusHandle,
+    host: Host,
+    is_production_env: bool,
+) -> Result<(), anyhow::Error>
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    spawn_background_tasks(&app_state);
+    let app = build_router(app_state.clone(), metrics_handle);
+    let served = match host {
+        Host::Server => serve(app, is_production_env).await,
+        #[cfg(feature = "lambda")]
+        Host::Lambda => lambda::serve(app).await,
+    };
+
+    // whatever the state still holds in memory is written out before the process exits
+    app_state.shutdown().await;
+    served
+}
+
+/// Hands the tasks running next to the server to the spawner of the state, loops to its
+/// supervisor, which restarts them when they panic.
+fn spawn_background_tasks<T, F>(app_state: &Arc<AppState<T, F>>)
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    spawn_warm_up(app_state.clone());
+    spawn_datastore_maintenance(app_state.clone());
+    spawn_anomaly_analyzer(app_state.clone());
+    spawn_retention(app_state.clone());
+    spawn_events_publisher(app_state.clone());
+    spawn_secrets_reload(app_state.clone());
+}
+
+/// Writes views the datastore holds in memory, e.g. served from local counts or counted during
+/// an outage, to xata at regular intervals; views still pending at shutdown are written when the
+/// state shuts down.
+fn spawn_datastore_maintenance<T, F>(app_state: Arc<AppState<T, F>>)
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    let datastore_state = app_state.clone();
+    app_state.supervisor.supervise("datastore", move || {
+        let app_state = datastore_state.clone();
+        Box::pin(async move {
+            app_state.db.maintain().await;
+        })
+    });
+}
+
+/// Connects to the upstreams while the server starts, so the first request after a scale-to-zero
+/// wakeup doesn't wait for dns lookups and tls handshakes, then primes the badge cache.
+fn spawn_warm_up<T, F>(app_state: Arc<AppState<T, F>>)
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    let spawner = app_state.spawner.clone();
+    spawner.spawn(Box::pin(async move {
+        tokio::join!(app_state.db.warm_up(), app_state.badge.warm_up());
+
+        if let Some(top_n) = app_state.config.cache_priming_top_n {
+            priming::prime_badge_cache(&app_state.db, &app_state.badge, top_n).await;
+        }
+    }));
+}
+
+fn spawn_anomaly_analyzer<T, F>(app_state: Arc<AppState<T, F>>)
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    if app_state.anomalies.is_some() {
+        let anomaly_state = app_state.clone();
+        app_state.supervisor.supervise("anomaly", move || {
+            let app_state = anomaly_state.clone();
+            Box::pin(async move {
+                if let Some(anomalies) = &app_state.anomalies {
+                    anomalies.analyze_loop(&app_state.db).await;
+                }
+            })
+        });
+    }
+}
+
+fn spawn_retention<T, F>(app_state: Arc<AppState<T, F>>)
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    if let Some(retention) = app_state.config.retention.clone() {
+        let retention_state = app_state.clone();
+        app_state.supervisor.supervise("retention", move || {
+            let app_state = retention_state.clone();
+            let retention = retention.clone();
+            Box::pin(async move {
+                retention::retention_loop(&app_state.db, &retention).await;
+            })
+        });
+    }
+}
+
+fn spawn_secrets_reload<T, F>(app_state: Arc<AppState<T, F>>)
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    if let Some(interval) = app_state.config.secrets_reload_interval {
+        let secrets = app_state.config.secrets.clone();
+        app_state.supervisor.supervise("secrets", move || {
+            let secrets = secrets.clone();
+            Box::pin(async move {
+                secrets.reload_loop(interval).await;
+            })
+        });
+    }
+}
+
+fn spawn_events_publisher<T, F>(app_state: Arc<AppState<T, F>>)
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    if app_state.events.is_some() {
+        let events_state = app_state.clone();
+        app_state
+            .supervisor
+            .supervise("events", move || match &events_state.events {
+                Some(events) => events.publisher(),
+                None => Box::pin(async {}),
+            });
+    }
+}
+
+async fn serve(app: Router, is_production_env: bool) -> Result<(), anyhow::Error> {
+    // async thread to keep server alive by hitting health check route at regular intervals
+    // let _server_keep_alive_loop_handle = task::spawn(async move {
+    //     server_keep_alive.health_check_loop().await;
+    // });
+
+    let shutdown_timeout = std::env::var("SHUTDOWN_TIMEOUT")
+        .map_or(Ok(DEFAULT_SHUTDOWN_TIMEOUT), |timeout| {
+            timeout.parse::<u64>()
+        })?;
+
+    // start server
+    let listener = listen(is_production_env).await?;
+    let addr = listener.local_addr()?;
+    let (draining_tx, draining_rx) = oneshot::channel();
+    let server = axum::serve(listener, app.into_make_service()).with_graceful_shutdown(async {
+        shutdown_signal().await;
+        tracing::info!("draining connections");
+        let _ = draining_tx.send(());
+    });
+
+    tracing::info!("server running on {}", addr);
+
+    // connections still open once draining timed out are dropped
+    let drained = async {
+        match draining_rx.await {
+            Ok(()) => tokio::time::sleep(Duration::from_secs(shutdown_timeout)).await,
+            Err(_) => std::future::pending().await,
+        }
+    };
+
+    // block until server shuts down
+    tokio::select! {
+        result = server.into_future() => {
+            if let Err(err) = result {
+                tracing::error!("server encountered an error: {}", err);
+            }
+        }
+        _ = drained => {
+            tracing::warn!("connections still open after {}s of draining", shutdown_timeout);
+        }
+    }
+
+    Ok(())
+}
+
+/// Listens on the socket inherited from systemd socket activation (`LISTEN_FDS`), so the socket
+/// stays open and queues connections while the service restarts, or else binds `PORT`.
+async fn listen(is_production_env: bool) -> Result<TcpListener, anyhow::Error> {
+    if let Some(listener) = ListenFd::from_env().take_tcp_listener(0)? {
+        tracing::info!("listening on the socket passed by systemd");
+        listener.set_nonblocking(true)?;
+        return Ok(TcpListener::from_std(listener)?);
+    }
+
+    // read port from env variable
+    let port = std::env::var("PORT")
+        .expect("missing env variable PORT")
+        .parse::<u16>()?;
+
+    let addr: SocketAddr = match is_production_env {
+        false => format!("127.0.0.1:{}", port).parse()?,
+        true => format!("[::]:{}", port).parse()?, // for fly.io
+    };
+
+    if std::env::var("REUSE_PORT").is_ok_and(|reuse| reuse == "true") {
+        return reuse_port(addr);
+    }
+    Ok(TcpListener::bind(addr).await?)
+}
+
+/// Binds the address with `SO_REUSEPORT`, so a restarted process can listen next to the one it
+/// replaces: the kernel balances new connections between both until the old one stops accepting
+/// and drains. Lets rolling restarts of several processes on one host keep serving badges.
+fn reuse_port(addr: SocketAddr) -> Result<TcpListener, anyhow::Error> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    #[cfg(not(unix))]
+    tracing::error!("REUSE_PORT is set but SO_REUSEPORT is only supported on unix");
+    socket.bind(addr)?;
+
+    Ok(socket.listen(LISTEN_BACKLOG)?)
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl+c handler");
+    };
+
+    // sent by systemd when stopping or restarting the service
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install terminate signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("shutdown signal received");
+}
@@ -0,0 +1,148 @@
+use serde::{de::Error, Deserialize, Deserializer};
+
+/// Digit grouping of a locale; a small table covers the locales badges are most viewed in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Locale {
+    separator: &'static str,
+    grouping: Grouping,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Grouping {
+    /// 1,234,567
+    Thousands,
+    /// 12,34,567 as in the Indian numbering system
+    Indian,
+}
+
+// locales by language tag, falling back to the language alone
+const LOCALES: &[(&str, &str, Grouping)] = &[
+    ("en", ",", Grouping::Thousands),
+    ("en-in", ",", Grouping::Indian),
+    ("hi", ",", Grouping::Indian),
+    ("bn", ",", Grouping::Indian),
+    ("ta", ",", Grouping::Indian),
+    ("te", ",", Grouping::Indian),
+    ("mr", ",", Grouping::Indian),
+    ("de", ".", Grouping::Thousands),
+    ("de-ch", "’", Grouping::Thousands),
+    ("es", ".", Grouping::Thousands),
+    ("es-mx", ",", Grouping::Thousands),
+    ("it", ".", Grouping::Thousands),
+    ("nl", ".", Grouping::Thousands),
+    ("pt", ".", Grouping::Thousands),
+    ("tr", ".", Grouping::Thousands),
+    ("id", ".", Grouping::Thousands),
+    ("fr", "\u{202f}", Grouping::Thousands),
+    ("ru", "\u{a0}", Grouping::Thousands),
+    ("uk", "\u{a0}", Grouping::Thousands),
+    ("pl", "\u{a0}", Grouping::Thousands),
+    ("sv", "\u{a0}", Grouping::Thousands),
+    ("cs", "\u{a0}", Grouping::Thousands),
+    ("ja", ",", Grouping::Thousands),
+    ("zh", ",", Grouping::Thousands),
+    ("ko", ",", Grouping::Thousands),
+];
+
+impl Locale {
+    /// Locale of a language tag such as `de-DE` or `hi_IN`; tags are matched case-insensitively
+    /// and fall back to their language.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let tag = tag.replace('_', "-").to_ascii_lowercase();
+        let language = tag.split('-').next()?;
+
+        [tag.as_str(), language].iter().find_map(|tag| {
+            LOCALES
+                .iter()
+                .find(|(name, _, _)| name == tag)
+                .map(|&(_, separator, grouping)| Locale {
+                    separator,
+                    grouping,
+                })
+        })
+    }
+
+    /// Views with the locale's digit grouping, e.g. `1.234.567` or `12,34,567`.
+    pub fn format(&self, views: u64) -> String {
+        let digits = views.to_string();
+        let mut groups = Vec::new();
+        let mut rest = digits.as_str();
+
+        // the last group has three digits, the ones before two in indian grouping
+        let mut size = 3;
+        while rest.len() > size {
+            let (head, group) = rest.split_at(rest.len() - size);
+            groups.push(group);
+            rest = head;
+            if self.grouping == Grouping::Indian {
+                size = 2;
+            }
+        }
+        groups.push(rest);
+
+        groups.reverse();
+        groups.join(self.separator)
+    }
+}
+
+/// Views abbreviated to at most one decimal, e.g. `100k` or `1.5M`; the decimal is cut off, not
+/// rounded, so a goal is never shown as reached early.
+pub fn compact(views: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1_000_000_000, "B"), (1_000_000, "M"), (1_000, "k")];
+
+    for (unit, suffix) in UNITS {
+        if views >= unit {
+            let tenths = views / (unit / 10);
+            return match tenths % 10 {
+                0 => format!("{}{}", tenths / 10, suffix),
+                decimal => format!("{}.{}{}", tenths / 10, decimal, suffix),
+            };
+        }
+    }
+    views.to_string()
+}
+
+impl<'de> Deserialize<'de> for Locale {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let tag = String::deserialize(deserializer)?;
+        Locale::from_tag(&tag)
+            .ok_or_else(|| D::Error::custom(format!("unsupported locale `{}`", tag)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_groups_digits_by_locale() {
+        let format = |tag, views| Locale::from_tag(tag).unwrap().format(views);
+
+        assert_eq!(format("en-US", 1234567), "1,234,567");
+        assert_eq!(format("de-DE", 1234567), "1.234.567");
+        assert_eq!(format("hi-IN", 1234567), "12,34,567");
+        assert_eq!(format("en_IN", 123456789), "12,34,56,789");
+        assert_eq!(format("fr", 1234), "1\u{202f}234");
+        assert_eq!(format("de", 123), "123");
+        assert_eq!(format("hi", 1000), "1,000");
+        assert_eq!(format("es", 0), "0");
+    }
+
+    #[test]
+    fn it_abbreviates_views() {
+        assert_eq!(compact(999), "999");
+        assert_eq!(compact(1000), "1k");
+        assert_eq!(compact(1999), "1.9k");
+        assert_eq!(compact(100_000), "100k");
+        assert_eq!(compact(1_500_000), "1.5M");
+        assert_eq!(compact(u64::MAX), "18446744073.7B");
+    }
+
+    #[test]
+    fn it_falls_back_to_language() {
+        assert_eq!(Locale::from_tag("de-AT"), Locale::from_tag("de"));
+        assert_eq!(Locale::from_tag("xx-YY"), None);
+        assert_eq!(Locale::from_tag(""), None);
+    }
+}
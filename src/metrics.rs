@@ -0,0 +1,188 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use metrics::{
+    counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id},
+    Subscriber,
+};
+use tracing_subscriber::{
+    filter::filter_fn,
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+pub const UPSTREAM_POOL_MAX_IDLE: &str = "upstream_pool_max_idle_connections";
+pub const UPSTREAM_IN_FLIGHT: &str = "upstream_requests_in_flight";
+pub const UPSTREAM_WAIT_SECONDS: &str = "upstream_request_wait_seconds";
+pub const UPSTREAM_SLOW_REQUESTS: &str = "upstream_slow_requests_total";
+pub const VIEWS_OVER_BUDGET: &str = "views_over_budget_total";
+pub const DATASTORE_OPERATIONS: &str = "datastore_operations_total";
+pub const DATASTORE_OPERATION_SECONDS: &str = "datastore_operation_seconds";
+
+pub fn setup_recorder() -> Result<PrometheusHandle, Error> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("_seconds".to_string()),
+            &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0],
+        )?
+        .install_recorder()?;
+
+    describe_gauge!(
+        UPSTREAM_POOL_MAX_IDLE,
+        "maximum number of idle connections kept per upstream host"
+    );
+    describe_gauge!(
+        UPSTREAM_IN_FLIGHT,
+        "number of requests to an upstream awaiting a response"
+    );
+    describe_histogram!(
+        UPSTREAM_WAIT_SECONDS,
+        Unit::Seconds,
+        "time until upstream response headers arrive, including connection setup"
+    );
+    describe_counter!(
+        UPSTREAM_SLOW_REQUESTS,
+        "requests to an upstream answered slower than its `<PREFIX>_SLOW_MS` threshold"
+    );
+
+    describe_counter!(
+        VIEWS_OVER_BUDGET,
+        "views served without reaching the datastore as the request budget was used up"
+    );
+
+    describe_counter!(
+        DATASTORE_OPERATIONS,
+        "operations sent to a datastore backend, by `read` or `write` kind"
+    );
+    describe_histogram!(
+        DATASTORE_OPERATION_SECONDS,
+        Unit::Seconds,
+        "time taken by datastore operations, by backend and operation"
+    );
+
+    Ok(handle)
+}
+
+pub fn record_datastore_operations(backend: &'static str, kind: &'static str, operations: u64) {
+    counter!(DATASTORE_OPERATIONS, "backend" => backend, "kind" => kind).increment(operations);
+}
+
+pub fn record_over_budget() {
+    counter!(VIEWS_OVER_BUDGET).increment(1);
+}
+
+pub fn record_pool_size(upstream: &'static str, max_idle_per_host: usize) {
+    gauge!(UPSTREAM_POOL_MAX_IDLE, "upstream" => upstream).set(max_idle_per_host as f64);
+}
+
+/// Tracks a request to an upstream from the moment it is sent until the guard is dropped.
+/// Requests slower than the threshold are logged as warnings, in the span of the datastore
+/// operation sending them if any.
+pub struct UpstreamRequest {
+    upstream: &'static str,
+    slow_threshold: Option<Duration>,
+    started_at: Instant,
+}
+
+impl UpstreamRequest {
+    pub fn start(upstream: &'static str, slow_threshold: Option<Duration>) -> UpstreamRequest {
+        gauge!(UPSTREAM_IN_FLIGHT, "upstream" => upstream).increment(1.0);
+
+        UpstreamRequest {
+            upstream,
+            slow_threshold,
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl Drop for UpstreamRequest {
+    fn drop(&mut self) {
+        let elapsed = self.started_at.elapsed();
+        gauge!(UPSTREAM_IN_FLIGHT, "upstream" => self.upstream).decrement(1.0);
+        histogram!(UPSTREAM_WAIT_SECONDS, "upstream" => self.upstream)
+            .record(elapsed.as_secs_f64());
+
+        if let Some(threshold) = self.slow_threshold.filter(|threshold| elapsed > *threshold) {
+            counter!(UPSTREAM_SLOW_REQUESTS, "upstream" => self.upstream).increment(1);
+            tracing::warn!(
+                "slow response from {}: {:.1?}, over the {:?} threshold",
+                self.upstream,
+                elapsed,
+                threshold
+            );
+        }
+    }
+}
+
+/// Layer timing the spans of datastore operations, those with a `backend` field, into the
+/// `datastore_operation_seconds` histogram. Spans are timed whatever the log level.
+pub fn span_latency_layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    SpanLatency.with_filter(filter_fn(|metadata| {
+        metadata.is_span() && metadata.fields().field("backend").is_some()
+    }))
+}
+
+struct SpanLatency;
+
+// kept in the extensions of a timed span
+struct SpanTiming {
+    backend: String,
+    started_at: Instant,
+}
+
+#[derive(Default)]
+struct BackendField(Option<String>);
+
+impl Visit for BackendField {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "backend" {
+            self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+}
+
+impl<S> Layer<S> for SpanLatency
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut backend = BackendField::default();
+        attrs.record(&mut backend);
+        if let (Some(span), Some(backend)) = (ctx.span(id), backend.0) {
+            span.extensions_mut().insert(SpanTiming {
+                backend,
+                started_at: Instant::now(),
+            });
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(timing) = span.extensions_mut().remove::<SpanTiming>() else {
+            return;
+        };
+
+        let latency = timing.started_at.elapsed();
+        histogram!(DATASTORE_OPERATION_SECONDS, "backend" => timing.backend.clone(), "operation" => span.name())
+            .record(latency.as_secs_f64());
+        tracing::debug!(
+            backend = timing.backend,
+            operation = span.name(),
+            latency_ms = latency.as_millis() as u64,
+            "datastore operation finished"
+        );
+    }
+}
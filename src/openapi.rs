@@ -0,0 +1,156 @@
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use super::admin::{CacheReport, MergeRequest, OwnerToken, ReloadedSecrets, RenameRequest};
+use super::anomaly::Flag;
+use super::api::{IncrementRequest, OnboardRequest, UserExport};
+use super::cache::{CacheStats, KeyStats};
+use super::datastore::{
+    CapturedHeader, CapturedRequest, CapturedResponse, DatastoreUsage, Freshness, UsageBucket,
+    UserRecord, UserRecordPage, UserViews, UserViewsPage,
+};
+use super::error::{ErrorBody, ErrorCode};
+use super::experiment::ExperimentResults;
+use super::handler::{ViewDiagnosis, ViewOutcome, ViewsSummary};
+use super::history::DayViews;
+use super::quota::RateLimit;
+use super::sampling::RequestSample;
+use super::status::{ErrorRate, Status, UpstreamStatus};
+use super::supervisor::{Readiness, TaskHealth, TaskState};
+use super::user_trace::UserTrace;
+use super::{admin, api, assets, handler, pages, status, tenant};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        handler::health_check_handler,
+        handler::readiness_handler,
+        status::status_handler,
+        handler::metrics_handler,
+        handler::profile_views_handler,
+        handler::profile_views_head_handler,
+        handler::counter_handler,
+        handler::counter_head_handler,
+        handler::alt_text_handler,
+        handler::count_json_handler,
+        handler::streak_handler,
+        handler::record_handler,
+        handler::debug_handler,
+        handler::counter_png_handler,
+        handler::counter_png_head_handler,
+        handler::counter_webp_handler,
+        handler::counter_webp_head_handler,
+        handler::tenant_views_handler,
+        handler::tenant_views_head_handler,
+        tenant::tenant_users_handler,
+        pages::landing_handler,
+        pages::builder_handler,
+        pages::privacy_handler,
+        pages::builder_preview_handler,
+        assets::asset_handler,
+        assets::favicon_handler,
+        api::increments_handler,
+        api::counts_handler,
+        api::experiment_results_handler,
+        api::export_user_handler,
+        api::onboard_handler,
+        admin::export_handler,
+        admin::list_users_handler,
+        admin::delete_user_handler,
+        admin::restore_user_handler,
+        admin::issue_owner_token_handler,
+        admin::rename_user_handler,
+        admin::merge_users_handler,
+        admin::list_flags_handler,
+        admin::clear_flag_handler,
+        admin::list_samples_handler,
+        admin::usage_handler,
+        admin::start_trace_handler,
+        admin::stop_trace_handler,
+        admin::list_captured_requests_handler,
+        admin::cache_stats_handler,
+        admin::clear_cache_handler,
+        admin::reload_secrets_handler,
+    ),
+    components(schemas(
+        UserRecord,
+        UserRecordPage,
+        UserViewsPage,
+        UserViews,
+        Flag,
+        RequestSample,
+        UserTrace,
+        CapturedRequest,
+        CapturedResponse,
+        CapturedHeader,
+        CacheReport,
+        ReloadedSecrets,
+        CacheStats,
+        KeyStats,
+        DatastoreUsage,
+        UsageBucket,
+        ViewDiagnosis,
+        ViewOutcome,
+        Freshness,
+        ViewsSummary,
+        RateLimit,
+        Readiness,
+        TaskHealth,
+        TaskState,
+        Status,
+        UpstreamStatus,
+        ErrorRate,
+        IncrementRequest,
+        ExperimentResults,
+        UserExport,
+        OnboardRequest,
+        DayViews,
+        OwnerToken,
+        RenameRequest,
+        MergeRequest,
+        ErrorBody,
+        ErrorCode
+    )),
+    modifiers(&BearerTokenSchemes)
+)]
+pub struct ApiDoc;
+
+struct BearerTokenSchemes;
+
+impl Modify for BearerTokenSchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            for (name, description) in [
+                (
+                    "admin_token",
+                    "`ADMIN_TOKEN`, or a jwt granting `JWT_ADMIN_SCOPE` when `JWT_JWKS_URL` is set",
+                ),
+                (
+                    "api_key",
+                    "`API_KEY`, or a jwt granting `JWT_API_SCOPE` when `JWT_JWKS_URL` is set",
+                ),
+                ("tenant_api_key", "`api_key` of the tenant"),
+                (
+                    "owner_token",
+                    "Token of the user issued by an admin at `/admin/users/{user_name}/token`",
+                ),
+                (
+                    "github_token",
+                    "GitHub OAuth token issued to the user, accepted with `ONBOARDING=explicit`",
+                ),
+            ] {
+                components.add_security_scheme(
+                    name,
+                    SecurityScheme::Http(
+                        HttpBuilder::new()
+                            .scheme(HttpAuthScheme::Bearer)
+                            .description(Some(description))
+                            .build(),
+                    ),
+                );
+            }
+        }
+    }
+}
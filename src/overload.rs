@@ -0,0 +1,233 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, Request, State as StateExtractor},
+    http::{header, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::Semaphore;
+use tokio::time;
+
+use super::badge::{ShieldsIoFetcher, ShieldsIoParams, UNAVAILABLE_BADGE};
+use super::badge_params::BadgeQuery;
+use super::config::Config;
+use super::datastore::DatastoreOperations;
+use super::error::{ApiError, ErrorCode};
+use super::quota::RateLimit;
+use super::raster::Font;
+use super::routes;
+use super::state::AppState;
+
+/// Requests handled at once, see [`limit_concurrency`].
+pub struct ConcurrencyLimit {
+    slots: Arc<Semaphore>,
+    wait: Duration,
+    rate_limit: RateLimit,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(config: &Config) -> ConcurrencyLimit {
+        ConcurrencyLimit {
+            slots: Arc::new(Semaphore::new(config.max_concurrent_requests)),
+            wait: config.shed_wait,
+            rate_limit: RateLimit {
+                limit: config.max_concurrent_requests as u64,
+                remaining: 0,
+                reset: config.shed_retry_after,
+            },
+        }
+    }
+}
+
+/// Handles up to `MAX_CONCURRENT_REQUESTS` requests at once. Requests beyond wait up to
+/// `SHED_WAIT_MS` for their turn, riding out bursts, and are shed past it, see [`shed_response`].
+pub async fn limit_concurrency<T, F>(
+    StateExtractor(state): StateExtractor<Arc<AppState<T, F>>>,
+    request: Request,
+    next: Next,
+) -> Response
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    let limit = &state.concurrency;
+    match time::timeout(limit.wait, limit.slots.clone().acquire_owned()).await {
+        Ok(Ok(_slot)) => next.run(request).await,
+        // the semaphore is never closed
+        Ok(Err(_)) | Err(_) => shed_response(&state, request.uri()).await,
+    }
+}
+
+/// Answers requests shed while the server is at its concurrency limit. Badge routes get a
+/// badge of the views last served for the user, or the "unavailable" badge for users without
+/// any, with a 200 like badges of panicked handlers so READMEs still show a badge; everything
+/// else gets an error body. Both tell the seconds to wait before retrying.
+pub async fn shed_response(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    uri: &Uri,
+) -> Response {
+    tracing::warn!("overloaded, shedding request to `{}`", uri.path());
+
+    let mut response = match routes::counter_badge_user(uri.path()) {
+        Some(user_name) => {
+            let views = state.db.cached_views(&user_name).await;
+            let badge = views
+                .and_then(|views| shed_badge(state, uri, views))
+                .unwrap_or_else(|| UNAVAILABLE_BADGE.to_string());
+            (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, "image/svg+xml"),
+                    (header::CACHE_CONTROL, "no-store"),
+                ],
+                badge,
+            )
+                .into_response()
+        }
+        None => {
+            ApiError::new(ErrorCode::Overloaded, "too many concurrent requests").into_response()
+        }
+    };
+
+    let rate_limit = state.concurrency.rate_limit;
+    let headers = response.headers_mut();
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(rate_limit.reset));
+    rate_limit.insert_headers(headers);
+    response
+}
+
+// laid out locally, sparing a request to shields.io while overloaded; `None` for badge params
+// the badge can't be laid out with
+fn shed_badge(
+    state: &AppState<impl DatastoreOperations, impl ShieldsIoFetcher>,
+    uri: &Uri,
+    views: u64,
+) -> Option<String> {
+    let Query(query) = Query::<BadgeQuery>::try_from_uri(uri).ok()?;
+    let params = ShieldsIoParams::from_query(&query).ok()?;
+    state
+        .raster
+        .layout(&params, &views.to_string(), Font::default())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Path, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::badge::Shields;
+    use crate::cache::CacheStores;
+    use crate::datastore::Memory;
+    use crate::runtime::TokioSpawner;
+    use pretty_assertions::assert_eq;
+
+    fn state(db: Memory) -> AppState<Memory, Shields> {
+        let mut config = Config::from_env();
+        config.max_concurrent_requests = 512;
+        config.shed_retry_after = 5;
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        AppState::new(db, shields, config, caches, Arc::new(TokioSpawner))
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_a_turn_before_shedding() {
+        let mut config = Config::from_env();
+        config.max_concurrent_requests = 1;
+        config.shed_wait = Duration::from_millis(100);
+        let caches = CacheStores::new(&config);
+        let shields = Shields::new(&config, caches.clone()).unwrap();
+        let state = AppState::new(
+            Memory::new(),
+            shields,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        );
+        let app = Router::new()
+            .route(
+                "/sleep/:millis",
+                get(|Path(millis): Path<u64>| async move {
+                    time::sleep(Duration::from_millis(millis)).await;
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                Arc::new(state),
+                limit_concurrency,
+            ));
+        let call = |uri: &str| {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request)
+        };
+
+        // the second request gets its turn once the first is done within the wait
+        let (first, second) = tokio::join!(call("/sleep/10"), call("/sleep/0"));
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.unwrap().status(), StatusCode::OK);
+
+        let (first, second) = tokio::join!(call("/sleep/500"), call("/sleep/0"));
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    async fn body(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_sheds_badge_requests_with_last_views() {
+        let db = Memory::new();
+        db.onboard_user("octocat").await.unwrap();
+        let state = state(db);
+        let uri: Uri = format!(
+            "{}?label=views&color=blue&style=flat",
+            routes::counter("octocat")
+        )
+        .parse()
+        .unwrap();
+
+        let response = shed_response(&state, &uri).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+        assert!(body(response).await.contains("views: 1"));
+    }
+
+    #[tokio::test]
+    async fn it_sheds_badge_requests_of_unknown_users_with_unavailable_badge() {
+        let state = state(Memory::new());
+        let uri: Uri = format!("{}?label=views", routes::counter("octocat"))
+            .parse()
+            .unwrap();
+
+        let response = shed_response(&state, &uri).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        assert_eq!(body(response).await, UNAVAILABLE_BADGE);
+    }
+
+    #[tokio::test]
+    async fn it_sheds_api_requests_with_error_body() {
+        let state = state(Memory::new());
+        let uri: Uri = routes::ADMIN_USERS.parse().unwrap();
+
+        let response = shed_response(&state, &uri).await;
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+        assert_eq!(response.headers()["ratelimit-limit"], "512");
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+        assert_eq!(response.headers()["ratelimit-reset"], "5");
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State as StateExtractor,
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+
+use super::badge::{ShieldsIoFetcher, ShieldsIoParams};
+use super::badge_params::BadgeParams;
+use super::datastore::DatastoreOperations;
+use super::handler::badge_response;
+use super::routes;
+use super::state::AppState;
+
+// views shown by badge previews, long enough to give an idea of the final badge width
+const PREVIEW_VIEWS: u64 = 1234;
+
+/// Landing page with usage instructions.
+#[utoipa::path(
+    get,
+    path = "/",
+    responses((status = 200, description = "Landing page", content_type = "text/html"))
+)]
+pub async fn landing_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    headers: HeaderMap,
+) -> Html<String> {
+    let base_url = match &state.config.public_url {
+        Some(public_url) => public_url.clone(),
+        None => {
+            let host = headers
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .unwrap_or("localhost");
+            format!("https://{}", host)
+        }
+    };
+
+    let counter_url = format!("{}{}", base_url, routes::counter("<github-user-name>"));
+    Html(include_str!("landing.html").replace("{counter_url}", &escape_html(&counter_url)))
+}
+
+/// Badge builder page.
+#[utoipa::path(
+    get,
+    path = "/builder",
+    responses((status = 200, description = "Badge builder", content_type = "text/html"))
+)]
+pub async fn builder_handler() -> Html<&'static str> {
+    Html(include_str!("builder.html"))
+}
+
+/// What the server stores about badge viewers.
+#[utoipa::path(
+    get,
+    path = "/privacy",
+    responses((status = 200, description = "Privacy notice", content_type = "text/html"))
+)]
+pub async fn privacy_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+) -> Html<String> {
+    let sampling = match &state.sampler {
+        Some(sampler) => format!(
+            "The metadata of {}% of requests is sampled to understand traffic patterns: time, \
+             url, response status and duration, user agent, referrer and a hash of the client \
+             address salted anew on every restart. The most recent 10,000 samples are kept in \
+             memory and are lost on restart.",
+            sampler.rate() * 100.0
+        ),
+        None => "No request metadata is sampled.".to_string(),
+    };
+
+    Html(include_str!("privacy.html").replace("{sampling}", &escape_html(&sampling)))
+}
+
+/// Renders a badge with a sample count, without counting a view.
+#[utoipa::path(
+    get,
+    path = "/builder/preview.svg",
+    params(ShieldsIoParams),
+    responses(
+        (status = 200, description = "Preview badge", content_type = "image/svg+xml", body = String),
+        (status = 500, description = "shields.io failure"),
+    )
+)]
+pub async fn builder_preview_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    query: BadgeParams,
+) -> Response {
+    match state.badge.fetch(&query, PREVIEW_VIEWS).await {
+        Ok(badge) => badge_response(badge),
+        Err(err) => {
+            tracing::error!(
+                "failed to fetch preview badge from shields.io, reason: {}",
+                err
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,62 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+
+use axum::response::Response;
+
+use super::badge::UNAVAILABLE_BADGE;
+use super::handler::badge_response;
+
+thread_local! {
+    // captured by the panic hook, the stack is already unwound once the panic is caught
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+/// Keeps a backtrace of the latest panic for [`handle_panic`], then runs the previous hook.
+pub fn install_hook() {
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        BACKTRACE.with(|backtrace| *backtrace.borrow_mut() = Some(Backtrace::force_capture()));
+        previous_hook(info);
+    }));
+}
+
+/// Answers requests whose handler panicked with the "unavailable" badge, so a README still
+/// renders a badge.
+pub fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic_message(err.as_ref());
+    let backtrace = BACKTRACE.with(|backtrace| backtrace.borrow_mut().take());
+
+    match backtrace {
+        Some(backtrace) => {
+            tracing::error!("request handler panicked: {}\n{}", message, backtrace)
+        }
+        None => tracing::error!("request handler panicked: {}", message),
+    }
+
+    badge_response(UNAVAILABLE_BADGE.to_string())
+}
+
+/// Message the code panicked with, given the payload of the caught panic.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown reason")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{header, StatusCode};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_answers_panics_with_unavailable_badge() {
+        let response = handle_panic(Box::new("boom"));
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+    }
+}
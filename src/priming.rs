@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use super::badge::{ShieldsIoFetcher, ShieldsIoParams};
+use super::datastore::DatastoreOperations;
+
+/// Badge params of the snippet on the landing page, the ones most READMEs end up using.
+fn default_badge_params() -> ShieldsIoParams {
+    ShieldsIoParams::new("profile views", "blue", "flat")
+}
+
+/// Fetches the badge templates needed by the `top_n` most viewed users, so their first requests
+/// after a deploy don't wait on shields.io.
+pub async fn prime_badge_cache(
+    db: &impl DatastoreOperations,
+    badge: &impl ShieldsIoFetcher,
+    top_n: usize,
+) {
+    let users = match db.top_users(top_n).await {
+        Ok(users) => users,
+        Err(err) => {
+            tracing::warn!("failed to load most viewed users, reason: {}", err);
+            return;
+        }
+    };
+
+    // templates only depend on the badge params and the number of digits of the views
+    let views_by_digits: BTreeMap<usize, u64> = users
+        .iter()
+        .map(|user| (user.views.to_string().len(), user.views))
+        .collect();
+
+    let params = default_badge_params();
+    for views in views_by_digits.values() {
+        if let Err(err) = badge.fetch(&params, *views).await {
+            tracing::warn!("failed to prime badge cache, reason: {}", err);
+            return;
+        }
+    }
+
+    tracing::info!("primed badge cache for {} most viewed users", users.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::datastore::Memory;
+    use crate::shutdown::Shutdown;
+    use anyhow::Error;
+    use axum::async_trait;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Default)]
+    struct RecordingFetcher {
+        fetched: Mutex<Vec<String>>,
+    }
+
+    impl Shutdown for RecordingFetcher {}
+
+    #[async_trait]
+    impl ShieldsIoFetcher for RecordingFetcher {
+        async fn fetch_message(&self, _: &ShieldsIoParams, message: &str) -> Result<String, Error> {
+            self.fetched.lock().unwrap().push(message.to_string());
+            Ok(message.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn it_fetches_one_template_per_digit_count() {
+        let db = Memory::new();
+        for (user_name, views) in [("alice", 3), ("bob", 12), ("carol", 15), ("dave", 1)] {
+            db.onboard_user(user_name).await.unwrap();
+            for _ in 1..views {
+                db.get_latest_views(user_name).await.unwrap();
+            }
+        }
+        let badge = RecordingFetcher::default();
+
+        prime_badge_cache(&db, &badge, 3).await;
+
+        // dave isn't among the top 3, so single digit counts come from alice
+        assert_eq!(*badge.fetched.lock().unwrap(), vec!["3", "12"]);
+    }
+}
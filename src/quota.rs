@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::config::BudgetConfig;
+
+const RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// State of a limit as reported by the `RateLimit-*` headers.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the limit resets
+    pub reset: u64,
+}
+
+impl RateLimit {
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATE_LIMIT_RESET, HeaderValue::from(self.reset));
+    }
+}
+
+/// Caps the views counted per user and UTC day. Buckets live in process memory, so each instance
+/// enforces the cap on its own and a restart starts the day over.
+pub struct DailyQuota {
+    limit: u64,
+    buckets: Mutex<DailyBuckets>,
+}
+
+#[derive(Default)]
+struct DailyBuckets {
+    day: Option<NaiveDate>,
+    users: HashMap<String, Bucket>,
+}
+
+struct Bucket {
+    increments: u64,
+    // count when the last increment was counted, served once the quota is used up
+    last_views: u64,
+}
+
+impl DailyQuota {
+    pub fn new(limit: u64) -> DailyQuota {
+        DailyQuota {
+            limit,
+            buckets: Mutex::new(DailyBuckets::default()),
+        }
+    }
+
+    /// Views to serve instead of counting, when the user used up today's quota.
+    pub fn capped_views(&self, user_name: &str) -> Option<u64> {
+        self.capped_views_on(Utc::now().date_naive(), user_name)
+    }
+
+    /// Records a counted view.
+    pub fn record(&self, user_name: &str, views: u64) {
+        self.record_on(Utc::now().date_naive(), user_name, views)
+    }
+
+    /// Views of today's quota left for the user.
+    pub fn rate_limit(&self, user_name: &str) -> RateLimit {
+        self.rate_limit_at(Utc::now(), user_name)
+    }
+
+    fn rate_limit_at(&self, now: DateTime<Utc>, user_name: &str) -> RateLimit {
+        let day = now.date_naive();
+        let increments = self
+            .buckets
+            .lock()
+            .unwrap()
+            .for_day(day)
+            .get(user_name)
+            .map_or(0, |bucket| bucket.increments);
+        let tomorrow = day
+            .succ_opt()
+            .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+            .map(|tomorrow| tomorrow.and_utc());
+
+        RateLimit {
+            limit: self.limit,
+            remaining: self.limit.saturating_sub(increments),
+            reset: tomorrow.map_or(0, |tomorrow| (tomorrow - now).num_seconds().max(0) as u64),
+        }
+    }
+
+    fn capped_views_on(&self, day: NaiveDate, user_name: &str) -> Option<u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets
+            .for_day(day)
+            .get(user_name)
+            .filter(|bucket| bucket.increments >= self.limit)
+            .map(|bucket| bucket.last_views)
+    }
+
+    fn record_on(&self, day: NaiveDate, user_name: &str, views: u64) {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .for_day(day)
+            .entry(user_name.to_string())
+            .or_insert(Bucket {
+                increments: 0,
+                last_views: views,
+            });
+
+        bucket.increments += 1;
+        bucket.last_views = views;
+
+        if bucket.increments == self.limit {
+            tracing::warn!(
+                "user `{}` reached the daily quota of {} views, serving {} views until tomorrow",
+                user_name,
+                self.limit,
+                views
+            );
+        }
+    }
+}
+
+/// Token bucket capping the views counted per second across all users, protecting a metered
+/// datastore from traffic storms. Views past the budget are served from the views cached for the
+/// user, see [`DatastoreOperations::cached_views`](crate::datastore::DatastoreOperations::cached_views),
+/// shared by all instances when a shared cache is configured. Like the daily quota, each instance
+/// keeps its own budget.
+pub struct RequestBudget {
+    per_second: f64,
+    burst: f64,
+    tokens: Mutex<(f64, Instant)>,
+}
+
+impl RequestBudget {
+    pub fn new(config: &BudgetConfig) -> RequestBudget {
+        RequestBudget {
+            per_second: config.per_second,
+            burst: config.burst,
+            tokens: Mutex::new((config.burst, Instant::now())),
+        }
+    }
+
+    /// Takes a view from the budget, `false` once it's used up.
+    pub fn try_spend(&self) -> bool {
+        self.try_spend_at(Instant::now())
+    }
+
+    /// Whether the budget is used up, without taking a view from it.
+    pub fn is_used_up(&self) -> bool {
+        let tokens = self.tokens.lock().unwrap();
+        self.available_at(*tokens, Instant::now()) < 1.0
+    }
+
+    fn available_at(&self, (available, refilled_at): (f64, Instant), now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(refilled_at).as_secs_f64();
+        (available + elapsed * self.per_second).min(self.burst)
+    }
+
+    fn try_spend_at(&self, now: Instant) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        let available = self.available_at(*tokens, now);
+
+        match available >= 1.0 {
+            true => *tokens = (available - 1.0, now),
+            false => *tokens = (available, now),
+        }
+        available >= 1.0
+    }
+}
+
+impl DailyBuckets {
+    fn for_day(&mut self, day: NaiveDate) -> &mut HashMap<String, Bucket> {
+        if self.day != Some(day) {
+            self.day = Some(day);
+            self.users.clear();
+        }
+        &mut self.users
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const USER_NAME: &str = "octocat";
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 6, day).unwrap()
+    }
+
+    #[test]
+    fn it_caps_views_once_quota_is_used_up() {
+        let quota = DailyQuota::new(2);
+
+        quota.record_on(day(1), USER_NAME, 41);
+        assert_eq!(quota.capped_views_on(day(1), USER_NAME), None);
+
+        quota.record_on(day(1), USER_NAME, 42);
+        assert_eq!(quota.capped_views_on(day(1), USER_NAME), Some(42));
+        assert_eq!(quota.capped_views_on(day(1), "other_user"), None);
+    }
+
+    #[test]
+    fn it_refills_request_budget_over_time() {
+        let budget = RequestBudget::new(&BudgetConfig {
+            per_second: 2.0,
+            burst: 3.0,
+        });
+        let start = Instant::now();
+
+        assert!((0..3).all(|_| budget.try_spend_at(start)));
+        assert!(!budget.try_spend_at(start));
+        assert!(budget.try_spend_at(start + Duration::from_millis(500)));
+        assert!(!budget.try_spend_at(start + Duration::from_millis(500)));
+        // refills up to the burst only
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| budget.try_spend_at(later)));
+        assert!(!budget.try_spend_at(later));
+    }
+
+    #[test]
+    fn it_reports_remaining_quota() {
+        let quota = DailyQuota::new(2);
+        let now: DateTime<Utc> = "2023-06-01T23:00:00Z".parse().unwrap();
+
+        quota.record_on(now.date_naive(), USER_NAME, 42);
+        assert_eq!(
+            quota.rate_limit_at(now, USER_NAME),
+            RateLimit {
+                limit: 2,
+                remaining: 1,
+                reset: 3600,
+            }
+        );
+
+        quota.record_on(now.date_naive(), USER_NAME, 43);
+        quota.record_on(now.date_naive(), USER_NAME, 44);
+        assert_eq!(quota.rate_limit_at(now, USER_NAME).remaining, 0);
+    }
+
+    #[test]
+    fn it_resets_quota_every_day() {
+        let quota = DailyQuota::new(1);
+
+        quota.record_on(day(1), USER_NAME, 42);
+        assert_eq!(quota.capped_views_on(day(1), USER_NAME), Some(42));
+        assert_eq!(quota.capped_views_on(day(2), USER_NAME), None);
+    }
+}
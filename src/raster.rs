@@ -0,0 +1,771 @@
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Error};
+use image::{codecs::webp::WebPEncoder, ExtendedColorType};
+use resvg::{tiny_skia, usvg};
+use serde::Deserialize;
+
+use super::badge::{escape, minify, named_color, ShieldsIoParams};
+use super::cache::{CacheCounters, CacheStats, Lru};
+
+const FONT_SIZE: f32 = 11.0;
+// space on either side of the label and message
+const HORIZONTAL_PADDING: f32 = 5.0;
+// background of the part of a progress bar not reached yet
+const PROGRESS_TRACK_COLOR: &str = "#9f9f9f";
+
+// badges rendered without their message kept around; counts of as many digits lay out alike, so
+// a counter's frame gets reused until its count gains a digit
+const CACHE_CAPACITY: usize = 256;
+// measured text widths kept around, labels mostly, along with the latest messages
+const WIDTH_CACHE_CAPACITY: usize = 1024;
+// hides everything but text, so the message alone gets drawn over its frame; clip paths ignore
+// opacity, so text clipped by the badge's shape stays clipped the same
+const TEXT_ONLY_STYLE: &str =
+    "<style>rect,path,circle,ellipse,line,polyline,polygon,image{opacity:0}</style>";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RasterFormat {
+    Png,
+    Webp,
+}
+
+impl RasterFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            RasterFormat::Png => "image/png",
+            RasterFormat::Webp => "image/webp",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontFamily {
+    #[default]
+    Sans,
+    Serif,
+    Mono,
+}
+
+impl FontFamily {
+    fn name(&self) -> &'static str {
+        match self {
+            FontFamily::Sans => "DejaVu Sans",
+            FontFamily::Serif => "DejaVu Serif",
+            FontFamily::Mono => "DejaVu Sans Mono",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontWeight {
+    #[default]
+    Normal,
+    Bold,
+}
+
+impl FontWeight {
+    fn name(&self) -> &'static str {
+        match self {
+            FontWeight::Normal => "normal",
+            FontWeight::Bold => "bold",
+        }
+    }
+}
+
+/// Font of badges laid out locally.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Font {
+    pub family: FontFamily,
+    pub weight: FontWeight,
+}
+
+impl Font {
+    /// Font asked for by badge params, if any.
+    pub fn from_params(family: Option<FontFamily>, weight: Option<FontWeight>) -> Option<Font> {
+        match (family, weight) {
+            (None, None) => None,
+            (family, weight) => Some(Font {
+                family: family.unwrap_or_default(),
+                weight: weight.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+/// Whether shields.io lays the text out right; its width tables only cover latin scripts, so
+/// e.g. arabic, hebrew or CJK labels get badges of the wrong width.
+pub fn has_shields_widths(text: &str) -> bool {
+    text.chars().all(|c| c <= '\u{24f}')
+}
+
+/// Badge to render.
+pub enum RasterBadge {
+    /// Svg badge, with the text of its message when it shows a count
+    Svg {
+        svg: String,
+        message: Option<String>,
+    },
+    /// Badge laid out in the font right before it gets rendered, see
+    /// [`Rasterizer::layout_progress`]
+    Layout {
+        params: ShieldsIoParams,
+        message: String,
+        font: Font,
+        progress: Option<f32>,
+    },
+}
+
+impl RasterBadge {
+    /// Badge rendered as a whole, e.g. an error badge.
+    pub fn new(svg: String) -> RasterBadge {
+        RasterBadge::Svg { svg, message: None }
+    }
+
+    /// Badge whose message gets drawn over the cached rest of it.
+    pub fn with_message(svg: String, message: &str) -> RasterBadge {
+        RasterBadge::Svg {
+            svg,
+            message: Some(message.to_string()),
+        }
+    }
+}
+
+// rendered badge without its message, with its hits and its title with the message masked
+struct Frame {
+    pixmap: tiny_skia::Pixmap,
+    hits: u64,
+    title: String,
+}
+
+/// Renders svg badges to raster images, for platforms which don't allow svg embeds.
+pub struct Rasterizer {
+    // badges ask for `Verdana,Geneva,DejaVu Sans,sans-serif`, the embedded DejaVu fonts keep the
+    // output independent of the fonts installed on the host
+    fontdb: Arc<usvg::fontdb::Database>,
+    // badges rendered without their message by scale and svg, see `Rasterizer::render`
+    frames: Mutex<Lru<Frame>>,
+    cache_counters: CacheCounters,
+    // advance widths by font and escaped text
+    widths: Mutex<Lru<f32>>,
+}
+
+impl Rasterizer {
+    /// Rasterizer with the embedded fonts and those found in `font_dir`, which glyphs missing
+    /// from the embedded fonts, e.g. CJK ones, fall back to.
+    pub fn new(font_dir: Option<&str>) -> Rasterizer {
+        let mut fontdb = usvg::fontdb::Database::new();
+        fontdb.load_font_data(include_bytes!("../assets/fonts/DejaVuSans.ttf").to_vec());
+        fontdb.load_font_data(include_bytes!("../assets/fonts/DejaVuSans-Bold.ttf").to_vec());
+        fontdb.load_font_data(include_bytes!("../assets/fonts/DejaVuSerif.ttf").to_vec());
+        fontdb.load_font_data(include_bytes!("../assets/fonts/DejaVuSerif-Bold.ttf").to_vec());
+        fontdb.load_font_data(include_bytes!("../assets/fonts/DejaVuSansMono.ttf").to_vec());
+        fontdb.load_font_data(include_bytes!("../assets/fonts/DejaVuSansMono-Bold.ttf").to_vec());
+        fontdb.set_sans_serif_family("DejaVu Sans");
+        fontdb.set_serif_family("DejaVu Serif");
+        fontdb.set_monospace_family("DejaVu Sans Mono");
+
+        if let Some(font_dir) = font_dir {
+            let embedded = fontdb.len();
+            if let Err(err) = load_font_dir(&mut fontdb, font_dir) {
+                tracing::error!("failed to load fonts from {}: {}", font_dir, err);
+            }
+            tracing::info!("loaded {} fonts from {}", fontdb.len() - embedded, font_dir);
+        }
+
+        Rasterizer {
+            fontdb: Arc::new(fontdb),
+            frames: Mutex::new(Lru::new(CACHE_CAPACITY)),
+            cache_counters: CacheCounters::default(),
+            widths: Mutex::new(Lru::new(WIDTH_CACHE_CAPACITY)),
+        }
+    }
+
+    /// Stats of the frame cache, keyed by scale and the badge's title with its message masked,
+    /// e.g. `2x: profile views: ####`.
+    pub fn cache_stats(&self) -> CacheStats {
+        let frames = self.frames.lock().unwrap();
+        let entries = frames
+            .iter()
+            .map(|(_, frame)| (frame.title.clone(), frame.hits));
+        self.cache_counters.stats(entries)
+    }
+
+    /// Drops the cached frames.
+    pub fn clear_cache(&self) {
+        self.frames.lock().unwrap().clear();
+    }
+
+    /// Lays out a flat badge with the text measured in the font, instead of relying on
+    /// shields.io's widths. Right-to-left text is reordered when the badge gets rendered.
+    pub fn layout(
+        &self,
+        params: &ShieldsIoParams,
+        message: &str,
+        font: Font,
+    ) -> Result<String, Error> {
+        self.layout_progress(params, message, font, None)
+    }
+
+    /// Lays out a flat badge like [`Rasterizer::layout`], with the message's background filled
+    /// in the badge color up to `progress`, between 0 and 1, as a progress bar.
+    pub fn layout_progress(
+        &self,
+        params: &ShieldsIoParams,
+        message: &str,
+        font: Font,
+        progress: Option<f32>,
+    ) -> Result<String, Error> {
+        let (label, message) = (escape(params.label()), escape(message));
+        let label_width = self.text_width(&label, font)?.ceil() + 2.0 * HORIZONTAL_PADDING;
+        let message_width = self.text_width(&message, font)?.ceil() + 2.0 * HORIZONTAL_PADDING;
+        let width = label_width + message_width;
+
+        let (radius, gradient) = match params.style() {
+            "flat-square" => (0, ""),
+            _ => (3, r#"<rect width="100%" height="20" fill="url(#s)"/>"#),
+        };
+        let color = badge_color(params.color());
+        let message_rect = match progress {
+            Some(progress) => format!(
+                r##"<rect x="{x}" width="{width}" height="20" fill="{PROGRESS_TRACK_COLOR}"/><rect x="{x}" width="{filled}" height="20" fill="{color}"/>"##,
+                x = label_width,
+                width = message_width,
+                filled = (message_width * progress.clamp(0.0, 1.0)).round(),
+            ),
+            None => format!(
+                r##"<rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/>"##
+            ),
+        };
+
+        // minified like the upstream badges, whatever the layout below gets formatted like
+        let svg = format!(
+            concat!(
+                r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}">"##,
+                r##"<title>{label}: {message}</title>"##,
+                r##"<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>"##,
+                r##"<clipPath id="r"><rect width="{width}" height="20" rx="{radius}" fill="#fff"/></clipPath>"##,
+                r##"<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/>{message_rect}{gradient}</g>"##,
+                r##"<g fill="#fff" text-anchor="middle" font-family="{family}" font-weight="{weight}" font-size="{font_size}">"##,
+                r##"<text x="{label_x}" y="14">{label}</text><text x="{message_x}" y="14">{message}</text></g></svg>"##,
+            ),
+            width = width,
+            label = label,
+            message = message,
+            radius = radius,
+            label_width = label_width,
+            message_rect = message_rect,
+            gradient = gradient,
+            family = font.family.name(),
+            weight = font.weight.name(),
+            font_size = FONT_SIZE,
+            label_x = label_width / 2.0,
+            message_x = label_width + message_width / 2.0,
+        );
+        Ok(minify(&svg))
+    }
+
+    // advance width of the escaped text, shaped the way it gets rendered; measured once per font
+    fn text_width(&self, text: &str, font: Font) -> Result<f32, Error> {
+        let key = format!("{:?} {:?} {}", font.family, font.weight, text);
+        if let Some(width) = self.widths.lock().unwrap().get_mut(&key) {
+            return Ok(*width);
+        }
+        let width = self.measure(text, font)?;
+        self.widths.lock().unwrap().insert(key, width);
+        Ok(width)
+    }
+
+    fn measure(&self, text: &str, font: Font) -> Result<f32, Error> {
+        let svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1"><text font-family="{}" font-weight="{}" font-size="{}">{}</text></svg>"#,
+            font.family.name(),
+            font.weight.name(),
+            FONT_SIZE,
+            text,
+        );
+        let options = usvg::Options {
+            fontdb: self.fontdb.clone(),
+            ..usvg::Options::default()
+        };
+        let tree = usvg::Tree::from_str(&svg, &options)?;
+
+        Ok(tree
+            .root()
+            .children()
+            .iter()
+            .find_map(|node| match node {
+                usvg::Node::Text(text) => Some(text.bounding_box().width()),
+                _ => None,
+            })
+            .unwrap_or(0.0))
+    }
+
+    /// Lays out and renders the badge on the blocking pool, off the threads serving requests.
+    /// The badge's message is drawn over the badge rendered without it, cached per scale and svg: badges of
+    /// the same params share it as long as their counts have as many digits.
+    pub async fn render(
+        self: &Arc<Self>,
+        badge: RasterBadge,
+        format: RasterFormat,
+        scale: f32,
+    ) -> Result<Vec<u8>, Error> {
+        let raster = self.clone();
+        tokio::task::spawn_blocking(move || raster.render_blocking(&badge, format, scale)).await?
+    }
+
+    fn render_blocking(
+        &self,
+        badge: &RasterBadge,
+        format: RasterFormat,
+        scale: f32,
+    ) -> Result<Vec<u8>, Error> {
+        let (svg, message) = match badge {
+            RasterBadge::Svg { svg, message } => (Cow::Borrowed(svg), message.as_deref()),
+            RasterBadge::Layout {
+                params,
+                message,
+                font,
+                progress,
+            } => {
+                let svg = self.layout_progress(params, message, *font, *progress)?;
+                (Cow::Owned(svg), Some(message.as_str()))
+            }
+        };
+        let message = message
+            .map(escape)
+            .filter(|message| shows_message(&svg, message));
+        let pixmap = match message {
+            Some(message) => {
+                let mut pixmap = self.frame(&svg, &message, scale)?;
+                let text = only_message(&svg, &message);
+                self.draw(&text, scale, &mut pixmap)?;
+                pixmap
+            }
+            None => self.render_pixmap(&svg, scale)?,
+        };
+
+        match format {
+            RasterFormat::Png => Ok(pixmap.encode_png()?),
+            RasterFormat::Webp => encode_webp(&pixmap),
+        }
+    }
+
+    // badge rendered without its message, from the cache when rendered before
+    fn frame(&self, svg: &str, message: &str, scale: f32) -> Result<tiny_skia::Pixmap, Error> {
+        let frame = without_message(svg, message);
+        let key = format!("{}x {}", scale, frame);
+        if let Some(cached) = self.frames.lock().unwrap().get_mut(&key) {
+            cached.hits += 1;
+            self.cache_counters.hit();
+            return Ok(cached.pixmap.clone());
+        }
+        self.cache_counters.miss();
+
+        let pixmap = self.render_pixmap(&frame, scale)?;
+        let mask: String = message.chars().map(|_| '#').collect();
+        let title = svg
+            .split_once("<title>")
+            .and_then(|(_, rest)| rest.split_once("</title>"))
+            .map_or("untitled", |(title, _)| title);
+        let cached = Frame {
+            pixmap: pixmap.clone(),
+            hits: 0,
+            title: format!("{}x: {}", scale, title.replace(message, &mask)),
+        };
+        self.frames.lock().unwrap().insert(key, cached);
+
+        Ok(pixmap)
+    }
+
+    fn render_pixmap(&self, svg: &str, scale: f32) -> Result<tiny_skia::Pixmap, Error> {
+        let tree = self.parse(svg)?;
+        let size = tree
+            .size()
+            .to_int_size()
+            .scale_by(scale)
+            .ok_or_else(|| anyhow!("invalid scale: {}", scale))?;
+        let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+            .ok_or_else(|| anyhow!("invalid badge size: {:?}", size))?;
+
+        resvg::render(
+            &tree,
+            tiny_skia::Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+
+        Ok(pixmap)
+    }
+
+    // draws the svg over what the pixmap already shows
+    fn draw(&self, svg: &str, scale: f32, pixmap: &mut tiny_skia::Pixmap) -> Result<(), Error> {
+        let tree = self.parse(svg)?;
+        resvg::render(
+            &tree,
+            tiny_skia::Transform::from_scale(scale, scale),
+            &mut pixmap.as_mut(),
+        );
+        Ok(())
+    }
+
+    fn parse(&self, svg: &str) -> Result<usvg::Tree, Error> {
+        let options = usvg::Options {
+            fontdb: self.fontdb.clone(),
+            ..usvg::Options::default()
+        };
+        Ok(usvg::Tree::from_str(svg, &options)?)
+    }
+}
+
+// whether the escaped message is the text of one of the badge's text elements
+fn shows_message(svg: &str, message: &str) -> bool {
+    !message.is_empty() && svg.contains(&format!(">{}</text>", message))
+}
+
+// the badge with the message's text, title and aria label left out, the same svg for any message
+// of the same width
+fn without_message(svg: &str, message: &str) -> String {
+    let svg = retain_text(svg, |text| text != message);
+    let svg = remove_between(&svg, "<title>", "</title>");
+    remove_between(&svg, r#" aria-label=""#, "\"")
+}
+
+// the badge's message alone, drawn in place over its frame
+fn only_message(svg: &str, message: &str) -> String {
+    let svg = retain_text(svg, |text| text == message);
+    match svg
+        .find("<svg")
+        .and_then(|start| svg[start..].find('>').map(|end| start + end + 1))
+    {
+        Some(end) => format!("{}{}{}", &svg[..end], TEXT_ONLY_STYLE, &svg[end..]),
+        None => svg,
+    }
+}
+
+// empties the text elements whose text isn't kept
+fn retain_text(svg: &str, keep: impl Fn(&str) -> bool) -> String {
+    let mut retained = String::with_capacity(svg.len());
+    let mut rest = svg;
+    while let Some(start) = rest.find("<text") {
+        let Some(text_start) = rest[start..].find('>').map(|end| start + end + 1) else {
+            break;
+        };
+        let Some(text_end) = rest[text_start..]
+            .find("</text>")
+            .map(|end| text_start + end)
+        else {
+            break;
+        };
+        retained.push_str(&rest[..text_start]);
+        if keep(&rest[text_start..text_end]) {
+            retained.push_str(&rest[text_start..text_end]);
+        }
+        rest = &rest[text_end..];
+    }
+    retained.push_str(rest);
+    retained
+}
+
+fn remove_between(text: &str, start: &str, end: &str) -> String {
+    let Some(from) = text.find(start) else {
+        return text.to_string();
+    };
+    match text[from + start.len()..].find(end) {
+        Some(to) => format!(
+            "{}{}",
+            &text[..from],
+            &text[from + start.len() + to + end.len()..]
+        ),
+        None => text.to_string(),
+    }
+}
+
+fn load_font_dir(fontdb: &mut usvg::fontdb::Database, font_dir: &str) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(font_dir)? {
+        let path = entry?.path();
+        let is_font = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                matches!(
+                    extension.to_ascii_lowercase().as_str(),
+                    "ttf" | "otf" | "ttc" | "otc"
+                )
+            });
+        if is_font {
+            fontdb.load_font_data(std::fs::read(path)?);
+        }
+    }
+    Ok(())
+}
+
+// hex values of shields.io's named colors; other colors are hex values or css colors
+fn badge_color(color: &str) -> String {
+    match named_color(color) {
+        Some(hex) => format!("#{}", hex),
+        None if matches!(color.len(), 3 | 4 | 6 | 8)
+            && color.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            format!("#{}", color)
+        }
+        None => escape(color),
+    }
+}
+
+fn encode_webp(pixmap: &tiny_skia::Pixmap) -> Result<Vec<u8>, Error> {
+    // pixmaps hold premultiplied colors, encoders expect straight alpha
+    let rgba: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    let mut webp = Vec::new();
+    WebPEncoder::new_lossless(&mut webp).encode(
+        &rgba,
+        pixmap.width(),
+        pixmap.height(),
+        ExtendedColorType::Rgba8,
+    )?;
+
+    Ok(webp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::badge::UNAVAILABLE_BADGE;
+    use pretty_assertions::assert_eq;
+
+    // shields.io's flat badge, its text drawn with a shadow in a scaled coordinate system
+    fn shields_badge(message: &str) -> String {
+        format!(
+            concat!(
+                r##"<svg xmlns="http://www.w3.org/2000/svg" width="108" height="20" role="img" aria-label="Profile views: {message}"><title>Profile views: {message}</title>"##,
+                r##"<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>"##,
+                r##"<clipPath id="r"><rect width="108" height="20" rx="3" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="79" height="20" fill="#555"/><rect x="79" width="29" height="20" fill="#007ec6"/><rect width="108" height="20" fill="url(#s)"/></g>"##,
+                r##"<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" text-rendering="geometricPrecision" font-size="110">"##,
+                r##"<text aria-hidden="true" x="405" y="150" fill="#010101" fill-opacity=".3" transform="scale(.1)" textLength="690">Profile views</text><text x="405" y="140" transform="scale(.1)" fill="#fff" textLength="690">Profile views</text>"##,
+                r##"<text aria-hidden="true" x="925" y="150" fill="#010101" fill-opacity=".3" transform="scale(.1)" textLength="190">{message}</text><text x="925" y="140" transform="scale(.1)" fill="#fff" textLength="190">{message}</text></g></svg>"##,
+            ),
+            message = message,
+        )
+    }
+
+    #[tokio::test]
+    async fn it_renders_scaled_png() {
+        let png = Arc::new(Rasterizer::new(None))
+            .render(
+                RasterBadge::new(UNAVAILABLE_BADGE.to_string()),
+                RasterFormat::Png,
+                2.0,
+            )
+            .await
+            .unwrap();
+        let pixmap = tiny_skia::Pixmap::decode_png(&png).unwrap();
+
+        assert_eq!((pixmap.width(), pixmap.height()), (308, 40));
+        // text got rendered, i.e. there are white pixels on top of the grey badge
+        assert!(pixmap
+            .pixels()
+            .iter()
+            .any(|pixel| pixel.red() > 200 && pixel.green() > 200 && pixel.blue() > 200));
+    }
+
+    #[test]
+    fn it_lays_out_badges_to_fit_the_text() {
+        let raster = Rasterizer::new(None);
+        let width = |label: &str, font| {
+            let params = ShieldsIoParams::new(label, "blue", "flat");
+            let svg = raster.layout(&params, "1234", font).unwrap();
+            let pixmap = raster.render_pixmap(&svg, 1.0).unwrap();
+            pixmap.width()
+        };
+
+        let sans = Font::default();
+        let bold = Font {
+            weight: FontWeight::Bold,
+            ..sans
+        };
+        let mono = Font {
+            family: FontFamily::Mono,
+            ..sans
+        };
+        assert!(width("profile views", bold) > width("profile views", sans));
+        assert!(width("iiiiiiii", mono) > width("iiiiiiii", sans));
+        // hebrew and arabic labels get their measured width, not shields.io's estimate
+        assert!(width("צפיות בפרופיל", sans) > width("צפיות", sans));
+        assert!(width("مشاهدات الملف الشخصي", sans) > width("مشاهدات", sans));
+    }
+
+    #[test]
+    fn it_escapes_badge_text() {
+        let params = ShieldsIoParams::new("<views>", "ff0000", "flat-square");
+        let svg = Rasterizer::new(None)
+            .layout(&params, "1 & 2", Font::default())
+            .unwrap();
+
+        assert!(svg.contains("&lt;views&gt;: 1 &amp; 2"));
+        assert!(svg.contains(r##"fill="#ff0000""##));
+        assert!(has_shields_widths("profile views déjà"));
+        assert!(!has_shields_widths("ビュー"));
+    }
+
+    #[test]
+    fn it_lays_out_progress_bars() {
+        let params = ShieldsIoParams::new("goal", "ff0000", "flat");
+        let raster = Rasterizer::new(None);
+        let svg = raster
+            .layout_progress(&params, "50%", Font::default(), Some(0.5))
+            .unwrap();
+        let message_width =
+            raster.text_width("50%", Font::default()).unwrap().ceil() + 2.0 * HORIZONTAL_PADDING;
+
+        assert!(svg.contains(&format!(
+            r##"width="{}" height="20" fill="#ff0000""##,
+            (message_width / 2.0).round()
+        )));
+        assert!(svg.contains(PROGRESS_TRACK_COLOR));
+        assert!(!raster
+            .layout(&params, "50%", Font::default())
+            .unwrap()
+            .contains(PROGRESS_TRACK_COLOR));
+    }
+
+    #[tokio::test]
+    async fn it_renders_webp() {
+        let webp = Arc::new(Rasterizer::new(None))
+            .render(
+                RasterBadge::new(UNAVAILABLE_BADGE.to_string()),
+                RasterFormat::Webp,
+                1.0,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(&webp[..4], b"RIFF");
+        assert_eq!(&webp[8..12], b"WEBP");
+    }
+
+    #[test]
+    fn it_draws_the_message_over_its_frame() {
+        let raster = Rasterizer::new(None);
+        let mut badges = vec![shields_badge("1234")];
+        for style in ["flat", "flat-square", "for-the-badge"] {
+            let params = ShieldsIoParams::new("profile views", "blue", style);
+            badges.push(raster.layout(&params, "1234", Font::default()).unwrap());
+        }
+
+        for svg in badges {
+            let badge = RasterBadge::with_message(svg.clone(), "1234");
+            let drawn = raster
+                .render_blocking(&badge, RasterFormat::Png, 2.0)
+                .unwrap();
+            let whole = raster
+                .render_blocking(&RasterBadge::new(svg), RasterFormat::Png, 2.0)
+                .unwrap();
+            assert_eq!(drawn, whole);
+        }
+        // every message got drawn over a frame rendered without it
+        let stats = raster.cache_stats();
+        assert_eq!(stats.hits + stats.misses, 4);
+    }
+
+    #[tokio::test]
+    async fn it_reuses_frames_for_counts_of_as_many_digits() {
+        let raster = Arc::new(Rasterizer::new(None));
+        let params = ShieldsIoParams::new("profile views", "blue", "flat");
+        for (message, scale) in [("1234", 1.0), ("5678", 1.0), ("12345", 1.0), ("5678", 2.0)] {
+            let svg = raster.layout(&params, message, Font::default()).unwrap();
+            let badge = RasterBadge::with_message(svg, message);
+            raster
+                .render(badge, RasterFormat::Png, scale)
+                .await
+                .unwrap();
+        }
+
+        let stats = raster.cache_stats();
+        assert_eq!((stats.size, stats.hits, stats.misses), (3, 1, 3));
+        assert!(stats
+            .top_keys
+            .iter()
+            .any(|key| key.key == "1x: profile views: ####" && key.hits == 1));
+    }
+
+    #[tokio::test]
+    async fn it_lays_badges_out_when_rendering() {
+        let raster = Arc::new(Rasterizer::new(None));
+        let params = ShieldsIoParams::new("profile views", "blue", "flat");
+        let svg = raster.layout(&params, "1234", Font::default()).unwrap();
+
+        let badge = RasterBadge::Layout {
+            params,
+            message: "1234".to_string(),
+            font: Font::default(),
+            progress: None,
+        };
+        let laid_out = raster.render(badge, RasterFormat::Png, 1.0).await.unwrap();
+        let badge = RasterBadge::with_message(svg, "1234");
+        let rendered = raster.render(badge, RasterFormat::Png, 1.0).await.unwrap();
+
+        assert_eq!(laid_out, rendered);
+        // the text got measured once per font
+        let widths = raster.widths.lock().unwrap();
+        assert_eq!(widths.iter().count(), 2);
+        assert!(widths.peek("Sans Normal profile views").is_some());
+    }
+
+    // the svg of locally laid out badges is snapshotted, so any change to it shows up in review
+    #[test]
+    fn it_lays_out_badges_of_every_style() {
+        let raster = Rasterizer::new(None);
+        for style in ["flat", "flat-square", "plastic", "for-the-badge", "social"] {
+            let params = ShieldsIoParams::new("profile views", "blue", style);
+            let svg = raster.layout(&params, "1234", Font::default()).unwrap();
+            insta::assert_snapshot!(format!("style_{}", style), svg);
+        }
+    }
+
+    #[test]
+    fn it_lays_out_badges_of_every_length() {
+        let raster = Rasterizer::new(None);
+        let params = ShieldsIoParams::new("views", "brightgreen", "flat");
+        for message in ["7", "1234", "1234567890"] {
+            let svg = raster.layout(&params, message, Font::default()).unwrap();
+            insta::assert_snapshot!(format!("length_{}", message.len()), svg);
+        }
+        let svg = raster
+            .layout_progress(&params, "75%", Font::default(), Some(0.75))
+            .unwrap();
+        insta::assert_snapshot!("progress", svg);
+    }
+
+    #[test]
+    fn it_lays_out_translated_badges() {
+        let raster = Rasterizer::new(None);
+        for lang in ["de", "ru", "ar", "he", "ja"] {
+            let params: ShieldsIoParams = serde_json::from_value(serde_json::json!({
+                "label_lang": lang,
+                "color": "ff69b4",
+                "style": "flat",
+            }))
+            .unwrap();
+            let svg = raster.layout(&params, "1234", Font::default()).unwrap();
+            insta::assert_snapshot!(format!("locale_{}", lang), svg);
+        }
+        let bold_serif = Font {
+            family: FontFamily::Serif,
+            weight: FontWeight::Bold,
+        };
+        let params = ShieldsIoParams::new("profile views", "blue", "flat");
+        let svg = raster.layout(&params, "1234", bold_serif).unwrap();
+        insta::assert_snapshot!("font_serif_bold", svg);
+    }
+}
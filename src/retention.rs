@@ -0,0 +1,296 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use chrono::{DateTime, Months, Utc};
+use tokio::time::{self, Instant};
+
+use super::admin::csv_field;
+use super::config::RetentionConfig;
+use super::datastore::{DatastoreError, DatastoreOperations, UserRecord};
+
+const SCAN_PAGE_SIZE: usize = 200;
+const ARCHIVE_HEADER: &str = "removed_at,user_name,views,created_at,last_viewed_at,deleted_at\n";
+
+#[derive(thiserror::Error, Debug)]
+pub enum RetentionError {
+    #[error(transparent)]
+    Datastore(#[from] DatastoreError),
+
+    #[error("failed to archive users: {0}")]
+    Archive(#[from] std::io::Error),
+}
+
+/// Removes dormant users at every interval, the first time one interval after boot so restarts
+/// don't scan the whole table each time.
+pub async fn retention_loop(db: &impl DatastoreOperations, config: &RetentionConfig) {
+    let mut interval = time::interval_at(Instant::now() + config.interval, config.interval);
+    loop {
+        interval.tick().await;
+        match remove_dormant_users(db, config, Utc::now()).await {
+            Ok(0) => {}
+            Ok(removed) => tracing::info!("removed {} dormant users", removed),
+            Err(err) => tracing::error!("failed to remove dormant users, reason: {}", err),
+        }
+    }
+}
+
+/// Removes users not viewed for `dormant_months` and not allowlisted, returning how many were
+/// removed. Each user is looked up again right before its removal, sparing users viewed since
+/// the scan, and archived before it's removed, so a failed archive removes nobody; users whose
+/// removal failed are archived again by the next run.
+pub async fn remove_dormant_users(
+    db: &impl DatastoreOperations,
+    config: &RetentionConfig,
+    now: DateTime<Utc>,
+) -> Result<usize, RetentionError> {
+    let cutoff = now
+        .checked_sub_months(Months::new(config.dormant_months))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+    let mut dormant = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = db.list_users(cursor, SCAN_PAGE_SIZE).await?;
+        dormant.extend(
+            page.users
+                .into_iter()
+                .filter(|user| is_dormant(user, cutoff, &config.allowlist)),
+        );
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    let mut removed = 0;
+    for user in dormant {
+        // the scan can take a while over large tables, views counted since then revive the user
+        let Some(user) = db.get_user(&user.user_name).await? else {
+            continue;
+        };
+        if !is_dormant(&user, cutoff, &config.allowlist) {
+            continue;
+        }
+        archive(&config.archive, &user, now)?;
+        db.purge_user(&user.user_name).await?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+// users counted before views were timestamped fall back to their record's last update
+fn is_dormant(user: &UserRecord, cutoff: DateTime<Utc>, allowlist: &[String]) -> bool {
+    !allowlist.contains(&user.user_name) && user.last_viewed_at.unwrap_or(user.updated_at) < cutoff
+}
+
+fn archive(path: &str, user: &UserRecord, now: DateTime<Utc>) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut rows = match file.metadata()?.len() {
+        0 => ARCHIVE_HEADER.to_string(),
+        _ => String::new(),
+    };
+
+    let timestamp = |at: Option<DateTime<Utc>>| at.map(|at| at.to_rfc3339()).unwrap_or_default();
+    rows.push_str(&format!(
+        "{},{},{},{},{},{}\n",
+        now.to_rfc3339(),
+        csv_field(&user.user_name),
+        user.views,
+        user.created_at.to_rfc3339(),
+        timestamp(user.last_viewed_at),
+        timestamp(user.deleted_at),
+    ));
+
+    file.write_all(rows.as_bytes())?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::async_trait;
+
+    use super::*;
+    use crate::datastore::{
+        DayStats, Fields, Flag, Increment, Memory, Op, Page, StoredUser, UserViews,
+    };
+    use crate::shutdown::Shutdown;
+    use pretty_assertions::assert_eq;
+
+    /// Memory datastore whose user gets viewed right after being scanned.
+    struct ViewedDuringScan {
+        db: Memory,
+        user_name: &'static str,
+        viewed_at: DateTime<Utc>,
+    }
+
+    impl Shutdown for ViewedDuringScan {}
+
+    #[async_trait]
+    impl DatastoreOperations for ViewedDuringScan {
+        async fn get_latest_views(&self, user_name: &str) -> Result<u64, DatastoreError> {
+            self.db.get_latest_views(user_name).await
+        }
+        async fn onboard_user(&self, user_name: &str) -> Result<u64, DatastoreError> {
+            self.db.onboard_user(user_name).await
+        }
+        async fn register_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+            self.db.register_user(user_name).await
+        }
+        async fn increment_views(
+            &self,
+            increments: &[Increment],
+        ) -> Result<Vec<UserViews>, DatastoreError> {
+            self.db.increment_views(increments).await
+        }
+        async fn delete_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+            self.db.delete_user(user_name).await
+        }
+        async fn restore_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+            self.db.restore_user(user_name).await
+        }
+        async fn purge_user(&self, user_name: &str) -> Result<(), DatastoreError> {
+            self.db.purge_user(user_name).await
+        }
+        async fn transaction(
+            &self,
+            ops: Vec<Op>,
+        ) -> Result<Vec<Option<StoredUser>>, DatastoreError> {
+            self.db.transaction(ops).await
+        }
+        async fn scan(
+            &self,
+            cursor: Option<String>,
+            limit: usize,
+        ) -> Result<Page<UserViews>, DatastoreError> {
+            self.db.scan(cursor, limit).await
+        }
+        async fn scan_prefix(
+            &self,
+            prefix: &str,
+            cursor: Option<String>,
+            limit: usize,
+        ) -> Result<Page<UserViews>, DatastoreError> {
+            self.db.scan_prefix(prefix, cursor, limit).await
+        }
+        async fn get_many(&self, user_names: &[String]) -> Result<Vec<UserViews>, DatastoreError> {
+            self.db.get_many(user_names).await
+        }
+        async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>, DatastoreError> {
+            self.db.get_user(user_name).await
+        }
+        async fn get_peak_day_views(&self, user_name: &str) -> Result<Option<u64>, DatastoreError> {
+            self.db.get_peak_day_views(user_name).await
+        }
+        async fn get_day_stats(&self, user_name: &str) -> Result<Option<DayStats>, DatastoreError> {
+            self.db.get_day_stats(user_name).await
+        }
+        async fn list_users(
+            &self,
+            cursor: Option<String>,
+            limit: usize,
+        ) -> Result<Page<UserRecord>, DatastoreError> {
+            let page = self.db.list_users(cursor, limit).await?;
+            let viewed = Fields {
+                last_viewed_at: Some(self.viewed_at),
+                ..Fields::default()
+            };
+            let update = Op::Update(self.user_name.to_string(), viewed);
+            self.db.transaction(vec![update]).await?;
+            Ok(page)
+        }
+        async fn top_users(&self, limit: usize) -> Result<Vec<UserViews>, DatastoreError> {
+            self.db.top_users(limit).await
+        }
+
+        async fn put_flag(&self, flag: &Flag) -> Result<(), DatastoreError> {
+            self.db.put_flag(flag).await
+        }
+
+        async fn list_flags(&self) -> Result<Vec<Flag>, DatastoreError> {
+            self.db.list_flags().await
+        }
+
+        async fn delete_flag(&self, user_name: &str) -> Result<bool, DatastoreError> {
+            self.db.delete_flag(user_name).await
+        }
+    }
+
+    #[tokio::test]
+    async fn it_archives_and_removes_dormant_users() {
+        let db = Memory::new();
+        for user_name in ["alice", "bob", "carol"] {
+            db.onboard_user(user_name).await.unwrap();
+        }
+        let archive = std::env::temp_dir().join(format!("retention-{}.csv", std::process::id()));
+        let config = RetentionConfig {
+            dormant_months: 12,
+            interval: Duration::from_secs(60),
+            archive: archive.to_string_lossy().to_string(),
+            allowlist: vec!["bob".to_string()],
+        };
+
+        // nobody is dormant yet
+        assert_eq!(
+            remove_dormant_users(&db, &config, Utc::now())
+                .await
+                .unwrap(),
+            0
+        );
+        assert!(!archive.exists());
+
+        let later = Utc::now() + chrono::Duration::days(400);
+        assert_eq!(remove_dormant_users(&db, &config, later).await.unwrap(), 2);
+
+        assert!(db.get_user("alice").await.unwrap().is_none());
+        assert!(db.get_user("bob").await.unwrap().is_some());
+        let archived = std::fs::read_to_string(&archive).unwrap();
+        std::fs::remove_file(&archive).unwrap();
+        let rows: Vec<Vec<&str>> = archived
+            .lines()
+            .map(|row| row.split(',').collect())
+            .collect();
+        assert_eq!(
+            rows[0],
+            ARCHIVE_HEADER.trim_end().split(',').collect::<Vec<_>>()
+        );
+        assert_eq!(
+            rows[1..]
+                .iter()
+                .map(|row| (row[1], row[2]))
+                .collect::<Vec<_>>(),
+            vec![("alice", "1"), ("carol", "1")]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_spares_users_viewed_since_the_scan() {
+        let later = Utc::now() + chrono::Duration::days(400);
+        let db = ViewedDuringScan {
+            db: Memory::new(),
+            user_name: "bob",
+            viewed_at: later,
+        };
+        for user_name in ["alice", "bob"] {
+            db.onboard_user(user_name).await.unwrap();
+        }
+        let archive =
+            std::env::temp_dir().join(format!("retention-{}-viewed.csv", std::process::id()));
+        let config = RetentionConfig {
+            dormant_months: 12,
+            interval: Duration::from_secs(60),
+            archive: archive.to_string_lossy().to_string(),
+            allowlist: Vec::new(),
+        };
+
+        assert_eq!(remove_dormant_users(&db, &config, later).await.unwrap(), 1);
+
+        assert!(db.get_user("alice").await.unwrap().is_none());
+        assert!(db.get_user("bob").await.unwrap().is_some());
+        let archived = std::fs::read_to_string(&archive).unwrap();
+        std::fs::remove_file(&archive).unwrap();
+        assert_eq!(archived.lines().count(), 2);
+        assert!(!archived.contains("bob"));
+    }
+}
@@ -0,0 +1,466 @@
+use std::sync::Arc;
+
+use axum::routing::{delete, get, head, post};
+use axum::{middleware, Extension, Router};
+use metrics_exporter_prometheus::PrometheusHandle;
+use tower_http::catch_panic::CatchPanicLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use super::badge::ShieldsIoFetcher;
+use super::datastore::DatastoreOperations;
+use super::openapi::ApiDoc;
+use super::state::AppState;
+#[cfg(feature = "sentry")]
+use super::telemetry;
+use super::{
+    admin, api, assets, auth, cors, error, handler, overload, pages, panic, routes, sampling,
+    status, tenant,
+};
+
+/// Routes of the app. Building them has no side effects, the background tasks are spawned by
+/// `spawn_background_tasks` of the server.
+pub fn build_router<T, F>(
+    app_state: Arc<AppState<T, F>>,
+    metrics_handle: PrometheusHandle,
+) -> Router
+where
+    T: DatastoreOperations + Send + Sync + 'static,
+    F: ShieldsIoFetcher + Send + Sync + 'static,
+{
+    let json_cors = cors::json_layer(&app_state.config.cors_allowed_origins);
+    let badge_cors = cors::badge_layer();
+
+    let router = Router::new()
+        .route(routes::LANDING, get(pages::landing_handler))
+        .route(routes::FAVICON, get(assets::favicon_handler))
+        .route(routes::ASSETS, get(assets::asset_handler))
+        .route(routes::HEALTHZ, head(handler::health_check_handler))
+        .route(routes::READYZ, get(handler::readiness_handler))
+        .route(routes::STATUS, get(status::status_handler))
+        .route(
+            routes::METRICS,
+            get(handler::metrics_handler).layer(Extension(metrics_handle)),
+        )
+        .route(
+            routes::COUNTER,
+            get(handler::profile_views_handler)
+                .head(handler::profile_views_head_handler)
+                .layer(badge_cors.clone()),
+        )
+        .route(
+            routes::COUNTER_NEGOTIATED,
+            get(handler::counter_handler)
+                .head(handler::counter_head_handler)
+                .layer(badge_cors.clone()),
+        )
+        .route(
+            routes::ALT_TEXT,
+            get(handler::alt_text_handler).layer(json_cors.clone()),
+        )
+        .route(
+            routes::COUNT_JSON,
+            get(handler::count_json_handler).layer(json_cors.clone()),
+        )
+        .route(
+            routes::STREAK,
+            get(handler::streak_handler).layer(badge_cors.clone()),
+        )
+        .route(
+            routes::RECORD,
+            get(handler::record_handler).layer(badge_cors.clone()),
+        )
+        .route(routes::DEBUG, get(handler::debug_handler))
+        .route(
+            routes::COUNTER_PNG,
+            get(handler::counter_png_handler)
+                .head(handler::counter_png_head_handler)
+                .layer(badge_cors.clone()),
+        )
+        .route(
+            routes::COUNTER_WEBP,
+            get(handler::counter_webp_handler)
+                .head(handler::counter_webp_head_handler)
+                .layer(badge_cors.clone()),
+        )
+        .route(
+            routes::TENANT_COUNTER,
+            get(handler::tenant_views_handler)
+                .head(handler::tenant_views_head_handler)
+                .layer(badge_cors),
+        )
+        .route(
+            routes::TENANT_USERS,
+            get(tenant::tenant_users_handler).layer(json_cors.clone()),
+        )
+        .route(routes::BUILDER, get(pages::builder_handler))
+        .route(routes::PRIVACY, get(pages::privacy_handler))
+        .route(routes::BUILDER_PREVIEW, get(pages::builder_preview_handler))
+        .route(
+            routes::API_INCREMENTS,
+            post(api::increments_handler).layer(json_cors.clone()),
+        )
+        .route(
+            routes::API_COUNTS,
+            get(api::counts_handler).layer(json_cors.clone()),
+        )
+        .route(
+            routes::API_EXPERIMENT,
+            get(api::experiment_results_handler).layer(json_cors.clone()),
+        )
+        .route(
+            routes::API_USER_EXPORT,
+            get(api::export_user_handler).layer(json_cors.clone()),
+        )
+        .route(
+            routes::API_ONBOARD,
+            post(api::onboard_handler).layer(json_cors),
+        )
+        .route(routes::ADMIN_EXPORT, get(admin::export_handler))
+        .route(routes::ADMIN_USERS, get(admin::list_users_handler))
+        .route(routes::ADMIN_USER, delete(admin::delete_user_handler))
+        .route(
+            routes::ADMIN_USER_RESTORE,
+            post(admin::restore_user_handler),
+        )
+        .route(
+            routes::ADMIN_USER_TOKEN,
+            post(admin::issue_owner_token_handler),
+        )
+        .route(routes::ADMIN_USER_RENAME, post(admin::rename_user_handler))
+        .route(routes::ADMIN_USER_MERGE, post(admin::merge_users_handler))
+        .route(routes::ADMIN_FLAGS, get(admin::list_flags_handler))
+        .route(routes::ADMIN_FLAG, delete(admin::clear_flag_handler))
+        .route(routes::ADMIN_SAMPLES, get(admin::list_samples_handler))
+        .route(routes::ADMIN_USAGE, get(admin::usage_handler))
+        .route(
+            routes::ADMIN_DEBUG,
+            post(admin::start_trace_handler).delete(admin::stop_trace_handler),
+        )
+        .route(
+            routes::ADMIN_DEBUG_REQUESTS,
+            get(admin::list_captured_requests_handler),
+        )
+        .route(
+            routes::ADMIN_CACHE,
+            get(admin::cache_stats_handler).delete(admin::clear_cache_handler),
+        )
+        .route(
+            routes::ADMIN_RELOAD_SECRETS,
+            post(admin::reload_secrets_handler),
+        )
+        .merge(SwaggerUi::new(routes::DOCS).url(routes::OPENAPI, ApiDoc::openapi()))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::enforce_policy,
+        ))
+        .layer(CatchPanicLayer::custom(panic::handle_panic))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            overload::limit_concurrency,
+        ))
+        .layer(middleware::from_fn(error::attach_request_id))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            sampling::sample_requests,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            status::track_responses,
+        ));
+
+    #[cfg(feature = "sentry")]
+    let router = router.layer(middleware::from_fn(telemetry::sentry_request_scope));
+
+    let router = router
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(app_state.clone());
+
+    // custom domains rewrite the uri, which has to happen before the routes are matched
+    Router::new()
+        .fallback_service(router)
+        .layer(middleware::from_fn_with_state(
+            app_state,
+            tenant::route_by_host,
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use axum::async_trait;
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::{header, HeaderValue, Method, StatusCode};
+    use axum::response::Response;
+    use chrono::Utc;
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::badge::ShieldsIoParams;
+    use crate::cache::CacheStores;
+    use crate::config::{Config, TenantConfig};
+    use crate::datastore::{DayStats, Fields, Memory, Op};
+    use crate::runtime::TokioSpawner;
+    use crate::shutdown::Shutdown;
+    use pretty_assertions::assert_eq;
+
+    const USER_NAME: &str = "octocat";
+
+    // badges are rendered without asking shields.io
+    struct MessageFetcher;
+
+    impl Shutdown for MessageFetcher {}
+
+    #[async_trait]
+    impl ShieldsIoFetcher for MessageFetcher {
+        async fn fetch_message(&self, _: &ShieldsIoParams, message: &str) -> Result<String, Error> {
+            Ok(format!("<svg>{}</svg>", message))
+        }
+    }
+
+    async fn test_app(config: Config) -> (Router, Arc<AppState<Memory, MessageFetcher>>) {
+        let caches = CacheStores::new(&config);
+        let state = Arc::new(AppState::new(
+            Memory::new(),
+            MessageFetcher,
+            config,
+            caches,
+            Arc::new(TokioSpawner),
+        ));
+        state.db.onboard_user(USER_NAME).await.unwrap();
+        let metrics_handle = PrometheusBuilder::new().build_recorder().handle();
+        (build_router(state.clone(), metrics_handle), state)
+    }
+
+    async fn send(app: &Router, method: Method, uri: &str, origin: Option<&str>) -> Response {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(origin) = origin {
+            request = request.header(header::ORIGIN, origin);
+        }
+        app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_lets_any_page_fetch_negotiated_counters() {
+        let mut config = Config::from_env();
+        config.cors_allowed_origins = vec![];
+        let (app, _) = test_app(config).await;
+        let uri = format!("/{}/counter?format=json", USER_NAME);
+
+        let response = send(&app, Method::GET, &uri, Some("https://example.com")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(&HeaderValue::from_static("*"))
+        );
+
+        let preflight = Request::builder()
+            .method(Method::OPTIONS)
+            .uri(&uri)
+            .header(header::ORIGIN, "https://example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(preflight).await.unwrap();
+        assert_eq!(
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(&HeaderValue::from_static("*"))
+        );
+
+        // json routes still only answer the configured origins
+        let uri = format!("/{}/count.json", USER_NAME);
+        let response = send(&app, Method::GET, &uri, Some("https://example.com")).await;
+        assert_eq!(
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            None
+        );
+    }
+
+    // headers of the response, but the length of its body and the id of its request
+    fn comparable_headers(response: &Response) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                ![header::CONTENT_LENGTH.as_str(), "x-request-id"].contains(&name.as_str())
+            })
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+            .collect();
+        headers.sort();
+        headers
+    }
+
+    async fn body(response: Response) -> axum::body::Bytes {
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_answers_head_requests_with_the_headers_of_get_requests() {
+        let (app, _) = test_app(Config::from_env()).await;
+        let badge_params = "label=views&color=blue&style=flat";
+        for uri in [
+            format!("/{}/counter.svg?{}", USER_NAME, badge_params),
+            format!("/{}/counter.png?{}", USER_NAME, badge_params),
+            format!("/{}/counter.webp?{}", USER_NAME, badge_params),
+            format!("/{}/counter?format=json", USER_NAME),
+            format!("/{}/counter?format=text", USER_NAME),
+            format!("/{}/counter?format=svg&{}", USER_NAME, badge_params),
+        ] {
+            let get = send(&app, Method::GET, &uri, Some("https://example.com")).await;
+            let head = send(&app, Method::HEAD, &uri, Some("https://example.com")).await;
+
+            assert_eq!(head.status(), get.status(), "{}", uri);
+            assert_eq!(
+                comparable_headers(&head),
+                comparable_headers(&get),
+                "{}",
+                uri
+            );
+            assert!(!body(get).await.is_empty(), "{}", uri);
+            assert!(body(head).await.is_empty(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_counts_head_requests_only_when_configured() {
+        let uri = format!("/{}/counter.svg", USER_NAME);
+
+        let mut config = Config::from_env();
+        config.count_head_requests = false;
+        let (app, state) = test_app(config).await;
+        for _ in 0..2 {
+            let response = send(&app, Method::HEAD, &uri, None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = send(&app, Method::HEAD, &format!("/{}/counter", USER_NAME), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // onboarding counted the first view
+        assert_eq!(
+            state.db.get_user(USER_NAME).await.unwrap().unwrap().views,
+            1
+        );
+
+        let mut config = Config::from_env();
+        config.count_head_requests = true;
+        let (app, state) = test_app(config).await;
+        for _ in 0..2 {
+            let response = send(&app, Method::HEAD, &uri, None).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers().get("x-count-freshness"),
+                Some(&HeaderValue::from_static("live"))
+            );
+        }
+        assert_eq!(
+            state.db.get_user(USER_NAME).await.unwrap().unwrap().views,
+            3
+        );
+    }
+
+    fn with_tenant(mut config: Config) -> Config {
+        config.tenants.insert(
+            "acme".to_string(),
+            TenantConfig {
+                api_key: "key".to_string(),
+                label: Some("views".to_string()),
+                color: Some("blue".to_string()),
+                style: Some("flat".to_string()),
+                daily_view_quota: Some(1),
+                trend_threshold: None,
+                domains: vec![],
+            },
+        );
+        config
+    }
+
+    #[tokio::test]
+    async fn it_rejects_tenant_users_on_plain_routes() {
+        let mut config = with_tenant(Config::from_env());
+        config.explicit_onboarding = true;
+        let (app, state) = test_app(config).await;
+        let badge_params = "label=views&color=blue&style=flat";
+
+        let response = send(&app, Method::GET, "/t/acme/alice/counter.svg", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(&app, Method::GET, "/t/acme/alice%2Fbob/counter.svg", None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        for uri in [
+            format!("/acme%2Falice/counter.svg?{}", badge_params),
+            format!("/acme%2Fbob/counter.svg?{}", badge_params),
+            "/acme%2Falice/counter?format=json".to_string(),
+        ] {
+            let response = send(&app, Method::GET, &uri, None).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+
+        // neither counted past the tenant's quota nor onboarded past explicit onboarding
+        assert_eq!(
+            state
+                .db
+                .get_user("acme/alice")
+                .await
+                .unwrap()
+                .unwrap()
+                .views,
+            1
+        );
+        assert_eq!(state.db.get_user("acme/bob").await.unwrap(), None);
+        assert_eq!(state.db.get_user("acme/alice/bob").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn it_shows_views_gained_from_the_stored_day_stats() {
+        let (app, state) = test_app(Config::from_env()).await;
+        let yesterday = Utc::now().date_naive().pred_opt().unwrap();
+        let day_stats = DayStats {
+            day: yesterday,
+            day_views: 3,
+            peak_views: 3,
+            streak: 0,
+            recent_views: vec![4, 0, 0, 0, 0, 0, 10],
+        };
+        let fields = Fields {
+            day_stats: Some(day_stats),
+            ..Fields::default()
+        };
+        let ops = vec![Op::Update(USER_NAME.to_string(), fields)];
+        state.db.transaction(ops).await.unwrap();
+
+        let uri = format!(
+            "/{}/counter.svg?label=views&color=blue&style=flat&delta=week&delta_only=true",
+            USER_NAME
+        );
+        let response = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // the view just counted, the 3 of yesterday and the 4 of the day before
+        let body = body(response).await;
+        assert!(String::from_utf8_lossy(&body).ends_with(">+8 this week</svg>"));
+    }
+
+    #[tokio::test]
+    async fn it_describes_views_of_renamed_users() {
+        let (app, state) = test_app(Config::from_env()).await;
+        state
+            .db
+            .merge_users(&[USER_NAME.to_string()], "monalisa")
+            .await
+            .unwrap();
+
+        let uri = format!("/{}/alt.txt", USER_NAME);
+        let response = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            body(response).await,
+            format!("1 profile views for {}", USER_NAME)
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Paths of the app's routes, matched by the router and built by pages, middlewares and tests,
+//! so a route is spelled out once. The OpenAPI docs spell them the `{user_name}` way, see the
+//! tests keeping both in sync.
+
+// path segment of the user whose views a route is about
+const USER_NAME: &str = "/:user_name";
+
+pub const LANDING: &str = "/";
+pub const FAVICON: &str = "/favicon.ico";
+pub const ASSETS: &str = "/assets/*path";
+pub const HEALTHZ: &str = "/healthz";
+pub const READYZ: &str = "/readyz";
+pub const STATUS: &str = "/status";
+pub const METRICS: &str = "/metrics";
+pub const DOCS: &str = "/docs";
+pub const OPENAPI: &str = "/openapi.json";
+
+pub const COUNTER: &str = "/:user_name/counter.svg";
+pub const COUNTER_PNG: &str = "/:user_name/counter.png";
+pub const COUNTER_WEBP: &str = "/:user_name/counter.webp";
+/// Counter in the format the client asks for, svg, json or text.
+pub const COUNTER_NEGOTIATED: &str = "/:user_name/counter";
+pub const ALT_TEXT: &str = "/:user_name/alt.txt";
+pub const COUNT_JSON: &str = "/:user_name/count.json";
+pub const STREAK: &str = "/:user_name/streak.svg";
+pub const RECORD: &str = "/:user_name/record.svg";
+pub const DEBUG: &str = "/:user_name/debug";
+
+pub const TENANT_COUNTER: &str = "/t/:tenant/:user_name/counter.svg";
+pub const TENANT_USERS: &str = "/t/:tenant/users";
+
+pub const BUILDER: &str = "/builder";
+pub const BUILDER_PREVIEW: &str = "/builder/preview.svg";
+pub const PRIVACY: &str = "/privacy";
+
+pub const API_INCREMENTS: &str = "/api/increments";
+pub const API_COUNTS: &str = "/api/counts";
+pub const API_EXPERIMENT: &str = "/api/experiments/:user_name";
+pub const API_USER_EXPORT: &str = "/api/users/:user_name/export";
+pub const API_ONBOARD: &str = "/api/onboard";
+
+pub const ADMIN_EXPORT: &str = "/admin/export.csv";
+pub const ADMIN_USERS: &str = "/admin/users";
+pub const ADMIN_USER: &str = "/admin/users/:user_name";
+pub const ADMIN_USER_RESTORE: &str = "/admin/users/:user_name/restore";
+pub const ADMIN_USER_TOKEN: &str = "/admin/users/:user_name/token";
+pub const ADMIN_USER_RENAME: &str = "/admin/users/:user_name/rename";
+pub const ADMIN_USER_MERGE: &str = "/admin/users/:user_name/merge";
+pub const ADMIN_FLAGS: &str = "/admin/flags";
+pub const ADMIN_FLAG: &str = "/admin/flags/:user_name";
+pub const ADMIN_SAMPLES: &str = "/admin/samples";
+pub const ADMIN_USAGE: &str = "/admin/usage";
+pub const ADMIN_DEBUG: &str = "/admin/debug/:user_name";
+// static segments take precedence, so a user named `requests` can't be traced
+pub const ADMIN_DEBUG_REQUESTS: &str = "/admin/debug/requests";
+pub const ADMIN_CACHE: &str = "/admin/cache";
+pub const ADMIN_RELOAD_SECRETS: &str = "/admin/reload-secrets";
+
+/// Path of the user's counter badge, e.g. `/octocat/counter.svg`.
+pub fn counter(user_name: &str) -> String {
+    COUNTER.replacen(USER_NAME, &format!("/{}", user_name), 1)
+}
+
+/// Path of a tenant route, `path` being the route as served on the tenant's custom domain.
+pub fn tenant(tenant: &str, path: &str) -> String {
+    format!("/t/{}{}", tenant, path)
+}
+
+/// User whose counter badge the path is, in any image format; tenant users are given by the key
+/// they're stored under, `<tenant>/<user>`. `None` for paths of other routes.
+pub fn counter_badge_user(path: &str) -> Option<String> {
+    let user = [COUNTER, COUNTER_PNG, COUNTER_WEBP]
+        .iter()
+        .filter_map(|route| route.strip_prefix(USER_NAME))
+        .find_map(|suffix| path.strip_suffix(suffix))?
+        .strip_prefix('/')?;
+    let user = user.strip_prefix("t/").unwrap_or(user);
+    (!user.is_empty()).then(|| user.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use utoipa::OpenApi;
+
+    use super::*;
+    use crate::openapi::ApiDoc;
+    use pretty_assertions::assert_eq;
+
+    const ROUTES: [&str; 43] = [
+        LANDING,
+        FAVICON,
+        ASSETS,
+        HEALTHZ,
+        READYZ,
+        STATUS,
+        METRICS,
+        COUNTER,
+        COUNTER_PNG,
+        COUNTER_WEBP,
+        COUNTER_NEGOTIATED,
+        ALT_TEXT,
+        COUNT_JSON,
+        STREAK,
+        RECORD,
+        DEBUG,
+        TENANT_COUNTER,
+        TENANT_USERS,
+        BUILDER,
+        BUILDER_PREVIEW,
+        PRIVACY,
+        API_INCREMENTS,
+        API_COUNTS,
+        API_EXPERIMENT,
+        API_USER_EXPORT,
+        API_ONBOARD,
+        ADMIN_EXPORT,
+        ADMIN_USERS,
+        ADMIN_USER,
+        ADMIN_USER_RESTORE,
+        ADMIN_USER_TOKEN,
+        ADMIN_USER_RENAME,
+        ADMIN_USER_MERGE,
+        ADMIN_FLAGS,
+        ADMIN_FLAG,
+        ADMIN_SAMPLES,
+        ADMIN_USAGE,
+        ADMIN_DEBUG,
+        ADMIN_DEBUG_REQUESTS,
+        ADMIN_CACHE,
+        ADMIN_RELOAD_SECRETS,
+        // served by swagger ui, which documents neither itself nor the spec
+        DOCS,
+        OPENAPI,
+    ];
+
+    // `/:user_name/counter.svg` as `/{user_name}/counter.svg`
+    fn openapi_path(route: &str) -> String {
+        route
+            .split('/')
+            .map(|segment| match segment.strip_prefix([':', '*']) {
+                Some(param) => format!("{{{}}}", param),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[test]
+    fn it_documents_every_route() {
+        let docs = ApiDoc::openapi();
+        let undocumented: Vec<&str> = ROUTES
+            .iter()
+            .filter(|route| ![DOCS, OPENAPI].contains(route))
+            .filter(|route| !docs.paths.paths.contains_key(&openapi_path(route)))
+            .copied()
+            .collect();
+
+        assert_eq!(undocumented, Vec::<&str>::new());
+    }
+
+    #[test]
+    fn it_builds_paths() {
+        assert_eq!(counter("octocat"), "/octocat/counter.svg");
+        assert_eq!(
+            tenant("rustaceans", "/octocat/counter.svg"),
+            "/t/rustaceans/octocat/counter.svg"
+        );
+        assert_eq!(
+            counter_badge_user("/octocat/counter.webp"),
+            Some("octocat".to_string())
+        );
+        assert_eq!(
+            counter_badge_user("/t/rustaceans/octocat/counter.svg"),
+            Some("rustaceans/octocat".to_string())
+        );
+        assert_eq!(counter_badge_user("/octocat/count.json"), None);
+    }
+}
@@ -0,0 +1,20 @@
+use std::future::Future;
+use std::pin::Pin;
+
+pub type Task = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Runs the server's background tasks, e.g. flushing views or publishing view events. Building
+/// the state and the router never spawns anything; tasks are handed to the spawner of the
+/// `AppState`, so hosts embedding the router can run them on their own executor.
+pub trait Spawner: Send + Sync {
+    fn spawn(&self, task: Task);
+}
+
+/// Spawns tasks on the tokio runtime the server runs on.
+pub struct TokioSpawner;
+
+impl Spawner for TokioSpawner {
+    fn spawn(&self, task: Task) {
+        tokio::spawn(task);
+    }
+}
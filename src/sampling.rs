@@ -0,0 +1,196 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{
+    extract::Request,
+    extract::State as StateExtractor,
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::badge::ShieldsIoFetcher;
+use super::client_identity::{client_identity, SubnetPrefixes};
+use super::datastore::DatastoreOperations;
+use super::geo;
+use super::state::AppState;
+
+// samples kept, older ones are dropped first
+const SAMPLE_CAPACITY: usize = 10_000;
+
+/// Metadata of a sampled request; client addresses are only kept as a salted hash.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct RequestSample {
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    /// Path and query of the request
+    pub uri: String,
+    pub status: u16,
+    pub duration_ms: u64,
+    /// Hash of the client's address or subnet, salted per server start so it can't be reversed
+    pub client: Option<String>,
+    pub user_agent: Option<String>,
+    pub referrer: Option<String>,
+}
+
+/// Records the metadata of a fraction of requests, kept in memory apart from the counts.
+pub struct RequestSampler {
+    rate: f64,
+    requests: AtomicU64,
+    client_salt: RandomState,
+    client_prefixes: SubnetPrefixes,
+    samples: Mutex<VecDeque<RequestSample>>,
+}
+
+impl RequestSampler {
+    pub fn new(rate: f64, client_prefixes: SubnetPrefixes) -> RequestSampler {
+        RequestSampler {
+            rate,
+            requests: AtomicU64::new(0),
+            client_salt: RandomState::new(),
+            client_prefixes,
+            samples: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Fraction of requests sampled, between 0 and 1.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    // spreads samples evenly, e.g. every 100th request at a rate of 1%
+    fn should_sample(&self) -> bool {
+        let request = self.requests.fetch_add(1, Ordering::Relaxed);
+        (((request + 1) as f64) * self.rate).floor() > ((request as f64) * self.rate).floor()
+    }
+
+    fn record(&self, sample: RequestSample) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() >= SAMPLE_CAPACITY {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// Most recent samples, newest first.
+    pub fn samples(&self, limit: usize) -> Vec<RequestSample> {
+        let samples = self.samples.lock().unwrap();
+        samples.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Samples of requests for the user's routes, e.g. views of their badges, newest first.
+    pub fn user_samples(&self, user_name: &str) -> Vec<RequestSample> {
+        let prefix = format!("/{}/", user_name);
+        let samples = self.samples.lock().unwrap();
+        samples
+            .iter()
+            .rev()
+            .filter(|sample| sample.uri.starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn client(&self, headers: &HeaderMap) -> Option<String> {
+        client_identity(headers, self.client_prefixes)
+            .map(|client| format!("{:016x}", self.client_salt.hash_one(client)))
+    }
+}
+
+/// Samples requests when `ANALYTICS_SAMPLE_RATE` is set, except those of viewers from the
+/// `BLOCKED_COUNTRIES`.
+pub async fn sample_requests<T, F>(
+    StateExtractor(state): StateExtractor<Arc<AppState<T, F>>>,
+    request: Request,
+    next: Next,
+) -> Response
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    let Some(sampler) = state
+        .sampler
+        .as_ref()
+        .filter(|_| !geo::is_blocked(&state.config.blocked_countries, request.headers()))
+        .filter(|sampler| sampler.should_sample())
+    else {
+        return next.run(request).await;
+    };
+
+    let headers = request.headers();
+    let header = |name: header::HeaderName| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let mut sample = RequestSample {
+        timestamp: Utc::now(),
+        method: request.method().to_string(),
+        uri: request.uri().to_string(),
+        status: 0,
+        duration_ms: 0,
+        client: sampler.client(headers),
+        user_agent: header(header::USER_AGENT),
+        referrer: header(header::REFERER),
+    };
+
+    let started_at = Instant::now();
+    let response = next.run(request).await;
+    sample.status = response.status().as_u16();
+    sample.duration_ms = started_at.elapsed().as_millis() as u64;
+    sampler.record(sample);
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn sample(uri: &str) -> RequestSample {
+        RequestSample {
+            timestamp: Utc::now(),
+            method: "GET".to_string(),
+            uri: uri.to_string(),
+            status: 200,
+            duration_ms: 1,
+            client: None,
+            user_agent: None,
+            referrer: None,
+        }
+    }
+
+    #[test]
+    fn it_samples_requests_at_rate() {
+        let sampler = RequestSampler::new(0.01, SubnetPrefixes::default());
+        let sampled = (0..1000).filter(|_| sampler.should_sample()).count();
+        assert_eq!(sampled, 10);
+
+        let sampler = RequestSampler::new(1.0, SubnetPrefixes::default());
+        assert!((0..10).all(|_| sampler.should_sample()));
+    }
+
+    #[test]
+    fn it_keeps_most_recent_samples() {
+        let sampler = RequestSampler::new(1.0, SubnetPrefixes::default());
+        for request in 0..SAMPLE_CAPACITY + 2 {
+            sampler.record(sample(&format!("/{}", request)));
+        }
+
+        let uris: Vec<String> = sampler
+            .samples(2)
+            .into_iter()
+            .map(|sample| sample.uri)
+            .collect();
+        assert_eq!(uris, vec!["/10001", "/10000"]);
+        assert_eq!(sampler.samples(usize::MAX).len(), SAMPLE_CAPACITY);
+    }
+}
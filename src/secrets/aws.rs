@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use aws_config::timeout::TimeoutConfig;
+use aws_config::{AppName, BehaviorVersion};
+use aws_sdk_secretsmanager::error::DisplayErrorContext;
+use aws_sdk_secretsmanager::Client;
+use tokio::sync::OnceCell;
+
+// secrets are fetched at startup and on reload only, a slow api mustn't hold them up for long
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads the secrets from a json secret of AWS Secrets Manager, e.g.
+/// `{"XATA_API_KEY": "...", "ADMIN_TOKEN": "..."}`. Region and credentials are resolved like
+/// by the AWS cli: from the `AWS_*` variables, the shared config files, SSO, or the role of the
+/// Lambda function, ECS task or EC2 instance, refreshed as they expire.
+pub struct SecretsManager {
+    // resolving the config may ask the instance metadata service, so it waits for the first fetch
+    client: OnceCell<Client>,
+    /// `AWS_SECRET_ID`, name or arn of the secret
+    secret_id: String,
+}
+
+impl SecretsManager {
+    pub fn from_env(var: &impl Fn(&str) -> Option<String>) -> SecretsManager {
+        SecretsManager {
+            client: OnceCell::new(),
+            secret_id: var("AWS_SECRET_ID").unwrap_or_default(),
+        }
+    }
+
+    async fn client(&self) -> &Client {
+        self.client
+            .get_or_init(|| async {
+                let timeouts = TimeoutConfig::builder().operation_timeout(TIMEOUT).build();
+                let config = aws_config::defaults(BehaviorVersion::latest())
+                    // sent along with the sdk's user agent, like `http_client::USER_AGENT`
+                    .app_name(AppName::new("profile-views-counter").expect("valid app name"))
+                    .timeout_config(timeouts)
+                    .load()
+                    .await;
+                Client::new(&config)
+            })
+            .await
+    }
+
+    pub async fn fetch(&self) -> Result<HashMap<String, String>, Error> {
+        let response = self
+            .client()
+            .await
+            .get_secret_value()
+            .secret_id(&self.secret_id)
+            .send()
+            .await
+            // errors name the error type, e.g. `ResourceNotFoundException`, but no secrets
+            .map_err(|err| {
+                anyhow!(
+                    "secrets manager failed to get `{}`: {}",
+                    self.secret_id,
+                    DisplayErrorContext(err)
+                )
+            })?;
+
+        let secret = response
+            .secret_string()
+            .ok_or_else(|| anyhow!("`{}` is not a string secret", self.secret_id))?;
+        serde_json::from_str(secret)
+            .map_err(|_| anyhow!("`{}` is not a json object of strings", self.secret_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_secretsmanager::config::{Credentials, Region};
+    use wiremock::matchers::{body_json, header, header_regex, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_reads_json_secrets() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("x-amz-target", "secretsmanager.GetSecretValue"))
+            .and(header_regex(
+                "authorization",
+                "^AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/[0-9]{8}/us-east-1/secretsmanager/aws4_request, ",
+            ))
+            .and(body_json(
+                serde_json::json!({ "SecretId": "profile-views-counter" }),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(
+                    r#"{"Name": "profile-views-counter", "SecretString": "{\"XATA_API_KEY\": \"key\"}"}"#,
+                    "application/x-amz-json-1.1",
+                ),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        let config = aws_sdk_secretsmanager::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url(server.uri())
+            .credentials_provider(Credentials::new(
+                "AKIDEXAMPLE",
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                None,
+                None,
+                "test",
+            ))
+            .build();
+        let vars = HashMap::from([("AWS_SECRET_ID", "profile-views-counter")]);
+        let secrets_manager =
+            SecretsManager::from_env(&|name| vars.get(name).map(|value| value.to_string()));
+        secrets_manager
+            .client
+            .set(Client::from_conf(config))
+            .unwrap();
+
+        assert_eq!(
+            secrets_manager.fetch().await.unwrap(),
+            HashMap::from([("XATA_API_KEY".to_string(), "key".to_string())])
+        );
+    }
+}
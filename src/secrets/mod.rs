@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use anyhow::Error;
+use tokio::time;
+
+#[cfg(feature = "aws-secrets-manager")]
+mod aws;
+#[cfg(feature = "vault")]
+mod vault;
+
+pub const XATA_API_KEY: &str = "XATA_API_KEY";
+pub const ADMIN_TOKEN: &str = "ADMIN_TOKEN";
+pub const API_KEY: &str = "API_KEY";
+pub const OWNER_TOKEN_SECRET: &str = "OWNER_TOKEN_SECRET";
+pub const GITHUB_TOKEN: &str = "GITHUB_TOKEN";
+
+/// Variables holding secrets, each of which may be read from a file instead.
+pub const SECRETS: [&str; 5] = [
+    XATA_API_KEY,
+    ADMIN_TOKEN,
+    API_KEY,
+    OWNER_TOKEN_SECRET,
+    GITHUB_TOKEN,
+];
+
+/// Secrets read from their variable or from the file named by `<NAME>_FILE`, e.g. a mounted
+/// Docker or Kubernetes secret, for environments where keys must not sit in variables. Secrets
+/// set in neither are resolved by the `SECRETS_PROVIDER`, if any. Secrets from files are read
+/// again by [`Secrets::reload`] and those from the provider fetched again by [`Secrets::fetch`],
+/// so rotated secrets apply without a restart.
+pub struct Secrets {
+    files: HashMap<&'static str, PathBuf>,
+    provider: Option<Provider>,
+    // secrets set in neither a variable nor a file, resolved by the provider
+    provided: Vec<&'static str>,
+    values: RwLock<HashMap<&'static str, String>>,
+}
+
+impl Secrets {
+    pub fn from_env() -> Secrets {
+        Secrets::new(|name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn new(var: impl Fn(&str) -> Option<String>) -> Secrets {
+        let var = |name: &str| var(name).filter(|value| !value.is_empty());
+        let mut files = HashMap::new();
+        let mut provided = Vec::new();
+        let mut values = HashMap::new();
+        for name in SECRETS {
+            match (var(&file_variable(name)), var(name)) {
+                (Some(path), _) => {
+                    files.insert(name, PathBuf::from(path));
+                }
+                (None, Some(value)) => {
+                    values.insert(name, value);
+                }
+                (None, None) => provided.push(name),
+            }
+        }
+
+        let secrets = Secrets {
+            files,
+            provider: Provider::from_env(&var),
+            provided,
+            values: RwLock::new(values),
+        };
+        secrets.reload();
+        secrets
+    }
+
+    /// Current value of the secret, `None` when it's not configured.
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values.read().unwrap().get(name).cloned()
+    }
+
+    /// Reads the secrets kept in files again, returning the names of those which changed. A file
+    /// which can't be read keeps its secret at the previous value.
+    pub fn reload(&self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        for (name, path) in &self.files {
+            let value = match read_secret_file(path) {
+                Ok(value) => value,
+                Err(err) => {
+                    tracing::error!("failed to read {} from `{}`: {}", name, path.display(), err);
+                    continue;
+                }
+            };
+
+            if self.update(name, value) {
+                changed.push(*name);
+            }
+        }
+        changed
+    }
+
+    /// Fetches the secrets kept by the provider, returning the names of those which changed.
+    /// Nothing changes when the provider can't be reached.
+    pub async fn fetch(&self) -> Result<Vec<&'static str>, Error> {
+        let Some(provider) = &self.provider else {
+            return Ok(Vec::new());
+        };
+        let mut fetched = provider.fetch().await?;
+
+        let mut changed = Vec::new();
+        for name in &self.provided {
+            let value = fetched.remove(*name).filter(|value| !value.is_empty());
+            if self.update(name, value) {
+                changed.push(*name);
+            }
+        }
+        Ok(changed)
+    }
+
+    fn update(&self, name: &'static str, value: Option<String>) -> bool {
+        let mut values = self.values.write().unwrap();
+        if values.get(name) == value.as_ref() {
+            return false;
+        }
+        match value {
+            Some(value) => values.insert(name, value),
+            None => values.remove(name),
+        };
+        true
+    }
+
+    /// Reads the secrets kept in files and fetches those kept by the provider again, returning
+    /// the names of those which changed. Secrets from files are reloaded even when the provider
+    /// can't be reached.
+    pub async fn refresh(&self) -> Result<Vec<&'static str>, Error> {
+        let mut changed = self.reload();
+        let fetched = self.fetch().await;
+        for name in changed.iter().chain(fetched.iter().flatten()) {
+            tracing::info!("reloaded {}", name);
+        }
+        changed.extend(fetched?);
+        Ok(changed)
+    }
+
+    /// Refreshes the secrets at regular intervals.
+    pub async fn reload_loop(&self, interval: Duration) {
+        let mut interval = time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.refresh().await {
+                tracing::error!("failed to fetch secrets from the provider: {}", err);
+            }
+        }
+    }
+}
+
+/// Secret manager resolving the secrets set in neither a variable nor a file, chosen by
+/// `SECRETS_PROVIDER`. Each needs a build with the feature of the same name.
+enum Provider {
+    /// `vault`, reads a KV secret from HashiCorp Vault.
+    #[cfg(feature = "vault")]
+    Vault(vault::Vault),
+    /// `aws-secrets-manager`, reads a json secret from AWS Secrets Manager.
+    #[cfg(feature = "aws-secrets-manager")]
+    AwsSecretsManager(aws::SecretsManager),
+}
+
+impl Provider {
+    #[cfg_attr(
+        not(any(feature = "vault", feature = "aws-secrets-manager")),
+        allow(unused_variables)
+    )]
+    fn from_env(var: &impl Fn(&str) -> Option<String>) -> Option<Provider> {
+        // unsupported providers are reported by the config check
+        match var("SECRETS_PROVIDER")?.as_str() {
+            #[cfg(feature = "vault")]
+            "vault" => Some(Provider::Vault(vault::Vault::from_env(var))),
+            #[cfg(feature = "aws-secrets-manager")]
+            "aws-secrets-manager" => Some(Provider::AwsSecretsManager(
+                aws::SecretsManager::from_env(var),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Every secret the provider keeps, by name.
+    async fn fetch(&self) -> Result<HashMap<String, String>, Error> {
+        match *self {
+            #[cfg(feature = "vault")]
+            Provider::Vault(ref vault) => vault.fetch().await,
+            #[cfg(feature = "aws-secrets-manager")]
+            Provider::AwsSecretsManager(ref secrets_manager) => secrets_manager.fetch().await,
+        }
+    }
+}
+
+/// Variable naming the file a secret is read from, e.g. `XATA_API_KEY_FILE`.
+pub fn file_variable(name: &str) -> String {
+    format!("{}_FILE", name)
+}
+
+/// Contents of a secret file without the trailing newline editors add, `None` when empty.
+pub fn read_secret_file(path: &std::path::Path) -> std::io::Result<Option<String>> {
+    let value = std::fs::read_to_string(path)?;
+    let value = value.trim_end_matches(['\r', '\n']);
+    Ok(Some(value.to_string()).filter(|value| !value.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_reads_secrets_from_variables_and_files() {
+        let path = std::env::temp_dir().join(format!("admin-token-{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let vars = HashMap::from([
+            ("API_KEY".to_string(), "key".to_string()),
+            ("ADMIN_TOKEN_FILE".to_string(), path.display().to_string()),
+        ]);
+        let secrets = Secrets::new(|name| vars.get(name).cloned());
+
+        assert_eq!(secrets.get(API_KEY), Some("key".to_string()));
+        assert_eq!(secrets.get(ADMIN_TOKEN), Some("first".to_string()));
+        assert_eq!(secrets.get(XATA_API_KEY), None);
+
+        std::fs::write(&path, "second").unwrap();
+        assert_eq!(secrets.reload(), vec![ADMIN_TOKEN]);
+        assert_eq!(secrets.get(ADMIN_TOKEN), Some("second".to_string()));
+        assert_eq!(secrets.reload(), Vec::<&str>::new());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
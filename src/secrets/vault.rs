@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use serde_json::Value;
+use vaultrs::client::{VaultClient, VaultClientSettingsBuilder};
+use vaultrs::{kv1, kv2};
+
+// secrets are fetched at startup and on reload only, a slow vault mustn't hold them up for long
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads the secrets from a KV secret of HashiCorp Vault, each stored under the name of its
+/// variable, e.g. `XATA_API_KEY`.
+pub struct Vault {
+    /// `VAULT_ADDR`, e.g. `https://vault.example.dev:8200`
+    address: String,
+    /// `VAULT_TOKEN`
+    token: String,
+    /// `VAULT_SECRET_PATH`, the api path of the secret without `/v1`, e.g.
+    /// `secret/data/profile-views-counter` for the KV v2 engine mounted at `secret`, or
+    /// `kv/profile-views-counter` for a KV v1 engine mounted at `kv`
+    path: String,
+}
+
+impl Vault {
+    pub fn from_env(var: &impl Fn(&str) -> Option<String>) -> Vault {
+        Vault {
+            address: var("VAULT_ADDR").unwrap_or_default(),
+            token: var("VAULT_TOKEN").unwrap_or_default(),
+            path: var("VAULT_SECRET_PATH")
+                .unwrap_or_default()
+                .trim_matches('/')
+                .to_string(),
+        }
+    }
+
+    fn client(&self) -> Result<VaultClient, Error> {
+        // the settings builder panics on invalid addresses
+        reqwest::Url::parse(&self.address)
+            .map_err(|err| anyhow!("invalid vault address `{}`: {}", self.address, err))?;
+        let settings = VaultClientSettingsBuilder::default()
+            .address(&self.address)
+            .token(&self.token)
+            .timeout(Some(TIMEOUT))
+            .build()?;
+        Ok(VaultClient::new(settings)?)
+    }
+
+    pub async fn fetch(&self) -> Result<HashMap<String, String>, Error> {
+        let client = self.client()?;
+        let (mount, path) = self
+            .path
+            .split_once('/')
+            .ok_or_else(|| anyhow!("`{}` names no secret of a mount", self.path))?;
+        // the KV v2 engine serves secrets under `data/` of its mount
+        let secret: HashMap<String, Value> = match path.strip_prefix("data/") {
+            Some(path) => kv2::read(&client, mount, path).await,
+            None => kv1::get(&client, mount, path).await,
+        }
+        // the error alone only tells the request failed, its sources tell why
+        .map_err(|err| anyhow!("vault failed to read `{}`: {:#}", self.path, Error::new(err)))?;
+
+        Ok(secret
+            .into_iter()
+            .filter_map(|(name, value)| match value {
+                Value::String(value) => Some((name, value)),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn vault(server: &MockServer, secret_path: &str) -> Vault {
+        let vars = HashMap::from([
+            ("VAULT_ADDR", server.uri()),
+            ("VAULT_TOKEN", "root".to_string()),
+            ("VAULT_SECRET_PATH", secret_path.to_string()),
+        ]);
+        Vault::from_env(&|name| vars.get(name).cloned())
+    }
+
+    #[tokio::test]
+    async fn it_reads_kv_v2_secrets() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/secret/data/profile-views-counter"))
+            .and(header("x-vault-token", "root"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                r#"{"request_id": "1", "lease_id": "", "renewable": false, "lease_duration": 0, "data": {"data": {"XATA_API_KEY": "key", "retries": 3}, "metadata": {"created_time": "2024-01-01T00:00:00Z", "custom_metadata": null, "deletion_time": "", "destroyed": false, "version": 2}}, "wrap_info": null, "warnings": null, "auth": null}"#,
+                "application/json",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let vault = vault(&server, "/secret/data/profile-views-counter");
+
+        assert_eq!(
+            vault.fetch().await.unwrap(),
+            HashMap::from([("XATA_API_KEY".to_string(), "key".to_string())])
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reads_kv_v1_secrets() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/kv/profile-views-counter"))
+            .and(header("x-vault-token", "root"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                r#"{"request_id": "1", "lease_id": "", "renewable": false, "lease_duration": 0, "data": {"XATA_API_KEY": "key"}, "wrap_info": null, "warnings": null, "auth": null}"#,
+                "application/json",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let vault = vault(&server, "kv/profile-views-counter");
+
+        assert_eq!(
+            vault.fetch().await.unwrap(),
+            HashMap::from([("XATA_API_KEY".to_string(), "key".to_string())])
+        );
+    }
+}
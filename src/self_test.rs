@@ -0,0 +1,120 @@
+use std::fmt::Display;
+
+use anyhow::{anyhow, Context, Error};
+
+use super::badge::{Shields, ShieldsIoFetcher, ShieldsIoParams};
+use super::cache::CacheStores;
+use super::config::{self, Config};
+use super::datastore::{DatastoreError, DatastoreOperations, Xata};
+
+// underscores aren't allowed in github user names, so the scratch record never clashes with a user
+const SCRATCH_USER: &str = "__self_test__";
+
+/// Checks config, datastore and badge service the way a deploy would use them, reporting every
+/// step on stdout. Fails on the first broken step.
+pub async fn run() -> Result<(), Error> {
+    let config = Config::from_env();
+
+    report(
+        "config",
+        check_env().context("fix the environment variables listed above"),
+    )?;
+    report(
+        "secrets",
+        config.secrets.fetch().await.map(drop).context(
+            "check that the SECRETS_PROVIDER is reachable and its credentials may read the secret",
+        ),
+    )?;
+
+    let db = report(
+        "datastore client",
+        Xata::new(&config).context("set XATA_DB_ENDPOINT, XATA_API_KEY and XATA_TABLE_NAME"),
+    )?;
+    report(
+        "datastore transaction",
+        check_datastore(&db).await.context(
+            "check that XATA_DB_ENDPOINT points to the branch transaction api and the api key may write to XATA_TABLE_NAME",
+        ),
+    )?;
+
+    let badge = report(
+        "badge client",
+        Shields::new(&config, CacheStores::new(&config))
+            .context("check HTTPS_PROXY and the SHIELDS_* settings"),
+    )?;
+    report(
+        "badge fetch",
+        badge
+            .fetch(&ShieldsIoParams::new("views", "blue", "flat"), 1)
+            .await
+            .context("check that shields.io is reachable from this host"),
+    )?;
+
+    println!("self test passed");
+    Ok(())
+}
+
+fn report<T>(step: &str, result: Result<T, Error>) -> Result<T, Error> {
+    match &result {
+        Ok(_) => println!("ok    {}", step),
+        Err(err) => println!("FAIL  {}: {:#}", step, err),
+    }
+    result
+}
+
+fn check_env() -> Result<(), Error> {
+    let problems: Vec<String> = config::check_env(true)
+        .iter()
+        .map(|problem| problem.to_string())
+        .collect();
+
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(anyhow!(problems.join(", "))),
+    }
+}
+
+/// Counts a view on the scratch record. The record is kept deleted, so the count is reverted by
+/// the datastore and the record never shows up in exports.
+async fn check_datastore(db: &impl DatastoreOperations) -> Result<(), Error> {
+    match db.get_latest_views(SCRATCH_USER).await {
+        Err(DatastoreError::UserDeleted(_)) => Ok(()),
+        Ok(_) => delete_scratch_user(db).await,
+        Err(DatastoreError::UserNotFound(_)) => {
+            db.onboard_user(SCRATCH_USER).await.map_err(context)?;
+            delete_scratch_user(db).await
+        }
+        Err(err) => Err(context(err)),
+    }
+}
+
+async fn delete_scratch_user(db: &impl DatastoreOperations) -> Result<(), Error> {
+    db.delete_user(SCRATCH_USER).await.map_err(context)
+}
+
+fn context(err: impl Display) -> Error {
+    anyhow!("scratch record `{}`: {}", SCRATCH_USER, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::Memory;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_leaves_scratch_record_deleted() {
+        let db = Memory::new();
+
+        check_datastore(&db).await.unwrap();
+        check_datastore(&db).await.unwrap();
+
+        assert_eq!(
+            db.list_users(None, 10).await.unwrap().users[0]
+                .deleted_at
+                .is_some(),
+            true
+        );
+        assert_eq!(db.scan(None, 10).await.unwrap().users, vec![]);
+    }
+}
@@ -0,0 +1,9 @@
+use axum::async_trait;
+
+/// Winds down a part of the state once the server stopped taking requests, e.g. writing views
+/// still held in memory to the datastore or publishing queued events. Parts holding nothing
+/// worth finishing keep the default, which does nothing.
+#[async_trait]
+pub trait Shutdown: Send + Sync {
+    async fn shutdown(&self) {}
+}
@@ -1,9 +1,55 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+
+use super::anomaly::AnomalyDetector;
 use super::badge::ShieldsIoFetcher;
+use super::cache::CacheStores;
+use super::config::Config;
 use super::datastore::DatastoreOperations;
+use super::events::ViewEvents;
+use super::experiment::Experiments;
+use super::first_seen::FirstSeen;
+use super::github::GitHub;
+use super::history::ViewHistory;
+#[cfg(feature = "jwt")]
+use super::jwt::JwtValidator;
+use super::overload::ConcurrencyLimit;
+use super::quota::{DailyQuota, RequestBudget};
+use super::raster::Rasterizer;
+use super::runtime::Spawner;
+use super::sampling::RequestSampler;
+use super::shutdown::Shutdown;
+use super::status::ResponseWindow;
+use super::supervisor::Supervisor;
+use super::tenant::Tenants;
+use super::user_trace::UserTraces;
 
 pub struct AppState<T: DatastoreOperations, F: ShieldsIoFetcher> {
     pub db: T,
     pub badge: F,
+    pub config: Config,
+    pub caches: CacheStores,
+    pub spawner: Arc<dyn Spawner>,
+    pub supervisor: Supervisor,
+    pub responses: ResponseWindow,
+    pub raster: Arc<Rasterizer>,
+    pub concurrency: ConcurrencyLimit,
+    pub quota: Option<DailyQuota>,
+    pub budget: Option<RequestBudget>,
+    pub anomalies: Option<AnomalyDetector>,
+    pub tenants: Tenants,
+    pub events: Option<ViewEvents>,
+    pub history: ViewHistory,
+    pub first_seen: FirstSeen,
+    pub sampler: Option<RequestSampler>,
+    pub traces: UserTraces,
+    pub experiments: Experiments,
+    /// Tells who holds the GitHub OAuth tokens claiming user names with `ONBOARDING=explicit`,
+    /// and whether user names exist with `USER_VERIFICATION=github`
+    pub github: Option<GitHub>,
+    #[cfg(feature = "jwt")]
+    pub jwt: Option<JwtValidator>,
 }
 
 impl<T, F> AppState<T, F>
@@ -11,7 +57,62 @@ where
     T: DatastoreOperations,
     F: ShieldsIoFetcher,
 {
-    pub fn new(db: T, badge: F) -> AppState<T, F> {
-        AppState { db, badge }
+    /// State of the app; background tasks are left to the spawner, nothing is spawned here.
+    pub fn new(
+        db: T,
+        badge: F,
+        config: Config,
+        caches: CacheStores,
+        spawner: Arc<dyn Spawner>,
+    ) -> AppState<T, F> {
+        AppState {
+            db,
+            badge,
+            raster: Arc::new(Rasterizer::new(config.raster_font_dir.as_deref())),
+            concurrency: ConcurrencyLimit::new(&config),
+            quota: config.daily_view_quota.map(DailyQuota::new),
+            budget: config.request_budget.as_ref().map(RequestBudget::new),
+            anomalies: config.anomaly.clone().map(AnomalyDetector::new),
+            tenants: Tenants::new(&config.tenants),
+            events: config
+                .events
+                .as_ref()
+                .map(|events| ViewEvents::new(events, config.client_prefixes)),
+            history: ViewHistory::new(),
+            first_seen: FirstSeen::new(),
+            sampler: config
+                .analytics_sample_rate
+                .map(|rate| RequestSampler::new(rate, config.client_prefixes)),
+            traces: UserTraces::new(),
+            experiments: Experiments::new(caches.shared.clone(), spawner.clone()),
+            github: (config.explicit_onboarding || config.verify_github_users)
+                .then(|| GitHub::new(&config)),
+            #[cfg(feature = "jwt")]
+            jwt: config.jwt.as_ref().map(JwtValidator::new),
+            supervisor: Supervisor::new(spawner.clone()),
+            responses: ResponseWindow::new(),
+            config,
+            caches,
+            spawner,
+        }
+    }
+}
+
+#[async_trait]
+impl<T, F> Shutdown for AppState<T, F>
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    /// Winds down the parts holding work in memory once the server stopped, before the
+    /// process exits and drops them.
+    async fn shutdown(&self) {
+        tracing::info!("shutting down state");
+        let events = async {
+            if let Some(events) = &self.events {
+                events.shutdown().await;
+            }
+        };
+        tokio::join!(self.db.shutdown(), self.badge.shutdown(), events);
     }
 }
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Request, State as StateExtractor},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::badge::ShieldsIoFetcher;
+use super::datastore::DatastoreOperations;
+use super::pages::escape_html;
+use super::state::AppState;
+
+// minutes of responses the error rate is computed over
+const ERROR_RATE_WINDOW_MINUTES: i64 = 5;
+// share of server errors past which the instance reports as degraded
+const DEGRADED_ERROR_RATE: f64 = 0.05;
+
+// outcome of the latest request to each upstream, by upstream name
+static UPSTREAMS: Mutex<BTreeMap<&'static str, UpstreamStatus>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct UpstreamStatus {
+    /// Whether the latest request got a response other than a server error
+    pub reachable: bool,
+    /// When the latest request was answered or failed
+    pub checked_at: DateTime<Utc>,
+    /// Why the latest request failed
+    pub error: Option<String>,
+}
+
+/// Records the outcome of a request to an upstream for the status page. Upstreams are never
+/// probed by the status page itself, so it can't be used to flood them.
+pub fn record_upstream(upstream: &'static str, result: &Result<reqwest::Response, reqwest::Error>) {
+    let error = match result {
+        Ok(response) if response.status().is_server_error() => {
+            Some(format!("status code {}", response.status()))
+        }
+        Ok(_) => None,
+        // the url isn't shown, it may point to a private endpoint
+        Err(err) if err.is_timeout() => Some("timed out".to_string()),
+        Err(err) if err.is_connect() => Some("failed to connect".to_string()),
+        Err(_) => Some("request failed".to_string()),
+    };
+    let status = UpstreamStatus {
+        reachable: error.is_none(),
+        checked_at: Utc::now(),
+        error,
+    };
+    UPSTREAMS.lock().unwrap().insert(upstream, status);
+}
+
+fn upstream(upstream: &str) -> Option<UpstreamStatus> {
+    UPSTREAMS.lock().unwrap().get(upstream).cloned()
+}
+
+/// Responses served in the last minutes, by the minute they were served in.
+#[derive(Default)]
+pub struct ResponseWindow {
+    // minute since the epoch, responses and server errors in it; oldest first
+    minutes: Mutex<VecDeque<(i64, u64, u64)>>,
+}
+
+impl ResponseWindow {
+    pub fn new() -> ResponseWindow {
+        ResponseWindow::default()
+    }
+
+    fn record(&self, now: DateTime<Utc>, server_error: bool) {
+        let minute = now.timestamp() / 60;
+        let mut minutes = self.minutes.lock().unwrap();
+        match minutes.back_mut() {
+            Some((last, responses, errors)) if *last == minute => {
+                *responses += 1;
+                *errors += server_error as u64;
+            }
+            _ => minutes.push_back((minute, 1, server_error as u64)),
+        }
+        while minutes
+            .front()
+            .is_some_and(|(first, _, _)| *first <= minute - ERROR_RATE_WINDOW_MINUTES)
+        {
+            minutes.pop_front();
+        }
+    }
+
+    fn errors(&self, now: DateTime<Utc>) -> ErrorRate {
+        let since = now.timestamp() / 60 - ERROR_RATE_WINDOW_MINUTES;
+        let minutes = self.minutes.lock().unwrap();
+        let (responses, server_errors) = minutes
+            .iter()
+            .filter(|(minute, _, _)| *minute > since)
+            .fold(
+                (0, 0),
+                |(responses, errors), (_, minute_responses, minute_errors)| {
+                    (responses + minute_responses, errors + minute_errors)
+                },
+            );
+
+        ErrorRate {
+            window_minutes: ERROR_RATE_WINDOW_MINUTES as u64,
+            responses,
+            server_errors,
+            rate: match responses {
+                0 => 0.0,
+                responses => server_errors as f64 / responses as f64,
+            },
+        }
+    }
+}
+
+/// Counts responses and server errors for the error rate of the status page.
+pub async fn track_responses<T, F>(
+    StateExtractor(state): StateExtractor<Arc<AppState<T, F>>>,
+    request: Request,
+    next: Next,
+) -> Response
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    let response = next.run(request).await;
+    state
+        .responses
+        .record(Utc::now(), response.status().is_server_error());
+    response
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Status {
+    /// Whether an upstream is unreachable, a background task crashed or many requests fail
+    pub degraded: bool,
+    /// Latest request to xata, `null` until the first one
+    pub datastore: Option<UpstreamStatus>,
+    /// Latest request to shields.io, `null` until the first one
+    pub shields: Option<UpstreamStatus>,
+    /// Badge templates cached by the instance, `null` for fetchers without a cache
+    pub cached_templates: Option<usize>,
+    /// Views served but yet to be written to the datastore
+    pub pending_views: u64,
+    /// View events waiting to be published
+    pub queued_events: usize,
+    /// Whether the background tasks run, see `/readyz`
+    pub tasks_ready: bool,
+    pub errors: ErrorRate,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorRate {
+    pub window_minutes: u64,
+    /// Responses served in the window
+    pub responses: u64,
+    /// Responses with a 5xx status in the window
+    pub server_errors: u64,
+    /// Share of server errors among the responses, from 0 to 1
+    pub rate: f64,
+}
+
+/// Summary of the instance's health for operators, as html or, when asked for in the `Accept`
+/// header, json.
+#[utoipa::path(
+    get,
+    path = "/status",
+    params(("Accept" = Option<String>, Header, description = "`application/json` for json, html otherwise")),
+    responses(
+        (status = 200, description = "Status page", content_type = "text/html"),
+        (status = 200, description = "Status summary", content_type = "application/json", body = Status),
+    )
+)]
+pub async fn status_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    headers: HeaderMap,
+) -> Response {
+    let errors = state.responses.errors(Utc::now());
+    let datastore = upstream("xata");
+    let shields = upstream("shields");
+    let tasks_ready = state.supervisor.readiness().ready;
+    let status = Status {
+        degraded: [&datastore, &shields].iter().any(|upstream| {
+            upstream
+                .as_ref()
+                .is_some_and(|upstream| !upstream.reachable)
+        }) || !tasks_ready
+            || errors.rate > DEGRADED_ERROR_RATE,
+        datastore,
+        shields,
+        cached_templates: state.badge.cache_stats().await.map(|stats| stats.size),
+        pending_views: state.db.pending_views().await,
+        queued_events: state.events.as_ref().map_or(0, |events| events.backlog()),
+        tasks_ready,
+        errors,
+    };
+
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    match wants_json {
+        true => Json(status).into_response(),
+        false => Html(render(&status)).into_response(),
+    }
+}
+
+fn render(status: &Status) -> String {
+    let upstream = |upstream: &Option<UpstreamStatus>| match upstream {
+        None => "no requests yet".to_string(),
+        Some(UpstreamStatus {
+            reachable: true,
+            checked_at,
+            ..
+        }) => format!("reachable as of {}", checked_at.format("%F %T UTC")),
+        Some(UpstreamStatus {
+            checked_at, error, ..
+        }) => format!(
+            "unreachable as of {}: {}",
+            checked_at.format("%F %T UTC"),
+            error.as_deref().unwrap_or("unknown reason")
+        ),
+    };
+
+    include_str!("pages/status.html")
+        .replace(
+            "{summary}",
+            match status.degraded {
+                true => "Degraded",
+                false => "Operational",
+            },
+        )
+        .replace("{datastore}", &escape_html(&upstream(&status.datastore)))
+        .replace("{shields}", &escape_html(&upstream(&status.shields)))
+        .replace(
+            "{cached_templates}",
+            &status
+                .cached_templates
+                .map_or("no cache".to_string(), |size| size.to_string()),
+        )
+        .replace("{pending_views}", &status.pending_views.to_string())
+        .replace("{queued_events}", &status.queued_events.to_string())
+        .replace(
+            "{tasks}",
+            match status.tasks_ready {
+                true => "running",
+                false => "a task crashed and waits to be restarted",
+            },
+        )
+        .replace(
+            "{errors}",
+            &format!(
+                "{:.1}% of {} responses in the last {} minutes",
+                status.errors.rate * 100.0,
+                status.errors.responses,
+                status.errors.window_minutes
+            ),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_computes_the_error_rate_over_recent_minutes() {
+        let window = ResponseWindow::new();
+        let now: DateTime<Utc> = "2024-05-01T12:00:30Z".parse().unwrap();
+        let old = now - chrono::Duration::minutes(ERROR_RATE_WINDOW_MINUTES);
+        window.record(old, true);
+        window.record(now, false);
+        window.record(now, false);
+        window.record(now, false);
+        window.record(now, true);
+
+        let errors = window.errors(now);
+        assert_eq!(
+            (errors.responses, errors.server_errors, errors.rate),
+            (4, 1, 0.25)
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::FutureExt;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::panic::panic_message;
+use super::runtime::{Spawner, Task};
+
+// wait before the first restart of a crashed task, doubled with every crash in a row
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Runs the background loops of the server, e.g. flushing views or enforcing retention,
+/// restarting a loop that panicked after a backoff. Their health is reported by `/readyz`.
+pub struct Supervisor {
+    spawner: Arc<dyn Spawner>,
+    tasks: Arc<Mutex<BTreeMap<&'static str, TaskHealth>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct TaskHealth {
+    pub state: TaskState,
+    /// Times the task panicked and was restarted
+    pub restarts: u32,
+    /// Message of the latest panic
+    pub last_panic: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Panicked, waiting for the backoff before restarting
+    Restarting,
+    /// Returned, which loops only do when there's nothing left to do
+    Stopped,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    /// Whether no background task is waiting to be restarted
+    pub ready: bool,
+    /// Health of the background tasks by name
+    pub tasks: BTreeMap<String, TaskHealth>,
+}
+
+impl Supervisor {
+    pub fn new(spawner: Arc<dyn Spawner>) -> Supervisor {
+        Supervisor {
+            spawner,
+            tasks: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Runs the task built by `task` on the spawner, building and running a new one whenever
+    /// it panics.
+    pub fn supervise<B>(&self, name: &'static str, task: B)
+    where
+        B: Fn() -> Task + Send + Sync + 'static,
+    {
+        let tasks = self.tasks.clone();
+        let set_state = move |state: TaskState, panic: Option<String>| {
+            let mut tasks = tasks.lock().unwrap();
+            let health = tasks.entry(name).or_insert(TaskHealth {
+                state,
+                restarts: 0,
+                last_panic: None,
+            });
+            health.state = state;
+            if panic.is_some() {
+                health.restarts += 1;
+                health.last_panic = panic;
+            }
+        };
+        set_state(TaskState::Running, None);
+
+        self.spawner.spawn(Box::pin(async move {
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                let started_at = Instant::now();
+                let Err(panic) = AssertUnwindSafe(task()).catch_unwind().await else {
+                    set_state(TaskState::Stopped, None);
+                    return;
+                };
+
+                let message = panic_message(panic.as_ref()).to_string();
+                tracing::error!("background task `{}` panicked: {}", name, message);
+                // a task that ran well for a while before crashing starts over with a short wait
+                if started_at.elapsed() > MAX_BACKOFF {
+                    backoff = MIN_BACKOFF;
+                }
+                set_state(TaskState::Restarting, Some(message));
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+
+                tracing::info!("restarting background task `{}`", name);
+                set_state(TaskState::Running, None);
+            }
+        }));
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        let tasks = self.tasks.lock().unwrap();
+        Readiness {
+            ready: tasks
+                .values()
+                .all(|health| health.state != TaskState::Restarting),
+            tasks: tasks
+                .iter()
+                .map(|(name, health)| (name.to_string(), health.clone()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::runtime::TokioSpawner;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn it_restarts_panicking_tasks() {
+        let supervisor = Supervisor::new(Arc::new(TokioSpawner));
+        let runs = Arc::new(AtomicU32::new(0));
+        let task_runs = runs.clone();
+        supervisor.supervise("flaky", move || {
+            let runs = task_runs.clone();
+            Box::pin(async move {
+                if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("boom");
+                }
+            })
+        });
+
+        tokio::task::yield_now().await;
+        let readiness = supervisor.readiness();
+        assert!(!readiness.ready);
+        assert_eq!(readiness.tasks["flaky"].state, TaskState::Restarting);
+
+        tokio::time::sleep(MIN_BACKOFF + Duration::from_millis(200)).await;
+        let readiness = supervisor.readiness();
+        assert!(readiness.ready);
+        assert_eq!(
+            readiness.tasks["flaky"],
+            TaskHealth {
+                state: TaskState::Stopped,
+                restarts: 1,
+                last_panic: Some("boom".to_string()),
+            }
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,105 @@
+use dotenv::dotenv;
+use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Layer};
+
+use super::metrics;
+
+#[cfg(feature = "sentry")]
+use axum::{extract::Request, middleware::Next, response::Response};
+
+/// Keeps error reporting running; events still queued are flushed when dropped.
+pub struct TelemetryGuard {
+    #[cfg(feature = "sentry")]
+    _sentry: Option<sentry::ClientInitGuard>,
+}
+
+pub fn setup(is_production_env: bool) -> TelemetryGuard {
+    setup_logger(is_production_env);
+
+    TelemetryGuard {
+        #[cfg(feature = "sentry")]
+        _sentry: setup_sentry(is_production_env),
+    }
+}
+
+fn setup_logger(is_production_env: bool) {
+    let logs = match is_production_env {
+        // local env
+        false => {
+            dotenv().ok();
+            tracing_subscriber::fmt::layer().pretty().boxed()
+        }
+        // production env
+        true => tracing_subscriber::fmt::layer()
+            .json()
+            .with_target(false)
+            .boxed(),
+    };
+
+    // error events are reported, lower levels are attached to them as breadcrumbs
+    #[cfg(feature = "sentry")]
+    let logs = logs
+        .and_then(sentry::integrations::tracing::layer())
+        .boxed();
+
+    // only logs are filtered by `RUST_LOG`, datastore spans are timed whatever the log level
+    let subscriber = tracing_subscriber::registry()
+        .with(logs.with_filter(EnvFilter::from_default_env()))
+        .with(metrics::span_latency_layer());
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("failed to set global default subscriber");
+}
+
+/// Reports panics and logged errors to sentry when `SENTRY_DSN` is set.
+#[cfg(feature = "sentry")]
+fn setup_sentry(is_production_env: bool) -> Option<sentry::ClientInitGuard> {
+    let dsn = std::env::var("SENTRY_DSN")
+        .ok()
+        .filter(|dsn| !dsn.is_empty())?;
+    let dsn = match dsn.parse::<sentry::types::Dsn>() {
+        Ok(dsn) => dsn,
+        Err(err) => {
+            tracing::warn!("invalid SENTRY_DSN, errors are not reported: {}", err);
+            return None;
+        }
+    };
+
+    tracing::info!("reporting errors to sentry");
+    let mut options = sentry::ClientOptions::new();
+    options.dsn = Some(dsn);
+    options.release = sentry::release_name!();
+    options.environment = Some(
+        match is_production_env {
+            true => "production",
+            false => "development",
+        }
+        .into(),
+    );
+    options.attach_stacktrace = true;
+
+    Some(sentry::init(options))
+}
+
+/// Reports events raised while handling a request with the request's id, method and path.
+#[cfg(feature = "sentry")]
+pub async fn sentry_request_scope(request: Request, next: Next) -> Response {
+    use std::sync::Arc;
+
+    use sentry::{Hub, SentryFutureExt};
+
+    use super::error::REQUEST_ID_HEADER;
+
+    let hub = Arc::new(Hub::new_from_top(Hub::current()));
+    hub.configure_scope(|scope| {
+        if let Some(request_id) = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|request_id| request_id.to_str().ok())
+        {
+            scope.set_tag("request_id", request_id);
+        }
+        scope.set_tag("http.method", request.method());
+        scope.set_tag("http.path", request.uri().path());
+    });
+
+    next.run(request).bind_hub(hub).await
+}
@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::Request,
+    extract::{Host, Path, Query, State as StateExtractor},
+    http::{HeaderMap, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use super::admin::ListUsersParams;
+use super::auth::{bearer_token, constant_time_eq};
+use super::badge::{ShieldsIoFetcher, ShieldsIoParams};
+use super::badge_params::BadgeQuery;
+use super::config::TenantConfig;
+use super::datastore::{DatastoreOperations, Page, UserViews};
+use super::error::{ApiError, ErrorCode};
+use super::github;
+use super::quota::DailyQuota;
+use super::routes;
+use super::state::AppState;
+
+/// Community served by the deployment. Its users are kept under the `<tenant>/` prefix, which
+/// can't clash with plain GitHub user names, so tenants never see each other's counts.
+pub struct Tenant {
+    name: String,
+    config: TenantConfig,
+    pub quota: Option<DailyQuota>,
+}
+
+impl Tenant {
+    /// Key the user's views are stored under; names GitHub wouldn't allow, e.g. a percent-encoded
+    /// `alice%2Fbob` nesting keys within the tenant, are rejected.
+    pub fn user_key(&self, user_name: &str) -> Result<String, ApiError> {
+        if !github::is_user_name(user_name) {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("invalid user name `{}`", user_name),
+            ));
+        }
+        Ok(format!("{}{}", self.prefix(), user_name))
+    }
+
+    fn prefix(&self) -> String {
+        format!("{}/", self.name)
+    }
+
+    /// Badge params of the request, falling back to the tenant's defaults; describes the first
+    /// param missing from both otherwise.
+    pub fn badge_params(&self, query: &BadgeQuery) -> Result<ShieldsIoParams, String> {
+        let label = match (&query.label, &query.label_lang) {
+            (None, None) => self.config.label.clone(),
+            (label, _) => label.clone(),
+        };
+        ShieldsIoParams::from_query(&BadgeQuery {
+            label,
+            label_lang: query.label_lang.clone(),
+            color: query.color.clone().or_else(|| self.config.color.clone()),
+            style: query.style.clone().or_else(|| self.config.style.clone()),
+        })
+    }
+
+    /// Trend threshold of the tenant's badges, in percent.
+    pub fn trend_threshold(&self) -> Option<f64> {
+        self.config.trend_threshold
+    }
+
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        match bearer_token(headers) {
+            Some(token) if constant_time_eq(token.as_bytes(), self.config.api_key.as_bytes()) => {
+                Ok(())
+            }
+            _ => Err(ApiError::new(
+                ErrorCode::Unauthorized,
+                "missing or invalid tenant api key",
+            )),
+        }
+    }
+}
+
+pub struct Tenants {
+    tenants: HashMap<String, Tenant>,
+    // custom domain to tenant name
+    domains: HashMap<String, String>,
+}
+
+impl Tenants {
+    pub fn new(config: &HashMap<String, TenantConfig>) -> Tenants {
+        let tenants = config
+            .iter()
+            .map(|(name, config)| {
+                let tenant = Tenant {
+                    name: name.clone(),
+                    config: config.clone(),
+                    quota: config.daily_view_quota.map(DailyQuota::new),
+                };
+                (name.clone(), tenant)
+            })
+            .collect();
+
+        let domains = config
+            .iter()
+            .flat_map(|(name, config)| {
+                config
+                    .domains
+                    .iter()
+                    .map(|domain| (domain.to_ascii_lowercase(), name.clone()))
+            })
+            .collect();
+
+        Tenants { tenants, domains }
+    }
+
+    /// Tenant served on the host, which may carry a port.
+    fn by_host(&self, host: &str) -> Option<&Tenant> {
+        let domain = host.split(':').next()?.to_ascii_lowercase();
+        self.domains
+            .get(&domain)
+            .and_then(|name| self.tenants.get(name))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Tenant, ApiError> {
+        self.tenants.get(name).ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::TenantNotFound,
+                format!("tenant `{}` not found", name),
+            )
+        })
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Path)]
+pub struct TenantPathParams {
+    tenant: String,
+}
+
+/// Serves the tenant routes on the tenants' custom domains, e.g.
+/// `views.example.dev/octocat/counter.svg` as `/t/<tenant>/octocat/counter.svg`. The uri gets
+/// rewritten, so this has to wrap the router instead of being one of its layers.
+pub async fn route_by_host<T, F>(
+    StateExtractor(state): StateExtractor<Arc<AppState<T, F>>>,
+    host: Option<Host>,
+    mut request: Request,
+    next: Next,
+) -> Response
+where
+    T: DatastoreOperations,
+    F: ShieldsIoFetcher,
+{
+    let tenant_uri = host
+        .and_then(|Host(host)| state.tenants.by_host(&host))
+        .and_then(|tenant| tenant_uri(&tenant.name, request.uri()));
+
+    if let Some(uri) = tenant_uri {
+        *request.uri_mut() = uri;
+    }
+    next.run(request).await
+}
+
+fn tenant_uri(tenant: &str, uri: &Uri) -> Option<Uri> {
+    let path = uri.path();
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    if !matches!(segments.as_slice(), [_, "counter.svg"] | ["users"]) {
+        return None;
+    }
+
+    let uri = match uri.query() {
+        Some(query) => format!("{}?{}", routes::tenant(tenant, path), query),
+        None => routes::tenant(tenant, path),
+    };
+    uri.parse().ok()
+}
+
+/// Lists the tenant's users with their views, in user name order.
+#[utoipa::path(
+    get,
+    path = "/t/{tenant}/users",
+    params(TenantPathParams, ListUsersParams),
+    security(("tenant_api_key" = [])),
+    responses(
+        (status = 200, description = "Page of users", body = UserViewsPage),
+        (status = 401, description = "Missing or invalid tenant api key", body = ErrorBody),
+        (status = 404, description = "Tenant not found", body = ErrorBody),
+        (status = 500, description = "Datastore failure", body = ErrorBody),
+    )
+)]
+pub async fn tenant_users_handler(
+    StateExtractor(state): StateExtractor<
+        Arc<AppState<impl DatastoreOperations, impl ShieldsIoFetcher>>,
+    >,
+    Path(path_params): Path<TenantPathParams>,
+    Query(params): Query<ListUsersParams>,
+    headers: HeaderMap,
+) -> Response {
+    let tenant = match state.tenants.get(&path_params.tenant) {
+        Ok(tenant) => tenant,
+        Err(err) => return err.into_response(),
+    };
+    if let Err(err) = tenant.authorize(&headers) {
+        return err.into_response();
+    }
+
+    let prefix = tenant.prefix();
+    let limit = params.limit();
+    match state.db.scan_prefix(&prefix, params.cursor, limit).await {
+        Ok(page) => Json(Page {
+            users: page
+                .users
+                .into_iter()
+                .map(|user| UserViews {
+                    user_name: user.user_name[prefix.len()..].to_string(),
+                    views: user.views,
+                })
+                .collect(),
+            next_cursor: page.next_cursor,
+        })
+        .into_response(),
+        Err(err) => {
+            tracing::error!(
+                "failed to list users of tenant `{}`, reason: {}",
+                tenant.name,
+                err
+            );
+            ApiError::new(ErrorCode::DatastoreUnavailable, "failed to list users").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::Memory;
+    use pretty_assertions::assert_eq;
+
+    fn tenants() -> Tenants {
+        Tenants::new(&HashMap::from([(
+            "rustaceans".to_string(),
+            TenantConfig {
+                api_key: "key".to_string(),
+                label: Some("views".to_string()),
+                color: Some("orange".to_string()),
+                style: None,
+                daily_view_quota: None,
+                trend_threshold: None,
+                domains: vec!["views.example.dev".to_string()],
+            },
+        )]))
+    }
+
+    #[test]
+    fn it_falls_back_to_tenant_badge_defaults() {
+        let tenants = tenants();
+        let tenant = tenants.get("rustaceans").unwrap();
+
+        let query = BadgeQuery {
+            style: Some("flat".to_string()),
+            ..BadgeQuery::default()
+        };
+        assert!(tenant.badge_params(&query).is_ok());
+
+        let query = BadgeQuery::default();
+        assert_eq!(
+            tenant.badge_params(&query).err().as_deref(),
+            Some("missing style")
+        );
+        assert!(tenants.get("gophers").is_err());
+    }
+
+    #[test]
+    fn it_routes_custom_domains_to_tenant_routes() {
+        let tenants = tenants();
+        let tenant = tenants.by_host("Views.Example.dev:443").unwrap();
+        assert_eq!(tenant.name, "rustaceans");
+        assert!(tenants.by_host("example.dev").is_none());
+
+        let uri = "/octocat/counter.svg?style=flat".parse().unwrap();
+        assert_eq!(
+            tenant_uri(&tenant.name, &uri).unwrap(),
+            "/t/rustaceans/octocat/counter.svg?style=flat"
+        );
+        let uri = "/users".parse().unwrap();
+        assert_eq!(
+            tenant_uri(&tenant.name, &uri).unwrap(),
+            "/t/rustaceans/users"
+        );
+        let uri = "/healthz".parse().unwrap();
+        assert_eq!(tenant_uri(&tenant.name, &uri), None);
+    }
+
+    #[tokio::test]
+    async fn it_isolates_tenant_users() {
+        let tenants = tenants();
+        let tenant = tenants.get("rustaceans").unwrap();
+        let db = Memory::new();
+        db.onboard_user("octocat").await.unwrap();
+        db.onboard_user(&tenant.user_key("octocat").unwrap())
+            .await
+            .unwrap();
+        db.onboard_user("rustaceans-fan").await.unwrap();
+
+        let page = db.scan_prefix(&tenant.prefix(), None, 10).await.unwrap();
+
+        assert_eq!(
+            page.users,
+            vec![UserViews {
+                user_name: "rustaceans/octocat".to_string(),
+                views: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn it_rejects_user_names_nesting_keys() {
+        let tenants = tenants();
+        let tenant = tenants.get("rustaceans").unwrap();
+
+        assert_eq!(tenant.user_key("octocat").unwrap(), "rustaceans/octocat");
+        for user_name in ["alice/bob", "", "../octocat", "octo cat"] {
+            assert!(tenant.user_key(user_name).is_err(), "{}", user_name);
+        }
+    }
+}
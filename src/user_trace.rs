@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Users whose requests log every decision taken for them, until their trace expires.
+#[derive(Default)]
+pub struct UserTraces {
+    expiries: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, ToSchema)]
+pub struct UserTrace {
+    pub user_name: String,
+    /// Requests of the user stop being traced after this
+    pub expires_at: DateTime<Utc>,
+}
+
+impl UserTraces {
+    pub fn new() -> UserTraces {
+        UserTraces::default()
+    }
+
+    /// Traces the user's requests for `ttl`, replacing a running trace of the user.
+    pub fn start(&self, user_name: &str, ttl: Duration) -> UserTrace {
+        self.start_at(Utc::now(), user_name, ttl)
+    }
+
+    /// Stops tracing the user, returning whether the user was traced.
+    pub fn stop(&self, user_name: &str) -> bool {
+        self.expiries.lock().unwrap().remove(user_name).is_some()
+    }
+
+    pub fn is_traced(&self, user_name: &str) -> bool {
+        self.is_traced_at(Utc::now(), user_name)
+    }
+
+    fn start_at(&self, now: DateTime<Utc>, user_name: &str, ttl: Duration) -> UserTrace {
+        let mut expiries = self.expiries.lock().unwrap();
+        // expired traces are only dropped here, so they can't pile up
+        expiries.retain(|_, expires_at| *expires_at > now);
+
+        let expires_at = now + ttl;
+        expiries.insert(user_name.to_string(), expires_at);
+        UserTrace {
+            user_name: user_name.to_string(),
+            expires_at,
+        }
+    }
+
+    fn is_traced_at(&self, now: DateTime<Utc>, user_name: &str) -> bool {
+        // cheap check first, as every counted view asks
+        let expiries = self.expiries.lock().unwrap();
+        !expiries.is_empty()
+            && expiries
+                .get(user_name)
+                .is_some_and(|expires_at| *expires_at > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn it_traces_users_until_expiry() {
+        let traces = UserTraces::new();
+        let now = Utc::now();
+
+        let trace = traces.start_at(now, "alice", Duration::seconds(600));
+        assert_eq!(trace.expires_at, now + Duration::seconds(600));
+        assert!(traces.is_traced_at(now + Duration::seconds(599), "alice"));
+        assert!(!traces.is_traced_at(now + Duration::seconds(600), "alice"));
+        assert!(!traces.is_traced_at(now, "bob"));
+
+        // starting another trace drops the expired ones
+        traces.start_at(now + Duration::seconds(700), "bob", Duration::seconds(60));
+        assert!(!traces.stop("alice"));
+        assert!(traces.stop("bob"));
+    }
+}